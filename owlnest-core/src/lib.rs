@@ -60,3 +60,84 @@ pub mod expect {
 pub mod alias {
     pub type Callback<T> = tokio::sync::oneshot::Sender<T>;
 }
+
+pub mod snapshot {
+    use serde::{Serialize, de::DeserializeOwned};
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc;
+    use tracing::warn;
+
+    /// Serializes snapshots to JSON and writes them to disk on a dedicated thread,
+    /// so that neither blocks the caller.
+    /// Snapshots queued behind a write are skipped in favour of the latest one.
+    pub struct SnapshotWriter<T> {
+        /// Snapshots along with who to tell once they are written.
+        tx: mpsc::Sender<(T, Option<mpsc::Sender<()>>)>,
+    }
+    impl<T: Serialize + Send + 'static> SnapshotWriter<T> {
+        /// `name` describes the content in logs, e.g. "blob journal".
+        pub fn spawn(path: PathBuf, name: &'static str) -> Self {
+            let (tx, rx) = mpsc::channel::<(T, Option<mpsc::Sender<()>>)>();
+            std::thread::spawn(move || {
+                while let Ok((mut snapshot, ack)) = rx.recv() {
+                    let mut acks = Vec::from_iter(ack);
+                    while let Ok((newer, ack)) = rx.try_recv() {
+                        snapshot = newer;
+                        acks.extend(ack);
+                    }
+                    let result = serde_json::to_vec(&snapshot)
+                        .map_err(std::io::Error::from)
+                        .and_then(|bytes| write_atomic(&path, &bytes));
+                    if let Err(e) = result {
+                        warn!("Failed to write {} {:?}: {}", name, path, e);
+                    }
+                    for ack in acks {
+                        let _ = ack.send(());
+                    }
+                }
+            });
+            Self { tx }
+        }
+        pub fn save(&self, snapshot: T) {
+            let _ = self.tx.send((snapshot, None));
+        }
+        /// Write the snapshot and wait until the write is done.
+        /// This blocks the caller, it's meant for shutting down.
+        pub fn flush(&self, snapshot: T) {
+            let (ack_tx, ack_rx) = mpsc::channel();
+            if self.tx.send((snapshot, Some(ack_tx))).is_ok() {
+                let _ = ack_rx.recv();
+            }
+        }
+    }
+    impl<T> std::fmt::Debug for SnapshotWriter<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("SnapshotWriter")
+        }
+    }
+
+    /// Read the snapshot at the path, `None` if the file doesn't exist or cannot be parsed.
+    pub fn load<T: DeserializeOwned>(path: &Path, name: &str) -> Option<T> {
+        match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| warn!("Failed to parse {} {:?}: {}", name, path, e))
+                .ok(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Failed to read {} {:?}: {}", name, path, e);
+                None
+            }
+        }
+    }
+
+    /// Write to a temporary file first, so a crash won't leave a truncated file.
+    pub fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+        use std::io::Write;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        std::fs::rename(tmp, path)
+    }
+}
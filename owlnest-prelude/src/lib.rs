@@ -15,6 +15,7 @@ pub mod swarm_prelude {}
 pub mod behaviour_prelude {
     pub use libp2p::core::transport::PortUse;
     pub use libp2p::core::Endpoint;
+    pub use libp2p::swarm::CloseConnection;
     pub use libp2p::swarm::NetworkBehaviour;
     pub use libp2p::swarm::NotifyHandler;
     pub use libp2p::swarm::{ConnectionClosed, ConnectionDenied, ConnectionHandler, ConnectionId};
@@ -23,6 +24,8 @@ pub mod behaviour_prelude {
 }
 
 pub mod lib_prelude {
+    pub use libp2p::core::SignedEnvelope;
+    pub use libp2p::identity::{Keypair, SigningError};
     pub use libp2p::{Multiaddr, PeerId};
 }
 
@@ -70,17 +73,35 @@ pub mod utils {
                 ))
             }
 
-            pub async fn recv<S>(mut stream: S) -> io::Result<(S, Vec<u8>)>
+            /// Default upper bound of bytes that [`recv`] will accept in a single message.
+            pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 128 * 1024;
+
+            /// Receive a message of at most [`DEFAULT_MAX_MESSAGE_SIZE`] bytes.
+            pub async fn recv<S>(stream: S) -> io::Result<(S, Vec<u8>)>
+            where
+                S: AsyncRead + AsyncWrite + Unpin,
+            {
+                recv_with_limit(stream, DEFAULT_MAX_MESSAGE_SIZE).await
+            }
+
+            /// Receive a message of at most `max_size` bytes.  
+            /// The stream will be aborted if the remote announces a longer message.
+            pub async fn recv_with_limit<S>(
+                mut stream: S,
+                max_size: usize,
+            ) -> io::Result<(S, Vec<u8>)>
             where
                 S: AsyncRead + AsyncWrite + Unpin,
             {
                 let mut buf = [0u8; 8];
                 stream.read_exact(&mut buf).await?;
                 let bytes_to_read = usize::from_be_bytes(buf);
-                if bytes_to_read / 1024 > 128 {
+                if bytes_to_read > max_size {
                     return io::Result::Err(io::Error::new(
                         io::ErrorKind::ConnectionAborted,
-                        "Stream too long. Terminating.",
+                        format!(
+                            "Stream too long({bytes_to_read} bytes, limit {max_size} bytes). Terminating."
+                        ),
                     ));
                 }
                 let mut msg_buf: Vec<u8> = Vec::with_capacity(bytes_to_read);
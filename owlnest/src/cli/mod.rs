@@ -107,7 +107,7 @@ fn handle_command(
             command,
         )),
         #[cfg(any(feature = "owlnest-protocols", feature = "owlnest-blob"))]
-        Blob(command) => executor.block_on(blob::cli::handle_blob(manager.blob(), command)),
+        Blob(command) => executor.block_on(blob::cli::handle_blob(manager, command)),
         #[cfg(any(feature = "owlnest-protocols", feature = "owlnest-messaging"))]
         Messaging(command) => executor.block_on(messaging::cli::handle_messaging(
             manager.messaging(),
@@ -124,6 +124,11 @@ fn handle_command(
         Mdns(command) => executor.block_on(mdns::cli::handle_mdns(manager.mdns(), command)),
         #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-relay-client"))]
         RelayClient(command) => relay_client::cli::handle_relay_client(manager, command),
+        #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-client"))]
+        Rendezvous(command) => executor.block_on(rendezvous::cli::handle_rendezvous(
+            manager.rendezvous(),
+            command,
+        )),
         #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-gossipsub"))]
         Gossipsub(command) => {
             executor.block_on(gossipsub::cli::handle_gossipsub(
@@ -234,6 +239,10 @@ enum Command {
     #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-relay-client"))]
     #[command(subcommand)]
     RelayClient(relay_client::cli::RelayClient),
+    /// Subcommand for managing `libp2p-rendezvous` protocol.
+    #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-client"))]
+    #[command(subcommand)]
+    Rendezvous(rendezvous::cli::Rendezvous),
     /// Subcommand for using various utilities.
     #[command(subcommand)]
     Utils(utils::Utils),
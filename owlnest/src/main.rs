@@ -36,8 +36,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
     let mgr = setup_peer(ident.clone(), config, rt.handle().clone());
     let shutdown_notifier = std::sync::Arc::new(Notify::const_new());
-    cli::setup_interactive_shell(ident.clone(), mgr, shutdown_notifier.clone());
-    rt.block_on(shutdown_notifier.notified());
+    cli::setup_interactive_shell(ident.clone(), mgr.clone(), shutdown_notifier.clone());
+    rt.block_on(async {
+        shutdown_notifier.notified().await;
+        mgr.swarm().shutdown().await
+    });
     Ok(())
 }
 
@@ -45,6 +45,12 @@ pub struct SwarmConfig {
     /// Config for client part of `libp2p-relay`
     #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-gossipsub"))]
     pub gossipsub: gossipsub::Config,
+    /// Config for client part of `libp2p-rendezvous`
+    #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-client"))]
+    pub rendezvous: rendezvous::Config,
+    /// Config for server part of `libp2p-rendezvous`
+    #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-server"))]
+    pub rendezvous_server: rendezvous_server::Config,
 }
 
 /// Some utility functions for setting up tests
@@ -54,12 +60,6 @@ pub mod test_suit {
     /// Set up a swarm with default config and random identity
     /// on a dedicated `tokio` runtime.
     pub fn setup_default() -> (Manager, std::sync::Arc<Notify>) {
-        let rt = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .expect("Tokio runtime to be created successfully");
-        let ident = identity::IdentityUnion::generate();
-        let guard = rt.enter();
         let swarm_config = SwarmConfig {
             swarm: Default::default(),
             #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-autonat"))]
@@ -80,7 +80,22 @@ pub mod test_suit {
             relay_server: protocols::relay_server::Config::default(),
             #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-gossipsub"))]
             gossipsub: gossipsub::Config::default(),
+            #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-client"))]
+            rendezvous: rendezvous::Config::default(),
+            #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-server"))]
+            rendezvous_server: rendezvous_server::Config::default(),
         };
+        setup_with_config(swarm_config)
+    }
+    /// Set up a swarm with the given config and random identity
+    /// on a dedicated `tokio` runtime.
+    pub fn setup_with_config(swarm_config: SwarmConfig) -> (Manager, std::sync::Arc<Notify>) {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("Tokio runtime to be created successfully");
+        let ident = identity::IdentityUnion::generate();
+        let guard = rt.enter();
         let mgr = swarm::Builder::new(swarm_config).build(ident, rt.handle().clone());
         drop(guard);
         let shutdown_notifier = std::sync::Arc::new(Notify::const_new());
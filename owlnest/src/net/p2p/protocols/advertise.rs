@@ -28,12 +28,15 @@ impl Handle {
             rx,
         )
     }
-    /// Send query to a remote for current advertisements.
-    /// Will return `Err(Error::NotProviding)` for peers who don't support this protocol.
+    /// Send query to a remote for current advertisements,
+    /// only those with the tag if supplied.
+    /// Will return `Ok(None)` if the remote is not providing,
+    /// and `Err(Error::NotProviding)` for peers who don't support this protocol.
     pub async fn query_advertised_peer(
         &self,
         relay: PeerId,
-    ) -> Result<Option<Box<[PeerId]>>, Error> {
+        tag: Option<String>,
+    ) -> Result<Option<Box<[AdvertisedRecord]>>, Error> {
         let mut listener = self.swarm_event_source.subscribe();
         let fut = listen_event!(listener for Advertise,
             OutEvent::QueryAnswered { from, list } => {
@@ -47,7 +50,38 @@ impl Handle {
                 }
             }
         );
-        let ev = InEvent::QueryAdvertisedPeer { peer: relay };
+        let ev = InEvent::QueryAdvertisedPeer { peer: relay, tag };
+        self.sender.send(ev).await.expect("");
+        match future_timeout!(fut, 10000) {
+            Ok(v) => v,
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+    /// Subscribe to changes of advertisements on a remote provider,
+    /// only those with the tag if supplied.
+    /// Returns current advertisements, changes are then emitted as
+    /// [`OutEvent::RemoteAdvertisementChanged`] until unsubscribed or disconnected.
+    /// Will return `Ok(None)` if the remote is not providing,
+    /// and `Err(Error::NotProviding)` for peers who don't support this protocol.
+    pub async fn subscribe(
+        &self,
+        provider: PeerId,
+        tag: Option<String>,
+    ) -> Result<Option<Box<[AdvertisedRecord]>>, Error> {
+        let mut listener = self.swarm_event_source.subscribe();
+        let fut = listen_event!(listener for Advertise,
+            OutEvent::QueryAnswered { from, list } => {
+                if *from == provider {
+                    return Ok(list.clone());
+                }
+            }
+            OutEvent::Error(Error::NotProviding(peer)) => {
+                if *peer == provider {
+                    return Err(Error::NotProviding(*peer))
+                }
+            }
+        );
+        let ev = InEvent::Subscribe { provider, tag };
         self.sender.send(ev).await.expect("");
         match future_timeout!(fut, 10000) {
             Ok(v) => v,
@@ -70,8 +104,8 @@ impl Handle {
     generate_handler_method!(
         /// List all peers that supports and connected to this peer.
         ListConnected:list_connected()->Box<[PeerId]>;
-        /// List all advertisement on local peer.
-        ListAdvertised:list_advertised()->Box<[PeerId]>;
+        /// List all unexpired advertisements on local peer.
+        ListAdvertised:list_advertised()->Box<[AdvertisedRecord]>;
         /// Get provider state of local peer.
         /// Will return a immediate state report, e.g. only changes caused by this operation.
         GetProviderState:provider_state()->bool;
@@ -79,6 +113,8 @@ impl Handle {
     generate_handler_method!(
         /// Clear all advertisements on local peer.
         ClearAdvertised:clear_advertised();
+        /// Stop getting changes from a remote provider.
+        Unsubscribe:unsubscribe(provider: &PeerId);
     );
     #[allow(unused)]
     fn next_id(&self) -> u64 {
@@ -92,23 +128,54 @@ impl Handle {
         /// Set provider state of local peer.
         /// Will return a recent(not immediate) state change.
         SetProviderState:set_provider_state(target_state: |bool|) -> bool;
-        /// Set advertisement on a remote peer.
-        /// This function will return immediately, the effect is not guaranteed:
-        /// - peers that are not connected
-        /// - peers that don't support this protocol
-        /// - peers that are not providing
-        /// ## Silent failure
-        SetRemoteAdvertisement:set_remote_advertisement(remote: &PeerId, state: |bool|) -> ();
     );
+    /// Post an advertisement of local peer on a remote peer, or retract it with `None`.
+    /// Addresses of local peer are filled in if the advertisement has none.
+    /// The advertisement expires after its TTL, post it again to renew.
+    /// `token` is required by providers that only accept advertisers with a token.
+    /// Will return `Err(Error::Rejected)` with the reason given by the remote,
+    /// and `Err(Error::NotProviding)` for peers that are not connected.
+    pub async fn set_remote_advertisement(
+        &self,
+        remote: &PeerId,
+        advertisement: Option<Advertisement>,
+        token: Option<String>,
+    ) -> Result<(), Error> {
+        let remote = *remote;
+        let mut listener = self.swarm_event_source.subscribe();
+        let fut = listen_event!(listener for Advertise,
+            OutEvent::RemoteAdvertisementResult { from, result } => {
+                if *from == remote {
+                    return result.clone().map_err(Error::Rejected);
+                }
+            }
+            OutEvent::Error(Error::NotProviding(peer)) => {
+                if *peer == remote {
+                    return Err(Error::NotProviding(*peer))
+                }
+            }
+        );
+        let ev = InEvent::SetRemoteAdvertisement {
+            remote,
+            advertisement,
+            token,
+        };
+        self.sender.send(ev).await.expect("");
+        match future_timeout!(fut, 10000) {
+            Ok(v) => v,
+            Err(_) => Err(Error::Timeout),
+        }
+    }
 }
 
 pub mod cli {
 
     use super::*;
+    use crate::net::p2p::swarm::{BehaviourEvent, SwarmEvent};
     use clap::Subcommand;
     use libp2p::PeerId;
-    use prettytable::table;
-    use printable::iter::PrintableIter;
+    use prettytable::{row, Table};
+    use tokio::sync::broadcast;
 
     /// Subcommand for managing `owlnest-advertise` protocol.  
     /// `owlnest-advertise` intends to provide a machine-operable way
@@ -120,51 +187,85 @@ pub mod cli {
     #[derive(Debug, Subcommand)]
     pub enum Advertise {
         /// Post an AD on or retract an AD from the remote.  
-        /// This command will return immediately, and it's effect is not guaranteed.
-        /// The remote can still be showing the AD.
+        /// The remote can reject the AD, e.g. when it's full or only accepts some peers.
         SetRemoteAdvertisement {
             /// Peer ID of the remote peer.
             remote: PeerId,
             /// `true` to posting an AD, `false` to retract an AD.
             state: bool,
+            /// Service tags of the AD, can be supplied multiple times.
+            #[arg(long = "tag")]
+            tags: Vec<String>,
+            /// Metadata of the AD in `key=value` form, can be supplied multiple times.
+            #[arg(long = "meta", value_parser = parse_key_value)]
+            metadata: Vec<(String, String)>,
+            /// Seconds until the AD expires on the remote.
+            #[arg(long, default_value_t = record::DEFAULT_TTL_SECS)]
+            ttl: u64,
+            /// Token required by the remote to post an AD.
+            #[arg(long)]
+            token: Option<String>,
         },
         /// Query for all ADs on the remote peer.
         QueryAdvertised {
             /// Peer ID of the remote peer.
             remote: PeerId,
+            /// Only show ADs with this service tag.
+            #[arg(long)]
+            tag: Option<String>,
         },
         /// Subcommand for managing local provider, e.g whether or not to
         /// answer query from other peers.
         #[command(subcommand)]
         Provider(provider::Provider),
+        /// Show ADs on the remote peer and changes to them as they happen,
+        /// until interrupted with Ctrl+C.
+        Watch {
+            /// Peer ID of the remote peer.
+            remote: PeerId,
+            /// Only show ADs with this service tag.
+            #[arg(long)]
+            tag: Option<String>,
+        },
     }
 
     pub async fn handle_advertise(handle: &Handle, command: Advertise) {
         use Advertise::*;
         match command {
             Provider(command) => provider::handle_provider(handle, command).await,
-            SetRemoteAdvertisement { remote, state } => {
-                handle.set_remote_advertisement(&remote, state).await;
-                println!("OK")
+            Watch { remote, tag } => watch(handle, remote, tag).await,
+            SetRemoteAdvertisement {
+                remote,
+                state,
+                tags,
+                metadata,
+                ttl,
+                token,
+            } => {
+                let advertisement = state.then(|| {
+                    let mut advertisement = Advertisement::new().with_ttl(ttl);
+                    advertisement.tags = tags;
+                    advertisement.metadata = metadata.into_iter().collect();
+                    advertisement
+                });
+                match handle
+                    .set_remote_advertisement(&remote, advertisement, token)
+                    .await
+                {
+                    Ok(()) => println!("OK"),
+                    Err(e) => println!("Failed to set advertisement on {remote}: {e}"),
+                }
             }
-            QueryAdvertised { remote } => {
-                let result = handle.query_advertised_peer(remote).await;
+            QueryAdvertised { remote, tag } => {
+                let result = handle.query_advertised_peer(remote, tag).await;
                 match result {
                     Ok(v) => {
                         if v.is_none() {
                             return println!("Remote {remote} is not providing");
                         }
                         let list = v.expect("Already handled");
-                        let table = table!(
-                            [format!("Peers advertised by\n{}", remote)],
-                            [list
-                                .iter()
-                                .printable()
-                                .with_left_bound("")
-                                .with_right_bound("")
-                                .with_separator("\n")]
-                        );
-                        table.printstd();
+                        println!("Peers advertised by {remote}:");
+                        print_records(&list);
                     }
                     Err(_) => println!(
                         "Remote {remote} is not connected or doesn't support `owlput-advertise`."
@@ -174,6 +275,95 @@ pub mod cli {
         }
     }
 
+    /// Print advertisements as a table.
+    fn print_records(list: &[AdvertisedRecord]) {
+        let mut table = Table::new();
+        table.set_titles(row!["Peer", "Addresses", "Tags", "Metadata", "Expires In"]);
+        for record in list {
+            let advertisement = &record.advertisement;
+            let addrs = advertisement
+                .listen_addrs
+                .iter()
+                .map(|v| v.to_string())
+                .chain(
+                    advertisement
+                        .relay_addrs
+                        .iter()
+                        .map(|v| format!("{v} (relayed)")),
+                )
+                .collect::<Vec<_>>();
+            let metadata = advertisement
+                .metadata
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>();
+            table.add_row(row![
+                record.peer,
+                addrs.join("\n"),
+                advertisement.tags.join(", "),
+                metadata.join("\n"),
+                format!("{}s", record.expires_in_secs)
+            ]);
+        }
+        table.printstd()
+    }
+
+    async fn watch(handle: &Handle, remote: PeerId, tag: Option<String>) {
+        // Subscribe to local events first, so that no change is missed.
+        let mut listener = handle.swarm_event_source.subscribe();
+        let list = match handle.subscribe(remote, tag).await {
+            Ok(Some(list)) => list,
+            Ok(None) => return println!("Remote {remote} is not providing"),
+            Err(e) => return println!("Cannot watch {remote}: {e}"),
+        };
+        println!("Peers advertised by {remote}:");
+        print_records(&list);
+        println!("Watching for changes, press Ctrl+C to stop.");
+        loop {
+            let ev = tokio::select! {
+                ev = listener.recv() => ev,
+                _ = tokio::signal::ctrl_c() => break,
+            };
+            let ev = match ev {
+                Ok(ev) => ev,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            match ev.as_ref() {
+                SwarmEvent::Behaviour(BehaviourEvent::Advertise(
+                    OutEvent::RemoteAdvertisementChanged { from, change },
+                )) if *from == remote => match change {
+                    AdvertisementChange::Added(record) => {
+                        println!("Added:");
+                        print_records(std::slice::from_ref(record))
+                    }
+                    AdvertisementChange::Renewed(record) => {
+                        println!("Renewed:");
+                        print_records(std::slice::from_ref(record))
+                    }
+                    AdvertisementChange::Expired(peer) => println!("Expired: {peer}"),
+                    AdvertisementChange::Removed(peer) => println!("Removed: {peer}"),
+                },
+                SwarmEvent::ConnectionClosed {
+                    peer_id,
+                    num_established: 0,
+                    ..
+                } if *peer_id == remote => {
+                    println!("Disconnected from {remote}");
+                    return;
+                }
+                _ => {}
+            }
+        }
+        handle.unsubscribe(&remote).await;
+    }
+
+    fn parse_key_value(s: &str) -> Result<(String, String), String> {
+        s.split_once('=')
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .ok_or(format!("Expected `key=value`, got `{s}`"))
+    }
+
     mod provider {
         use clap::{arg, Subcommand};
 
@@ -213,7 +403,8 @@ pub mod cli {
                 State => println!("isProviding:{}", handle.provider_state().await),
                 ListAdvertised => {
                     let list = handle.list_advertised().await;
-                    println!("Advertising: \n{list:?}");
+                    println!("Advertising:");
+                    print_records(&list);
                 }
                 RemoveAdvertise { peer } => {
                     match handle.remove_advertised(&peer).await {
@@ -233,6 +424,7 @@ pub mod cli {
 
 #[cfg(test)]
 mod test {
+    use super::Advertisement;
     use crate::{net::p2p::test_suit::setup_default, sleep};
     use anyhow::Ok;
     use libp2p::Multiaddr;
@@ -261,19 +453,22 @@ mod test {
             .block_on(peer1_m.advertise().set_provider_state(true)));
         trace!("provider state set");
         sleep!(200);
-        peer2_m.executor().block_on(
-            peer2_m
-                .advertise()
-                .set_remote_advertisement(&peer1_id, true),
-        );
+        peer2_m
+            .executor()
+            .block_on(peer2_m.advertise().set_remote_advertisement(
+                &peer1_id,
+                Some(Advertisement::new()),
+                None,
+            ))?;
         assert!(peer2_m.swarm().is_connected_blocking(&peer1_id));
         trace!("peer 1 connected and advertisement set");
         sleep!(200);
         assert!(peer2_m
             .executor()
-            .block_on(peer2_m.advertise().query_advertised_peer(peer1_id))?
+            .block_on(peer2_m.advertise().query_advertised_peer(peer1_id, None))?
             .expect("peer to exist")
-            .contains(&peer2_id));
+            .iter()
+            .any(|v| v.peer == peer2_id));
         trace!("found advertisement for peer2 on peer1");
         assert!(!peer1_m
             .executor()
@@ -283,15 +478,15 @@ mod test {
         assert!(
             peer2_m
                 .executor()
-                .block_on(peer2_m.advertise().query_advertised_peer(peer1_id))?
+                .block_on(peer2_m.advertise().query_advertised_peer(peer1_id, None))?
                 == None
         );
         trace!("advertisement no longer available");
         peer2_m.executor().block_on(
             peer2_m
                 .advertise()
-                .set_remote_advertisement(&peer1_id, false),
-        );
+                .set_remote_advertisement(&peer1_id, None, None),
+        )?;
         trace!("removed advertisement on peer1(testing presistence)");
         assert!(peer1_m
             .executor()
@@ -301,7 +496,7 @@ mod test {
         assert!(
             peer2_m
                 .executor()
-                .block_on(peer2_m.advertise().query_advertised_peer(peer1_id))?
+                .block_on(peer2_m.advertise().query_advertised_peer(peer1_id, None))?
                 .expect("peer to exist")
                 .len()
                 == 0
@@ -309,6 +504,344 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn query_by_tag_and_expire() -> anyhow::Result<()> {
+        use crate::net::p2p::test_suit::setup_with_config;
+        let mut config = crate::net::p2p::SwarmConfig::default();
+        config.advertise = super::config::Config::default().with_max_ttl(2);
+        let (peer1_m, _) = setup_with_config(config);
+        let (peer2_m, _) = setup_default();
+        peer1_m
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        peer2_m
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep!(200);
+        let peer1_id = peer1_m.identity().get_peer_id();
+        let peer2_id = peer2_m.identity().get_peer_id();
+        peer2_m
+            .swarm()
+            .dial_blocking(&peer1_m.swarm().list_listeners_blocking()[0])?;
+        sleep!(200);
+        assert!(peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().set_provider_state(true)));
+        let advertisement = Advertisement::new()
+            .with_tag("relay")
+            .with_metadata("region", "local")
+            .with_ttl(60);
+        peer2_m
+            .executor()
+            .block_on(peer2_m.advertise().set_remote_advertisement(
+                &peer1_id,
+                Some(advertisement),
+                None,
+            ))?;
+        sleep!(200);
+        let list = peer2_m
+            .executor()
+            .block_on(
+                peer2_m
+                    .advertise()
+                    .query_advertised_peer(peer1_id, Some("relay".into())),
+            )?
+            .expect("peer to be providing");
+        assert_eq!(list.len(), 1);
+        // Signature is verified by both the provider and the querier.
+        assert_eq!(list[0].peer, peer2_id);
+        assert!(list[0].seq > 0);
+        assert_eq!(list[0].advertisement.metadata["region"], "local");
+        // TTL is capped by the provider.
+        assert!(list[0].expires_in_secs <= 2);
+        assert!(list[0]
+            .advertisement
+            .listen_addrs
+            .contains(&peer2_m.swarm().list_listeners_blocking()[0]));
+        assert!(peer2_m
+            .executor()
+            .block_on(
+                peer2_m
+                    .advertise()
+                    .query_advertised_peer(peer1_id, Some("storage".into())),
+            )?
+            .expect("peer to be providing")
+            .is_empty());
+        sleep!(3000);
+        assert!(peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().list_advertised())
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn reject_and_evict() -> anyhow::Result<()> {
+        use super::config::{Access, AccessMode, Eviction};
+        use super::{Error, Rejection};
+        use crate::net::p2p::test_suit::setup_with_config;
+        let mut config = crate::net::p2p::SwarmConfig::default();
+        config.advertise = super::config::Config::default()
+            .with_capacity(1, Eviction::Oldest)
+            .with_access(Access {
+                mode: AccessMode::Token,
+                allow_list: vec![],
+                token: "secret".into(),
+            });
+        let (peer1_m, _) = setup_with_config(config);
+        let (peer2_m, _) = setup_default();
+        let (peer3_m, _) = setup_default();
+        peer1_m
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep!(200);
+        let peer1_id = peer1_m.identity().get_peer_id();
+        let peer1_addr = peer1_m.swarm().list_listeners_blocking()[0].clone();
+        peer2_m.swarm().dial_blocking(&peer1_addr)?;
+        peer3_m.swarm().dial_blocking(&peer1_addr)?;
+        sleep!(200);
+        let advertise = |manager: &crate::net::p2p::swarm::Manager, token: Option<&str>| {
+            manager
+                .executor()
+                .block_on(manager.advertise().set_remote_advertisement(
+                    &peer1_id,
+                    Some(Advertisement::new()),
+                    token.map(Into::into),
+                ))
+        };
+        assert_eq!(
+            advertise(&peer2_m, Some("secret")),
+            Err(Error::Rejected(Rejection::NotProviding))
+        );
+        assert!(peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().set_provider_state(true)));
+        assert_eq!(
+            advertise(&peer2_m, None),
+            Err(Error::Rejected(Rejection::InvalidToken))
+        );
+        advertise(&peer2_m, Some("secret"))?;
+        // Capacity is reached, the advertisement of peer2 is evicted.
+        advertise(&peer3_m, Some("secret"))?;
+        let list = peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().list_advertised());
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].peer, peer3_m.identity().get_peer_id());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn reject_forged_advertisement() -> anyhow::Result<()> {
+        use super::{Error, InEvent, OutEvent, Rejection, SignedAdvertisement};
+        use crate::net::p2p::swarm::{BehaviourEvent, SwarmEvent};
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+        let (peer1_m, _) = setup_default();
+        let (peer2_m, _) = setup_default();
+        let (peer3_m, _) = setup_default();
+        peer1_m
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep!(200);
+        let peer1_id = peer1_m.identity().get_peer_id();
+        peer2_m
+            .swarm()
+            .dial_blocking(&peer1_m.swarm().list_listeners_blocking()[0])?;
+        sleep!(200);
+        assert!(peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().set_provider_state(true)));
+        // Post the signed advertisement from peer2 as is.
+        let post = |advertisement: SignedAdvertisement| {
+            peer2_m.executor().block_on(async {
+                let mut listener = peer2_m.event_subscriber().subscribe();
+                let result = async {
+                    loop {
+                        let ev = listener.recv().await.map_err(|_| Error::Channel)?;
+                        if let SwarmEvent::Behaviour(BehaviourEvent::Advertise(
+                            OutEvent::RemoteAdvertisementResult { from, result },
+                        )) = ev.as_ref()
+                        {
+                            if *from == peer1_id {
+                                return result.clone().map_err(Error::Rejected);
+                            }
+                        }
+                    }
+                };
+                let ev = InEvent::SetRemoteSignedAdvertisement {
+                    remote: peer1_id,
+                    advertisement,
+                    token: None,
+                };
+                peer2_m.advertise().sender.send(ev).await.expect("");
+                tokio::time::timeout(Duration::from_secs(10), result)
+                    .await
+                    .unwrap_or(Err(Error::Timeout))
+            })
+        };
+        let seq = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let advertisement = Advertisement::new().with_metadata("region", "local");
+        // Signed by peer3 but sent by peer2.
+        let signed_by_other = SignedAdvertisement::sign(
+            &peer3_m.identity().get_keypair(),
+            seq,
+            advertisement.clone(),
+        )?;
+        assert!(matches!(
+            post(signed_by_other),
+            Err(Error::Rejected(Rejection::Invalid(_)))
+        ));
+        let signed =
+            SignedAdvertisement::sign(&peer2_m.identity().get_keypair(), seq, advertisement)?;
+        let mut envelope = serde_json::to_value(&signed)?;
+        let bytes = serde_json::from_value::<Vec<u8>>(envelope["envelope"].take())?;
+        // Altered after signing, the payload is still valid.
+        let mut altered = bytes.clone();
+        let at = altered
+            .windows(5)
+            .position(|v| v == b"local")
+            .expect("Metadata to be in the payload");
+        altered[at..at + 5].copy_from_slice(b"LOCAL");
+        assert!(matches!(
+            post(serde_json::from_value(
+                serde_json::json!({ "envelope": altered })
+            )?),
+            Err(Error::Rejected(Rejection::Invalid(_)))
+        ));
+        // Forged signature, which is the last field of the envelope.
+        let mut forged = bytes;
+        *forged.last_mut().expect("Envelope not to be empty") ^= 0xff;
+        assert!(matches!(
+            post(serde_json::from_value(
+                serde_json::json!({ "envelope": forged })
+            )?),
+            Err(Error::Rejected(Rejection::Invalid(_)))
+        ));
+        assert!(peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().list_advertised())
+            .is_empty());
+        post(signed)?;
+        let list = peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().list_advertised());
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].peer, peer2_m.identity().get_peer_id());
+        assert_eq!(list[0].advertisement.metadata["region"], "local");
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn push_changes_to_subscriber() -> anyhow::Result<()> {
+        use super::{AdvertisementChange, OutEvent};
+        use crate::net::p2p::swarm::{BehaviourEvent, SwarmEvent};
+        let (peer1_m, _) = setup_default();
+        let (peer2_m, _) = setup_default();
+        let (peer3_m, _) = setup_default();
+        peer1_m
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep!(200);
+        let peer1_id = peer1_m.identity().get_peer_id();
+        let peer3_id = peer3_m.identity().get_peer_id();
+        let peer1_addr = peer1_m.swarm().list_listeners_blocking()[0].clone();
+        peer2_m.swarm().dial_blocking(&peer1_addr)?;
+        peer3_m.swarm().dial_blocking(&peer1_addr)?;
+        sleep!(200);
+        assert!(peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().set_provider_state(true)));
+        let mut listener = peer2_m.event_subscriber().subscribe();
+        let snapshot = peer2_m
+            .executor()
+            .block_on(peer2_m.advertise().subscribe(peer1_id, None))?
+            .expect("peer to be providing");
+        assert!(snapshot.is_empty());
+        sleep!(200);
+        peer3_m
+            .executor()
+            .block_on(peer3_m.advertise().set_remote_advertisement(
+                &peer1_id,
+                Some(Advertisement::new().with_tag("relay")),
+                None,
+            ))?;
+        peer3_m.executor().block_on(
+            peer3_m
+                .advertise()
+                .set_remote_advertisement(&peer1_id, None, None),
+        )?;
+        sleep!(200);
+        let mut changes = Vec::new();
+        while let std::result::Result::Ok(ev) = listener.try_recv() {
+            if let SwarmEvent::Behaviour(BehaviourEvent::Advertise(
+                OutEvent::RemoteAdvertisementChanged { from, change },
+            )) = ev.as_ref()
+            {
+                assert_eq!(*from, peer1_id);
+                changes.push(change.clone());
+            }
+        }
+        assert_eq!(changes.len(), 2);
+        assert!(
+            matches!(&changes[0], AdvertisementChange::Added(record) if record.peer == peer3_id)
+        );
+        assert_eq!(changes[1], AdvertisementChange::Removed(peer3_id));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn restore_provider_after_restart() -> anyhow::Result<()> {
+        use crate::net::p2p::test_suit::setup_with_config;
+        let dir = temp_dir::TempDir::new()?;
+        let mut config = crate::net::p2p::SwarmConfig::default();
+        config.advertise = super::config::Config::default()
+            // Only the snapshot taken on shutdown is written.
+            .with_persistence(dir.path().join("advertise.json").to_string_lossy(), 3600);
+        let (peer1_m, _) = setup_with_config(config.clone());
+        let (peer2_m, _) = setup_default();
+        peer1_m
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep!(200);
+        let peer1_id = peer1_m.identity().get_peer_id();
+        let peer2_id = peer2_m.identity().get_peer_id();
+        peer2_m
+            .swarm()
+            .dial_blocking(&peer1_m.swarm().list_listeners_blocking()[0])?;
+        sleep!(200);
+        assert!(peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().set_provider_state(true)));
+        peer2_m
+            .executor()
+            .block_on(peer2_m.advertise().set_remote_advertisement(
+                &peer1_id,
+                Some(Advertisement::new().with_tag("relay")),
+                None,
+            ))?;
+        peer1_m.executor().block_on(peer1_m.swarm().shutdown());
+        sleep!(200);
+        // Connections are closed along with the first instance.
+        assert!(!peer2_m.swarm().is_connected_blocking(&peer1_id));
+        drop(peer1_m);
+        let (peer1_m, _) = setup_with_config(config);
+        assert!(peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().provider_state()));
+        let list = peer1_m
+            .executor()
+            .block_on(peer1_m.advertise().list_advertised());
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].peer, peer2_id);
+        assert!(list[0].advertisement.has_tag("relay"));
+        Ok(())
+    }
+
     // Attach when necessary
     #[allow(unused)]
     fn setup_logging() {
@@ -1,23 +1,52 @@
 use super::*;
-use owlnest_blob::error::{CancellationError, FileRecvError, FileSendError};
-use owlnest_blob::Config;
-pub use owlnest_blob::{config, error, Behaviour, InEvent, OutEvent};
-pub use owlnest_blob::{RecvInfo, SendInfo};
+#[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+use crate::net::p2p::swarm::manager::Manager;
+use crate::net::p2p::swarm::{behaviour::BehaviourEvent, SwarmEvent};
+use futures::{StreamExt, TryStreamExt};
+use owlnest_blob::bundle::{BundleFile, BundleInfo};
+use owlnest_blob::config::{Share, Throttle};
+use owlnest_blob::error::{
+    CancellationError, FileRecvError, FileSendError, ShareError, StoreError, TransferError,
+};
+use owlnest_blob::store::{hash_chunk, ContentStore, Manifest, StoredContent};
+pub use owlnest_blob::{bundle, config, error, store, Behaviour, InEvent, OutEvent};
+pub use owlnest_blob::{AutoAcceptDecision, CatalogEntry, Cid, RecvInfo, SendInfo};
+use owlnest_blob::{Config, StreamSink, StreamSource};
 use owlnest_core::error::OperationError;
-use std::path::Path;
+use std::collections::HashSet;
+use std::fs::File;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{broadcast, watch};
 use tracing::trace;
 
+/// Live progress of transfers on the command line.
+pub mod progress;
+
+/// Chunks of content requested at the same time, across all providers.
+const PARALLEL_CHUNK_REQUESTS: usize = 8;
+/// Times to check if providers are connected before getting content, 100ms apart.
+#[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+const PROVIDER_CONNECT_ATTEMPTS: usize = 30;
+/// How long to look for the final event of a transfer that is no longer listed.
+const CATCH_UP_GRACE: Duration = Duration::from_secs(1);
+
 /// A handle that can communicate with the behaviour within the swarm.
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct Handle {
     sender: mpsc::Sender<InEvent>,
     swarm_event_source: EventSender,
+    /// Local content store, `None` if disabled.
+    store: Option<ContentStore>,
 }
 impl Handle {
     pub(crate) fn new(
-        _config: &Config,
+        config: &Config,
         buffer_size: usize,
         swarm_event_source: &EventSender,
     ) -> (Self, mpsc::Receiver<InEvent>) {
@@ -26,6 +55,8 @@ impl Handle {
             Self {
                 sender: tx,
                 swarm_event_source: swarm_event_source.clone(),
+                store: (!config.store_path.is_empty())
+                    .then(|| ContentStore::new(&config.store_path)),
             },
             rx,
         )
@@ -34,11 +65,14 @@ impl Handle {
     /// A request will be sent first, no chunk of the file will be sent
     /// until the remote accepted the request.  
     /// Folders are not allowed.  
+    /// The file will be hashed before the request is sent,
+    /// so that the transfer can be resumed safely.  
+    /// The returned handle resolves once all bytes are sent.
     pub async fn send_file(
         &self,
         to: PeerId,
         path: impl AsRef<Path>,
-    ) -> Result<u64, FileSendError> {
+    ) -> Result<TransferHandle, FileSendError> {
         if path.as_ref().is_dir() {
             // Reject sending directory
             return Err(FileSendError::IsDirectory);
@@ -53,11 +87,119 @@ impl Handle {
                 std::io::ErrorKind::PermissionDenied => FileSendError::PermissionDenied,
                 e => FileSendError::OtherFsError(e),
             })?;
+        let bytes_total = file
+            .metadata()
+            .map_err(|e| FileSendError::OtherFsError(e.kind()))?
+            .len();
+        let (file, content_hash) = tokio::task::spawn_blocking(move || {
+            let mut file = file;
+            owlnest_blob::hash_file(&mut file).map(|hash| (file, hash))
+        })
+        .await
+        .expect("Hashing task not to panic")
+        .map_err(|e| FileSendError::OtherFsError(e.kind()))?;
         let (tx, rx) = oneshot::channel();
         let ev = InEvent::SendFile {
             file,
             file_path: path.as_ref().to_owned(),
             to,
+            content_hash: content_hash.to_vec(),
+            callback: tx,
+        };
+        self.start_send(ev, rx, bytes_total).await
+    }
+    /// Send bytes from the reader to the target peer, shown to remote as a file named `name`.
+    /// Bytes are read only after the remote accepted the request.  
+    /// `size_hint` is the expected amount of bytes, the reader may end at a different length.
+    /// Streamed sends are neither verified against a hash nor resumed after disconnection.
+    pub async fn send_stream(
+        &self,
+        to: PeerId,
+        name: impl Into<String>,
+        size_hint: u64,
+        stream: impl AsyncRead + Send + Unpin + 'static,
+    ) -> Result<TransferHandle, FileSendError> {
+        let (tx, rx) = oneshot::channel();
+        let ev = InEvent::SendStream {
+            stream: StreamSource::new(stream),
+            name: name.into(),
+            size_hint,
+            to,
+            content_hash: Vec::new(),
+            callback: tx,
+        };
+        self.start_send(ev, rx, size_hint).await
+    }
+    async fn start_send(
+        &self,
+        ev: InEvent,
+        rx: oneshot::Receiver<Result<u64, FileSendError>>,
+        bytes_total: u64,
+    ) -> Result<TransferHandle, FileSendError> {
+        // Subscribe before sending, so that no event of this send is missed.
+        let listener = self.swarm_event_source.subscribe();
+        send_swarm!(self.sender, ev);
+        let local_send_id = future_timeout!(rx, 1000)
+            .map_err(OperationError::from)?
+            .expect(owlnest_core::expect::CALLBACK_CLEAR)?;
+        Ok(TransferHandle::track(
+            self.clone(),
+            listener,
+            local_send_id,
+            true,
+            bytes_total,
+        ))
+    }
+    /// Send multiple files as a bundle, the remote accepts them as a whole.
+    /// Files are placed at the root of the bundle with their original names.
+    pub async fn send_files(
+        &self,
+        to: PeerId,
+        paths: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> Result<u64, FileSendError> {
+        let mut files = Vec::new();
+        for path in paths {
+            let path = path.as_ref().to_owned();
+            let file_name = match path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => return Err(FileSendError::FileNotFound),
+            };
+            if files.iter().any(|(_, name)| *name == file_name) {
+                let reason = format!("Duplicated file name {file_name}");
+                return Err(FileSendError::InvalidBundle(reason));
+            }
+            files.push((path, file_name));
+        }
+        self.send_bundle(to, move || Ok(files)).await
+    }
+    /// Send all files in the directory as a bundle, recursively.
+    /// The directory itself becomes the root of the bundle on remote.
+    /// Symbolic links and empty directories are skipped.
+    pub async fn send_directory(
+        &self,
+        to: PeerId,
+        path: impl AsRef<Path>,
+    ) -> Result<u64, FileSendError> {
+        let dir = path.as_ref().to_owned();
+        self.send_bundle(to, move || walk_directory(&dir)).await
+    }
+    /// Open and hash all files off the async runtime, then send them as a bundle.
+    async fn send_bundle<F>(&self, to: PeerId, collect: F) -> Result<u64, FileSendError>
+    where
+        F: FnOnce() -> Result<Vec<(PathBuf, String)>, FileSendError> + Send + 'static,
+    {
+        let files = tokio::task::spawn_blocking(move || {
+            collect()?
+                .into_iter()
+                .map(|(path, relative_path)| open_bundle_file(path, relative_path))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await
+        .expect("Hashing task not to panic")?;
+        let (tx, rx) = oneshot::channel();
+        let ev = InEvent::SendBundle {
+            files,
+            to,
             callback: tx,
         };
         send_swarm!(self.sender, ev);
@@ -65,33 +207,130 @@ impl Handle {
             .map_err(OperationError::from)?
             .expect(owlnest_core::expect::CALLBACK_CLEAR)
     }
+    /// Accept all files of a pending bundle.
+    /// The tree of the bundle is recreated under the given directory,
+    /// fail if any of the files already exists(no overwrite).
+    pub async fn recv_bundle(
+        &self,
+        local_bundle_id: u64,
+        dir: impl AsRef<Path>,
+    ) -> Result<(), FileRecvError> {
+        let bundle = self
+            .list_bundles()
+            .await
+            .iter()
+            .find(|v| v.local_bundle_id == local_bundle_id && !v.outbound)
+            .cloned()
+            .ok_or(FileRecvError::PendingRecvNotFound(local_bundle_id))?;
+        let dir = dir.as_ref().to_owned();
+        let files = tokio::task::spawn_blocking(move || create_bundle_files(&dir, &bundle))
+            .await
+            .expect("File creation task not to panic")?;
+        let (tx, rx) = oneshot::channel();
+        let ev = InEvent::AcceptBundle {
+            local_bundle_id,
+            files,
+            callback: tx,
+        };
+        send_swarm!(self.sender, ev);
+        handle_callback!(rx)
+    }
     /// Accept a pending recv.
     /// If the path provided is an existing directory, the file will be written
     /// to the directory with its original name.
     /// If the path provided is an existing file, an error will be returned.
+    /// Bytes are written to a partial file with `.part` appended to its name,
+    /// which is renamed once the transfer completes.  
+    /// The returned handle resolves once the file is written and verified.
     pub async fn recv_file(
         &self,
         recv_id: u64,
         path_to_write: impl AsRef<Path>,
-    ) -> Result<Duration, FileRecvError> {
+    ) -> Result<TransferHandle, FileRecvError> {
         trace!("Accepting recv id {recv_id}");
-        let path_to_write = path_to_write.as_ref();
+        let info = self
+            .list_pending_recv()
+            .await
+            .iter()
+            .find(|v| v.local_recv_id == recv_id)
+            .cloned()
+            .ok_or(FileRecvError::PendingRecvNotFound(recv_id))?;
+        let mut path_to_write = path_to_write.as_ref().to_owned();
+        if path_to_write.is_dir() {
+            path_to_write.push(info.file_name);
+        }
+        let fs_error = |path: &Path, error| FileRecvError::FsError {
+            path: path.to_string_lossy().to_string(),
+            error,
+        };
+        if path_to_write.exists() {
+            return Err(fs_error(&path_to_write, std::io::ErrorKind::AlreadyExists));
+        }
+        let part_path = owlnest_blob::part_path(&path_to_write);
         let file = std::fs::OpenOptions::new()
             .create_new(true)
             .read(true)
             .write(true)
-            .open(path_to_write)
-            .map_err(|e| FileRecvError::FsError {
-                path: path_to_write.to_string_lossy().to_string(),
-                error: e.kind(),
-            })?;
+            .open(&part_path)
+            .map_err(|e| fs_error(&part_path, e.kind()))?;
 
         let (tx, rx) = oneshot::channel();
         let ev = InEvent::AcceptFile {
             file,
             recv_id,
             callback: tx,
-            path: path_to_write.into(),
+            path: path_to_write,
+        };
+        self.start_recv(ev, rx, recv_id, info.bytes_total).await
+    }
+    /// Accept a pending recv, writing bytes to the writer instead of a file.
+    /// The writer is shut down once all bytes are written.
+    /// Streamed recvs cannot be resumed after disconnection.
+    pub async fn recv_stream(
+        &self,
+        recv_id: u64,
+        stream: impl AsyncWrite + Send + Unpin + 'static,
+    ) -> Result<TransferHandle, FileRecvError> {
+        let bytes_total = self
+            .list_pending_recv()
+            .await
+            .iter()
+            .find(|v| v.local_recv_id == recv_id)
+            .map(|v| v.bytes_total)
+            .ok_or(FileRecvError::PendingRecvNotFound(recv_id))?;
+        let (tx, rx) = oneshot::channel();
+        let ev = InEvent::AcceptStream {
+            stream: StreamSink::new(stream),
+            recv_id,
+            callback: tx,
+        };
+        self.start_recv(ev, rx, recv_id, bytes_total).await
+    }
+    async fn start_recv(
+        &self,
+        ev: InEvent,
+        rx: oneshot::Receiver<Result<Duration, FileRecvError>>,
+        recv_id: u64,
+        bytes_total: u64,
+    ) -> Result<TransferHandle, FileRecvError> {
+        let listener = self.swarm_event_source.subscribe();
+        send_swarm!(self.sender, ev);
+        handle_callback!(rx)?;
+        Ok(TransferHandle::track(
+            self.clone(),
+            listener,
+            recv_id,
+            false,
+            bytes_total,
+        ))
+    }
+    /// Continue a recv that was interrupted by disconnection or restart.
+    /// The sender must be connected. Returns the offset the transfer continues from.
+    pub async fn resume_recv(&self, local_recv_id: u64) -> Result<u64, FileRecvError> {
+        let (tx, rx) = oneshot::channel();
+        let ev = InEvent::ResumeRecv {
+            local_recv_id,
+            callback: tx,
         };
         send_swarm!(self.sender, ev);
         handle_callback!(rx)
@@ -122,27 +361,718 @@ impl Handle {
         send_swarm!(self.sender, ev);
         handle_callback!(rx)
     }
+    /// Replace the bandwidth limits and the send queue policy.
+    /// Ongoing sends are affected immediately.
+    pub async fn set_throttle(&self, throttle: Throttle) {
+        let (tx, rx) = oneshot::channel();
+        let ev = InEvent::SetThrottle {
+            throttle,
+            callback: tx,
+        };
+        send_swarm!(self.sender, ev);
+        handle_callback!(rx)
+    }
+    /// List a directory shared by remote.
+    /// An empty path lists the shares that local is allowed to access.
+    pub async fn browse(
+        &self,
+        peer: PeerId,
+        path: impl Into<String>,
+    ) -> Result<Box<[CatalogEntry]>, ShareError> {
+        let (tx, rx) = oneshot::channel();
+        let ev = InEvent::Browse {
+            peer,
+            path: path.into(),
+            callback: tx,
+        };
+        send_swarm!(self.sender, ev);
+        handle_callback!(rx)
+    }
+    /// Download a file shared by remote, `path` is relative to the root of shares,
+    /// e.g. `share_name/dir/file`.
+    /// If the path provided is an existing directory, the file will be written
+    /// to the directory with its original name.
+    /// The returned handle resolves once the file is written and verified.
+    pub async fn fetch(
+        &self,
+        peer: PeerId,
+        path: impl Into<String>,
+        path_to_write: impl AsRef<Path>,
+    ) -> Result<TransferHandle, ShareError> {
+        let path = path.into();
+        let mut path_to_write = path_to_write.as_ref().to_owned();
+        if path_to_write.is_dir() {
+            let file_name = path
+                .trim_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or_default();
+            path_to_write.push(file_name);
+        }
+        let fs_error = |path: &Path, error| ShareError::FsError {
+            path: path.to_string_lossy().to_string(),
+            error,
+        };
+        if path_to_write.exists() {
+            return Err(fs_error(&path_to_write, std::io::ErrorKind::AlreadyExists));
+        }
+        let part_path = owlnest_blob::part_path(&path_to_write);
+        let file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&part_path)
+            .map_err(|e| fs_error(&part_path, e.kind()))?;
+        let listener = self.swarm_event_source.subscribe();
+        let (tx, rx) = oneshot::channel();
+        let ev = InEvent::Fetch {
+            peer,
+            path,
+            file,
+            target: path_to_write,
+            callback: tx,
+        };
+        send_swarm!(self.sender, ev);
+        match handle_callback!(rx) {
+            Ok((recv_id, bytes_total)) => Ok(TransferHandle::track(
+                self.clone(),
+                listener,
+                recv_id,
+                false,
+                bytes_total,
+            )),
+            Err(e) => {
+                let _ = std::fs::remove_file(&part_path);
+                Err(e)
+            }
+        }
+    }
+    /// Add the file to the local content store, returns its content ID.
+    /// Use [`publish`] to announce it to the network as well.
+    pub async fn store_put(&self, path: impl AsRef<Path>) -> Result<Cid, StoreError> {
+        let store = self.store.clone().ok_or(StoreError::Disabled)?;
+        let path = path.as_ref().to_owned();
+        tokio::task::spawn_blocking(move || store.put_file(path))
+            .await
+            .expect("Store task not to panic")
+    }
+    /// List content in the local content store.
+    pub async fn store_list(&self) -> Result<Vec<StoredContent>, StoreError> {
+        let store = self.store.clone().ok_or(StoreError::Disabled)?;
+        tokio::task::spawn_blocking(move || store.list())
+            .await
+            .expect("Store task not to panic")
+    }
+    /// Request a chunk or a manifest from the content store of remote.
+    /// The data is not verified.
+    pub async fn request_chunk(&self, peer: PeerId, hash: Vec<u8>) -> Result<Vec<u8>, StoreError> {
+        let (tx, rx) = oneshot::channel();
+        let ev = InEvent::RequestChunk {
+            peer,
+            hash,
+            callback: tx,
+        };
+        send_swarm!(self.sender, ev);
+        handle_callback!(rx)
+    }
+    /// Download the content into the local content store from the providers,
+    /// then write it to the path. Chunks are requested from all providers in
+    /// parallel and verified against their hash, chunks already in the store
+    /// are not requested again.  
+    /// If the path provided is an existing directory, the file will be written
+    /// to the directory with its original name.  
+    /// Returns the path of the file written.
+    pub async fn store_get(
+        &self,
+        cid: Cid,
+        providers: &[PeerId],
+        path_to_write: impl AsRef<Path>,
+    ) -> Result<PathBuf, StoreError> {
+        let store = self.store.clone().ok_or(StoreError::Disabled)?;
+        let connected = self.list_connected().await;
+        let providers = providers
+            .iter()
+            .filter(|v| connected.contains(v))
+            .copied()
+            .collect::<Vec<_>>();
+        let local_manifest = {
+            let store = store.clone();
+            tokio::task::spawn_blocking(move || store.manifest(&cid))
+                .await
+                .expect("Store task not to panic")?
+        };
+        let manifest = match local_manifest {
+            Some(v) => v,
+            None => self.request_manifest(&store, cid, &providers).await?,
+        };
+        let missing = {
+            let store = store.clone();
+            let chunks = manifest.chunks.clone();
+            tokio::task::spawn_blocking(move || {
+                chunks
+                    .into_iter()
+                    .filter(|v| !store.has_chunk(v))
+                    .collect::<HashSet<_>>()
+            })
+            .await
+            .expect("Store task not to panic")
+        };
+        if !missing.is_empty() && providers.is_empty() {
+            return Err(StoreError::NoProvider);
+        }
+        futures::stream::iter(missing.into_iter().enumerate())
+            .map(|(index, hash)| self.request_verified_chunk(&store, hash, &providers, index))
+            .buffer_unordered(PARALLEL_CHUNK_REQUESTS)
+            .try_collect::<()>()
+            .await?;
+        let mut path_to_write = path_to_write.as_ref().to_owned();
+        if path_to_write.is_dir() {
+            // The name comes from remote, only the last component is used.
+            let name = Path::new(&manifest.name)
+                .file_name()
+                .map(|v| v.to_owned())
+                .unwrap_or_else(|| cid.to_string().into());
+            path_to_write.push(name);
+        }
+        if path_to_write.exists() {
+            return Err(StoreError::FsError {
+                path: path_to_write.to_string_lossy().to_string(),
+                error: std::io::ErrorKind::AlreadyExists,
+            });
+        }
+        let target = path_to_write.clone();
+        tokio::task::spawn_blocking(move || store.export(&cid, target))
+            .await
+            .expect("Store task not to panic")?;
+        Ok(path_to_write)
+    }
+    /// Get the manifest from the first provider that has it.
+    async fn request_manifest(
+        &self,
+        store: &ContentStore,
+        cid: Cid,
+        providers: &[PeerId],
+    ) -> Result<Manifest, StoreError> {
+        let mut error = StoreError::NoProvider;
+        for peer in providers {
+            let manifest = match self.request_chunk(*peer, cid.0.to_vec()).await {
+                Ok(data) if Cid::of(&data) == cid => Manifest::decode(&data),
+                Ok(_) => {
+                    error = StoreError::Corrupted;
+                    continue;
+                }
+                Err(e) => {
+                    error = e;
+                    continue;
+                }
+            };
+            let manifest = manifest.ok_or(StoreError::Corrupted)?;
+            let store = store.clone();
+            let to_insert = manifest.clone();
+            tokio::task::spawn_blocking(move || store.insert_manifest(&to_insert))
+                .await
+                .expect("Store task not to panic")?;
+            return Ok(manifest);
+        }
+        Err(error)
+    }
+    /// Request the chunk from providers in turn, starting from the one
+    /// at `index`, until one of them responds with the correct data.
+    async fn request_verified_chunk(
+        &self,
+        store: &ContentStore,
+        hash: [u8; 32],
+        providers: &[PeerId],
+        index: usize,
+    ) -> Result<(), StoreError> {
+        let mut error = StoreError::NoProvider;
+        for offset in 0..providers.len() {
+            let peer = providers[(index + offset) % providers.len()];
+            match self.request_chunk(peer, hash.to_vec()).await {
+                Ok(data) if hash_chunk(&data) == hash => {
+                    let store = store.clone();
+                    tokio::task::spawn_blocking(move || store.insert_chunk(&data))
+                        .await
+                        .expect("Store task not to panic")?;
+                    return Ok(());
+                }
+                Ok(_) => {
+                    trace!("Chunk from {peer} doesn't match its hash");
+                    error = StoreError::Corrupted
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
     generate_handler_method!(
-        /// List receives that are still in pending phase.
-        /// Ongoing receives should be tracked by the user interface.
+        /// Share a directory with remote peers, replacing the share of the same name.
+        SetShare:set_share(share: |Share|);
+        /// Stop sharing a directory. Returns `false` if it's not shared.
+        RemoveShare:remove_share(name: |String|)->bool;
+        /// List directories shared by local.
+        ListShares:list_shares()->Box<[Share]>;
+        /// Get the bandwidth limits and the send queue policy in effect.
+        GetThrottle:throttle()->Throttle;
+        /// Change the priority of a send, higher goes first.
+        /// Returns `false` if the send is not found.
+        SetPriority:set_priority(local_send_id:u64,priority:u8)->bool;
+        /// List receives that are pending, ongoing or interrupted.
+        /// Completed, failed and cancelled receives are not listed.
         ListRecv:list_pending_recv()->Box<[RecvInfo]>;
-        /// List sends that are still in pending phase.
-        /// Ongoing sends should be tracked by the user interface.
+        /// List sends that are pending, ongoing or interrupted.
+        /// Completed, failed and cancelled sends are not listed.
         ListSend:list_pending_send()->Box<[SendInfo]>;
         /// List all peers that have successfully negotiated this protocol.
         ListConnected:list_connected()->Box<[PeerId]>;
+        /// List bundles that are pending or ongoing, in both directions.
+        ListBundle:list_bundles()->Box<[BundleInfo]>;
     );
 }
 
+/// Add the file to the local content store and announce local peer
+/// as its provider through kad.
+#[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+pub async fn publish(manager: &Manager, path: impl AsRef<Path>) -> Result<Cid, StoreError> {
+    let cid = manager.blob().store_put(path).await?;
+    announce(manager, cid).await?;
+    Ok(cid)
+}
+
+/// Announce local peer as a provider of the content through kad.
+/// Returns once the announcement is stored locally, it is sent to
+/// other peers in the background, see [`kad::Handle::provide`].
+/// Announcements are not persisted, stored content should be announced
+/// again after restart.
+#[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+pub async fn announce(manager: &Manager, cid: Cid) -> Result<(), StoreError> {
+    manager
+        .kad()
+        .provide(libp2p::kad::RecordKey::new(&cid.0))
+        .await
+        .map_err(|e| StoreError::Announce(e.to_string()))?;
+    Ok(())
+}
+
+/// Find providers of the content through kad, connect to them and
+/// download the content, see [`Handle::store_get`].
+/// Local peer is announced as a provider once the content is complete.
+#[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+pub async fn get(
+    manager: &Manager,
+    cid: Cid,
+    path_to_write: impl AsRef<Path>,
+) -> Result<PathBuf, StoreError> {
+    let local = manager.identity().get_peer_id();
+    let providers = manager
+        .kad()
+        .providers(libp2p::kad::RecordKey::new(&cid.0))
+        .await
+        .into_iter()
+        .filter(|v| *v != local)
+        .collect::<Vec<_>>();
+    for peer in providers.iter() {
+        if manager.swarm().is_connected(peer).await {
+            continue;
+        }
+        let addresses = manager.kad().lookup(peer).await;
+        for address in addresses.iter().flat_map(|v| v.iter()) {
+            if manager.swarm().dial(address).await.is_ok() {
+                break;
+            }
+        }
+    }
+    // Wait for the protocol to be negotiated on new connections.
+    for _ in 0..PROVIDER_CONNECT_ATTEMPTS {
+        let connected = manager.blob().list_connected().await;
+        if providers.iter().all(|v| connected.contains(v)) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let path = manager
+        .blob()
+        .store_get(cid, &providers, path_to_write)
+        .await?;
+    announce(manager, cid).await?;
+    Ok(path)
+}
+
+/// Progress of a transfer, see [`TransferHandle::progress`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferProgress {
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// Final result of a completed transfer.
+#[derive(Debug, Clone)]
+pub struct TransferSummary {
+    /// Path of the file sent, or of the complete file received.
+    pub file_path: PathBuf,
+    pub bytes_total: u64,
+    /// Bytes transferred over the network after compression,
+    /// since the transfer started or last resumed.
+    pub bytes_on_wire: u64,
+    /// Time since the transfer started, including interruptions.
+    pub elapsed: Duration,
+    /// Average bytes per second over `elapsed`.
+    pub throughput: f64,
+}
+
+/// A send or recv that has been started.
+/// Await it for the final outcome, or poll [`TransferHandle::progress`] in the meantime.
+/// Dropping the handle doesn't cancel the transfer.
+#[derive(Debug)]
+pub struct TransferHandle {
+    local_id: u64,
+    progress: watch::Receiver<TransferProgress>,
+    outcome: oneshot::Receiver<Result<TransferSummary, TransferError>>,
+}
+impl TransferHandle {
+    /// Follow events of the transfer on a separate task.
+    fn track(
+        handle: Handle,
+        mut listener: broadcast::Receiver<std::sync::Arc<SwarmEvent>>,
+        local_id: u64,
+        outbound: bool,
+        bytes_total: u64,
+    ) -> Self {
+        let (progress_tx, progress) = watch::channel(TransferProgress {
+            bytes_done: 0,
+            bytes_total,
+        });
+        let (outcome_tx, outcome) = oneshot::channel();
+        tokio::spawn(async move {
+            let outcome = loop {
+                let ev = match listener.recv().await {
+                    Ok(ev) => ev,
+                    // Events of the transfer may have been dropped, catch up with its state.
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        match catch_up(&handle, local_id, outbound).await {
+                            Some(bytes_done) => {
+                                progress_tx.send_modify(|v| v.bytes_done = bytes_done);
+                                continue;
+                            }
+                            // The transfer has ended, its final event may still be buffered.
+                            None => {
+                                break drain(&mut listener, local_id, outbound, &progress_tx)
+                                    .await
+                                    .unwrap_or(Err(TransferError::Untracked))
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        break Err(TransferError::SwarmStopped)
+                    }
+                };
+                let ev = match ev.as_ref() {
+                    SwarmEvent::Behaviour(BehaviourEvent::Blob(ev)) => ev,
+                    _ => continue,
+                };
+                if let Some(outcome) = match_transfer_event(ev, local_id, outbound, &progress_tx) {
+                    break outcome;
+                }
+            };
+            let _ = outcome_tx.send(outcome);
+        });
+        Self {
+            local_id,
+            progress,
+            outcome,
+        }
+    }
+    /// Local send ID or local recv ID of the transfer.
+    pub fn id(&self) -> u64 {
+        self.local_id
+    }
+    /// Latest progress of the transfer.
+    pub fn progress(&self) -> TransferProgress {
+        *self.progress.borrow()
+    }
+    /// Wait for the next progress update.
+    /// Returns `None` once the transfer has ended.
+    pub async fn progress_changed(&mut self) -> Option<TransferProgress> {
+        self.progress.changed().await.ok()?;
+        Some(*self.progress.borrow_and_update())
+    }
+}
+impl Future for TransferHandle {
+    type Output = Result<TransferSummary, TransferError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.outcome)
+            .poll(cx)
+            .map(|result| result.unwrap_or(Err(TransferError::SwarmStopped)))
+    }
+}
+
+/// Bytes done of the transfer, `None` if it's no longer listed.
+async fn catch_up(handle: &Handle, local_id: u64, outbound: bool) -> Option<u64> {
+    if outbound {
+        handle
+            .list_pending_send()
+            .await
+            .iter()
+            .find(|v| v.local_send_id == local_id)
+            .map(|v| v.bytes_sent)
+    } else {
+        handle
+            .list_pending_recv()
+            .await
+            .iter()
+            .find(|v| v.local_recv_id == local_id)
+            .map(|v| v.bytes_received)
+    }
+}
+
+/// Look for the final event of the transfer among events that are buffered
+/// or arrive shortly, `None` if it's not found.
+async fn drain(
+    listener: &mut broadcast::Receiver<std::sync::Arc<SwarmEvent>>,
+    local_id: u64,
+    outbound: bool,
+    progress: &watch::Sender<TransferProgress>,
+) -> Option<Result<TransferSummary, TransferError>> {
+    let deadline = tokio::time::Instant::now() + CATCH_UP_GRACE;
+    loop {
+        let ev = match tokio::time::timeout_at(deadline, listener.recv()).await {
+            Ok(Ok(ev)) => ev,
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => return None,
+        };
+        if let SwarmEvent::Behaviour(BehaviourEvent::Blob(ev)) = ev.as_ref() {
+            if let Some(outcome) = match_transfer_event(ev, local_id, outbound, progress) {
+                return Some(outcome);
+            }
+        }
+    }
+}
+
+/// Update progress with the event, returns the outcome if the transfer has ended.
+fn match_transfer_event(
+    ev: &OutEvent,
+    local_id: u64,
+    outbound: bool,
+    progress: &watch::Sender<TransferProgress>,
+) -> Option<Result<TransferSummary, TransferError>> {
+    let update = |bytes_done: u64| {
+        progress.send_modify(|v| v.bytes_done = bytes_done);
+    };
+    match (outbound, ev) {
+        (
+            true,
+            OutEvent::SendProgressed {
+                local_send_id,
+                bytes_sent,
+                ..
+            },
+        ) if *local_send_id == local_id => update(*bytes_sent),
+        (
+            true,
+            OutEvent::SendResumed {
+                local_send_id,
+                offset,
+            },
+        ) if *local_send_id == local_id => update(*offset),
+        (
+            true,
+            OutEvent::SendCompleted {
+                local_send_id: id,
+                file_path,
+                bytes_total,
+                bytes_on_wire,
+                elapsed,
+                throughput,
+            },
+        )
+        | (
+            false,
+            OutEvent::RecvCompleted {
+                local_recv_id: id,
+                file_path,
+                bytes_total,
+                bytes_on_wire,
+                elapsed,
+                throughput,
+            },
+        ) if *id == local_id => {
+            update(*bytes_total);
+            return Some(Ok(TransferSummary {
+                file_path: file_path.clone(),
+                bytes_total: *bytes_total,
+                bytes_on_wire: *bytes_on_wire,
+                elapsed: *elapsed,
+                throughput: *throughput,
+            }));
+        }
+        (
+            true,
+            OutEvent::OngoingSendError {
+                local_send_id: id,
+                error,
+            },
+        )
+        | (
+            false,
+            OutEvent::OngoingRecvError {
+                local_recv_id: id,
+                error,
+            },
+        ) if *id == local_id => {
+            return Some(Err(TransferError::Failed(error.clone())));
+        }
+        (true, OutEvent::CancelledSend(id)) | (false, OutEvent::CancelledRecv(id))
+            if *id == local_id =>
+        {
+            return Some(Err(TransferError::Cancelled));
+        }
+        (
+            false,
+            OutEvent::RecvProgressed {
+                local_recv_id,
+                bytes_received,
+                ..
+            },
+        ) if *local_recv_id == local_id => update(*bytes_received),
+        (
+            false,
+            OutEvent::RecvResumed {
+                local_recv_id,
+                offset,
+            },
+        ) if *local_recv_id == local_id => update(*offset),
+        _ => {}
+    }
+    None
+}
+
+fn fs_error(e: std::io::Error) -> FileSendError {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => FileSendError::FileNotFound,
+        std::io::ErrorKind::PermissionDenied => FileSendError::PermissionDenied,
+        e => FileSendError::OtherFsError(e),
+    }
+}
+
+/// Collect all files under the directory, with paths relative to its parent.
+fn walk_directory(dir: &Path) -> Result<Vec<(PathBuf, String)>, FileSendError> {
+    if !dir.is_dir() {
+        return Err(FileSendError::InvalidBundle(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    let root = dir
+        .canonicalize()
+        .map_err(fs_error)?
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "bundle".into());
+    let mut files = Vec::new();
+    let mut pending = vec![(dir.to_owned(), root)];
+    while let Some((dir, relative_dir)) = pending.pop() {
+        for entry in std::fs::read_dir(&dir).map_err(fs_error)? {
+            let entry = entry.map_err(fs_error)?;
+            let file_type = entry.file_type().map_err(fs_error)?;
+            let relative_path = format!("{relative_dir}/{}", entry.file_name().to_string_lossy());
+            if file_type.is_dir() {
+                pending.push((entry.path(), relative_path));
+            } else if file_type.is_file() {
+                files.push((entry.path(), relative_path));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Open and hash a file to be sent in a bundle.
+fn open_bundle_file(path: PathBuf, relative_path: String) -> Result<BundleFile, FileSendError> {
+    if path.is_dir() {
+        return Err(FileSendError::IsDirectory);
+    }
+    let mut file = File::open(&path).map_err(fs_error)?;
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        file.metadata().map_err(fs_error)?.permissions().mode()
+    };
+    #[cfg(not(unix))]
+    let mode = 0;
+    let content_hash = owlnest_blob::hash_file(&mut file).map_err(fs_error)?;
+    Ok(BundleFile {
+        file,
+        file_path: path,
+        relative_path,
+        mode,
+        content_hash: content_hash.to_vec(),
+    })
+}
+
+/// Create partial files for every file in the bundle under the directory.
+/// Files already created are removed if any of them fails.
+fn create_bundle_files(
+    dir: &Path,
+    bundle: &BundleInfo,
+) -> Result<Vec<(u64, File, PathBuf)>, FileRecvError> {
+    let mut created: Vec<(u64, File, PathBuf)> = Vec::new();
+    let result = bundle.files.iter().try_for_each(|info| {
+        let fs_error = |path: &Path, error| FileRecvError::FsError {
+            path: path.to_string_lossy().to_string(),
+            error,
+        };
+        let relative_path =
+            bundle::sanitize_relative_path(&info.relative_path).ok_or_else(|| {
+                fs_error(
+                    Path::new(&info.relative_path),
+                    std::io::ErrorKind::InvalidInput,
+                )
+            })?;
+        let path = dir.join(relative_path);
+        if path.exists() {
+            return Err(fs_error(&path, std::io::ErrorKind::AlreadyExists));
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| fs_error(parent, e.kind()))?;
+        }
+        let part_path = owlnest_blob::part_path(&path);
+        let file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&part_path)
+            .map_err(|e| fs_error(&part_path, e.kind()))?;
+        // Keep owner write permission so that the transfer can be resumed.
+        #[cfg(unix)]
+        if info.mode != 0 {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = std::fs::Permissions::from_mode(info.mode & 0o777 | 0o200);
+            file.set_permissions(permissions)
+                .map_err(|e| fs_error(&part_path, e.kind()))?;
+        }
+        created.push((info.local_id, file, path));
+        Ok(())
+    });
+    if let Err(e) = result {
+        for (_, _, path) in created {
+            let _ = std::fs::remove_file(owlnest_blob::part_path(&path));
+        }
+        return Err(e);
+    }
+    Ok(created)
+}
+
 pub mod cli {
-    use super::Handle;
+    use super::Cid;
+    use crate::net::p2p::swarm::manager::Manager;
     use clap::Subcommand;
-    use prettytable::table;
+    use owlnest_blob::config::SendQueue;
+    use prettytable::{row, table};
     use printable::iter::PrintableIter;
 
     #[derive(Debug, Subcommand)]
     pub enum Blob {
-        /// Send a file to remote. Does not take folders or multiple files.
+        /// Send a file to remote.
+        /// Use `send-dir` or `send-files` for folders or multiple files.
         #[command(arg_required_else_help = true)]
         Send {
             /// Peer to send the file to.
@@ -151,11 +1081,52 @@ pub mod cli {
             /// Path to the file.
             #[arg(required = true)]
             file_path: String,
+            /// Show the progress and return once the send has ended.
+            #[arg(long)]
+            wait: bool,
+        },
+        /// Send all files in a folder to remote, recursively.
+        #[command(arg_required_else_help = true)]
+        SendDir {
+            /// Peer to send the folder to.
+            #[arg(required = true)]
+            remote: libp2p::PeerId,
+            /// Path to the folder.
+            #[arg(required = true)]
+            dir_path: String,
+        },
+        /// Send multiple files to remote, to be accepted as a whole.
+        #[command(arg_required_else_help = true)]
+        SendFiles {
+            /// Peer to send the files to.
+            #[arg(required = true)]
+            remote: libp2p::PeerId,
+            /// Paths to the files.
+            #[arg(required = true)]
+            file_paths: Vec<String>,
         },
         /// List all send operation, pending and ongoing.
         ListSend,
         /// List all recv operation, pending or ongoing.
         ListRecv,
+        /// Show live progress of transfers, until they have ended.
+        Watch {
+            /// Local send ID or local recv ID of the transfer to watch.
+            /// Watch all transfers if not supplied.
+            id: Option<u64>,
+        },
+        /// List all bundles of files, pending and ongoing.
+        ListBundle,
+        /// Accept all files in a bundle from remote.
+        #[command(arg_required_else_help = true)]
+        RecvBundle {
+            /// Bundle ID associated with the bundle.
+            #[arg(required = true)]
+            local_bundle_id: u64,
+            /// Folder to recreate the tree of files in.
+            #[arg(default_value = ".")]
+            dir: String,
+        },
         /// Accept a send request from remote.
         #[command(arg_required_else_help = true)]
         Recv {
@@ -170,6 +1141,14 @@ pub mod cli {
             #[arg(default_value = ".")]
             path_to_write: String,
         },
+        /// Resume an interrupted receive operation.
+        /// Works after restart if the transfer journal is enabled.
+        #[command(arg_required_else_help = true)]
+        Resume {
+            /// Recieve ID associated with the receive request.
+            #[arg(required = true)]
+            local_recv_id: u64,
+        },
         /// Cancel a pending or ongoing send operation.
         #[command(arg_required_else_help = true)]
         CancelSend {
@@ -184,37 +1163,183 @@ pub mod cli {
             #[arg(required = true)]
             local_recv_id: u64,
         },
-    }
-
-    pub async fn handle_blob(handle: &Handle, command: Blob) {
-        use Blob::*;
-        match command {
-            ListSend => {
-                let list = handle.list_pending_send().await;
-                let print_pending = list
-                    .iter()
-                    .filter(|v| !v.started)
-                    .printable()
-                    .with_left_bound("")
-                    .with_right_bound("")
-                    .with_separator("\n");
-                let print_started = list
+        /// Change the priority of a send, higher goes first.
+        /// Only takes effect when the send queue is `Priority`.
+        #[command(arg_required_else_help = true)]
+        SetPriority {
+            /// Send ID associated with the send request.
+            #[arg(required = true)]
+            local_send_id: u64,
+            /// Priority from 0 to 255, sends start with 0.
+            #[arg(required = true)]
+            priority: u8,
+        },
+        /// Show the bandwidth limits and the send queue policy in effect.
+        Throttle,
+        /// Change the bandwidth limits and the send queue policy.
+        /// Values not supplied are left unchanged, 0 for no limit.
+        SetThrottle {
+            /// Bytes per second of all sends combined.
+            #[arg(long)]
+            max_send_rate: Option<u64>,
+            /// Bytes per second of a single send.
+            #[arg(long)]
+            max_send_rate_per_transfer: Option<u64>,
+            /// Number of sends that can be ongoing at the same time.
+            #[arg(long)]
+            max_concurrent_sends: Option<usize>,
+            /// Order to start queued sends in, `fifo` or `priority`.
+            #[arg(long)]
+            send_queue: Option<String>,
+        },
+        /// List a directory shared by remote.
+        #[command(arg_required_else_help = true)]
+        Browse {
+            /// Peer that shares the directory.
+            #[arg(required = true)]
+            remote: libp2p::PeerId,
+            /// Path to list, in the form of `share_name/dir`.
+            /// Lists all accessible shares if not supplied.
+            #[arg(default_value = "")]
+            path: String,
+        },
+        /// Download a file shared by remote.
+        #[command(arg_required_else_help = true)]
+        Fetch {
+            /// Peer that shares the file.
+            #[arg(required = true)]
+            remote: libp2p::PeerId,
+            /// Path to the file, in the form of `share_name/dir/file`.
+            #[arg(required = true)]
+            path: String,
+            /// Path to write the file to, same as `recv`.
+            #[arg(default_value = ".")]
+            path_to_write: String,
+        },
+        /// Share a directory with remote peers, read-only.
+        #[command(arg_required_else_help = true)]
+        Share {
+            /// Name of the share seen by remote.
+            #[arg(required = true)]
+            name: String,
+            /// Path to the directory.
+            #[arg(required = true)]
+            path: String,
+            /// Allow all peers to browse and fetch.
+            #[arg(long)]
+            public: bool,
+            /// Peers allowed to browse and fetch, when not public.
+            #[arg(long = "allow")]
+            allowed_peers: Vec<libp2p::PeerId>,
+        },
+        /// Stop sharing a directory.
+        #[command(arg_required_else_help = true)]
+        Unshare {
+            /// Name of the share.
+            #[arg(required = true)]
+            name: String,
+        },
+        /// List directories shared by local.
+        Shares,
+        /// Add a file to the content store and announce it to the network.
+        #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+        #[command(arg_required_else_help = true)]
+        Put {
+            /// Path to the file.
+            #[arg(required = true)]
+            file_path: String,
+        },
+        /// Find providers of the content through the network and download it.
+        #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+        #[command(arg_required_else_help = true)]
+        Get {
+            /// Content ID returned by `put`.
+            #[arg(required = true)]
+            cid: Cid,
+            /// Path to write the file to, same as `recv`.
+            #[arg(default_value = ".")]
+            path_to_write: String,
+        },
+        /// Announce all complete content in the store to the network again,
+        /// e.g. after restart.
+        #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+        Announce,
+        /// List content in the content store.
+        Stored,
+    }
+
+    pub async fn handle_blob(manager: &Manager, command: Blob) {
+        use Blob::*;
+        let handle = manager.blob();
+        match command {
+            ListSend => {
+                let list = handle.list_pending_send().await;
+                let print_pending = list
                     .iter()
                     .filter(|v| !v.started)
                     .printable()
                     .with_left_bound("")
                     .with_right_bound("")
                     .with_separator("\n");
+                let print_started = list
+                    .iter()
+                    .filter(|v| v.started)
+                    .printable()
+                    .with_left_bound("")
+                    .with_right_bound("")
+                    .with_separator("\n");
                 let table = table!(
                     ["Pending Send", "Ongoing Send"],
                     [print_pending, print_started]
                 );
                 table.printstd()
             }
-            Send { remote, file_path } => {
+            ListRecv => {
+                let list = handle.list_pending_recv().await;
+                let print_pending = list
+                    .iter()
+                    .filter(|v| !v.started)
+                    .printable()
+                    .with_left_bound("")
+                    .with_right_bound("")
+                    .with_separator("\n");
+                let print_started = list
+                    .iter()
+                    .filter(|v| v.started)
+                    .printable()
+                    .with_left_bound("")
+                    .with_right_bound("")
+                    .with_separator("\n");
+                let table = table!(
+                    ["Pending Recv", "Ongoing Recv"],
+                    [print_pending, print_started]
+                );
+                table.printstd()
+            }
+            Watch { id } => super::progress::watch(handle, id).await,
+            Send {
+                remote,
+                file_path,
+                wait,
+            } => {
+                let name = std::path::Path::new(&file_path)
+                    .file_name()
+                    .map(|v| v.to_string_lossy().to_string())
+                    .unwrap_or_default();
                 let result = handle.send_file(remote, file_path).await;
                 match result {
-                    Ok(id) => println!("Send initated with ID {id}"),
+                    Ok(transfer) if wait => {
+                        println!("Send initated with ID {}", transfer.id());
+                        match super::progress::follow(transfer, true, name).await {
+                            Ok(summary) => println!(
+                                "Sent {} bytes in {:.1}s",
+                                summary.bytes_total,
+                                summary.elapsed.as_secs_f64()
+                            ),
+                            Err(e) => println!("{e}"),
+                        }
+                    }
+                    Ok(transfer) => println!("Send initated with ID {}", transfer.id()),
                     Err(e) => println!("Send failed with error {e:?}"),
                 }
             }
@@ -224,11 +1349,218 @@ pub mod cli {
             } => {
                 let result = handle.recv_file(local_recv_id, path_to_write).await;
                 match result {
-                    Ok(_rtt) => println!("Recv ID {local_recv_id} accepted"),
+                    Ok(_) => println!("Recv ID {local_recv_id} accepted"),
                     Err(e) => println!("Send failed with error {e:?}"),
                 }
             }
-            _ => todo!(),
+            SendDir { remote, dir_path } => match handle.send_directory(remote, dir_path).await {
+                Ok(id) => println!("Bundle send initated with ID {id}"),
+                Err(e) => println!("Send failed with error {e}"),
+            },
+            SendFiles { remote, file_paths } => match handle.send_files(remote, file_paths).await {
+                Ok(id) => println!("Bundle send initated with ID {id}"),
+                Err(e) => println!("Send failed with error {e}"),
+            },
+            ListBundle => {
+                let mut table = prettytable::Table::new();
+                table.set_titles(row!["ID", "Remote", "Direction", "Files", "Progress"]);
+                for bundle in handle.list_bundles().await.iter() {
+                    table.add_row(row![
+                        bundle.local_bundle_id,
+                        bundle.remote,
+                        if bundle.outbound { "Send" } else { "Recv" },
+                        format!("{}/{}", bundle.files_done(), bundle.files.len()),
+                        format!("{}/{} bytes", bundle.bytes_done(), bundle.bytes_total())
+                    ]);
+                }
+                table.printstd()
+            }
+            RecvBundle {
+                local_bundle_id,
+                dir,
+            } => match handle.recv_bundle(local_bundle_id, dir).await {
+                Ok(_) => println!("Bundle ID {local_bundle_id} accepted"),
+                Err(e) => println!("Recv failed with error {e}"),
+            },
+            Resume { local_recv_id } => match handle.resume_recv(local_recv_id).await {
+                Ok(offset) => println!("Recv ID {local_recv_id} resumed from {offset} bytes"),
+                Err(e) => println!("Resume failed with error {e}"),
+            },
+            CancelSend { local_send_id } => match handle.cancel_send(local_send_id).await {
+                Ok(()) => println!("Send ID {local_send_id} cancelled"),
+                Err(e) => println!("Cancellation failed with error {e}"),
+            },
+            CancelRecv { local_recv_id } => match handle.cancel_recv(local_recv_id).await {
+                Ok(()) => println!("Recv ID {local_recv_id} cancelled"),
+                Err(e) => println!("Cancellation failed with error {e}"),
+            },
+            SetPriority {
+                local_send_id,
+                priority,
+            } => {
+                if handle.set_priority(local_send_id, priority).await {
+                    println!("Priority of send ID {local_send_id} set to {priority}")
+                } else {
+                    println!("Send ID {local_send_id} not found")
+                }
+            }
+            Throttle => {
+                let throttle = handle.throttle().await;
+                let limit = |v: u64| match v {
+                    0 => "Unlimited".to_string(),
+                    v => format!("{v} bytes/s"),
+                };
+                let table = table!(
+                    ["Max send rate", limit(throttle.max_send_rate)],
+                    [
+                        "Max send rate per transfer",
+                        limit(throttle.max_send_rate_per_transfer)
+                    ],
+                    [
+                        "Max concurrent sends",
+                        match throttle.max_concurrent_sends {
+                            0 => "Unlimited".to_string(),
+                            v => v.to_string(),
+                        }
+                    ],
+                    ["Send queue", format!("{:?}", throttle.send_queue)]
+                );
+                table.printstd()
+            }
+            SetThrottle {
+                max_send_rate,
+                max_send_rate_per_transfer,
+                max_concurrent_sends,
+                send_queue,
+            } => {
+                let mut throttle = handle.throttle().await;
+                if let Some(send_queue) = send_queue {
+                    throttle.send_queue = match send_queue.to_lowercase().as_str() {
+                        "fifo" => SendQueue::Fifo,
+                        "priority" => SendQueue::Priority,
+                        _ => return println!("Unknown send queue {send_queue}"),
+                    };
+                }
+                throttle.max_send_rate = max_send_rate.unwrap_or(throttle.max_send_rate);
+                throttle.max_send_rate_per_transfer =
+                    max_send_rate_per_transfer.unwrap_or(throttle.max_send_rate_per_transfer);
+                throttle.max_concurrent_sends =
+                    max_concurrent_sends.unwrap_or(throttle.max_concurrent_sends);
+                handle.set_throttle(throttle).await;
+                println!("Throttle updated")
+            }
+            Browse { remote, path } => match handle.browse(remote, path).await {
+                Ok(entries) => {
+                    let mut table = prettytable::Table::new();
+                    table.set_titles(row!["Name", "Type", "Size", "Hash"]);
+                    for entry in entries.iter() {
+                        let hash = entry
+                            .content_hash
+                            .iter()
+                            .map(|v| format!("{v:02x}"))
+                            .collect::<String>();
+                        table.add_row(row![
+                            entry.name,
+                            if entry.is_dir { "Directory" } else { "File" },
+                            if entry.is_dir {
+                                String::new()
+                            } else {
+                                format!("{} bytes", entry.size)
+                            },
+                            hash
+                        ]);
+                    }
+                    table.printstd()
+                }
+                Err(e) => println!("Browse failed with error {e}"),
+            },
+            Fetch {
+                remote,
+                path,
+                path_to_write,
+            } => match handle.fetch(remote, path, path_to_write).await {
+                Ok(transfer) => println!("Fetch accepted with recv ID {}", transfer.id()),
+                Err(e) => println!("Fetch failed with error {e}"),
+            },
+            Share {
+                name,
+                path,
+                public,
+                allowed_peers,
+            } => {
+                let share = owlnest_blob::config::Share {
+                    name: name.clone(),
+                    path,
+                    public,
+                    allowed_peers,
+                };
+                handle.set_share(share).await;
+                println!("Directory shared as {name}")
+            }
+            Unshare { name } => {
+                if handle.remove_share(name.clone()).await {
+                    println!("Share {name} removed")
+                } else {
+                    println!("Share {name} not found")
+                }
+            }
+            Shares => {
+                let mut table = prettytable::Table::new();
+                table.set_titles(row!["Name", "Path", "Allowed peers"]);
+                for share in handle.list_shares().await.iter() {
+                    let allowed = if share.public {
+                        "Everyone".to_string()
+                    } else {
+                        share
+                            .allowed_peers
+                            .iter()
+                            .map(|v| v.to_string())
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    };
+                    table.add_row(row![share.name, share.path, allowed]);
+                }
+                table.printstd()
+            }
+            #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+            Put { file_path } => match super::publish(manager, file_path).await {
+                Ok(cid) => println!("File stored and announced with content ID {cid}"),
+                Err(e) => println!("Put failed with error {e}"),
+            },
+            #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+            Get { cid, path_to_write } => match super::get(manager, cid, path_to_write).await {
+                Ok(path) => println!("Content {cid} written to {}", path.display()),
+                Err(e) => println!("Get failed with error {e}"),
+            },
+            #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+            Announce => {
+                let list = match handle.store_list().await {
+                    Ok(v) => v,
+                    Err(e) => return println!("Cannot list the content store: {e}"),
+                };
+                for content in list.iter().filter(|v| v.complete) {
+                    if let Err(e) = super::announce(manager, content.cid).await {
+                        return println!("Announce failed with error {e}");
+                    }
+                }
+                println!("Content announced")
+            }
+            Stored => match handle.store_list().await {
+                Ok(list) => {
+                    let mut table = prettytable::Table::new();
+                    table.set_titles(row!["Content ID", "Name", "Size", "Complete"]);
+                    for content in list {
+                        table.add_row(row![
+                            content.cid,
+                            content.name,
+                            format!("{} bytes", content.size),
+                            content.complete
+                        ]);
+                    }
+                    table.printstd()
+                }
+                Err(e) => println!("Cannot list the content store: {e}"),
+            },
         }
     }
     pub mod send {
@@ -278,6 +1610,33 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn progress_of_pending_and_ongoing_send() -> anyhow::Result<()> {
+        use progress::{ProgressLine, TransferState};
+        let (peer1_m, peer2_m) = setup_peer()?;
+        send(&peer1_m, peer2_m.identity().get_peer_id(), SOURCE_FILE);
+        let list = peer1_m
+            .executor()
+            .block_on(peer1_m.blob().list_pending_send());
+        // Nothing sent yet, used to panic on division by zero.
+        assert_eq!(list[0].percent(), 0.0);
+        let _ = list[0].to_string();
+        let mut line = ProgressLine::from_send(&list[0]);
+        assert_eq!(line.state, TransferState::Pending);
+        assert!(line.apply(&OutEvent::SendProgressed {
+            local_send_id: line.local_id,
+            bytes_sent: line.bytes_total / 4,
+            bytes_total: line.bytes_total,
+        }));
+        assert_eq!(line.state, TransferState::Ongoing);
+        assert!((line.percent() - 25.0).abs() < 0.1);
+        assert!(!line.apply(&OutEvent::CancelledRecv(line.local_id)));
+        assert!(line.apply(&OutEvent::CancelledSend(line.local_id)));
+        assert!(line.state.is_finished());
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn cancel_single_send() -> anyhow::Result<()> {
@@ -377,6 +1736,802 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn slow_writer_does_not_block_swarm() -> anyhow::Result<()> {
+        use crate::net::p2p::{protocols::messaging::Message, test_suit::setup_with_config};
+        let (peer1_m, _) = setup_default();
+        let mut config = crate::net::p2p::SwarmConfig::default();
+        config.blob = config::Config::default().with_write_behind_chunks(2);
+        let (peer2_m, _) = setup_with_config(config);
+        let (peer3_m, _) = setup_default();
+        peer2_m.executor().block_on(
+            peer2_m
+                .swarm()
+                .listen(&Multiaddr::from_str("/ip4/127.0.0.1/tcp/0")?),
+        )?;
+        sleep!(100);
+        let peer2_listen = peer2_m.swarm().list_listeners_blocking()[0].clone();
+        peer1_m.swarm().dial_blocking(&peer2_listen)?;
+        sleep!(1000);
+        let peer1_id = peer1_m.identity().get_peer_id();
+        let peer2_id = peer2_m.identity().get_peer_id();
+        let source = TempDir::new()?;
+        let source_file = source.path().join("large_file");
+        std::fs::write(&source_file, vec![7u8; 64 << 18])?;
+        let dest = TempDir::new()?;
+        send(&peer1_m, peer2_id, source_file.to_str().unwrap());
+        let recv_id = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())[0]
+            .local_recv_id;
+        let manager_clone = peer2_m.clone();
+        let recv_finished = peer2_m.executor().spawn(async move {
+            let mut listener = manager_clone.event_subscriber().subscribe();
+            while let Ok(ev) = listener.recv().await {
+                if let SwarmEvent::Behaviour(BehaviourEvent::Blob(OutEvent::RecvProgressed {
+                    bytes_received,
+                    bytes_total,
+                    ..
+                })) = ev.as_ref()
+                {
+                    if bytes_received == bytes_total {
+                        return;
+                    }
+                }
+            }
+        });
+        peer2_m.executor().block_on(
+            peer2_m
+                .blob()
+                .recv_file(recv_id, dest.path().join("large_file")),
+        )?;
+        // Messages keep flowing while chunks are being written to the file
+        for i in 0..10 {
+            peer1_m.executor().block_on(async {
+                tokio::time::timeout(
+                    Duration::from_secs(5),
+                    peer1_m
+                        .messaging()
+                        .send_message(peer2_id, Message::new(peer1_id, peer2_id, i)),
+                )
+                .await
+            })??;
+        }
+        // So do pings of a new connection
+        let manager_clone = peer2_m.clone();
+        let peer3_id = peer3_m.identity().get_peer_id();
+        let ping_received = peer2_m.executor().spawn(async move {
+            let mut listener = manager_clone.event_subscriber().subscribe();
+            while let Ok(ev) = listener.recv().await {
+                if let SwarmEvent::Behaviour(BehaviourEvent::Ping(ev)) = ev.as_ref() {
+                    if ev.peer == peer3_id && ev.result.is_ok() {
+                        return;
+                    }
+                }
+            }
+        });
+        peer3_m.swarm().dial_blocking(&peer2_listen)?;
+        peer2_m.executor().block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), ping_received).await
+        })??;
+        peer2_m.executor().block_on(async {
+            tokio::time::timeout(Duration::from_secs(30), recv_finished).await
+        })??;
+        sleep!(100);
+        assert!(verify_file(&source_file, dest.path().join("large_file"))?);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn resume_after_disconnect() -> anyhow::Result<()> {
+        let (peer1_m, _) = setup_default();
+        let (peer2_m, _) = setup_default();
+        peer1_m.executor().block_on(
+            peer1_m
+                .swarm()
+                .listen(&Multiaddr::from_str("/ip4/127.0.0.1/tcp/0")?),
+        )?;
+        sleep!(100);
+        let peer1_listen = peer1_m.swarm().list_listeners_blocking()[0].clone();
+        peer2_m.swarm().dial_blocking(&peer1_listen)?;
+        sleep!(500);
+        let peer1_id = peer1_m.identity().get_peer_id();
+        let peer2_id = peer2_m.identity().get_peer_id();
+        let source = TempDir::new()?;
+        let source_file = source.path().join("large_file");
+        std::fs::write(
+            &source_file,
+            (0..64u32 << 18).map(|i| i as u8).collect::<Vec<_>>(),
+        )?;
+        let dest = TempDir::new()?;
+        let dest_file = dest.path().join("large_file");
+        send(&peer1_m, peer2_id, source_file.to_str().unwrap());
+        let recv_id = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())[0]
+            .local_recv_id;
+        // Disconnect as soon as the first chunk arrives
+        let manager_clone = peer2_m.clone();
+        let disconnected = peer2_m.executor().spawn(async move {
+            let mut listener = manager_clone.event_subscriber().subscribe();
+            while let Ok(ev) = listener.recv().await {
+                if let SwarmEvent::Behaviour(BehaviourEvent::Blob(OutEvent::RecvProgressed {
+                    local_recv_id,
+                    ..
+                })) = ev.as_ref()
+                {
+                    if *local_recv_id == recv_id {
+                        let _ = manager_clone.swarm().disconnect_peer_id(&peer1_id).await;
+                        return;
+                    }
+                }
+            }
+        });
+        peer2_m
+            .executor()
+            .block_on(peer2_m.blob().recv_file(recv_id, &dest_file))?;
+        peer2_m.executor().block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), disconnected).await
+        })??;
+        sleep!(1000);
+        let interrupted = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())
+            .iter()
+            .find(|v| v.local_recv_id == recv_id)
+            .cloned()
+            .expect("Interrupted recv to be kept");
+        assert!(interrupted.interrupted);
+        assert!(interrupted.bytes_received < interrupted.bytes_total);
+        assert!(owlnest_blob::part_path(&dest_file).exists());
+        assert!(!dest_file.exists());
+        peer2_m.swarm().dial_blocking(&peer1_listen)?;
+        sleep!(500);
+        let manager_clone = peer2_m.clone();
+        let recv_finished = peer2_m.executor().spawn(async move {
+            let mut listener = manager_clone.event_subscriber().subscribe();
+            while let Ok(ev) = listener.recv().await {
+                if let SwarmEvent::Behaviour(BehaviourEvent::Blob(OutEvent::RecvProgressed {
+                    bytes_received,
+                    bytes_total,
+                    ..
+                })) = ev.as_ref()
+                {
+                    if bytes_received == bytes_total {
+                        return;
+                    }
+                }
+            }
+        });
+        let offset = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().resume_recv(recv_id))?;
+        assert!(offset > 0 && offset < interrupted.bytes_total);
+        peer2_m.executor().block_on(async {
+            tokio::time::timeout(Duration::from_secs(10), recv_finished).await
+        })??;
+        sleep!(100);
+        assert!(verify_file(&source_file, &dest_file)?);
+        assert!(!owlnest_blob::part_path(&dest_file).exists());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn changed_file_is_not_accepted() -> anyhow::Result<()> {
+        let (peer1_m, peer2_m) = setup_peer()?;
+        let source = TempDir::new()?;
+        let source_file = source.path().join("changed_file");
+        std::fs::write(&source_file, vec![1u8; 3 << 18])?;
+        let dest = TempDir::new()?;
+        let dest_file = dest.path().join("changed_file");
+        send(
+            &peer1_m,
+            peer2_m.identity().get_peer_id(),
+            source_file.to_str().unwrap(),
+        );
+        // Same size, different content
+        std::fs::write(&source_file, vec![2u8; 3 << 18])?;
+        let manager_clone = peer1_m.clone();
+        let send_failed = peer1_m.executor().spawn(async move {
+            let mut listener = manager_clone.event_subscriber().subscribe();
+            while let Ok(ev) = listener.recv().await {
+                if let SwarmEvent::Behaviour(BehaviourEvent::Blob(OutEvent::OngoingSendError {
+                    ..
+                })) = ev.as_ref()
+                {
+                    return;
+                }
+            }
+        });
+        let recv_id = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())[0]
+            .local_recv_id;
+        peer2_m
+            .executor()
+            .block_on(peer2_m.blob().recv_file(recv_id, &dest_file))?;
+        peer1_m.executor().block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), send_failed).await
+        })??;
+        sleep!(200);
+        assert!(!dest_file.exists());
+        assert!(peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn changed_file_is_not_resumed() -> anyhow::Result<()> {
+        let (peer1_m, peer2_m) = setup_peer()?;
+        let peer1_id = peer1_m.identity().get_peer_id();
+        let peer1_listen = peer1_m.swarm().list_listeners_blocking()[0].clone();
+        let source = TempDir::new()?;
+        let source_file = source.path().join("large_file");
+        std::fs::write(&source_file, vec![1u8; 64 << 18])?;
+        let dest = TempDir::new()?;
+        let dest_file = dest.path().join("large_file");
+        send(
+            &peer1_m,
+            peer2_m.identity().get_peer_id(),
+            source_file.to_str().unwrap(),
+        );
+        let recv_id = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())[0]
+            .local_recv_id;
+        // Disconnect as soon as the first chunk arrives
+        let manager_clone = peer2_m.clone();
+        let disconnected = peer2_m.executor().spawn(async move {
+            let mut listener = manager_clone.event_subscriber().subscribe();
+            while let Ok(ev) = listener.recv().await {
+                if let SwarmEvent::Behaviour(BehaviourEvent::Blob(OutEvent::RecvProgressed {
+                    local_recv_id,
+                    ..
+                })) = ev.as_ref()
+                {
+                    if *local_recv_id == recv_id {
+                        let _ = manager_clone.swarm().disconnect_peer_id(&peer1_id).await;
+                        return;
+                    }
+                }
+            }
+        });
+        peer2_m
+            .executor()
+            .block_on(peer2_m.blob().recv_file(recv_id, &dest_file))?;
+        peer2_m.executor().block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), disconnected).await
+        })??;
+        sleep!(1000);
+        // Same size, different content
+        std::fs::write(&source_file, vec![2u8; 64 << 18])?;
+        peer2_m.swarm().dial_blocking(&peer1_listen)?;
+        sleep!(500);
+        let manager_clone = peer1_m.clone();
+        let send_failed = peer1_m.executor().spawn(async move {
+            let mut listener = manager_clone.event_subscriber().subscribe();
+            while let Ok(ev) = listener.recv().await {
+                if let SwarmEvent::Behaviour(BehaviourEvent::Blob(OutEvent::OngoingSendError {
+                    ..
+                })) = ev.as_ref()
+                {
+                    return;
+                }
+            }
+        });
+        let offset = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().resume_recv(recv_id))?;
+        peer1_m.executor().block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), send_failed).await
+        })??;
+        sleep!(200);
+        assert!(!dest_file.exists());
+        // Nothing beyond the offset is sent once the change is found.
+        let part = owlnest_blob::part_path(&dest_file);
+        assert!(!part.exists() || std::fs::metadata(&part)?.len() <= offset);
+        assert!(!peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())
+            .iter()
+            .any(|v| v.local_recv_id == recv_id));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn transfer_handle_resolves_on_completion() -> anyhow::Result<()> {
+        let (peer1_m, peer2_m) = setup_peer()?;
+        let dest = TempDir::new()?;
+        let dest_file = dest.path().join("test_locker_file");
+        let bytes_total = std::fs::metadata(SOURCE_FILE)?.len();
+        let send = peer1_m.executor().block_on(
+            peer1_m
+                .blob()
+                .send_file(peer2_m.identity().get_peer_id(), SOURCE_FILE),
+        )?;
+        sleep!(100);
+        let recv_id = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())[0]
+            .local_recv_id;
+        let recv = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().recv_file(recv_id, &dest_file))?;
+        assert_eq!(recv.id(), recv_id);
+        let sent = peer1_m
+            .executor()
+            .block_on(async { tokio::time::timeout(Duration::from_secs(5), send).await })??;
+        assert_eq!(sent.bytes_total, bytes_total);
+        assert_eq!(sent.file_path, PathBuf::from(SOURCE_FILE));
+        let (received, progress) = peer2_m.executor().block_on(async {
+            let mut recv = recv;
+            let received = tokio::time::timeout(Duration::from_secs(5), &mut recv).await;
+            (received, recv.progress())
+        });
+        let received = received??;
+        assert_eq!(received.bytes_total, bytes_total);
+        assert_eq!(received.file_path, dest_file);
+        assert!(received.throughput > 0.0);
+        assert_eq!(progress.bytes_done, bytes_total);
+        assert!(verify_file(SOURCE_FILE, &dest_file)?);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn cancelled_transfer_handle_resolves() -> anyhow::Result<()> {
+        let (peer1_m, peer2_m) = setup_peer()?;
+        let send = peer1_m.executor().block_on(
+            peer1_m
+                .blob()
+                .send_file(peer2_m.identity().get_peer_id(), SOURCE_FILE),
+        )?;
+        sleep!(100);
+        peer1_m
+            .executor()
+            .block_on(peer1_m.blob().cancel_send(send.id()))?;
+        let result = peer1_m
+            .executor()
+            .block_on(async { tokio::time::timeout(Duration::from_secs(1), send).await })?;
+        assert_eq!(result.unwrap_err(), TransferError::Cancelled);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn stream_send_recv_in_memory() -> anyhow::Result<()> {
+        use tokio::io::AsyncReadExt;
+        let (peer1_m, peer2_m) = setup_peer()?;
+        let data = (0..3u32 << 18).map(|i| i as u8).collect::<Vec<_>>();
+        // The hint is only an estimate, the stream is longer than announced.
+        let send = peer1_m.executor().block_on(peer1_m.blob().send_stream(
+            peer2_m.identity().get_peer_id(),
+            "generated",
+            1 << 18,
+            std::io::Cursor::new(data.clone()),
+        ))?;
+        sleep!(100);
+        let pending = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())[0]
+            .clone();
+        assert_eq!(pending.file_name, "generated");
+        let (writer, mut reader) = tokio::io::duplex(64 << 10);
+        let read_all = peer2_m.executor().spawn(async move {
+            let mut received = Vec::new();
+            reader.read_to_end(&mut received).await.map(|_| received)
+        });
+        let recv = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().recv_stream(pending.local_recv_id, writer))?;
+        let received = peer2_m
+            .executor()
+            .block_on(async { tokio::time::timeout(Duration::from_secs(5), recv).await })??;
+        assert_eq!(received.bytes_total, data.len() as u64);
+        let sent = peer1_m.executor().block_on(send)?;
+        assert_eq!(sent.bytes_total, data.len() as u64);
+        assert_eq!(peer2_m.executor().block_on(read_all)??, data);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn chunks_are_compressed_when_worthwhile() -> anyhow::Result<()> {
+        let (peer1_m, peer2_m) = setup_peer()?;
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        let random = source.path().join("random.bin");
+        let data = (0..3 << 18)
+            .map(|_| rand::random::<u8>())
+            .collect::<Vec<_>>();
+        std::fs::write(&random, data)?;
+        let mut summaries = Vec::new();
+        for path in [PathBuf::from(SOURCE_FILE), random.clone()] {
+            let send = peer1_m.executor().block_on(
+                peer1_m
+                    .blob()
+                    .send_file(peer2_m.identity().get_peer_id(), &path),
+            )?;
+            sleep!(100);
+            let recv_id = peer2_m
+                .executor()
+                .block_on(peer2_m.blob().list_pending_recv())[0]
+                .local_recv_id;
+            let dest_file = dest.path().join(path.file_name().unwrap());
+            let recv = peer2_m
+                .executor()
+                .block_on(peer2_m.blob().recv_file(recv_id, &dest_file))?;
+            let sent = peer1_m
+                .executor()
+                .block_on(async { tokio::time::timeout(Duration::from_secs(5), send).await })??;
+            let received = peer2_m
+                .executor()
+                .block_on(async { tokio::time::timeout(Duration::from_secs(5), recv).await })??;
+            assert_eq!(sent.bytes_on_wire, received.bytes_on_wire);
+            assert!(verify_file(&path, &dest_file)?);
+            summaries.push(sent);
+        }
+        // Text compresses well, progress is still in bytes of the file.
+        assert_eq!(
+            summaries[0].bytes_total,
+            std::fs::metadata(SOURCE_FILE)?.len()
+        );
+        assert!(summaries[0].bytes_on_wire < summaries[0].bytes_total / 2);
+        // Random bytes don't, compression is given up after the first chunk.
+        assert_eq!(summaries[1].bytes_on_wire, summaries[1].bytes_total);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn auto_accept_policy() -> anyhow::Result<()> {
+        use crate::net::p2p::test_suit::setup_with_config;
+        let (peer1_m, _) = setup_default();
+        let download = TempDir::new()?;
+        let mut config = crate::net::p2p::SwarmConfig::default();
+        config.blob.auto_accept = config::AutoAccept {
+            enabled: true,
+            trusted_peers: vec![peer1_m.identity().get_peer_id()],
+            max_file_size: 1 << 20,
+            file_patterns: vec!["*.lock".into(), "*.txt".into()],
+            download_dir: download.path().to_string_lossy().to_string(),
+            disk_quota: 0,
+            reject_unmatched: true,
+        };
+        let (peer2_m, _) = setup_with_config(config);
+        peer1_m.executor().block_on(
+            peer1_m
+                .swarm()
+                .listen(&Multiaddr::from_str("/ip4/127.0.0.1/tcp/0")?),
+        )?;
+        sleep!(100);
+        let peer1_listen = peer1_m.swarm().list_listeners_blocking()[0].clone();
+        peer2_m.swarm().dial_blocking(&peer1_listen)?;
+        sleep!(500);
+        let peer2_id = peer2_m.identity().get_peer_id();
+        let manager_clone = peer2_m.clone();
+        let decisions = peer2_m.executor().spawn(async move {
+            let mut listener = manager_clone.event_subscriber().subscribe();
+            let mut decisions = Vec::new();
+            let mut completed = 0;
+            while let Ok(ev) = listener.recv().await {
+                match ev.as_ref() {
+                    SwarmEvent::Behaviour(BehaviourEvent::Blob(OutEvent::AutoAccept {
+                        decision,
+                        ..
+                    })) => decisions.push(decision.clone()),
+                    SwarmEvent::Behaviour(BehaviourEvent::Blob(OutEvent::RecvCompleted {
+                        ..
+                    })) => completed += 1,
+                    _ => {}
+                }
+                if decisions.len() == 3 && completed == 2 {
+                    return decisions;
+                }
+            }
+            decisions
+        });
+        let source = TempDir::new()?;
+        let unmatched = source.path().join("unmatched.bin");
+        std::fs::write(&unmatched, b"unmatched")?;
+        // Same name twice, the second one is renamed.
+        send(&peer1_m, peer2_id, SOURCE_FILE);
+        send(&peer1_m, peer2_id, SOURCE_FILE);
+        send(&peer1_m, peer2_id, unmatched.to_str().unwrap());
+        let decisions = peer2_m
+            .executor()
+            .block_on(async { tokio::time::timeout(Duration::from_secs(10), decisions).await })??;
+        let accepted = decisions
+            .iter()
+            .filter(|v| matches!(v, AutoAcceptDecision::Accepted { .. }))
+            .count();
+        let rejected = decisions
+            .iter()
+            .filter(|v| matches!(v, AutoAcceptDecision::Rejected { .. }))
+            .count();
+        assert_eq!((accepted, rejected), (2, 1));
+        assert!(verify_file(
+            SOURCE_FILE,
+            download.path().join("Cargo.lock")
+        )?);
+        assert!(verify_file(
+            SOURCE_FILE,
+            download.path().join("Cargo (1).lock")
+        )?);
+        assert!(!download.path().join("unmatched.bin").exists());
+        assert!(peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn send_directory() -> anyhow::Result<()> {
+        let (peer1_m, peer2_m) = setup_peer()?;
+        let source = TempDir::new()?;
+        let root = source.path().join("tree");
+        std::fs::create_dir_all(root.join("nested/deeper"))?;
+        std::fs::write(root.join("top"), vec![1u8; 3 << 18])?;
+        std::fs::write(root.join("nested/middle"), b"middle")?;
+        std::fs::write(root.join("nested/deeper/bottom"), vec![3u8; 1 << 10])?;
+        let dest = TempDir::new()?;
+        peer1_m.executor().block_on(
+            peer1_m
+                .blob()
+                .send_directory(peer2_m.identity().get_peer_id(), &root),
+        )?;
+        sleep!(100);
+        let bundle = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_bundles())
+            .iter()
+            .find(|v| !v.outbound)
+            .cloned()
+            .expect("Bundle to be received");
+        assert_eq!(bundle.files.len(), 3);
+        let manager_clone = peer2_m.clone();
+        let local_bundle_id = bundle.local_bundle_id;
+        let bundle_completed = peer2_m.executor().spawn(async move {
+            let mut listener = manager_clone.event_subscriber().subscribe();
+            while let Ok(ev) = listener.recv().await {
+                if let SwarmEvent::Behaviour(BehaviourEvent::Blob(OutEvent::BundleCompleted(id))) =
+                    ev.as_ref()
+                {
+                    if *id == local_bundle_id {
+                        return;
+                    }
+                }
+            }
+        });
+        peer2_m
+            .executor()
+            .block_on(peer2_m.blob().recv_bundle(local_bundle_id, dest.path()))?;
+        peer2_m.executor().block_on(async {
+            tokio::time::timeout(Duration::from_secs(10), bundle_completed).await
+        })??;
+        sleep!(100);
+        for relative_path in ["top", "nested/middle", "nested/deeper/bottom"] {
+            assert!(verify_file(
+                root.join(relative_path),
+                dest.path().join("tree").join(relative_path)
+            )?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn unsafe_bundle_paths_are_rejected() {
+        use owlnest_blob::bundle::sanitize_relative_path;
+        for path in ["../x", "/abs", "a/../../b", "", "a\\..\\b"] {
+            assert!(sanitize_relative_path(path).is_none(), "{path}");
+        }
+        assert_eq!(
+            sanitize_relative_path("a/./b"),
+            Some(std::path::PathBuf::from("a/b"))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn throttled_sends_are_queued() -> anyhow::Result<()> {
+        let (peer1_m, peer2_m) = setup_peer()?;
+        let throttle = Throttle {
+            // The file fits in a single chunk, the limit kicks in after it.
+            max_send_rate_per_transfer: 40_000,
+            max_concurrent_sends: 1,
+            ..Default::default()
+        };
+        peer1_m
+            .executor()
+            .block_on(peer1_m.blob().set_throttle(throttle.clone()));
+        assert_eq!(
+            peer1_m.executor().block_on(peer1_m.blob().throttle()),
+            throttle
+        );
+        let to = peer2_m.identity().get_peer_id();
+        let first = peer1_m
+            .executor()
+            .block_on(peer1_m.blob().send_file(to, SOURCE_FILE))?;
+        let second = peer1_m
+            .executor()
+            .block_on(peer1_m.blob().send_file(to, SOURCE_FILE))?;
+        sleep!(100);
+        let dest = TempDir::new()?;
+        let mut pending = peer2_m
+            .executor()
+            .block_on(peer2_m.blob().list_pending_recv())
+            .to_vec();
+        pending.sort_by_key(|v| v.local_recv_id);
+        for (index, info) in pending.iter().enumerate() {
+            peer2_m.executor().block_on(
+                peer2_m
+                    .blob()
+                    .recv_file(info.local_recv_id, dest.path().join(index.to_string())),
+            )?;
+        }
+        sleep!(200);
+        let sends = peer1_m
+            .executor()
+            .block_on(peer1_m.blob().list_pending_send());
+        let queued = sends
+            .iter()
+            .find(|v| v.queued)
+            .expect("A send to be queued");
+        assert_eq!(queued.local_send_id, second.id());
+        assert!(peer1_m
+            .executor()
+            .block_on(peer1_m.blob().set_priority(second.id(), 1)));
+        let (first, second) = peer1_m.executor().block_on(async {
+            let first = tokio::time::timeout(Duration::from_secs(10), first).await;
+            let second = tokio::time::timeout(Duration::from_secs(10), second).await;
+            (first, second)
+        });
+        let (first, second) = (first??, second??);
+        assert!(first.elapsed >= Duration::from_secs(1));
+        assert!(second.elapsed >= Duration::from_secs(1));
+        sleep!(200);
+        for index in 0..2 {
+            assert!(verify_file(
+                SOURCE_FILE,
+                dest.path().join(index.to_string())
+            )?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn browse_and_fetch_shared_files() -> anyhow::Result<()> {
+        let (peer1_m, peer2_m) = setup_peer()?;
+        let shared = TempDir::new()?;
+        std::fs::create_dir(shared.path().join("docs"))?;
+        std::fs::copy(SOURCE_FILE, shared.path().join("docs").join("source"))?;
+        let peer2_id = peer2_m.identity().get_peer_id();
+        peer1_m.executor().block_on(async {
+            let blob = peer1_m.blob();
+            blob.set_share(Share {
+                name: "shared".into(),
+                path: shared.path().to_string_lossy().to_string(),
+                public: false,
+                allowed_peers: vec![peer2_id],
+            })
+            .await;
+            blob.set_share(Share {
+                name: "private".into(),
+                path: shared.path().to_string_lossy().to_string(),
+                public: false,
+                allowed_peers: vec![],
+            })
+            .await;
+        });
+        let peer1_id = peer1_m.identity().get_peer_id();
+        let browse = |path: &str| {
+            peer2_m
+                .executor()
+                .block_on(peer2_m.blob().browse(peer1_id, path))
+        };
+        let shares = browse("")?;
+        assert_eq!(shares.len(), 1);
+        assert_eq!(shares[0].name, "shared");
+        assert!(shares[0].is_dir);
+        let entries = browse("shared/docs")?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "source");
+        assert_eq!(entries[0].size, std::fs::metadata(SOURCE_FILE)?.len());
+        assert_eq!(
+            entries[0].content_hash,
+            owlnest_blob::hash_file(&mut File::open(SOURCE_FILE)?)?.to_vec()
+        );
+        assert!(matches!(browse("private"), Err(ShareError::Rejected(_))));
+        assert!(matches!(browse("shared/.."), Err(ShareError::Rejected(_))));
+
+        let dest = TempDir::new()?;
+        let fetch = peer2_m.executor().block_on(peer2_m.blob().fetch(
+            peer1_id,
+            "shared/docs/source",
+            dest.path(),
+        ))?;
+        let fetched = peer2_m
+            .executor()
+            .block_on(async { tokio::time::timeout(Duration::from_secs(5), fetch).await })??;
+        assert_eq!(fetched.file_path, dest.path().join("source"));
+        assert!(verify_file(SOURCE_FILE, dest.path().join("source"))?);
+        let rejected = peer2_m.executor().block_on(peer2_m.blob().fetch(
+            peer1_id,
+            "private/docs/source",
+            dest.path().join("private"),
+        ));
+        assert!(matches!(rejected, Err(ShareError::Rejected(_))));
+        assert!(!owlnest_blob::part_path(&dest.path().join("private")).exists());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn get_content_from_providers() -> anyhow::Result<()> {
+        use crate::net::p2p::{protocols::kad::kad, test_suit::setup_with_config};
+        let stores = [TempDir::new()?, TempDir::new()?, TempDir::new()?];
+        let peers = stores
+            .iter()
+            .map(|store| {
+                let mut config = crate::net::p2p::SwarmConfig::default();
+                config.blob =
+                    config::Config::default().with_store_path(store.path().to_string_lossy());
+                setup_with_config(config).0
+            })
+            .collect::<Vec<_>>();
+        let [peer1_m, peer2_m, peer3_m] = &peers[..] else {
+            unreachable!()
+        };
+        let mut cids = Vec::new();
+        for provider in [peer1_m, peer2_m] {
+            provider.executor().block_on(async {
+                provider.kad().set_mode(Some(kad::Mode::Server)).await?;
+                provider
+                    .swarm()
+                    .listen(&Multiaddr::from_str("/ip4/127.0.0.1/tcp/0")?)
+                    .await?;
+                anyhow::Ok(())
+            })?;
+            sleep!(100);
+            let listen = provider.swarm().list_listeners_blocking()[0].clone();
+            peer3_m.swarm().dial_blocking(&listen)?;
+            cids.push(
+                provider
+                    .executor()
+                    .block_on(publish(provider, SOURCE_FILE))?,
+            );
+        }
+        // Same content gets the same ID regardless of the store.
+        assert_eq!(cids[0], cids[1]);
+        sleep!(500);
+        let dest = TempDir::new()?;
+        let path = peer3_m
+            .executor()
+            .block_on(get(peer3_m, cids[0], dest.path()))?;
+        assert_eq!(path, dest.path().join("Cargo.lock"));
+        assert!(verify_file(SOURCE_FILE, &path)?);
+        let stored = peer3_m.executor().block_on(peer3_m.blob().store_list())?;
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].cid, cids[0]);
+        assert!(stored[0].complete);
+        let missing =
+            peer3_m
+                .executor()
+                .block_on(get(peer3_m, Cid([0u8; 32]), dest.path().join("missing")));
+        assert_eq!(missing, Err(StoreError::NoProvider));
+        Ok(())
+    }
+
     fn setup_peer() -> anyhow::Result<(Manager, Manager)> {
         let (peer1_m, _) = setup_default();
         let (peer2_m, _) = setup_default();
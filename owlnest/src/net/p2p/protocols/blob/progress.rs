@@ -0,0 +1,522 @@
+use super::*;
+use crossterm::{cursor, terminal, QueueableCommand};
+use std::io::Write;
+use std::time::Instant;
+
+/// Weight of the latest sample in the throughput estimate.
+const RATE_SMOOTHING: f64 = 0.3;
+/// Samples closer than this are merged, so that bursts of chunks
+/// don't make the rate jump around.
+const MIN_SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+const BAR_WIDTH: usize = 24;
+const NAME_WIDTH: usize = 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferState {
+    /// Waiting for the receiver to accept.
+    Pending,
+    /// Accepted, but waits for a free send slot.
+    Queued,
+    Ongoing,
+    /// The connection is lost, the transfer can be resumed.
+    Interrupted,
+    Completed,
+    Failed(String),
+    Cancelled,
+}
+impl TransferState {
+    /// Whether the transfer has ended, successfully or not.
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Completed | Self::Failed(_) | Self::Cancelled)
+    }
+    /// Whether bytes are moving, or about to.
+    pub fn is_active(&self) -> bool {
+        matches!(self, Self::Queued | Self::Ongoing)
+    }
+}
+impl std::fmt::Display for TransferState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pending => write!(f, "Pending"),
+            Self::Queued => write!(f, "Queued"),
+            Self::Ongoing => write!(f, "Ongoing"),
+            Self::Interrupted => write!(f, "Interrupted"),
+            Self::Completed => write!(f, "Completed"),
+            Self::Failed(error) => write!(f, "Failed: {error}"),
+            Self::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+/// Progress of a single transfer, with throughput estimated from updates.
+#[derive(Debug, Clone)]
+pub struct ProgressLine {
+    pub local_id: u64,
+    pub outbound: bool,
+    pub name: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub state: TransferState,
+    /// Smoothed bytes per second, 0 until two samples are taken.
+    pub rate: f64,
+    last_sample: Option<(Instant, u64)>,
+}
+impl ProgressLine {
+    pub fn new(local_id: u64, outbound: bool, name: impl Into<String>, bytes_total: u64) -> Self {
+        Self {
+            local_id,
+            outbound,
+            name: name.into(),
+            bytes_done: 0,
+            bytes_total,
+            state: TransferState::Pending,
+            rate: 0.0,
+            last_sample: None,
+        }
+    }
+    pub fn from_send(info: &SendInfo) -> Self {
+        let name = info
+            .file_path
+            .file_name()
+            .map(|v| v.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut line = Self::new(info.local_send_id, true, name, info.bytes_total);
+        line.bytes_done = info.bytes_sent;
+        line.state = if info.interrupted {
+            TransferState::Interrupted
+        } else if info.queued {
+            TransferState::Queued
+        } else if info.started {
+            TransferState::Ongoing
+        } else {
+            TransferState::Pending
+        };
+        line
+    }
+    pub fn from_recv(info: &RecvInfo) -> Self {
+        let mut line = Self::new(info.local_recv_id, false, &info.file_name, info.bytes_total);
+        line.bytes_done = info.bytes_received;
+        line.state = if info.interrupted {
+            TransferState::Interrupted
+        } else if info.started {
+            TransferState::Ongoing
+        } else {
+            TransferState::Pending
+        };
+        line
+    }
+    /// Record the bytes done so far.
+    pub fn update(&mut self, bytes_done: u64) {
+        self.update_at(bytes_done, Instant::now())
+    }
+    fn update_at(&mut self, bytes_done: u64, now: Instant) {
+        self.bytes_done = bytes_done;
+        let (last_time, last_bytes) = match self.last_sample {
+            Some(v) => v,
+            None => {
+                self.last_sample = Some((now, bytes_done));
+                return;
+            }
+        };
+        let elapsed = now.saturating_duration_since(last_time);
+        if elapsed < MIN_SAMPLE_INTERVAL {
+            return;
+        }
+        // Resumed transfers may restart from a lower offset.
+        let sample = bytes_done.saturating_sub(last_bytes) as f64 / elapsed.as_secs_f64();
+        self.rate = if self.rate == 0.0 {
+            sample
+        } else {
+            RATE_SMOOTHING * sample + (1.0 - RATE_SMOOTHING) * self.rate
+        };
+        self.last_sample = Some((now, bytes_done));
+    }
+    /// Percentage of bytes done, capped at 100.
+    pub fn percent(&self) -> f64 {
+        if self.bytes_total == 0 {
+            return 100.0;
+        }
+        (self.bytes_done as f64 * 100.0 / self.bytes_total as f64).min(100.0)
+    }
+    /// Estimated time until completion, `None` if not moving.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.state != TransferState::Ongoing || self.rate < 1.0 {
+            return None;
+        }
+        let remaining = self.bytes_total.saturating_sub(self.bytes_done);
+        Some(Duration::from_secs_f64(remaining as f64 / self.rate))
+    }
+    /// Apply the event if it belongs to this transfer.
+    /// Returns whether the line has changed.
+    pub fn apply(&mut self, ev: &OutEvent) -> bool {
+        use OutEvent::*;
+        let id = self.local_id;
+        match (self.outbound, ev) {
+            (
+                true,
+                SendProgressed {
+                    local_send_id,
+                    bytes_sent,
+                    ..
+                },
+            ) if *local_send_id == id => {
+                self.state = TransferState::Ongoing;
+                self.update(*bytes_sent);
+            }
+            (
+                false,
+                RecvProgressed {
+                    local_recv_id,
+                    bytes_received,
+                    ..
+                },
+            ) if *local_recv_id == id => {
+                self.state = TransferState::Ongoing;
+                self.update(*bytes_received);
+            }
+            (
+                true,
+                SendResumed {
+                    local_send_id,
+                    offset,
+                },
+            ) if *local_send_id == id => {
+                self.state = TransferState::Ongoing;
+                self.last_sample = None;
+                self.update(*offset);
+            }
+            (
+                false,
+                RecvResumed {
+                    local_recv_id,
+                    offset,
+                },
+            ) if *local_recv_id == id => {
+                self.state = TransferState::Ongoing;
+                self.last_sample = None;
+                self.update(*offset);
+            }
+            (true, SendQueued { local_send_id, .. }) if *local_send_id == id => {
+                self.state = TransferState::Queued
+            }
+            (true, SendInterrupted { local_send_id, .. })
+            | (
+                false,
+                RecvInterrupted {
+                    local_recv_id: local_send_id,
+                    ..
+                },
+            ) if *local_send_id == id => {
+                self.state = TransferState::Interrupted;
+                self.rate = 0.0;
+            }
+            (
+                true,
+                SendCompleted {
+                    local_send_id,
+                    bytes_total,
+                    ..
+                },
+            )
+            | (
+                false,
+                RecvCompleted {
+                    local_recv_id: local_send_id,
+                    bytes_total,
+                    ..
+                },
+            ) if *local_send_id == id => {
+                self.bytes_total = *bytes_total;
+                self.bytes_done = *bytes_total;
+                self.state = TransferState::Completed;
+            }
+            (
+                true,
+                OngoingSendError {
+                    local_send_id,
+                    error,
+                },
+            )
+            | (
+                false,
+                OngoingRecvError {
+                    local_recv_id: local_send_id,
+                    error,
+                },
+            ) if *local_send_id == id => self.state = TransferState::Failed(error.clone()),
+            (true, CancelledSend(local_send_id)) | (false, CancelledRecv(local_send_id))
+                if *local_send_id == id =>
+            {
+                self.state = TransferState::Cancelled
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+impl std::fmt::Display for ProgressLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let filled = (self.percent() / 100.0 * BAR_WIDTH as f64).round() as usize;
+        let name = if self.name.chars().count() > NAME_WIDTH {
+            let tail = self
+                .name
+                .chars()
+                .skip(self.name.chars().count() - (NAME_WIDTH - 3))
+                .collect::<String>();
+            format!("...{tail}")
+        } else {
+            self.name.clone()
+        };
+        write!(
+            f,
+            "{} {:>4} {:<NAME_WIDTH$} [{}{}] {:>5.1}% {:>10}/{:<10}",
+            if self.outbound { "Send" } else { "Recv" },
+            self.local_id,
+            name,
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.percent(),
+            format_bytes(self.bytes_done),
+            format_bytes(self.bytes_total),
+        )?;
+        if self.state == TransferState::Ongoing {
+            write!(f, " {:>10}/s", format_bytes(self.rate as u64))?;
+            match self.eta() {
+                Some(eta) => write!(f, " ETA {}", format_duration(eta))?,
+                None => write!(f, " ETA --:--")?,
+            }
+        }
+        write!(f, " {}", self.state)
+    }
+}
+
+/// Redraw progress lines in place as events arrive.
+/// Watches the transfer with the given local send or recv ID if supplied,
+/// otherwise all transfers, including those started while watching.
+/// Returns once all watched transfers have ended, or when watching all
+/// transfers, once no transfer is ongoing or queued.
+/// Stops early when interrupted with Ctrl+C.
+pub async fn watch(handle: &Handle, id: Option<u64>) {
+    // Subscribe before listing, so that no event is missed.
+    let mut listener = handle.swarm_event_source.subscribe();
+    let mut lines = list(handle, id).await;
+    if lines.is_empty() && id.is_some() {
+        println!("No transfer with ID {} is found", id.unwrap_or_default());
+        return;
+    }
+    println!("Press Ctrl+C to stop watching.");
+    let mut drawn = 0;
+    let mut timer = tokio::time::interval(REDRAW_INTERVAL);
+    loop {
+        drawn = redraw(&lines, drawn);
+        let done = match id {
+            Some(_) => lines.iter().all(|v| v.state.is_finished()),
+            None => !lines.iter().any(|v| v.state.is_active()),
+        };
+        if done {
+            break;
+        }
+        tokio::select! {
+            ev = listener.recv() => {
+                let ev = match ev {
+                    Ok(ev) => ev,
+                    // Events may have been dropped, catch up with the state of transfers.
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        catch_up_lines(handle, &mut listener, &mut lines, id).await;
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if let SwarmEvent::Behaviour(BehaviourEvent::Blob(ev)) = ev.as_ref() {
+                    if !lines.iter_mut().any(|v| v.apply(ev)) && id.is_none() {
+                        lines.extend(new_line(handle, ev).await);
+                    }
+                }
+            }
+            _ = timer.tick() => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+}
+
+/// Lines of transfers that are listed, filtered by ID if supplied.
+async fn list(handle: &Handle, id: Option<u64>) -> Vec<ProgressLine> {
+    let mut lines = handle
+        .list_pending_send()
+        .await
+        .iter()
+        .map(ProgressLine::from_send)
+        .chain(
+            handle
+                .list_pending_recv()
+                .await
+                .iter()
+                .map(ProgressLine::from_recv),
+        )
+        .filter(|v| id.is_none() || id == Some(v.local_id))
+        .collect::<Vec<_>>();
+    lines.sort_by_key(|v| (!v.outbound, v.local_id));
+    lines
+}
+
+/// Update lines with the listed state of transfers.
+/// Transfers that are no longer listed have ended, their final events
+/// are looked for among the buffered ones, see [`TransferHandle`].
+async fn catch_up_lines(
+    handle: &Handle,
+    listener: &mut broadcast::Receiver<std::sync::Arc<SwarmEvent>>,
+    lines: &mut Vec<ProgressLine>,
+    id: Option<u64>,
+) {
+    let listed = list(handle, id).await;
+    for line in lines.iter_mut().filter(|v| !v.state.is_finished()) {
+        match listed
+            .iter()
+            .find(|v| (v.outbound, v.local_id) == (line.outbound, line.local_id))
+        {
+            Some(listed) => {
+                line.state = listed.state.clone();
+                line.update(listed.bytes_done);
+            }
+            None => line.state = TransferState::Failed(TransferError::Untracked.to_string()),
+        }
+    }
+    for listed in listed {
+        if !lines
+            .iter()
+            .any(|v| (v.outbound, v.local_id) == (listed.outbound, listed.local_id))
+        {
+            lines.push(listed);
+        }
+    }
+    // Replace the unknown outcomes with the final events that are still buffered.
+    let untracked = TransferState::Failed(TransferError::Untracked.to_string());
+    let deadline = tokio::time::Instant::now() + CATCH_UP_GRACE;
+    while lines.iter().any(|v| v.state == untracked) {
+        let ev = match tokio::time::timeout_at(deadline, listener.recv()).await {
+            Ok(Ok(ev)) => ev,
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => return,
+        };
+        if let SwarmEvent::Behaviour(BehaviourEvent::Blob(ev)) = ev.as_ref() {
+            for line in lines.iter_mut().filter(|v| v.state == untracked) {
+                // Progress that was buffered before the end doesn't count.
+                let mut applied = line.clone();
+                if applied.apply(ev) && applied.state.is_finished() {
+                    *line = applied;
+                }
+            }
+        }
+    }
+}
+
+/// Render the progress of a single transfer until it ends.
+pub async fn follow(
+    mut transfer: TransferHandle,
+    outbound: bool,
+    name: impl Into<String>,
+) -> Result<TransferSummary, TransferError> {
+    let progress = transfer.progress();
+    let mut line = ProgressLine::new(transfer.id(), outbound, name, progress.bytes_total);
+    let mut drawn = redraw(std::slice::from_ref(&line), 0);
+    let mut timer = tokio::time::interval(REDRAW_INTERVAL);
+    loop {
+        tokio::select! {
+            progress = transfer.progress_changed() => match progress {
+                Some(progress) => {
+                    line.state = TransferState::Ongoing;
+                    line.update(progress.bytes_done);
+                }
+                // The transfer has ended.
+                None => break,
+            },
+            _ = timer.tick() => {}
+        }
+        drawn = redraw(std::slice::from_ref(&line), drawn);
+    }
+    let outcome = transfer.await;
+    line.state = match &outcome {
+        Ok(summary) => {
+            line.update(summary.bytes_total);
+            TransferState::Completed
+        }
+        Err(TransferError::Cancelled) => TransferState::Cancelled,
+        Err(e) => TransferState::Failed(e.to_string()),
+    };
+    redraw(std::slice::from_ref(&line), drawn);
+    outcome
+}
+
+/// A line for a transfer that started after watching started.
+/// Events of sends carry no file name, so it's looked up from the listed sends.
+async fn new_line(handle: &Handle, ev: &OutEvent) -> Option<ProgressLine> {
+    use OutEvent::*;
+    let mut line = match ev {
+        IncomingFile {
+            file_name,
+            local_recv_id,
+            bytes_total,
+            ..
+        } => {
+            return Some(ProgressLine::new(
+                *local_recv_id,
+                false,
+                file_name,
+                *bytes_total,
+            ))
+        }
+        SendProgressed { local_send_id, .. } => handle
+            .list_pending_send()
+            .await
+            .iter()
+            .find(|v| v.local_send_id == *local_send_id)
+            .map(ProgressLine::from_send)?,
+        RecvProgressed { local_recv_id, .. } => handle
+            .list_pending_recv()
+            .await
+            .iter()
+            .find(|v| v.local_recv_id == *local_recv_id)
+            .map(ProgressLine::from_recv)?,
+        _ => return None,
+    };
+    line.apply(ev);
+    Some(line)
+}
+
+/// Overwrite the lines drawn last time, returns the number of lines drawn.
+fn redraw(lines: &[ProgressLine], drawn: usize) -> usize {
+    let mut stdout = std::io::stdout();
+    if drawn > 0 {
+        let _ = stdout.queue(cursor::MoveToPreviousLine(drawn as u16));
+    }
+    for line in lines {
+        let _ = stdout.queue(terminal::Clear(terminal::ClearType::CurrentLine));
+        let _ = writeln!(stdout, "{line}");
+    }
+    let _ = stdout.flush();
+    lines.len()
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
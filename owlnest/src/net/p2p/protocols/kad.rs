@@ -2,18 +2,23 @@ use super::*;
 use crate::net::p2p::swarm::{behaviour::BehaviourEvent, SwarmEvent};
 use libp2p::StreamProtocol;
 use owlnest_core::error::OperationError;
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
 use std::str::FromStr;
 use tracing::{debug, info, trace};
 
 pub use libp2p::kad;
-/// An alias to the behaviour with in-memory record store.
-pub type Behaviour = kad::Behaviour<kad::store::MemoryStore>;
+/// An alias to the behaviour with the record store chosen in [`Config`].
+pub type Behaviour = kad::Behaviour<store::LocalStore>;
 /// An ailas to `libp2p::kad::Event` for unified naming.
 pub type OutEvent = kad::Event;
 pub use libp2p::kad::PROTOCOL_NAME;
 
 type PeerTreeMap = std::collections::BTreeMap<PeerId, kad::Addresses>;
 
+/// Record store that can outlive the process.
+pub mod store;
+
 /// Equivalent to `libp2p::kad::Config` that supports `serde`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -28,6 +33,8 @@ pub struct Config {
     kbucket_inserts: config::BucketInserts,
     caching: config::Caching,
     periodic_bootstrap_interval_sec: Option<u64>,
+    store: config::Store,
+    store_limits: config::StoreLimits,
 }
 impl Default for Config {
     fn default() -> Self {
@@ -43,10 +50,28 @@ impl Default for Config {
             kbucket_inserts: config::BucketInserts::OnConnected,
             caching: config::Caching::Enabled { max_peers: 1 },
             periodic_bootstrap_interval_sec: Some(5 * 60), // 5 min
+            store: config::Store::Volatile,
+            store_limits: config::StoreLimits::default(),
         }
     }
 }
 impl Config {
+    /// Choose where records and provider entries are kept.
+    pub fn with_store(mut self, store: config::Store) -> Self {
+        self.store = store;
+        self
+    }
+    /// Build the record store for the local peer as configured.
+    pub fn build_store(&self, local_peer_id: PeerId) -> store::LocalStore {
+        match &self.store {
+            config::Store::Volatile => {
+                store::LocalStore::volatile(local_peer_id, self.store_limits.clone())
+            }
+            config::Store::Persistent { path } => {
+                store::LocalStore::persistent(local_peer_id, self.store_limits.clone(), path)
+            }
+        }
+    }
     /// Convert to `libp2p::kad::Config` with a ptotocol string.  
     /// ### Protocol string  
     /// The protocol string is used to distinguish between different
@@ -70,6 +95,7 @@ impl Config {
             kbucket_inserts,
             caching,
             periodic_bootstrap_interval_sec,
+            ..
         } = self;
         let mut config = kad::Config::new(
             StreamProtocol::try_from_owned(protocol)
@@ -247,6 +273,62 @@ pub mod config {
             }
         }
     }
+
+    /// Where records and provider entries hosted by the local peer are kept.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub enum Store {
+        /// Kept in memory, lost when the peer stops.
+        Volatile,
+        /// Kept in memory and written to the file at `path`,
+        /// restored when the peer starts again.
+        Persistent { path: String },
+    }
+
+    /// Equivalent to `libp2p::kad::store::MemoryStoreConfig` that supports `serde`.
+    /// Applies to both kinds of [`Store`].
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct StoreLimits {
+        /// The maximum number of records.
+        pub max_records: usize,
+        /// The maximum size of record values, in bytes.
+        pub max_value_bytes: usize,
+        /// The maximum number of providers stored for a key.
+        pub max_providers_per_key: usize,
+        /// The maximum number of provider records for which the
+        /// local node is the provider.
+        pub max_provided_keys: usize,
+    }
+    impl Default for StoreLimits {
+        fn default() -> Self {
+            Self {
+                max_records: 1024,
+                max_value_bytes: 65 * 1024,
+                max_providers_per_key: K_VALUE.get(),
+                max_provided_keys: 1024,
+            }
+        }
+    }
+}
+
+/// Errors of record and provider operations.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The record or provider entry cannot be stored on local peer.
+    Store(kad::store::Error),
+    PutRecord(kad::PutRecordError),
+    GetRecord(kad::GetRecordError),
+    AddProvider(kad::AddProviderError),
+}
+impl std::error::Error for Error {}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Store(e) => write!(f, "Failed to store locally: {e}"),
+            Error::PutRecord(e) => write!(f, "Failed to put record: {e}"),
+            Error::GetRecord(e) => write!(f, "Failed to get record: {e}"),
+            Error::AddProvider(e) => write!(f, "Failed to announce provider: {e}"),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -264,6 +346,25 @@ pub(crate) enum InEvent {
         callback: Callback<kad::RoutingUpdate>,
     },
     SetMode(Option<kad::Mode>),
+    StartProviding {
+        key: kad::RecordKey,
+        callback: Callback<Result<kad::QueryId, kad::store::Error>>,
+    },
+    PutRecord {
+        record: kad::Record,
+        quorum: kad::Quorum,
+        callback: Callback<Result<kad::QueryId, kad::store::Error>>,
+    },
+    GetRecord {
+        key: kad::RecordKey,
+        callback: Callback<kad::QueryId>,
+    },
+    /// Stop the query early, e.g. enough records are found.
+    FinishQuery(kad::QueryId),
+    GetProviders {
+        key: kad::RecordKey,
+        callback: Callback<kad::QueryId>,
+    },
 }
 
 /// A handle that can communicate with the behaviour within the swarm.
@@ -272,10 +373,12 @@ pub struct Handle {
     sender: mpsc::Sender<InEvent>,
     swarm_event_source: EventSender,
     tree_map: std::sync::Arc<std::sync::RwLock<PeerTreeMap>>,
+    /// Used to resolve quorums into numbers of records.
+    replication_factor: NonZeroUsize,
 }
 impl Handle {
     pub(crate) fn new(
-        _config: &Config,
+        config: &Config,
         buffer_size: usize,
         swarm_event_source: &EventSender,
     ) -> (Self, mpsc::Receiver<InEvent>) {
@@ -303,6 +406,7 @@ impl Handle {
                 sender: tx,
                 swarm_event_source: swarm_event_source.clone(),
                 tree_map: tree_map_clone,
+                replication_factor: config.query_config.replication_factor,
             },
             rx,
         )
@@ -341,6 +445,189 @@ impl Handle {
         ));
         handle.await.unwrap()
     }
+    /// Store the record locally and on the peers closest to the key.
+    /// Returns once the record is stored on at least `quorum` peers,
+    /// or the query fails.
+    pub async fn put_record(
+        &self,
+        key: kad::RecordKey,
+        value: Vec<u8>,
+        quorum: kad::Quorum,
+    ) -> Result<kad::PutRecordOk, Error> {
+        let mut listener = self.swarm_event_source.subscribe();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.sender
+            .send(InEvent::PutRecord {
+                record: kad::Record::new(key, value),
+                quorum,
+                callback: tx,
+            })
+            .await
+            .expect("sending event to succeed");
+        let query_id = rx
+            .await
+            .expect("callback to succeed")
+            .map_err(Error::Store)?;
+        let handle = tokio::spawn(listen_event!(
+            listener for Kad,
+            OutEvent::OutboundQueryProgressed {
+                id,
+                result: kad::QueryResult::PutRecord(result),
+                ..
+            }=>
+            {
+                if query_id != *id {
+                    continue;
+                }
+                drop(listener);
+                return result.clone().map_err(Error::PutRecord);
+            }
+        ));
+        handle.await.unwrap()
+    }
+    /// Find the record across the network, including local store.
+    /// Returns once `quorum` copies are found, which may come from
+    /// different publishers or be of different versions.
+    pub async fn get_record(
+        &self,
+        key: kad::RecordKey,
+        quorum: kad::Quorum,
+    ) -> Result<Vec<kad::PeerRecord>, Error> {
+        let needed = quorum_size(quorum, self.replication_factor);
+        let mut listener = self.swarm_event_source.subscribe();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.sender
+            .send(InEvent::GetRecord {
+                key: key.clone(),
+                callback: tx,
+            })
+            .await
+            .expect("sending event to succeed");
+        let query_id = rx.await.expect("callback to succeed");
+        let sender = self.sender.clone();
+        let mut records = Vec::new();
+        let mut error = None;
+        let handle = tokio::spawn(listen_event!(
+            listener for Kad,
+            OutEvent::OutboundQueryProgressed {
+                id,
+                result: kad::QueryResult::GetRecord(result),
+                step,
+                ..
+            }=>
+            {
+                if query_id != *id {
+                    continue;
+                }
+                match result {
+                    Ok(kad::GetRecordOk::FoundRecord(record)) => records.push(record.clone()),
+                    Ok(kad::GetRecordOk::FinishedWithNoAdditionalRecord { .. }) => {}
+                    Err(e) => error = Some(e.clone()),
+                }
+                if records.len() >= needed.get() {
+                    if !step.last {
+                        let _ = sender.send(InEvent::FinishQuery(query_id)).await;
+                    }
+                    drop(listener);
+                    return Ok(records);
+                }
+                if step.last {
+                    drop(listener);
+                    return Err(Error::GetRecord(match error {
+                        Some(e) if records.is_empty() => e,
+                        _ => kad::GetRecordError::QuorumFailed {
+                            key,
+                            records,
+                            quorum: needed,
+                        },
+                    }));
+                }
+            }
+        ));
+        handle.await.unwrap()
+    }
+    /// Announce local peer as a provider of the key.
+    /// The announcement is kept in local store and republished periodically.
+    /// Returns once the announcement is sent to the peers closest to the key.
+    /// Timing out is not an error as the announcement is already stored locally,
+    /// it will reach other peers when republished.
+    pub async fn start_providing(&self, key: kad::RecordKey) -> Result<kad::AddProviderOk, Error> {
+        let mut listener = self.swarm_event_source.subscribe();
+        let query_id = self.provide_query(key).await?;
+        let handle = tokio::spawn(listen_event!(
+            listener for Kad,
+            OutEvent::OutboundQueryProgressed {
+                id,
+                result: kad::QueryResult::StartProviding(result),
+                ..
+            }=>
+            {
+                if query_id != *id {
+                    continue;
+                }
+                drop(listener);
+                return match result {
+                    Err(kad::AddProviderError::Timeout { key }) => {
+                        Ok(kad::AddProviderOk { key: key.clone() })
+                    }
+                    result => result.clone().map_err(Error::AddProvider),
+                };
+            }
+        ));
+        handle.await.unwrap()
+    }
+    /// Announce local peer as a provider of the key, like [`Handle::start_providing`]
+    /// but returns once the announcement is stored locally.
+    /// The announcement is sent to other peers in the background.
+    pub async fn provide(&self, key: kad::RecordKey) -> Result<(), Error> {
+        self.provide_query(key).await.map(|_| ())
+    }
+    async fn provide_query(&self, key: kad::RecordKey) -> Result<kad::QueryId, Error> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.sender
+            .send(InEvent::StartProviding { key, callback: tx })
+            .await
+            .expect("sending event to succeed");
+        rx.await.expect("callback to succeed").map_err(Error::Store)
+    }
+    /// Find peers that provide the key across the network,
+    /// including local peer if it does.
+    pub async fn providers(&self, key: kad::RecordKey) -> HashSet<PeerId> {
+        let mut listener = self.swarm_event_source.subscribe();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.sender
+            .send(InEvent::GetProviders { key, callback: tx })
+            .await
+            .expect("sending event to succeed");
+        let query_id = rx.await.expect("callback to succeed");
+        let mut providers = HashSet::new();
+        let handle = tokio::spawn(listen_event!(
+            listener for Kad,
+            OutEvent::OutboundQueryProgressed {
+                id,
+                result,
+                step,
+                ..
+            }=>
+            {
+                if query_id != *id {
+                    continue;
+                }
+                if let kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders {
+                    providers: found,
+                    ..
+                })) = result
+                {
+                    providers.extend(found.iter().copied());
+                }
+                if step.last {
+                    drop(listener);
+                    return providers;
+                }
+            }
+        ));
+        handle.await.unwrap()
+    }
     /// Perform a lookup on local peer store.
     pub async fn lookup(&self, peer_id: &PeerId) -> Option<kad::Addresses> {
         self.tree_map
@@ -410,6 +697,31 @@ pub(crate) fn map_in_event(ev: InEvent, behav: &mut Behaviour) {
             handle_callback_sender!(result=>callback);
         }
         SetMode(mode) => behav.set_mode(mode),
+        StartProviding { key, callback } => {
+            let result = behav.start_providing(key);
+            handle_callback_sender!(result=>callback);
+        }
+        PutRecord {
+            record,
+            quorum,
+            callback,
+        } => {
+            let result = behav.put_record(record, quorum);
+            handle_callback_sender!(result=>callback);
+        }
+        GetRecord { key, callback } => {
+            let query_id = behav.get_record(key);
+            handle_callback_sender!(query_id=>callback);
+        }
+        FinishQuery(id) => {
+            if let Some(mut query) = behav.query_mut(&id) {
+                query.finish()
+            }
+        }
+        GetProviders { key, callback } => {
+            let query_id = behav.get_providers(key);
+            handle_callback_sender!(query_id=>callback);
+        }
         InsertNode {
             peer_id,
             address,
@@ -421,6 +733,18 @@ pub(crate) fn map_in_event(ev: InEvent, behav: &mut Behaviour) {
     }
 }
 
+/// Number of records a quorum stands for.
+fn quorum_size(quorum: kad::Quorum, replication_factor: NonZeroUsize) -> NonZeroUsize {
+    match quorum {
+        kad::Quorum::One => NonZeroUsize::MIN,
+        kad::Quorum::Majority => {
+            NonZeroUsize::new(replication_factor.get() / 2 + 1).expect("value to be non-zero")
+        }
+        kad::Quorum::All => replication_factor,
+        kad::Quorum::N(n) => n.min(replication_factor),
+    }
+}
+
 pub(crate) fn ev_dispatch(ev: &OutEvent) {
     use kad::Event::*;
     match ev{
@@ -435,6 +759,7 @@ pub(crate) fn ev_dispatch(ev: &OutEvent) {
 pub mod cli {
     use super::*;
     use clap::{Subcommand, ValueEnum};
+    use prettytable::{row, Table};
 
     /// Subcommand for interacting with `libp2p-kad` protocol.  
     /// Kadelima protocol is an effecient routing algorithm
@@ -493,6 +818,41 @@ pub mod cli {
             #[arg(required = true)]
             address: Multiaddr,
         },
+        /// Store the record locally and on peers closest to the key.
+        Put {
+            /// The key of the record, as UTF-8 text.
+            #[arg(required = true)]
+            key: String,
+            /// The value of the record, as UTF-8 text.
+            #[arg(required = true)]
+            value: String,
+            /// Peers that must store the record for it to succeed:
+            /// `one`, `majority`, `all` or a number.
+            #[arg(long, default_value = "one", value_parser = parse_quorum)]
+            quorum: kad::Quorum,
+        },
+        /// Find the record across the network, including local store.
+        Get {
+            /// The key of the record, as UTF-8 text.
+            #[arg(required = true)]
+            key: String,
+            /// Copies of the record to find before returning:
+            /// `one`, `majority`, `all` or a number.
+            #[arg(long, default_value = "one", value_parser = parse_quorum)]
+            quorum: kad::Quorum,
+        },
+        /// Announce local peer as a provider of the key.
+        Provide {
+            /// The key to provide, as UTF-8 text.
+            #[arg(required = true)]
+            key: String,
+        },
+        /// Find peers that provide the key across the network.
+        Providers {
+            /// The key to look for, as UTF-8 text.
+            #[arg(required = true)]
+            key: String,
+        },
         /// Insert the default nodes of the network to local routing table.
         /// Currently those peers are from official IPFS nodes. Visit
         /// https://docs.ipfs.tech/how-to/modify-bootstrap-list/ for more
@@ -561,6 +921,33 @@ pub mod cli {
                 println!("Mode for kad has been set to {mode}")
             }
             Insert { .. } => {}
+            Put { key, value, quorum } => {
+                match handle
+                    .put_record(kad::RecordKey::new(&key), value.into_bytes(), quorum)
+                    .await
+                {
+                    Ok(_) => println!("Record {key} is stored"),
+                    Err(e) => println!("{e}"),
+                }
+            }
+            Get { key, quorum } => match handle.get_record(kad::RecordKey::new(&key), quorum).await
+            {
+                Ok(records) => print_records(&records),
+                Err(e) => println!("{e}"),
+            },
+            Provide { key } => match handle.start_providing(kad::RecordKey::new(&key)).await {
+                Ok(_) => println!("Local peer is now providing {key}"),
+                Err(e) => println!("{e}"),
+            },
+            Providers { key } => {
+                let providers = handle.providers(kad::RecordKey::new(&key)).await;
+                if providers.is_empty() {
+                    return println!("No provider of {key} is found");
+                }
+                for peer in providers {
+                    println!("{peer}")
+                }
+            }
             InsertDefault => {
                 let result = handle
                     .insert_node(
@@ -615,6 +1002,40 @@ pub mod cli {
             }
         }
     }
+
+    fn print_records(records: &[kad::PeerRecord]) {
+        let mut table = Table::new();
+        table.set_titles(row!["From", "Publisher", "Value"]);
+        for record in records {
+            let from = match record.peer {
+                Some(peer) => peer.to_string(),
+                None => "Local".into(),
+            };
+            let publisher = record
+                .record
+                .publisher
+                .map(|v| v.to_string())
+                .unwrap_or_default();
+            table.add_row(row![
+                from,
+                publisher,
+                String::from_utf8_lossy(&record.record.value)
+            ]);
+        }
+        table.printstd();
+    }
+
+    fn parse_quorum(s: &str) -> Result<kad::Quorum, String> {
+        match s.to_lowercase().as_str() {
+            "one" => Ok(kad::Quorum::One),
+            "majority" => Ok(kad::Quorum::Majority),
+            "all" => Ok(kad::Quorum::All),
+            n => n
+                .parse::<NonZeroUsize>()
+                .map(kad::Quorum::N)
+                .map_err(|_| format!("Invalid quorum {s:?}")),
+        }
+    }
 }
 
 pub(crate) mod swarm_hooks {
@@ -651,3 +1072,130 @@ pub(crate) mod swarm_hooks {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::kad;
+    use crate::{net::p2p::test_suit::setup_default, sleep};
+    use libp2p::Multiaddr;
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn put_get_and_provide() -> anyhow::Result<()> {
+        let (peer1_m, _) = setup_default();
+        let (peer2_m, _) = setup_default();
+        for peer in [&peer1_m, &peer2_m] {
+            peer.executor()
+                .block_on(peer.kad().set_mode(Some(kad::Mode::Server)))?;
+        }
+        peer1_m
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep!(200);
+        peer2_m
+            .swarm()
+            .dial_blocking(&peer1_m.swarm().list_listeners_blocking()[0])?;
+        sleep!(500);
+        let key = kad::RecordKey::new(&"greeting");
+        peer1_m.executor().block_on(peer1_m.kad().put_record(
+            key.clone(),
+            b"hello".to_vec(),
+            kad::Quorum::One,
+        ))?;
+        let records = peer2_m
+            .executor()
+            .block_on(peer2_m.kad().get_record(key, kad::Quorum::One))?;
+        assert_eq!(records[0].record.value, b"hello");
+        assert_eq!(
+            records[0].record.publisher,
+            Some(peer1_m.identity().get_peer_id())
+        );
+        let key = kad::RecordKey::new(&"service");
+        peer1_m
+            .executor()
+            .block_on(peer1_m.kad().start_providing(key.clone()))?;
+        let providers = peer2_m.executor().block_on(peer2_m.kad().providers(key));
+        assert!(providers.contains(&peer1_m.identity().get_peer_id()));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn restore_store_after_restart() -> anyhow::Result<()> {
+        use crate::net::p2p::test_suit::setup_with_config;
+        let dir = temp_dir::TempDir::new()?;
+        let mut config = crate::net::p2p::SwarmConfig::default();
+        config.kad = super::Config::default().with_store(super::config::Store::Persistent {
+            path: dir.path().join("kad.json").to_string_lossy().to_string(),
+        });
+        let (peer1_m, _) = setup_with_config(config.clone());
+        let (peer2_m, _) = setup_default();
+        for peer in [&peer1_m, &peer2_m] {
+            peer.executor()
+                .block_on(peer.kad().set_mode(Some(kad::Mode::Server)))?;
+        }
+        peer1_m
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep!(200);
+        peer2_m
+            .swarm()
+            .dial_blocking(&peer1_m.swarm().list_listeners_blocking()[0])?;
+        sleep!(500);
+        let key = kad::RecordKey::new(&"greeting");
+        peer1_m.executor().block_on(peer1_m.kad().put_record(
+            key.clone(),
+            b"hello".to_vec(),
+            kad::Quorum::One,
+        ))?;
+        // The next snapshot isn't due yet, only the shutdown writes the record.
+        peer1_m.executor().block_on(peer1_m.swarm().shutdown());
+        drop(peer1_m);
+        sleep!(200);
+        assert!(peer2_m.swarm().list_connected_blocking().is_empty());
+        let (peer1_m, _) = setup_with_config(config);
+        let records = peer1_m
+            .executor()
+            .block_on(peer1_m.kad().get_record(key, kad::Quorum::One))?;
+        assert_eq!(records[0].record.value, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn restore_store_drops_expired_entries() -> anyhow::Result<()> {
+        use super::{config::StoreLimits, store::LocalStore};
+        use kad::store::RecordStore;
+        use std::time::{Duration, Instant};
+        let dir = temp_dir::TempDir::new()?;
+        let path = dir.path().join("kad.json");
+        let local_peer_id = libp2p::PeerId::random();
+        let remote_peer_id = libp2p::PeerId::random();
+        let mut store = LocalStore::persistent(local_peer_id, StoreLimits::default(), &path);
+        let mut record = kad::Record::new(kad::RecordKey::new(&"greeting"), b"hello".to_vec());
+        record.expires = Some(Instant::now() + Duration::from_secs(60));
+        store.put(record)?;
+        let mut expiring = kad::Record::new(kad::RecordKey::new(&"stale"), b"bye".to_vec());
+        expiring.expires = Some(Instant::now() + Duration::from_millis(100));
+        store.put(expiring)?;
+        store.add_provider(kad::ProviderRecord::new(
+            kad::RecordKey::new(&"service"),
+            remote_peer_id,
+            Vec::new(),
+        ))?;
+        store.flush();
+        drop(store);
+        let store = LocalStore::persistent(local_peer_id, StoreLimits::default(), &path);
+        let record = store
+            .get(&kad::RecordKey::new(&"greeting"))
+            .expect("record to be restored");
+        assert_eq!(record.value, b"hello");
+        assert!(store.get(&kad::RecordKey::new(&"stale")).is_none());
+        assert_eq!(
+            store.providers(&kad::RecordKey::new(&"service"))[0].provider,
+            remote_peer_id
+        );
+        Ok(())
+    }
+}
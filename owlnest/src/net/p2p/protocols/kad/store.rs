@@ -0,0 +1,366 @@
+//! Record store of the kad behaviour, optionally backed by a file.
+use super::*;
+use kad::store::{Error as StoreError, MemoryStore, MemoryStoreConfig, RecordStore};
+use kad::{ProviderRecord, Record, RecordKey};
+use owlnest_core::snapshot::{self, SnapshotWriter};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often expired records and provider entries are swept from the store.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// Changes are written to disk at most this often.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Record store used by [`Behaviour`].
+/// Records and provider entries are kept in memory with the limits of
+/// [`config::StoreLimits`], and mirrored to disk when the store is
+/// [`config::Store::Persistent`].
+/// Changes are only marked, the snapshot is taken by [`LocalStore::tick`]
+/// so that a burst of changes is written once.
+pub struct LocalStore {
+    inner: MemoryStore,
+    journal: Option<Journal>,
+    last_sweep: Instant,
+    /// Whether there are changes not yet handed to the journal.
+    dirty: bool,
+    last_snapshot: Instant,
+}
+impl LocalStore {
+    /// Create a store that lives only in memory.
+    pub fn volatile(local_peer_id: PeerId, limits: config::StoreLimits) -> Self {
+        Self {
+            inner: MemoryStore::with_config(local_peer_id, limits.into()),
+            journal: None,
+            last_sweep: Instant::now(),
+            dirty: false,
+            last_snapshot: Instant::now(),
+        }
+    }
+    /// Create a store backed by the file at the given path, restoring
+    /// records and provider entries written by previous runs.
+    /// Expired entries and entries beyond the limits are dropped.
+    pub fn persistent(
+        local_peer_id: PeerId,
+        limits: config::StoreLimits,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        let (journal, snapshot) = Journal::load(path.into());
+        let mut inner = MemoryStore::with_config(local_peer_id, limits.into());
+        let now = Instant::now();
+        let now_unix = unix_millis();
+        let mut dropped = 0usize;
+        for record in snapshot.records {
+            let Some(record) = record.restore(now, now_unix) else {
+                dropped += 1;
+                continue;
+            };
+            if inner.put(record).is_err() {
+                dropped += 1;
+            }
+        }
+        for provider in snapshot.providers {
+            let Some(provider) = provider.restore(now, now_unix) else {
+                dropped += 1;
+                continue;
+            };
+            if inner.add_provider(provider).is_err() {
+                dropped += 1;
+            }
+        }
+        let mut store = Self {
+            inner,
+            journal: Some(journal),
+            last_sweep: now,
+            dirty: false,
+            last_snapshot: now,
+        };
+        if dropped > 0 {
+            debug!(
+                "Dropped {} expired or excess entries from kad store",
+                dropped
+            );
+            store.mark_dirty();
+        }
+        store
+    }
+    /// Remove expired records and provider entries.
+    pub fn sweep_expired(&mut self) {
+        let now = Instant::now();
+        self.last_sweep = now;
+        let is_expired = |expires: Option<Instant>| expires.map(|t| t <= now).unwrap_or(false);
+        let expired_records = self
+            .inner
+            .records()
+            .filter(|r| is_expired(r.expires))
+            .map(|r| r.key.clone())
+            .collect::<Vec<_>>();
+        // Providers of all keys can't be listed through `RecordStore`,
+        // so only keys that are known locally are checked.
+        let mut keys = self
+            .inner
+            .provided()
+            .map(|p| p.key.clone())
+            .collect::<Vec<_>>();
+        if let Some(journal) = self.journal.as_ref() {
+            keys.extend(journal.provider_keys.iter().cloned().map(RecordKey::from));
+        }
+        keys.sort_unstable_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        keys.dedup();
+        let expired_providers = keys
+            .iter()
+            .flat_map(|key| self.inner.providers(key))
+            .filter(|p| is_expired(p.expires))
+            .map(|p| (p.key, p.provider))
+            .collect::<Vec<_>>();
+        if expired_records.is_empty() && expired_providers.is_empty() {
+            return;
+        }
+        trace!(
+            "Sweeping {} records and {} provider entries from kad store",
+            expired_records.len(),
+            expired_providers.len()
+        );
+        for key in expired_records.iter() {
+            self.inner.remove(key);
+        }
+        for (key, provider) in expired_providers.iter() {
+            self.inner.remove_provider(key, provider);
+        }
+        self.mark_dirty();
+    }
+    /// Sweep expired entries and write changes to disk if they are due.
+    /// Called by the swarm on every turn of its event loop, so it must stay cheap.
+    pub fn tick(&mut self) {
+        self.maybe_sweep();
+        if self.dirty && self.last_snapshot.elapsed() >= SNAPSHOT_INTERVAL {
+            self.snapshot();
+        }
+    }
+    fn maybe_sweep(&mut self) {
+        if self.last_sweep.elapsed() >= SWEEP_INTERVAL {
+            self.sweep_expired();
+        }
+    }
+    fn mark_dirty(&mut self) {
+        self.dirty = self.journal.is_some();
+    }
+    /// Write changes since the last snapshot to disk and wait for the write.
+    /// Called when the swarm shuts down, as snapshots are only taken on an interval.
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        if let Some(snapshot) = self.take_snapshot() {
+            let journal = self.journal.as_ref().expect("Snapshot taken from journal");
+            journal.writer.flush(snapshot);
+        }
+    }
+    /// Hand the current content to the journal, if any.
+    /// Serialization and writing happen on the journal's thread.
+    fn snapshot(&mut self) {
+        if let Some(snapshot) = self.take_snapshot() {
+            let journal = self.journal.as_ref().expect("Snapshot taken from journal");
+            journal.writer.save(snapshot);
+        }
+    }
+    /// Current content as written to disk, `None` if the store is volatile.
+    fn take_snapshot(&mut self) -> Option<Snapshot> {
+        self.dirty = false;
+        self.last_snapshot = Instant::now();
+        let journal = self.journal.as_mut()?;
+        let now = Instant::now();
+        let now_unix = unix_millis();
+        let records = self
+            .inner
+            .records()
+            .map(|r| PersistedRecord::new(&r, now, now_unix))
+            .collect();
+        let providers = journal
+            .provider_keys
+            .iter()
+            .flat_map(|key| self.inner.providers(&RecordKey::from(key.clone())))
+            .map(|p| PersistedProvider::new(&p, now, now_unix))
+            .collect::<Vec<PersistedProvider>>();
+        journal.provider_keys = providers.iter().map(|p| p.key.clone()).collect();
+        Some(Snapshot { records, providers })
+    }
+}
+impl Drop for LocalStore {
+    fn drop(&mut self) {
+        // Don't lose changes made since the last snapshot.
+        if self.dirty {
+            self.snapshot();
+        }
+    }
+}
+impl RecordStore for LocalStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.inner.get(k)
+    }
+    fn put(&mut self, r: Record) -> Result<(), StoreError> {
+        self.maybe_sweep();
+        self.inner.put(r)?;
+        self.mark_dirty();
+        Ok(())
+    }
+    fn remove(&mut self, k: &RecordKey) {
+        if self.inner.get(k).is_none() {
+            return;
+        }
+        self.inner.remove(k);
+        self.mark_dirty();
+    }
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.inner.records()
+    }
+    fn add_provider(&mut self, record: ProviderRecord) -> Result<(), StoreError> {
+        self.maybe_sweep();
+        let key = record.key.clone();
+        self.inner.add_provider(record)?;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.provider_keys.insert(key.to_vec());
+        }
+        self.mark_dirty();
+        Ok(())
+    }
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        self.inner.providers(key)
+    }
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.inner.provided()
+    }
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        if !self.inner.providers(k).iter().any(|v| v.provider == *p) {
+            return;
+        }
+        self.inner.remove_provider(k, p);
+        self.mark_dirty();
+    }
+}
+
+/// A record as written to disk.
+/// Expiry is unix time in milliseconds, as `Instant` can't outlive the process.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PersistedRecord {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    publisher: Option<PeerId>,
+    expires_at: Option<u64>,
+}
+impl PersistedRecord {
+    fn new(record: &Record, now: Instant, now_unix: u64) -> Self {
+        Self {
+            key: record.key.to_vec(),
+            value: record.value.clone(),
+            publisher: record.publisher,
+            expires_at: record.expires.map(|t| to_unix(t, now, now_unix)),
+        }
+    }
+    fn restore(self, now: Instant, now_unix: u64) -> Option<Record> {
+        let expires = match self.expires_at {
+            Some(at) => Some(from_unix(at, now, now_unix)?),
+            None => None,
+        };
+        Some(Record {
+            key: RecordKey::from(self.key),
+            value: self.value,
+            publisher: self.publisher,
+            expires,
+        })
+    }
+}
+
+/// A provider entry as written to disk, see [`PersistedRecord`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PersistedProvider {
+    key: Vec<u8>,
+    provider: PeerId,
+    addresses: Vec<Multiaddr>,
+    expires_at: Option<u64>,
+}
+impl PersistedProvider {
+    fn new(record: &ProviderRecord, now: Instant, now_unix: u64) -> Self {
+        Self {
+            key: record.key.to_vec(),
+            provider: record.provider,
+            addresses: record.addresses.clone(),
+            expires_at: record.expires.map(|t| to_unix(t, now, now_unix)),
+        }
+    }
+    fn restore(self, now: Instant, now_unix: u64) -> Option<ProviderRecord> {
+        let expires = match self.expires_at {
+            Some(at) => Some(from_unix(at, now, now_unix)?),
+            None => None,
+        };
+        Some(ProviderRecord {
+            key: RecordKey::from(self.key),
+            provider: self.provider,
+            expires,
+            addresses: self.addresses,
+        })
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    records: Vec<PersistedRecord>,
+    providers: Vec<PersistedProvider>,
+}
+
+/// Writes snapshots of the store to disk on a dedicated thread.
+struct Journal {
+    writer: SnapshotWriter<Snapshot>,
+    /// Keys that have provider entries, as `MemoryStore` can only list
+    /// those provided by the local peer.
+    provider_keys: HashSet<Vec<u8>>,
+}
+impl Journal {
+    /// Load the last snapshot at the given path, empty if the file
+    /// doesn't exist or cannot be parsed.
+    fn load(path: PathBuf) -> (Self, Snapshot) {
+        let snapshot: Snapshot = snapshot::load(&path, "kad store").unwrap_or_default();
+        let provider_keys = snapshot.providers.iter().map(|p| p.key.clone()).collect();
+        (
+            Self {
+                writer: SnapshotWriter::spawn(path, "kad store"),
+                provider_keys,
+            },
+            snapshot,
+        )
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn to_unix(instant: Instant, now: Instant, now_unix: u64) -> u64 {
+    now_unix + instant.saturating_duration_since(now).as_millis() as u64
+}
+
+/// `None` if the time has already passed.
+fn from_unix(unix_ms: u64, now: Instant, now_unix: u64) -> Option<Instant> {
+    if unix_ms <= now_unix {
+        return None;
+    }
+    Some(now + Duration::from_millis(unix_ms - now_unix))
+}
+
+impl From<config::StoreLimits> for MemoryStoreConfig {
+    fn from(value: config::StoreLimits) -> Self {
+        MemoryStoreConfig {
+            max_records: value.max_records,
+            max_value_bytes: value.max_value_bytes,
+            max_providers_per_key: value.max_providers_per_key,
+            max_provided_keys: value.max_provided_keys,
+        }
+    }
+}
@@ -7,6 +7,11 @@ use store::MemMessageStore;
 
 pub use owlnest_messaging::*;
 
+/// Closed group conversations on top of messaging.
+pub mod group;
+/// Remote procedure calls on top of messaging.
+pub mod rpc;
+
 type MessageStore = Box<dyn store::MessageStore + 'static + Send + Sync>;
 
 /// A handle that can communicate with the behaviour within the swarm.
@@ -16,12 +21,14 @@ pub struct Handle {
     #[allow(unused)]
     swarm_event_source: EventSender,
     message_store: Arc<MessageStore>,
+    rpc: rpc::Rpc,
+    groups: group::Groups,
     #[allow(unused)]
     counter: Arc<AtomicU64>,
 }
 impl Handle {
     pub(crate) fn new(
-        _config: &Config,
+        config: &Config,
         buffer_size: usize,
         swarm_event_source: &EventSender,
     ) -> (Self, mpsc::Receiver<InEvent>) {
@@ -30,15 +37,29 @@ impl Handle {
             as Box<dyn store::MessageStore + 'static + Send + Sync>);
         let mut listener = swarm_event_source.subscribe();
         let store = message_store.clone();
+        let rpc = rpc::Rpc::new(tx.clone(), config.max_concurrent_rpc);
+        let rpc_clone = rpc.clone();
+        let groups = group::Groups::new(tx.clone(), message_store.clone(), buffer_size);
+        let groups_clone = groups.clone();
         tokio::spawn(async move {
             while let Ok(ev) = listener.recv().await {
                 if let SwarmEvent::Behaviour(BehaviourEvent::Messaging(ev)) = ev.as_ref() {
-                    if let OutEvent::IncomingMessage { from, msg } = ev {
-                        store::MessageStore::push_message(
-                            store.as_ref().as_ref(),
-                            from,
-                            msg.clone(),
-                        );
+                    match ev {
+                        OutEvent::IncomingMessage { from, msg } => {
+                            store::MessageStore::push_message(
+                                store.as_ref().as_ref(),
+                                from,
+                                msg.clone(),
+                            );
+                        }
+                        OutEvent::IncomingRpc { from, packet } => {
+                            rpc_clone.on_packet(*from, packet.clone())
+                        }
+                        OutEvent::IncomingGroupPacket { from, packet } => {
+                            groups_clone.on_packet(*from, packet.clone())
+                        }
+                        OutEvent::OutboundNegotiated(peer) => groups_clone.on_connected(*peer),
+                        _ => {}
                     }
                 }
             }
@@ -48,6 +69,8 @@ impl Handle {
                 sender: tx,
                 swarm_event_source: swarm_event_source.clone(),
                 message_store,
+                rpc,
+                groups,
                 counter: Arc::new(AtomicU64::new(0)),
             },
             rx,
@@ -55,6 +78,7 @@ impl Handle {
     }
     /// Send a message to the target peer.  
     /// Will return the time taken between sending and acknowledgement.
+    /// The message will be recorded in the message store once acknowledged.
     /// If the peer isn't connected, an error will be returned.
     pub async fn send_message(
         &self,
@@ -64,16 +88,32 @@ impl Handle {
         let (tx, rx) = oneshot::channel();
         let ev = InEvent::SendMessage {
             peer: peer_id,
-            message,
+            message: message.clone(),
             callback: tx,
         };
         send_swarm!(self.sender, ev);
-        handle_callback!(rx)
+        let result = handle_callback!(rx);
+        if result.is_ok() {
+            store::MessageStore::push_message(
+                self.message_store.as_ref().as_ref(),
+                &peer_id,
+                message,
+            );
+        }
+        result
     }
     generate_handler_method!(
         /// List all peers that is connected and supports this protocol.
         ListConnected:list_connected()->Box<[PeerId]>;
     );
+    /// Get a reference to the RPC facility built on top of this protocol.
+    pub fn rpc(&self) -> &rpc::Rpc {
+        &self.rpc
+    }
+    /// Get a reference to group conversations of local peer.
+    pub fn groups(&self) -> &group::Groups {
+        &self.groups
+    }
     /// Get a reference to the internal message store.
     pub fn message_store(&self) -> &MessageStore {
         &self.message_store
@@ -87,7 +127,9 @@ impl Handle {
 pub mod cli {
     use clap::Subcommand;
     use libp2p::PeerId;
+    use prettytable::{row, Table};
 
+    use super::store::{MessageQuery, MessageStore};
     use super::{Handle, Message, OutEvent};
     use crate::net::p2p::identity::IdentityUnion;
     use crate::net::p2p::swarm;
@@ -108,17 +150,99 @@ pub mod cli {
             #[arg(required = true)]
             message: String,
         },
+        /// Show message history with the given peer, oldest first.
+        History {
+            /// The peer whose history to show.
+            #[arg(required = true)]
+            peer_id: PeerId,
+            /// Show at most this many of the latest messages.
+            #[arg(long)]
+            limit: Option<usize>,
+            /// Only show messages sent at or after this time.
+            /// Accepts RFC 3339 time(e.g. `2024-01-01T00:00:00+08:00`)
+            /// or milliseconds since UNIX epoch.
+            #[arg(long, value_parser = parse_time)]
+            since: Option<u128>,
+            /// Print messages as JSON.
+            #[arg(long)]
+            json: bool,
+        },
+        /// List all peers that has a message history, along with the latest message.
+        Conversations,
+        /// Search all message history for the given text.
+        Search {
+            /// The text to search for.
+            #[arg(required = true)]
+            text: String,
+        },
+        /// Clear message history of the given peer.
+        /// Will clear history of all peers if no peer is supplied.
+        Clear {
+            /// The peer whose history to clear.
+            peer_id: Option<PeerId>,
+        },
+        /// Export message history of the given peer to a file, in JSON.
+        Export {
+            /// The peer whose history to export.
+            #[arg(required = true)]
+            peer_id: PeerId,
+            /// Path to the file to write to.
+            /// Overwriting existing file is not allowed.
+            #[arg(required = true)]
+            file: String,
+        },
+    }
+
+    fn parse_time(s: &str) -> Result<u128, String> {
+        if let Ok(millis) = s.parse::<u128>() {
+            return Ok(millis);
+        }
+        chrono::DateTime::parse_from_rfc3339(s)
+            .map(|time| time.timestamp_millis().max(0) as u128)
+            .map_err(|e| format!("Expecting RFC 3339 time or milliseconds since UNIX epoch: {e}"))
+    }
+
+    fn format_time(millis: u128) -> String {
+        chrono::DateTime::from_timestamp_millis(millis as i64)
+            .map(|time| {
+                time.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_else(|| millis.to_string())
+    }
+
+    fn print_messages(messages: &[Message]) {
+        let mut table = Table::new();
+        table.add_row(row!["Time", "From", "Message"]);
+        messages.iter().for_each(|msg| {
+            table.add_row(row![format_time(msg.time), msg.from, msg.msg]);
+        });
+        table.printstd();
     }
 
     pub fn setup(manager: &Manager) {
         let mut listener = manager.event_subscriber().subscribe();
         manager.executor().spawn(async move {
             while let Ok(ev) = listener.recv().await {
-                if let swarm::SwarmEvent::Behaviour(BehaviourEvent::Messaging(
-                    OutEvent::IncomingMessage { from, msg },
-                )) = ev.as_ref()
-                {
-                    println!("Incoming message from {from}: {}", msg.msg)
+                match ev.as_ref() {
+                    swarm::SwarmEvent::Behaviour(BehaviourEvent::Messaging(
+                        OutEvent::IncomingMessage { from, msg },
+                    )) => println!("Incoming message from {from}: {}", msg.msg),
+                    swarm::SwarmEvent::Behaviour(BehaviourEvent::Messaging(
+                        OutEvent::RateLimited {
+                            peer,
+                            violations,
+                            disconnected,
+                        },
+                    )) => {
+                        if *disconnected {
+                            println!(
+                                "Peer {peer} disconnected after {violations} rate limit violations"
+                            )
+                        }
+                    }
+                    _ => {}
                 }
             }
         });
@@ -126,6 +250,7 @@ pub mod cli {
 
     pub async fn handle_messaging(handle: &Handle, ident: &IdentityUnion, command: Messaging) {
         use Messaging::*;
+        let store = handle.message_store().as_ref();
         match command {
             Send { peer_id, message } => {
                 let msg = Message::new(ident.get_peer_id(), peer_id, message);
@@ -135,6 +260,81 @@ pub mod cli {
                     Err(e) => println!("Error occurred when sending message: {e}"),
                 }
             }
+            History {
+                peer_id,
+                limit,
+                since,
+                json,
+            } => {
+                let query = MessageQuery {
+                    since,
+                    limit,
+                    ..Default::default()
+                };
+                let messages = store.query_messages(&peer_id, &query);
+                if json {
+                    match serde_json::to_string_pretty(&messages) {
+                        Ok(v) => println!("{v}"),
+                        Err(e) => println!("Failed to serialize messages: {e}"),
+                    }
+                    return;
+                }
+                if messages.is_empty() {
+                    println!("No message history with peer {peer_id}");
+                    return;
+                }
+                print_messages(&messages)
+            }
+            Conversations => {
+                let conversations = store.list_conversations();
+                let mut table = Table::new();
+                table.add_row(row!["Peer ID", "Messages", "Last Active", "Last Message"]);
+                conversations.iter().for_each(|conversation| {
+                    let (time, text) = conversation
+                        .last_message
+                        .as_ref()
+                        .map(|msg| (format_time(msg.time), msg.msg.clone()))
+                        .unwrap_or_default();
+                    table.add_row(row![
+                        conversation.peer,
+                        conversation.message_count,
+                        time,
+                        text
+                    ]);
+                });
+                table.printstd();
+            }
+            Search { text } => {
+                let messages = store.search_messages(&text);
+                if messages.is_empty() {
+                    println!(r#"No message contains "{text}""#);
+                    return;
+                }
+                print_messages(&messages)
+            }
+            Clear { peer_id } => {
+                store.clear_message(peer_id.as_ref());
+                match peer_id {
+                    Some(peer_id) => println!("Message history with peer {peer_id} cleared"),
+                    None => println!("All message history cleared"),
+                }
+            }
+            Export { peer_id, file } => {
+                let messages = store.get_messages(&peer_id).unwrap_or_default();
+                let bytes = match serde_json::to_vec_pretty(&messages) {
+                    Ok(v) => v,
+                    Err(e) => return println!("Failed to serialize messages: {e}"),
+                };
+                let result = std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&file)
+                    .and_then(|mut f| std::io::Write::write_all(&mut f, &bytes));
+                match result {
+                    Ok(_) => println!("Exported {} messages to {file}", messages.len()),
+                    Err(e) => println!("Failed to write to {file}: {e}"),
+                }
+            }
         }
     }
 }
@@ -143,8 +343,34 @@ pub mod cli {
 pub mod store {
     use dashmap::DashMap;
     use libp2p::PeerId;
+    use owlnest_messaging::group::GroupId;
     use owlnest_messaging::Message;
 
+    /// Filter and pagination for querying message history.  
+    /// Timestamps are in milliseconds since UNIX epoch, same as `Message::time`.
+    #[derive(Debug, Clone, Default)]
+    pub struct MessageQuery {
+        /// Only include messages sent at or after this time.
+        pub since: Option<u128>,
+        /// Only include messages sent before this time.
+        pub until: Option<u128>,
+        /// Skip this many of the newest matching messages.
+        pub offset: usize,
+        /// Return at most this many messages.
+        pub limit: Option<usize>,
+    }
+
+    /// Summary of the conversation with a peer.
+    #[derive(Debug, Clone)]
+    pub struct Conversation {
+        /// The remote peer.
+        pub peer: PeerId,
+        /// Number of messages in the history.
+        pub message_count: usize,
+        /// The latest message, if any.
+        pub last_message: Option<Message>,
+    }
+
     /// The trait a message store need to implement.
     /// Currently the trait is modeled after volatile store.
     pub trait MessageStore {
@@ -152,24 +378,41 @@ pub mod store {
         fn insert_empty_record(&self, peer_id: &PeerId);
         /// Get all message history of a peer.
         fn get_messages(&self, peer_id: &PeerId) -> Option<Box<[Message]>>;
+        /// Get message history of a peer that matches the query, oldest first.  
+        /// Pagination starts from the newest message, so `limit` alone returns
+        /// the latest messages.
+        fn query_messages(&self, peer_id: &PeerId, query: &MessageQuery) -> Box<[Message]>;
+        /// Find all messages whose text contains the given string.
+        fn search_messages(&self, text: &str) -> Box<[Message]>;
         /// Append a message to the history of the given peer.
         fn push_message(&self, remote: &PeerId, message: Message);
         /// Get all peers that has a record in the store
         fn list_all_peers(&self) -> Box<[PeerId]>;
+        /// Get a summary of conversations with all peers that has a record in the store.
+        fn list_conversations(&self) -> Box<[Conversation]>;
         /// Clear the message history of the given peer permanently,
         /// but peer records will be retained.
         /// Will clear all history if not supplied with a peer ID.
         fn clear_message(&self, peer_id: Option<&PeerId>);
-        /// Empty the message store, including peer records
+        /// Append a message to the history of the given group.
+        fn push_group_message(&self, group_id: &GroupId, message: Message);
+        /// Get all message history of a group.
+        fn get_group_messages(&self, group_id: &GroupId) -> Option<Box<[Message]>>;
+        /// Clear the message history of the given group permanently.
+        /// Will clear history of all groups if not supplied with a group ID.
+        fn clear_group_messages(&self, group_id: Option<&GroupId>);
+        /// Empty the message store, including peer records and group history
         fn empty_store(&self);
     }
 
     /// In-memory volatile message store.
     /// All records will be lost permanently once the store is dropped
-    /// e.g. the peer is shutdown or sudden power loss.
+    /// e.g. the peer is shutdown or sudden power loss.  
+    /// Messages of each peer are kept sorted by time.
     #[derive(Debug, Clone, Default)]
     pub struct MemMessageStore {
         store: DashMap<PeerId, Vec<Message>>,
+        groups: DashMap<GroupId, Vec<Message>>,
     }
     impl MessageStore for MemMessageStore {
         fn insert_empty_record(&self, peer_id: &PeerId) {
@@ -182,18 +425,64 @@ pub mod store {
                 .get(peer_id)
                 .map(|v| v.value().clone().into_boxed_slice())
         }
-        fn push_message(&self, remote: &PeerId, message: Message) {
-            if self.store.get(remote).is_none() {
-                self.store.insert(*remote, vec![message]);
-                return;
-            }
-            if let Some(mut entry) = self.store.get_mut(remote) {
-                entry.value_mut().push(message)
+        fn query_messages(&self, peer_id: &PeerId, query: &MessageQuery) -> Box<[Message]> {
+            let entry = match self.store.get(peer_id) {
+                Some(entry) => entry,
+                None => return Box::new([]),
             };
+            let messages = entry.value();
+            let start = query
+                .since
+                .map(|since| messages.partition_point(|msg| msg.time < since))
+                .unwrap_or(0);
+            let end = query
+                .until
+                .map(|until| messages.partition_point(|msg| msg.time < until))
+                .unwrap_or(messages.len())
+                .max(start);
+            let end = end.saturating_sub(query.offset).max(start);
+            let start = query
+                .limit
+                .map(|limit| end.saturating_sub(limit).max(start))
+                .unwrap_or(start);
+            messages[start..end].into()
+        }
+        fn search_messages(&self, text: &str) -> Box<[Message]> {
+            let mut result = self
+                .store
+                .iter()
+                .flat_map(|entry| {
+                    entry
+                        .value()
+                        .iter()
+                        .filter(|msg| msg.msg.contains(text))
+                        .cloned()
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            result.sort_by_key(|msg| msg.time);
+            result.into_boxed_slice()
+        }
+        fn push_message(&self, remote: &PeerId, message: Message) {
+            let mut entry = self.store.entry(*remote).or_default();
+            let messages = entry.value_mut();
+            // Usually an append, unless clocks of the two peers disagree.
+            let index = messages.partition_point(|msg| msg.time <= message.time);
+            messages.insert(index, message);
         }
         fn list_all_peers(&self) -> Box<[PeerId]> {
             self.store.iter().map(|entry| *entry.key()).collect()
         }
+        fn list_conversations(&self) -> Box<[Conversation]> {
+            self.store
+                .iter()
+                .map(|entry| Conversation {
+                    peer: *entry.key(),
+                    message_count: entry.value().len(),
+                    last_message: entry.value().last().cloned(),
+                })
+                .collect()
+        }
         fn clear_message(&self, peer_id: Option<&PeerId>) {
             if peer_id.is_none() {
                 self.store
@@ -210,8 +499,28 @@ pub mod store {
                 entry.value_mut().shrink_to_fit()
             };
         }
+        fn push_group_message(&self, group_id: &GroupId, message: Message) {
+            let mut entry = self.groups.entry(*group_id).or_default();
+            let messages = entry.value_mut();
+            let index = messages.partition_point(|msg| msg.time <= message.time);
+            messages.insert(index, message);
+        }
+        fn get_group_messages(&self, group_id: &GroupId) -> Option<Box<[Message]>> {
+            self.groups
+                .get(group_id)
+                .map(|v| v.value().clone().into_boxed_slice())
+        }
+        fn clear_group_messages(&self, group_id: Option<&GroupId>) {
+            match group_id {
+                Some(group_id) => {
+                    self.groups.remove(group_id);
+                }
+                None => self.groups.clear(),
+            }
+        }
         fn empty_store(&self) {
             self.store.clear();
+            self.groups.clear();
         }
     }
 }
@@ -252,6 +561,348 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn test_chunked_send_recv() -> anyhow::Result<()> {
+        let (peer1, _) = setup_default();
+        let (peer2, _) = setup_default();
+        peer1
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        let mut peer1_message_watcher = spawn_watcher(&peer1);
+        sleep(Duration::from_millis(100));
+        peer2
+            .swarm()
+            .dial_blocking(&peer1.swarm().list_listeners_blocking()[0])?;
+        let peer1_id = peer1.identity().get_peer_id();
+        let peer2_id = peer2.identity().get_peer_id();
+        sleep(Duration::from_millis(1000));
+        // Larger than the default limit of a single frame
+        let text = "Chunked MESSAGE 分块信息。".repeat(16 * 1024);
+        peer2
+            .executor()
+            .block_on(
+                peer2
+                    .messaging()
+                    .send_message(peer1_id, Message::new(peer2_id, peer1_id, &text)),
+            )
+            .unwrap();
+        let (from, message_received) = peer1_message_watcher.blocking_recv().unwrap();
+        assert!(from == peer2_id && message_received.msg == text);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_rate_limited() -> anyhow::Result<()> {
+        use crate::net::p2p::{test_suit::setup_with_config, SwarmConfig};
+        let limited = |max_delay_ms| {
+            let mut config = SwarmConfig::default();
+            config.messaging = Config::default().with_rate_limit(RateLimit {
+                messages_per_sec: 10,
+                message_burst: 5,
+                max_delay_ms,
+                ..Default::default()
+            });
+            setup_with_config(config).0
+        };
+        // peer1 holds back messages over the limit, peer3 rejects them right away
+        let peer1 = limited(1000);
+        let (peer2, _) = setup_default();
+        let peer3 = limited(0);
+        for peer in [&peer1, &peer3] {
+            peer.swarm()
+                .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+            sleep(Duration::from_millis(100));
+            peer2
+                .swarm()
+                .dial_blocking(&peer.swarm().list_listeners_blocking()[0])?;
+        }
+        let peer1_id = peer1.identity().get_peer_id();
+        let peer2_id = peer2.identity().get_peer_id();
+        let peer3_id = peer3.identity().get_peer_id();
+        sleep(Duration::from_millis(1000));
+        let send = |to, i| {
+            peer2.executor().block_on(
+                peer2
+                    .messaging()
+                    .send_message(to, Message::new(peer2_id, to, i)),
+            )
+        };
+        // Messages over the burst are throttled but still accepted
+        let started = std::time::Instant::now();
+        for i in 0..15 {
+            assert!(send(peer1_id, i).is_ok());
+        }
+        assert!(started.elapsed() >= Duration::from_millis(500));
+        let rejected = (0..15)
+            .map(|i| send(peer3_id, i))
+            .filter(|result| matches!(result, Err(error::SendError::Rejected)))
+            .count();
+        assert!(rejected > 0);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_rpc() -> anyhow::Result<()> {
+        use owlnest_messaging::rpc::RpcError;
+        let (peer1, _) = setup_default();
+        let (peer2, _) = setup_default();
+        peer1
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep(Duration::from_millis(100));
+        peer2
+            .swarm()
+            .dial_blocking(&peer1.swarm().list_listeners_blocking()[0])?;
+        let peer1_id = peer1.identity().get_peer_id();
+        sleep(Duration::from_millis(1000));
+        let rpc1 = peer1.messaging().rpc();
+        assert!(rpc1.register("echo", |_, payload| async move { Ok(payload) }));
+        assert!(rpc1.register_typed("add", |_, (a, b): (u64, u64)| async move { Ok(a + b) }));
+        assert!(rpc1.register("fail", |_, _| async move { Err("failed".to_string()) }));
+        assert!(rpc1.register("slow", |_, payload| async move {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(payload)
+        }));
+        let rpc2 = peer2.messaging().rpc();
+        let timeout = Duration::from_secs(5);
+        peer2.executor().block_on(async {
+            assert_eq!(
+                rpc2.call(peer1_id, "echo", b"hello".to_vec(), timeout)
+                    .await
+                    .unwrap(),
+                b"hello"
+            );
+            assert_eq!(
+                rpc2.call_typed::<_, u64>(peer1_id, "add", &(1u64, 2u64), timeout)
+                    .await
+                    .unwrap(),
+                3
+            );
+            assert!(matches!(
+                rpc2.call(peer1_id, "missing", vec![], timeout).await,
+                Err(RpcError::MethodNotFound(_))
+            ));
+            assert!(matches!(
+                rpc2.call(peer1_id, "fail", vec![], timeout).await,
+                Err(RpcError::Failed(_))
+            ));
+            assert!(matches!(
+                rpc2.call(peer1_id, "slow", vec![], Duration::from_millis(200))
+                    .await,
+                Err(RpcError::Timeout)
+            ));
+        });
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_rpc_busy_and_cancel() -> anyhow::Result<()> {
+        use crate::net::p2p::{test_suit::setup_with_config, SwarmConfig};
+        use owlnest_messaging::rpc::RpcError;
+        use std::sync::atomic::AtomicBool;
+        let mut config = SwarmConfig::default();
+        config.messaging = Config::default().with_max_concurrent_rpc(1);
+        let (peer1, _) = setup_with_config(config);
+        let (peer2, _) = setup_default();
+        peer1
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep(Duration::from_millis(100));
+        peer2
+            .swarm()
+            .dial_blocking(&peer1.swarm().list_listeners_blocking()[0])?;
+        let peer1_id = peer1.identity().get_peer_id();
+        sleep(Duration::from_millis(1000));
+        let finished = Arc::new(AtomicBool::new(false));
+        let finished_clone = finished.clone();
+        let rpc1 = peer1.messaging().rpc();
+        assert!(rpc1.register("slow", move |_, payload| {
+            let finished = finished_clone.clone();
+            async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                finished.store(true, Ordering::SeqCst);
+                Ok(payload)
+            }
+        }));
+        let rpc2 = peer2.messaging().rpc();
+        let timeout = Duration::from_secs(5);
+        // Only one call is handled at a time
+        let (first, second) = peer2.executor().block_on(async {
+            let first = rpc2.call(peer1_id, "slow", vec![1], timeout);
+            let second = async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                rpc2.call(peer1_id, "slow", vec![2], timeout).await
+            };
+            tokio::join!(first, second)
+        });
+        assert_eq!(first.unwrap(), vec![1]);
+        assert!(matches!(second, Err(RpcError::Busy)));
+        // Giving up on a call cancels it on the remote
+        finished.store(false, Ordering::SeqCst);
+        assert!(matches!(
+            peer2.executor().block_on(rpc2.call(
+                peer1_id,
+                "slow",
+                vec![],
+                Duration::from_millis(200)
+            )),
+            Err(RpcError::Timeout)
+        ));
+        sleep(Duration::from_millis(1500));
+        assert!(!finished.load(Ordering::SeqCst));
+        // The slot is released once the call is cancelled
+        assert!(peer2
+            .executor()
+            .block_on(rpc2.call(peer1_id, "slow", vec![3], timeout))
+            .is_ok());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_group() -> anyhow::Result<()> {
+        use group::GroupEvent;
+        use store::MessageStore;
+        let (peer1, _) = setup_default();
+        let (peer2, _) = setup_default();
+        peer1
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep(Duration::from_millis(100));
+        peer2
+            .swarm()
+            .dial_blocking(&peer1.swarm().list_listeners_blocking()[0])?;
+        let peer1_id = peer1.identity().get_peer_id();
+        let peer2_id = peer2.identity().get_peer_id();
+        sleep(Duration::from_millis(1000));
+        let mut peer2_events = peer2.messaging().groups().subscribe();
+        let group = peer1
+            .messaging()
+            .groups()
+            .create_group(peer1_id, "test", [peer2_id]);
+        let next_event = |events: &mut tokio::sync::broadcast::Receiver<GroupEvent>| {
+            peer2.executor().block_on(async {
+                tokio::time::timeout(Duration::from_secs(5), events.recv())
+                    .await
+                    .unwrap()
+                    .unwrap()
+            })
+        };
+        assert!(
+            matches!(next_event(&mut peer2_events), GroupEvent::Joined(joined) if joined == group)
+        );
+        peer1
+            .messaging()
+            .groups()
+            .send_message(&group.id, Message::new(peer1_id, peer2_id, "Hello group"))?;
+        assert!(matches!(
+            next_event(&mut peer2_events),
+            GroupEvent::Message { group_id, message } if group_id == group.id && message.msg == "Hello group"
+        ));
+        let history = peer2
+            .messaging()
+            .message_store()
+            .get_group_messages(&group.id)
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        // Messages can't be sent on behalf of another member
+        assert!(peer2
+            .messaging()
+            .groups()
+            .send_message(&group.id, Message::new(peer1_id, peer2_id, "Spoofed"))
+            .is_err());
+        peer1
+            .messaging()
+            .groups()
+            .kick_member(&group.id, &peer2_id)?;
+        assert!(
+            matches!(next_event(&mut peer2_events), GroupEvent::Kicked(group_id) if group_id == group.id)
+        );
+        assert!(peer2.messaging().groups().get_group(&group.id).is_none());
+        // Kicked members can no longer send to the group
+        assert!(peer2
+            .messaging()
+            .groups()
+            .send_message(&group.id, Message::new(peer2_id, peer1_id, "Still here"))
+            .is_err());
+        // Nor are their packets accepted by remaining members, even if sent directly
+        let mut peer1_events = peer1.messaging().groups().subscribe();
+        let (tx, rx) = oneshot::channel();
+        let packet = group::Packet::Content {
+            group_id: group.id,
+            message: Message::new(peer2_id, peer1_id, "Still here"),
+        };
+        peer2
+            .executor()
+            .block_on(peer2.messaging().sender.send(InEvent::SendGroupPacket {
+                peer: peer1_id,
+                packet,
+                callback: tx,
+            }))?;
+        assert!(peer2.executor().block_on(rx)?.is_ok());
+        sleep(Duration::from_millis(500));
+        assert!(peer1_events.try_recv().is_err());
+        let history = peer1
+            .messaging()
+            .message_store()
+            .get_group_messages(&group.id)
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_query() {
+        use store::{MessageQuery, MessageStore};
+        let store = MemMessageStore::default();
+        let local = PeerId::random();
+        let remote = PeerId::random();
+        (0..10u128).for_each(|i| {
+            let mut message = Message::new(remote, local, format!("message {i}"));
+            message.time = i * 1000;
+            store.push_message(&remote, message);
+        });
+        let query = |query: MessageQuery| {
+            store
+                .query_messages(&remote, &query)
+                .iter()
+                .map(|msg| msg.time / 1000)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            query(MessageQuery {
+                limit: Some(3),
+                ..Default::default()
+            }),
+            vec![7, 8, 9]
+        );
+        assert_eq!(
+            query(MessageQuery {
+                since: Some(6000),
+                offset: 2,
+                ..Default::default()
+            }),
+            vec![6, 7]
+        );
+        assert_eq!(
+            query(MessageQuery {
+                since: Some(2000),
+                until: Some(5000),
+                limit: Some(10),
+                ..Default::default()
+            }),
+            vec![2, 3, 4]
+        );
+        assert_eq!(store.search_messages("message 4").len(), 1);
+        assert_eq!(store.list_conversations()[0].message_count, 10);
+        store.clear_message(Some(&remote));
+        assert!(query(MessageQuery::default()).is_empty());
+    }
+
     fn eq_message(lhs: &Message, rhs: &Message) -> bool {
         lhs.from == rhs.from && lhs.to == rhs.to && lhs.msg == rhs.msg
     }
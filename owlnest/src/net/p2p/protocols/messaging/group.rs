@@ -0,0 +1,462 @@
+use super::*;
+use dashmap::DashMap;
+use owlnest_messaging::group::{Group, GroupId, Packet};
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+/// Maximum number of packets kept for a single offline peer.
+/// Oldest packets will be dropped first.
+const MAX_PENDING_PACKETS: usize = 1024;
+/// Times to retry a packet that is rejected by or timed out on a connected peer.
+const MAX_RETRIES: u32 = 5;
+/// Delay before the first retry, doubled after every attempt.
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub enum GroupEvent {
+    /// Local peer has been added to a group.
+    Joined(Group),
+    /// Member list of a group has changed.
+    MembersChanged(Group),
+    /// Local peer has been removed from a group by its creator.
+    Kicked(GroupId),
+    /// A new message arrived in a group.
+    Message { group_id: GroupId, message: Message },
+}
+
+#[derive(Debug, Clone)]
+pub enum GroupError {
+    NotFound(GroupId),
+    /// Only the creator can change the member list.
+    NotCreator,
+    NotMember(PeerId),
+    /// The creator owns the member list and cannot leave the group.
+    CreatorCannotLeave,
+}
+impl std::fmt::Display for GroupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use GroupError::*;
+        match self {
+            NotFound(group_id) => write!(f, "Group {group_id} not found"),
+            NotCreator => f.write_str("Only the creator can change members of the group"),
+            NotMember(peer) => write!(f, "Peer {peer} is not a member of the group"),
+            CreatorCannotLeave => f.write_str("The creator cannot leave the group"),
+        }
+    }
+}
+impl std::error::Error for GroupError {}
+
+/// Work of the delivery task of a single peer.
+enum Outgoing {
+    Packet(Packet),
+    /// The peer is connected again, deliver packets kept while it's offline.
+    Flush,
+}
+
+#[derive(Debug, Clone)]
+struct GroupState {
+    group: Group,
+    /// Local peer as a member of the group.
+    local: PeerId,
+}
+impl GroupState {
+    fn is_creator(&self) -> bool {
+        self.group.creator == self.local
+    }
+    fn other_members(&self) -> Vec<PeerId> {
+        self.group
+            .members
+            .iter()
+            .filter(|peer| **peer != self.local)
+            .copied()
+            .collect()
+    }
+}
+
+/// Closed group conversations on top of messaging.
+/// Packets to a peer are delivered one at a time in the order they're sent.
+/// Packets to members that are not connected are kept
+/// and delivered once the member is connected again.
+#[derive(Clone)]
+pub struct Groups {
+    sender: mpsc::Sender<InEvent>,
+    message_store: Arc<MessageStore>,
+    /// Set once the swarm is built, joins are ignored before that.
+    local_peer_id: Arc<OnceLock<PeerId>>,
+    groups: Arc<DashMap<GroupId, GroupState>>,
+    /// Queues of the delivery tasks, indexed by remote peer.
+    outboxes: Arc<DashMap<PeerId, mpsc::UnboundedSender<Outgoing>>>,
+    /// Packets waiting for the peer to be connected.
+    pending_packets: Arc<DashMap<PeerId, VecDeque<Packet>>>,
+    event_sender: broadcast::Sender<GroupEvent>,
+}
+impl Groups {
+    pub(crate) fn new(
+        sender: mpsc::Sender<InEvent>,
+        message_store: Arc<MessageStore>,
+        buffer_size: usize,
+    ) -> Self {
+        Self {
+            sender,
+            message_store,
+            local_peer_id: Default::default(),
+            groups: Default::default(),
+            outboxes: Default::default(),
+            pending_packets: Default::default(),
+            event_sender: broadcast::channel(buffer_size.max(1)).0,
+        }
+    }
+    pub(crate) fn set_local_peer_id(&self, peer: PeerId) {
+        let _ = self.local_peer_id.set(peer);
+    }
+    /// Subscribe to events of all groups local peer is a member of.
+    pub fn subscribe(&self) -> broadcast::Receiver<GroupEvent> {
+        self.event_sender.subscribe()
+    }
+    /// Create a group with local peer as the creator.
+    /// All members will be informed, the creator is always a member.
+    pub fn create_group(
+        &self,
+        creator: PeerId,
+        name: impl Into<String>,
+        members: impl IntoIterator<Item = PeerId>,
+    ) -> Group {
+        let mut members = members
+            .into_iter()
+            .collect::<std::collections::BTreeSet<_>>();
+        members.insert(creator);
+        let group = Group {
+            id: GroupId(rand::random()),
+            name: name.into(),
+            creator,
+            members,
+        };
+        let state = GroupState {
+            group: group.clone(),
+            local: creator,
+        };
+        for member in state.other_members() {
+            self.deliver(
+                member,
+                Packet::Join {
+                    group: group.clone(),
+                    member,
+                },
+            );
+        }
+        self.groups.insert(group.id, state);
+        group
+    }
+    /// Add a member to a group created by local peer.
+    pub fn add_member(&self, group_id: &GroupId, member: PeerId) -> Result<Group, GroupError> {
+        let state = {
+            let mut entry = self
+                .groups
+                .get_mut(group_id)
+                .ok_or(GroupError::NotFound(*group_id))?;
+            if !entry.is_creator() {
+                return Err(GroupError::NotCreator);
+            }
+            entry.group.members.insert(member);
+            entry.value().clone()
+        };
+        self.deliver(
+            member,
+            Packet::Join {
+                group: state.group.clone(),
+                member,
+            },
+        );
+        self.broadcast_members(&state, Some(&member));
+        Ok(state.group)
+    }
+    /// Remove a member from a group created by local peer.
+    pub fn kick_member(&self, group_id: &GroupId, member: &PeerId) -> Result<Group, GroupError> {
+        let state = {
+            let mut entry = self
+                .groups
+                .get_mut(group_id)
+                .ok_or(GroupError::NotFound(*group_id))?;
+            if !entry.is_creator() {
+                return Err(GroupError::NotCreator);
+            }
+            if *member == entry.local || !entry.group.members.remove(member) {
+                return Err(GroupError::NotMember(*member));
+            }
+            entry.value().clone()
+        };
+        self.deliver(
+            *member,
+            Packet::Kick {
+                group_id: *group_id,
+            },
+        );
+        self.broadcast_members(&state, None);
+        Ok(state.group)
+    }
+    /// Leave a group local peer is a member of.
+    pub fn leave_group(&self, group_id: &GroupId) -> Result<(), GroupError> {
+        let state = self
+            .groups
+            .get(group_id)
+            .map(|entry| entry.value().clone())
+            .ok_or(GroupError::NotFound(*group_id))?;
+        if state.is_creator() {
+            return Err(GroupError::CreatorCannotLeave);
+        }
+        self.groups.remove(group_id);
+        for member in state.other_members() {
+            self.deliver(
+                member,
+                Packet::Leave {
+                    group_id: *group_id,
+                },
+            );
+        }
+        Ok(())
+    }
+    /// Send a message to all other members of the group.
+    /// The message will be recorded in the group history right away,
+    /// delivery to members that are not connected will be deferred.
+    pub fn send_message(&self, group_id: &GroupId, message: Message) -> Result<(), GroupError> {
+        let state = self
+            .groups
+            .get(group_id)
+            .map(|entry| entry.value().clone())
+            .ok_or(GroupError::NotFound(*group_id))?;
+        if message.from != state.local {
+            return Err(GroupError::NotMember(message.from));
+        }
+        for member in state.other_members() {
+            self.deliver(
+                member,
+                Packet::Content {
+                    group_id: *group_id,
+                    message: message.clone(),
+                },
+            );
+        }
+        store::MessageStore::push_group_message(
+            self.message_store.as_ref().as_ref(),
+            group_id,
+            message,
+        );
+        Ok(())
+    }
+    /// Get the group with the given ID, if local peer is a member.
+    pub fn get_group(&self, group_id: &GroupId) -> Option<Group> {
+        self.groups.get(group_id).map(|entry| entry.group.clone())
+    }
+    /// List all groups local peer is a member of.
+    pub fn list_groups(&self) -> Box<[Group]> {
+        self.groups
+            .iter()
+            .map(|entry| entry.group.clone())
+            .collect()
+    }
+    /// Number of packets waiting for the peer to be connected.
+    pub fn pending_packets(&self, peer: &PeerId) -> usize {
+        self.pending_packets
+            .get(peer)
+            .map(|entry| entry.len())
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn on_packet(&self, from: PeerId, packet: Packet) {
+        let group_id = packet.group_id();
+        match packet {
+            Packet::Join { group, member } => {
+                let local = match self.local_peer_id.get() {
+                    Some(peer) => *peer,
+                    None => return,
+                };
+                // The member list comes from the creator, but who local peer is doesn't.
+                if group.creator != from || member != local || !group.is_member(&local) {
+                    warn!("Ignoring invalid join of group {} from {}", group_id, from);
+                    return;
+                }
+                if let Some(state) = self.groups.get(&group_id) {
+                    // Group IDs are random, reject attempts to hijack a known group.
+                    if state.group.creator != from {
+                        return;
+                    }
+                }
+                self.groups.insert(
+                    group_id,
+                    GroupState {
+                        group: group.clone(),
+                        local,
+                    },
+                );
+                self.emit(GroupEvent::Joined(group))
+            }
+            Packet::Members { group } => {
+                let mut entry = match self.groups.get_mut(&group_id) {
+                    Some(entry) if entry.group.creator == from && group.creator == from => entry,
+                    _ => return,
+                };
+                if !group.is_member(&entry.local) {
+                    drop(entry);
+                    self.groups.remove(&group_id);
+                    return self.emit(GroupEvent::Kicked(group_id));
+                }
+                entry.group = group.clone();
+                drop(entry);
+                self.emit(GroupEvent::MembersChanged(group))
+            }
+            Packet::Leave { .. } => {
+                let state = {
+                    let mut entry = match self.groups.get_mut(&group_id) {
+                        Some(entry) if entry.group.creator != from => entry,
+                        _ => return,
+                    };
+                    if !entry.group.members.remove(&from) {
+                        return;
+                    }
+                    entry.value().clone()
+                };
+                // Make sure members that missed the leave are updated.
+                if state.is_creator() {
+                    self.broadcast_members(&state, None);
+                }
+                self.emit(GroupEvent::MembersChanged(state.group))
+            }
+            Packet::Kick { .. } => {
+                if self
+                    .groups
+                    .remove_if(&group_id, |_, state| state.group.creator == from)
+                    .is_some()
+                {
+                    self.emit(GroupEvent::Kicked(group_id))
+                }
+            }
+            Packet::Content { message, .. } => {
+                let is_member = self
+                    .groups
+                    .get(&group_id)
+                    .map(|entry| entry.group.is_member(&from))
+                    .unwrap_or(false);
+                if !is_member || message.from != from {
+                    debug!(
+                        "Dropping message of group {} from non-member {}",
+                        group_id, from
+                    );
+                    return;
+                }
+                store::MessageStore::push_group_message(
+                    self.message_store.as_ref().as_ref(),
+                    &group_id,
+                    message.clone(),
+                );
+                self.emit(GroupEvent::Message { group_id, message })
+            }
+        }
+    }
+    /// Deliver packets that are kept while the peer is offline.
+    pub(crate) fn on_connected(&self, peer: PeerId) {
+        if self.pending_packets.contains_key(&peer) {
+            self.enqueue(peer, Outgoing::Flush);
+        }
+    }
+
+    fn broadcast_members(&self, state: &GroupState, except: Option<&PeerId>) {
+        for member in state.other_members() {
+            if Some(&member) == except {
+                continue;
+            }
+            self.deliver(
+                member,
+                Packet::Members {
+                    group: state.group.clone(),
+                },
+            );
+        }
+    }
+    fn deliver(&self, peer: PeerId, packet: Packet) {
+        self.enqueue(peer, Outgoing::Packet(packet));
+    }
+    /// Queue the work on the delivery task of the peer, spawning it if needed.
+    fn enqueue(&self, peer: PeerId, outgoing: Outgoing) {
+        let outbox = self.outboxes.entry(peer).or_insert_with(|| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            tokio::spawn(self.clone().run_outbox(peer, rx));
+            tx
+        });
+        // The task holds a clone of `Groups`, so it never exits and drops the receiver.
+        let _ = outbox.send(outgoing);
+    }
+    async fn run_outbox(self, peer: PeerId, mut rx: mpsc::UnboundedReceiver<Outgoing>) {
+        while let Some(outgoing) = rx.recv().await {
+            match outgoing {
+                // Earlier packets are still waiting, don't overtake them.
+                Outgoing::Packet(packet) if self.pending_packets.contains_key(&peer) => {
+                    self.keep_pending(peer, packet)
+                }
+                Outgoing::Packet(packet) => {
+                    self.try_deliver(peer, packet).await;
+                }
+                Outgoing::Flush => {
+                    let mut packets = match self.pending_packets.remove(&peer) {
+                        Some((_, packets)) => packets.into_iter(),
+                        None => continue,
+                    };
+                    while let Some(packet) = packets.next() {
+                        if !self.try_deliver(peer, packet).await {
+                            // Offline again, keep the rest in order.
+                            packets
+                                .by_ref()
+                                .for_each(|packet| self.keep_pending(peer, packet));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    /// Returns `false` if the packet is kept for later delivery.
+    /// Packets rejected by or timed out on a connected peer are retried with backoff
+    /// rather than kept, as no reconnection will come to flush them.
+    async fn try_deliver(&self, peer: PeerId, packet: Packet) -> bool {
+        use error::SendError::*;
+        let mut backoff = RETRY_BACKOFF;
+        let mut retries = 0;
+        loop {
+            let (tx, rx) = oneshot::channel();
+            let ev = InEvent::SendGroupPacket {
+                peer,
+                packet: packet.clone(),
+                callback: tx,
+            };
+            send_swarm!(self.sender, ev);
+            match handle_callback!(rx) {
+                Ok(_) => {}
+                Err(PeerNotFound(_) | ConnectionClosed) => {
+                    self.keep_pending(peer, packet);
+                    return false;
+                }
+                Err(e @ (Timeout | Rejected)) if retries < MAX_RETRIES => {
+                    debug!("Retrying group packet to {} in {:?}: {}", peer, backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    retries += 1;
+                    continue;
+                }
+                Err(e) => warn!("Failed to deliver group packet to {}: {}", peer, e),
+            }
+            return true;
+        }
+    }
+    fn keep_pending(&self, peer: PeerId, packet: Packet) {
+        let mut pending = self.pending_packets.entry(peer).or_default();
+        if pending.len() >= MAX_PENDING_PACKETS {
+            pending.pop_front();
+        }
+        pending.push_back(packet);
+    }
+    fn emit(&self, event: GroupEvent) {
+        // No subscriber is not an error.
+        let _ = self.event_sender.send(event);
+    }
+}
@@ -0,0 +1,286 @@
+use super::*;
+use dashmap::DashMap;
+use futures::future::{AbortHandle, Abortable, BoxFuture};
+use futures::FutureExt;
+use owlnest_messaging::rpc::{Packet, RpcError};
+use serde::{de::DeserializeOwned, Serialize};
+use std::future::Future;
+use tracing::{debug, trace};
+
+type Method =
+    Arc<dyn Fn(PeerId, Vec<u8>) -> BoxFuture<'static, Result<Vec<u8>, String>> + Send + Sync>;
+
+/// Remote procedure calls on top of the messaging transport.
+/// Methods registered locally can be called by any connected peer,
+/// and methods registered on remote peers can be called with [`Rpc::call`].
+#[derive(Clone)]
+pub struct Rpc {
+    sender: mpsc::Sender<InEvent>,
+    methods: Arc<DashMap<String, Method>>,
+    /// Calls made by local peer that are waiting for a response.
+    pending_calls: Arc<DashMap<(PeerId, u64), oneshot::Sender<Result<Vec<u8>, RpcError>>>>,
+    /// Calls from remote peers that are being handled.
+    running_calls: Arc<DashMap<(PeerId, u64), AbortHandle>>,
+    /// Number of calls being handled, indexed by remote peer.
+    calls_in_flight: Arc<DashMap<PeerId, usize>>,
+    max_concurrent_calls: usize,
+    counter: Arc<AtomicU64>,
+}
+impl Rpc {
+    pub(crate) fn new(sender: mpsc::Sender<InEvent>, max_concurrent_calls: usize) -> Self {
+        Self {
+            sender,
+            methods: Default::default(),
+            pending_calls: Default::default(),
+            running_calls: Default::default(),
+            calls_in_flight: Default::default(),
+            max_concurrent_calls,
+            counter: Arc::new(AtomicU64::new(0)),
+        }
+    }
+    /// Register a method that can be called by remote peers.
+    /// The handler receives the caller and the raw payload of the request.
+    /// Returns `false` if a method with the same name already exists,
+    /// in which case the existing method is kept.
+    pub fn register<F, Fut>(&self, method: impl Into<String>, handler: F) -> bool
+    where
+        F: Fn(PeerId, Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<u8>, String>> + Send + 'static,
+    {
+        use dashmap::mapref::entry::Entry;
+        match self.methods.entry(method.into()) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                let method: Method = Arc::new(move |peer, payload| handler(peer, payload).boxed());
+                entry.insert(method);
+                true
+            }
+        }
+    }
+    /// Register a method whose request and response are serialized as JSON.
+    pub fn register_typed<Req, Resp, F, Fut>(&self, method: impl Into<String>, handler: F) -> bool
+    where
+        Req: DeserializeOwned + Send + 'static,
+        Resp: Serialize + 'static,
+        F: Fn(PeerId, Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Resp, String>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.register(method, move |peer, payload| {
+            let handler = handler.clone();
+            async move {
+                let request = serde_json::from_slice::<Req>(&payload)
+                    .map_err(|e| format!("Malformed request: {e}"))?;
+                let response = handler(peer, request).await?;
+                serde_json::to_vec(&response).map_err(|e| e.to_string())
+            }
+        })
+    }
+    /// Remove a registered method.
+    /// Calls that are already running will not be affected.
+    pub fn unregister(&self, method: &str) -> bool {
+        self.methods.remove(method).is_some()
+    }
+    /// List names of all registered methods.
+    pub fn list_methods(&self) -> Box<[String]> {
+        self.methods
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+    /// Call a method on the remote peer and wait for its response.
+    /// The remote will be told to cancel the call if the timeout is reached
+    /// or the returned future is dropped before completion.
+    pub async fn call(
+        &self,
+        peer: PeerId,
+        method: impl Into<String>,
+        payload: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, RpcError> {
+        let id = self.counter.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending_calls.insert((peer, id), tx);
+        let mut guard = CallGuard {
+            rpc: self,
+            peer,
+            id,
+            completed: false,
+        };
+        let request = Packet::Request {
+            id,
+            method: method.into(),
+            payload,
+        };
+        let result = match tokio::time::timeout(timeout, async {
+            self.send_packet(peer, request)
+                .await
+                .map_err(RpcError::Send)?;
+            rx.await.unwrap_or(Err(RpcError::Cancelled))
+        })
+        .await
+        {
+            Ok(result) => result,
+            Err(_) => Err(RpcError::Timeout),
+        };
+        // Remote has responded or the request never left, nothing to cancel.
+        if !matches!(result, Err(RpcError::Timeout) | Err(RpcError::Cancelled)) {
+            guard.completed = true;
+        }
+        result
+    }
+    /// Call a method whose request and response are serialized as JSON.
+    pub async fn call_typed<Req, Resp>(
+        &self,
+        peer: PeerId,
+        method: impl Into<String>,
+        request: &Req,
+        timeout: Duration,
+    ) -> Result<Resp, RpcError>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let payload = serde_json::to_vec(request).map_err(|e| RpcError::Codec(e.to_string()))?;
+        let response = self.call(peer, method, payload, timeout).await?;
+        serde_json::from_slice(&response).map_err(|e| RpcError::Codec(e.to_string()))
+    }
+    /// Cancel all pending calls made to the given peer.
+    pub fn cancel_all(&self, peer: &PeerId) {
+        self.pending_calls.retain(|(remote, _), _| remote != peer);
+    }
+
+    pub(crate) fn on_packet(&self, from: PeerId, packet: Packet) {
+        match packet {
+            Packet::Request {
+                id,
+                method,
+                payload,
+            } => self.on_request(from, id, method, payload),
+            Packet::Response { id, result } => {
+                if let Some((_, callback)) = self.pending_calls.remove(&(from, id)) {
+                    handle_callback_sender!(result=>callback);
+                }
+            }
+            Packet::Cancel { id } => {
+                if let Some((_, handle)) = self.running_calls.remove(&(from, id)) {
+                    handle.abort();
+                }
+            }
+        }
+    }
+    fn on_request(&self, from: PeerId, id: u64, method: String, payload: Vec<u8>) {
+        let handler = match self.methods.get(&method) {
+            Some(handler) => handler.value().clone(),
+            None => return self.respond(from, id, Err(RpcError::MethodNotFound(method))),
+        };
+        if self.running_calls.contains_key(&(from, id)) {
+            // Replacing the running call would leave it without a way to respond or cancel.
+            debug!("Duplicate RPC call {} from peer {}", id, from);
+            return self.respond(from, id, Err(RpcError::Busy));
+        }
+        {
+            let mut in_flight = self.calls_in_flight.entry(from).or_default();
+            if self.max_concurrent_calls != 0 && *in_flight >= self.max_concurrent_calls {
+                drop(in_flight);
+                return self.respond(from, id, Err(RpcError::Busy));
+            }
+            *in_flight += 1;
+        }
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        self.running_calls.insert((from, id), abort_handle);
+        let guard = InFlightGuard {
+            rpc: self.clone(),
+            peer: from,
+            id,
+        };
+        let rpc = self.clone();
+        let call = Abortable::new(
+            async move {
+                let result = handler(from, payload).await.map_err(RpcError::Failed);
+                // Stop accepting cancellation before responding.
+                rpc.running_calls.remove(&(from, id));
+                let _ = rpc.send_packet(from, Packet::Response { id, result }).await;
+            },
+            abort_registration,
+        );
+        tokio::spawn(async move {
+            let _guard = guard;
+            if call.await.is_err() {
+                trace!("RPC call {} from peer {} cancelled", id, from);
+            }
+        });
+    }
+    fn respond(&self, peer: PeerId, id: u64, result: Result<Vec<u8>, RpcError>) {
+        let rpc = self.clone();
+        tokio::spawn(async move {
+            let _ = rpc.send_packet(peer, Packet::Response { id, result }).await;
+        });
+    }
+    async fn send_packet(
+        &self,
+        peer: PeerId,
+        packet: Packet,
+    ) -> Result<Duration, error::SendError> {
+        let (tx, rx) = oneshot::channel();
+        let ev = InEvent::SendRpc {
+            peer,
+            packet,
+            callback: tx,
+        };
+        send_swarm!(self.sender, ev);
+        let result = handle_callback!(rx);
+        if let Err(e) = &result {
+            debug!("Failed to send RPC packet to peer {}: {}", peer, e);
+        }
+        result
+    }
+}
+
+/// Tell the remote to cancel the call if the caller gave up waiting.
+struct CallGuard<'a> {
+    rpc: &'a Rpc,
+    peer: PeerId,
+    id: u64,
+    completed: bool,
+}
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        self.rpc.pending_calls.remove(&(self.peer, self.id));
+        if self.completed {
+            return;
+        }
+        // Dropped outside of a runtime, e.g. during shutdown, the remote will time out instead.
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            trace!(
+                "Not cancelling RPC call {} to peer {}: no runtime",
+                self.id,
+                self.peer
+            );
+            return;
+        };
+        let rpc = self.rpc.clone();
+        let (peer, id) = (self.peer, self.id);
+        runtime.spawn(async move {
+            let _ = rpc.send_packet(peer, Packet::Cancel { id }).await;
+        });
+    }
+}
+
+/// Release the concurrency slot of a call, whether it's completed or aborted.
+struct InFlightGuard {
+    rpc: Rpc,
+    peer: PeerId,
+    id: u64,
+}
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.rpc.running_calls.remove(&(self.peer, self.id));
+        self.rpc
+            .calls_in_flight
+            .remove_if_mut(&self.peer, |_, in_flight| {
+                *in_flight -= 1;
+                *in_flight == 0
+            });
+    }
+}
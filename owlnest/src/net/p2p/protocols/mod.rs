@@ -60,6 +60,22 @@ pub mod relay_client;
 #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-relay-server"))]
 pub mod relay_server;
 
+/// # Libp2p Rendezvous(client)
+/// Support status: Tier 2
+/// ## About this protocol
+/// Allow local peer to register itself under namespaces on a rendezvous node,
+/// and discover peers registered by others, including libp2p apps other than owlnest.
+#[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-client"))]
+pub mod rendezvous;
+
+/// # Libp2p Rendezvous(server)
+/// Support status: Tier 2
+/// ## About this protocol
+/// Allow local peer to function as a rendezvous node that keeps
+/// registrations of other peers and answers their discovery requests.
+#[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-server"))]
+pub mod rendezvous_server;
+
 /// A behaviour that allows automated information sharing between peers.
 #[cfg(any(feature = "owlnest-protocols", feature = "owlnest-advertise"))]
 pub mod advertise;
@@ -0,0 +1,491 @@
+use super::*;
+use crate::net::p2p::swarm::{behaviour::BehaviourEvent, Swarm, SwarmEvent};
+use libp2p::rendezvous::client;
+use libp2p::swarm::dial_opts::{DialOpts, PeerCondition};
+use owlnest_core::error::OperationError;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+pub use client::Behaviour;
+pub use client::RegisterError;
+pub use libp2p::rendezvous::{Cookie, ErrorCode, Namespace, Registration, Ttl};
+/// An ailas to `libp2p::rendezvous::client::Event` for unified naming.
+pub type OutEvent = client::Event;
+
+/// Configuration for the rendezvous client.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// Dial peers as soon as they are discovered on a rendezvous node.
+    pub auto_dial: bool,
+}
+
+/// Errors of requests made to a rendezvous node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Local peer has no confirmed external address to register.
+    NoExternalAddresses,
+    /// Local peer record cannot be signed.
+    FailedToMakeRecord(String),
+    /// The rendezvous node rejected the request.
+    Rejected(ErrorCode),
+    Timeout,
+}
+impl std::error::Error for Error {}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Error::*;
+        match self {
+            NoExternalAddresses => f.write_str("Local peer has no external address"),
+            FailedToMakeRecord(e) => write!(f, "Failed to make peer record: {e}"),
+            Rejected(code) => write!(f, "Rejected by rendezvous node: {code:?}"),
+            Timeout => f.write_str("Timeout"),
+        }
+    }
+}
+impl From<RegisterError> for Error {
+    fn from(value: RegisterError) -> Self {
+        match value {
+            RegisterError::NoExternalAddresses => Self::NoExternalAddresses,
+            RegisterError::FailedToMakeRecord(e) => Self::FailedToMakeRecord(e.to_string()),
+        }
+    }
+}
+impl From<OperationError> for Error {
+    fn from(_: OperationError) -> Self {
+        Self::Timeout
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum InEvent {
+    Register {
+        namespace: Namespace,
+        rendezvous_node: PeerId,
+        ttl: Option<Ttl>,
+        callback: Callback<Result<(), RegisterError>>,
+    },
+    Unregister {
+        namespace: Namespace,
+        rendezvous_node: PeerId,
+    },
+    Discover {
+        namespace: Option<Namespace>,
+        cookie: Option<Cookie>,
+        limit: Option<u64>,
+        rendezvous_node: PeerId,
+    },
+    /// Dial a discovered peer if not connected yet.
+    Dial {
+        peer: PeerId,
+        addresses: Vec<Multiaddr>,
+    },
+}
+
+/// A handle that can communicate with the behaviour within the swarm.
+#[derive(Debug, Clone)]
+pub struct Handle {
+    sender: mpsc::Sender<InEvent>,
+    swarm_event_source: EventSender,
+}
+impl Handle {
+    pub(crate) fn new(
+        config: &Config,
+        buffer_size: usize,
+        swarm_event_source: &EventSender,
+    ) -> (Self, mpsc::Receiver<InEvent>) {
+        let (tx, rx) = mpsc::channel(buffer_size);
+        if config.auto_dial {
+            let tx = tx.clone();
+            let mut listener = swarm_event_source.subscribe();
+            tokio::spawn(async move {
+                loop {
+                    let ev = match listener.recv().await {
+                        Ok(ev) => ev,
+                        // Missed discoveries will be dialed on the next discovery.
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    };
+                    if let SwarmEvent::Behaviour(BehaviourEvent::Rendezvous(
+                        OutEvent::Discovered { registrations, .. },
+                    )) = ev.as_ref()
+                    {
+                        for registration in registrations {
+                            let ev = InEvent::Dial {
+                                peer: registration.record.peer_id(),
+                                addresses: registration.record.addresses().to_vec(),
+                            };
+                            if tx.send(ev).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+        (
+            Self {
+                sender: tx,
+                swarm_event_source: swarm_event_source.clone(),
+            },
+            rx,
+        )
+    }
+    /// Register local peer on the rendezvous node under the namespace,
+    /// along with its confirmed external addresses.
+    /// Returns the TTL granted by the node, the registration needs to be
+    /// renewed before it runs out.
+    pub async fn register(
+        &self,
+        namespace: Namespace,
+        rendezvous_node: PeerId,
+        ttl: Option<Ttl>,
+    ) -> Result<Ttl, Error> {
+        let mut listener = self.swarm_event_source.subscribe();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let ev = InEvent::Register {
+            namespace: namespace.clone(),
+            rendezvous_node,
+            ttl,
+            callback: tx,
+        };
+        self.sender.send(ev).await.expect("send to succeed");
+        rx.await.expect("callback to succeed")?;
+        let fut = listen_event!(listener for Rendezvous,
+            OutEvent::Registered { rendezvous_node: node, ttl, namespace: registered } => {
+                if *node == rendezvous_node && *registered == namespace {
+                    return Ok(*ttl);
+                }
+            }
+            OutEvent::RegisterFailed { rendezvous_node: node, namespace: failed, error } => {
+                if *node == rendezvous_node && *failed == namespace {
+                    return Err(Error::Rejected(*error));
+                }
+            }
+        );
+        future_timeout!(fut, 10000)?
+    }
+    /// Find peers registered on the rendezvous node, in the namespace if supplied.
+    /// Supply the cookie returned by a previous discovery to only get
+    /// registrations made since.
+    pub async fn discover(
+        &self,
+        rendezvous_node: PeerId,
+        namespace: Option<Namespace>,
+        cookie: Option<Cookie>,
+        limit: Option<u64>,
+    ) -> Result<(Vec<Registration>, Cookie), Error> {
+        let mut listener = self.swarm_event_source.subscribe();
+        let ev = InEvent::Discover {
+            namespace: namespace.clone(),
+            cookie,
+            limit,
+            rendezvous_node,
+        };
+        self.sender.send(ev).await.expect("send to succeed");
+        let fut = listen_event!(listener for Rendezvous,
+            OutEvent::Discovered { rendezvous_node: node, registrations, cookie } => {
+                if *node == rendezvous_node && cookie.namespace() == namespace.as_ref() {
+                    return Ok((registrations.clone(), cookie.clone()));
+                }
+            }
+            OutEvent::DiscoverFailed { rendezvous_node: node, namespace: failed, error } => {
+                if *node == rendezvous_node && *failed == namespace {
+                    return Err(Error::Rejected(*error));
+                }
+            }
+        );
+        future_timeout!(fut, 10000)?
+    }
+    generate_handler_method!(
+        /// Remove the registration of local peer in the namespace on the rendezvous node.
+        Unregister:unregister(namespace: |Namespace|, rendezvous_node: &PeerId);
+    );
+}
+
+pub(crate) fn map_in_event(ev: InEvent, swarm: &mut Swarm) {
+    use InEvent::*;
+    match ev {
+        Register {
+            namespace,
+            rendezvous_node,
+            ttl,
+            callback,
+        } => {
+            let result = swarm
+                .behaviour_mut()
+                .rendezvous
+                .register(namespace, rendezvous_node, ttl);
+            handle_callback_sender!(result=>callback);
+        }
+        Unregister {
+            namespace,
+            rendezvous_node,
+        } => swarm
+            .behaviour_mut()
+            .rendezvous
+            .unregister(namespace, rendezvous_node),
+        Discover {
+            namespace,
+            cookie,
+            limit,
+            rendezvous_node,
+        } => swarm
+            .behaviour_mut()
+            .rendezvous
+            .discover(namespace, cookie, limit, rendezvous_node),
+        Dial { peer, addresses } => {
+            if peer == *swarm.local_peer_id() {
+                return;
+            }
+            let opts = DialOpts::peer_id(peer)
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .addresses(addresses)
+                .build();
+            if let Err(e) = swarm.dial(opts) {
+                debug!("Failed to dial discovered peer {peer}: {e}");
+            }
+        }
+    }
+}
+
+/// Log the events to tracing
+pub(crate) fn ev_dispatch(ev: &OutEvent) {
+    use client::Event::*;
+    match ev {
+        Discovered {
+            rendezvous_node,
+            registrations,
+            ..
+        } => debug!(
+            "Discovered {} peers on rendezvous node {rendezvous_node}",
+            registrations.len()
+        ),
+        DiscoverFailed {
+            rendezvous_node,
+            namespace,
+            error,
+        } => info!(
+            "Failed to discover peers in namespace {namespace:?} on rendezvous node {rendezvous_node}: {error:?}"
+        ),
+        Registered {
+            rendezvous_node,
+            ttl,
+            namespace,
+        } => debug!(
+            "Registered in namespace {namespace} on rendezvous node {rendezvous_node} for {ttl}s"
+        ),
+        RegisterFailed {
+            rendezvous_node,
+            namespace,
+            error,
+        } => info!(
+            "Failed to register in namespace {namespace} on rendezvous node {rendezvous_node}: {error:?}"
+        ),
+        Expired { peer } => debug!("Registration of discovered peer {peer} expired"),
+    }
+}
+
+pub mod cli {
+    use super::*;
+    use clap::Subcommand;
+    use prettytable::{row, Table};
+
+    /// Subcommand for interacting with `libp2p-rendezvous` protocol.
+    /// Peers register themselves under namespaces on a rendezvous node,
+    /// so that others, including libp2p apps other than owlnest,
+    /// can discover them by namespace.
+    #[derive(Debug, Subcommand)]
+    pub enum Rendezvous {
+        /// Register local peer on the rendezvous node under the namespace.
+        /// Local peer needs at least one confirmed external address,
+        /// see `swarm external-addr`.
+        Register {
+            /// Peer ID of the rendezvous node.
+            rendezvous_node: PeerId,
+            #[arg(value_parser = parse_namespace)]
+            namespace: Namespace,
+            /// Seconds until the registration expires, default to 2 hours.
+            #[arg(long)]
+            ttl: Option<Ttl>,
+        },
+        /// Remove the registration of local peer on the rendezvous node.
+        Unregister {
+            /// Peer ID of the rendezvous node.
+            rendezvous_node: PeerId,
+            #[arg(value_parser = parse_namespace)]
+            namespace: Namespace,
+        },
+        /// Find peers registered on the rendezvous node.
+        Discover {
+            /// Peer ID of the rendezvous node.
+            rendezvous_node: PeerId,
+            /// Only find peers in this namespace.
+            #[arg(long, value_parser = parse_namespace)]
+            namespace: Option<Namespace>,
+            /// Cookie printed by a previous discovery,
+            /// so that only peers registered since are returned.
+            #[arg(long, value_parser = parse_cookie)]
+            cookie: Option<Cookie>,
+            /// Return at most this many peers.
+            #[arg(long)]
+            limit: Option<u64>,
+        },
+    }
+
+    /// Top-level handler for `rendezvous` command.
+    pub async fn handle_rendezvous(handle: &Handle, command: Rendezvous) {
+        use Rendezvous::*;
+        match command {
+            Register {
+                rendezvous_node,
+                namespace,
+                ttl,
+            } => match handle
+                .register(namespace.clone(), rendezvous_node, ttl)
+                .await
+            {
+                Ok(ttl) => println!("Registered in namespace {namespace} for {ttl}s"),
+                Err(e) => println!("Failed to register: {e}"),
+            },
+            Unregister {
+                rendezvous_node,
+                namespace,
+            } => {
+                handle.unregister(namespace.clone(), &rendezvous_node).await;
+                println!("Unregistered from namespace {namespace}")
+            }
+            Discover {
+                rendezvous_node,
+                namespace,
+                cookie,
+                limit,
+            } => match handle
+                .discover(rendezvous_node, namespace, cookie, limit)
+                .await
+            {
+                Ok((registrations, cookie)) => {
+                    print_registrations(&registrations);
+                    println!("Cookie: {}", encode_cookie(cookie));
+                }
+                Err(e) => println!("Failed to discover: {e}"),
+            },
+        }
+    }
+
+    fn print_registrations(registrations: &[Registration]) {
+        if registrations.is_empty() {
+            return println!("No peer is found");
+        }
+        let mut table = Table::new();
+        table.set_titles(row!["Peer ID", "Namespace", "TTL(s)", "Addresses"]);
+        for registration in registrations {
+            let addresses = registration
+                .record
+                .addresses()
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+            table.add_row(row![
+                registration.record.peer_id(),
+                registration.namespace,
+                registration.ttl,
+                addresses
+            ]);
+        }
+        table.printstd();
+    }
+
+    fn parse_namespace(s: &str) -> Result<Namespace, String> {
+        Namespace::new(s.to_string()).map_err(|e| e.to_string())
+    }
+
+    fn encode_cookie(cookie: Cookie) -> String {
+        cookie
+            .into_wire_encoding()
+            .iter()
+            .map(|v| format!("{v:02x}"))
+            .collect()
+    }
+
+    fn parse_cookie(s: &str) -> Result<Cookie, String> {
+        if s.len() % 2 != 0 || !s.is_ascii() {
+            return Err(format!("Invalid cookie {s:?}"));
+        }
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid cookie {s:?}: {e}"))?;
+        Cookie::from_wire_encoding(bytes).map_err(|e| format!("Invalid cookie {s:?}: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Namespace;
+    use crate::{net::p2p::test_suit::setup_default, sleep};
+    use libp2p::Multiaddr;
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn register_and_discover() -> anyhow::Result<()> {
+        let (peer1_m, _) = setup_default();
+        let (peer2_m, _) = setup_default();
+        let (peer3_m, _) = setup_default();
+        peer1_m
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        peer2_m
+            .swarm()
+            .listen_blocking(&"/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+        sleep!(200);
+        let peer1_id = peer1_m.identity().get_peer_id();
+        let peer2_id = peer2_m.identity().get_peer_id();
+        let peer1_addr = peer1_m.swarm().list_listeners_blocking()[0].clone();
+        // Registrations carry external addresses only.
+        peer2_m
+            .swarm()
+            .add_external_address_blocking(&peer2_m.swarm().list_listeners_blocking()[0]);
+        peer2_m.swarm().dial_blocking(&peer1_addr)?;
+        peer3_m.swarm().dial_blocking(&peer1_addr)?;
+        sleep!(200);
+        let namespace = Namespace::from_static("owlnest");
+        let ttl = peer2_m.executor().block_on(peer2_m.rendezvous().register(
+            namespace.clone(),
+            peer1_id,
+            None,
+        ))?;
+        assert!(ttl > 0);
+        let (registrations, cookie) = peer3_m.executor().block_on(
+            peer3_m
+                .rendezvous()
+                .discover(peer1_id, Some(namespace.clone()), None, None),
+        )?;
+        assert_eq!(registrations.len(), 1);
+        assert_eq!(registrations[0].record.peer_id(), peer2_id);
+        // Nothing new since the last discovery.
+        let (registrations, _) = peer3_m.executor().block_on(peer3_m.rendezvous().discover(
+            peer1_id,
+            Some(namespace.clone()),
+            Some(cookie),
+            None,
+        ))?;
+        assert!(registrations.is_empty());
+        peer2_m.executor().block_on(
+            peer2_m
+                .rendezvous()
+                .unregister(namespace.clone(), &peer1_id),
+        );
+        sleep!(200);
+        let (registrations, _) = peer3_m.executor().block_on(peer3_m.rendezvous().discover(
+            peer1_id,
+            Some(namespace),
+            None,
+            None,
+        ))?;
+        assert!(registrations.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use super::*;
+use libp2p::rendezvous::server;
+use tracing::{debug, info};
+
+pub use server::Behaviour;
+/// An ailas to `libp2p::rendezvous::server::Event` for unified naming.
+pub type OutEvent = server::Event;
+
+/// Configuration for the rendezvous server [`Behaviour`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Registrations with a shorter TTL are rejected, default to 2 hours.
+    pub min_ttl_sec: u64,
+    /// Registrations with a longer TTL are rejected, default to 72 hours.
+    pub max_ttl_sec: u64,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            min_ttl_sec: 2 * 60 * 60,
+            max_ttl_sec: 72 * 60 * 60,
+        }
+    }
+}
+impl From<Config> for server::Config {
+    fn from(value: Config) -> Self {
+        server::Config::default()
+            .with_min_ttl(value.min_ttl_sec)
+            .with_max_ttl(value.max_ttl_sec)
+    }
+}
+
+/// Log the events to tracing
+pub(crate) fn ev_dispatch(ev: &OutEvent) {
+    use server::Event::*;
+    match ev {
+        PeerRegistered { peer, registration } => debug!(
+            "Peer {peer} registered in namespace {} for {}s",
+            registration.namespace, registration.ttl
+        ),
+        PeerNotRegistered {
+            peer,
+            namespace,
+            error,
+        } => info!("Rejected registration from {peer} in namespace {namespace}: {error:?}"),
+        PeerUnregistered { peer, namespace } => {
+            debug!("Peer {peer} unregistered from namespace {namespace}")
+        }
+        DiscoverServed {
+            enquirer,
+            registrations,
+        } => debug!("Served {} registrations to {enquirer}", registrations.len()),
+        DiscoverNotServed { enquirer, error } => {
+            info!("Rejected discovery from {enquirer}: {error:?}")
+        }
+        RegistrationExpired(registration) => debug!(
+            "Registration of peer {} in namespace {} expired",
+            registration.record.peer_id(),
+            registration.namespace
+        ),
+    }
+}
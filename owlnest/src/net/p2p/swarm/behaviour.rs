@@ -14,5 +14,7 @@ use owlnest_proc::{generate_behaviour_select, with_field};
 #[cfg_attr(any(feature = "libp2p-protocols", feature = "libp2p-kad"), with_field({pub kad:Kad}))]
 #[cfg_attr(any(feature = "libp2p-protocols", feature = "libp2p-relay-client"), with_field({pub relay_client:RelayClient}))]
 #[cfg_attr(any(feature = "libp2p-protocols", feature = "libp2p-gossipsub"), with_field({pub gossipsub:Gossipsub}))]
+#[cfg_attr(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-client"), with_field({pub rendezvous:Rendezvous}))]
+#[cfg_attr(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-server"), with_field({pub rendezvous_server:RendezvousServer}))]
 #[generate_behaviour_select]
 pub struct Behaviour {}
@@ -116,6 +116,8 @@ pub fn handle_incoming_event(ev: Rx, swarm: &mut Swarm) {
         AutoNat(ev) => autonat::map_in_event(&mut swarm.behaviour_mut().autonat, ev),
         #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-gossipsub"))]
         Gossipsub(ev) => gossipsub::map_in_event(&mut swarm.behaviour_mut().gossipsub, ev),
+        #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-client"))]
+        Rendezvous(ev) => rendezvous::map_in_event(ev, swarm),
     }
 }
 
@@ -160,9 +162,20 @@ pub fn swarm_op_exec(swarm: &mut Swarm, ev: InEvent) {
             trace!("is conneted to {}: {}", peer_id, result);
             handle_callback_sender!( result => callback)
         }
+        Shutdown { .. } => unreachable!("Shutdown to be handled by the event loop"),
     }
 }
 
+/// Write what needs to outlive the swarm to disk before the swarm is dropped.
+#[allow(unused)]
+pub fn shutdown(swarm: &mut Swarm) {
+    trace!("Shutting down swarm");
+    #[cfg(any(feature = "owlnest-protocols", feature = "owlnest-advertise"))]
+    swarm.behaviour_mut().advertise.flush();
+    #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+    swarm.behaviour_mut().kad.store_mut().flush();
+}
+
 /// Use for external protocol that doesn't expose callback communication only
 #[allow(unused)]
 #[inline]
@@ -186,6 +199,10 @@ fn handle_behaviour_event(swarm: &mut Swarm, ev: &BehaviourEvent) {
         Upnp(ev) => upnp::ev_dispatch(ev),
         #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-ping"))]
         Ping(ev) => ping::ev_dispatch(ev),
+        #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-client"))]
+        Rendezvous(ev) => rendezvous::ev_dispatch(ev),
+        #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-server"))]
+        RendezvousServer(ev) => rendezvous_server::ev_dispatch(ev),
         _ => {}
     }
 }
@@ -88,5 +88,9 @@ impl SwarmHandle {
         /// Disconnect from the peer.
         /// Should be used in asynchronous contexts
         DisconnectFromPeerId:disconnect_peer_id(peer_id:&PeerId)->Result<(),()>;
+        /// Write persisted state to disk and stop the swarm, closing all connections.
+        /// Handles can no longer be used once this returns.
+        /// Should be used in asynchronous contexts
+        Shutdown:shutdown()->();
     );
 }
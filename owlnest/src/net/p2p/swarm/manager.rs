@@ -8,5 +8,6 @@ use owlnest_proc::{generate_manager, with_field};
 #[cfg_attr(any(feature = "libp2p-protocols", feature = "libp2p-autonat"), with_field({pub autonat:AutoNat}))]
 #[cfg_attr(any(feature = "libp2p-protocols", feature = "libp2p-mdns"), with_field({pub mdns:Mdns}))]
 #[cfg_attr(any(feature = "libp2p-protocols", feature = "libp2p-gossipsub"), with_field({pub gossipsub:Gossipsub}))]
+#[cfg_attr(any(feature = "libp2p-protocols", feature = "libp2p-rendezvous-client"), with_field({pub rendezvous:Rendezvous}))]
 #[generate_manager]
 pub(crate) struct RxBundle {}
@@ -2,6 +2,7 @@ use crate::net::p2p::swarm::manager::HandleBundle;
 use futures::StreamExt;
 use libp2p::PeerId;
 use owlnest_core::alias::Callback;
+use owlnest_macro::handle_callback_sender;
 use serde::{Deserialize, Serialize};
 use std::{fmt::Debug, sync::Arc};
 use tokio::select;
@@ -90,10 +91,15 @@ impl Builder {
         let guard = executor.enter();
         use crate::net::p2p::protocols::*;
         #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
-        let kad_store = libp2p::kad::store::MemoryStore::new(ident.get_peer_id());
+        let kad_store = self.config.kad.build_store(ident.get_peer_id());
         let (swarm_event_out, _) =
             tokio::sync::broadcast::channel(self.config.swarm.swarm_event_buffer_size);
         let (handle_bundle, mut rx_bundle) = HandleBundle::new(&self.config, &swarm_event_out);
+        #[cfg(any(feature = "owlnest-protocols", feature = "owlnest-messaging"))]
+        handle_bundle
+            .messaging
+            .groups()
+            .set_local_peer_id(ident.get_peer_id());
         let manager = manager::Manager::new(
             Arc::new(handle_bundle),
             ident.clone(),
@@ -125,7 +131,10 @@ impl Builder {
                     #[cfg(any(feature = "owlnest-protocols", feature = "owlnest-blob"))]
                     blob: blob::Behaviour::new(self.config.blob),
                     #[cfg(any(feature = "owlnest-protocols", feature = "owlnest-advertise"))]
-                    advertise: advertise::Behaviour::new(self.config.advertise),
+                    advertise: advertise::Behaviour::new(
+                        self.config.advertise,
+                        ident.get_keypair(),
+                    ),
                     #[cfg(any(feature = "owlnest-protocols", feature = "owlnest-messaging"))]
                     messaging: messaging::Behaviour::new(self.config.messaging),
                     #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
@@ -160,6 +169,18 @@ impl Builder {
                         Default::default(),
                     )
                     .unwrap(),
+                    #[cfg(any(
+                        feature = "libp2p-protocols",
+                        feature = "libp2p-rendezvous-client"
+                    ))]
+                    rendezvous: rendezvous::Behaviour::new(ident.get_keypair()),
+                    #[cfg(any(
+                        feature = "libp2p-protocols",
+                        feature = "libp2p-rendezvous-server"
+                    ))]
+                    rendezvous_server: rendezvous_server::Behaviour::new(
+                        self.config.rendezvous_server.into(),
+                    ),
                     // hyper:hyper::Behaviour::new(Default::default())
                 })
                 .expect("behaviour incorporation to succeed")
@@ -170,15 +191,20 @@ impl Builder {
             let swarm_event_buffer_upper_bound =
                 (self.config.swarm.swarm_event_buffer_size >> 2) << 2;
             let swarm_event_buffer_high_mark = self.config.swarm.swarm_event_buffer_size / 2;
-            loop {
+            let callback = loop {
                 trace!("Swarm event loop entered");
+                #[cfg(any(feature = "libp2p-protocols", feature = "libp2p-kad"))]
+                swarm.behaviour_mut().kad.store_mut().tick();
                 let timer = futures_timer::Delay::new(std::time::Duration::from_millis(
                     self.config.swarm.swarm_event_timeout,
                 ));
                 select! {
                     Some(ev) = rx_bundle.next() => {
                         trace!("Received incoming event {:?}",ev);
-                        handle_incoming_event(ev, &mut swarm)
+                        match ev {
+                            manager::Rx::Swarm(InEvent::Shutdown { callback }) => break callback,
+                            ev => handle_incoming_event(ev, &mut swarm),
+                        }
                     },
                     out_event = swarm.select_next_some(), if event_out.len() < swarm_event_buffer_upper_bound => {
                         trace!("Swarm generated an event {:?}",out_event);
@@ -192,7 +218,11 @@ impl Builder {
                         }
                     }
                 };
-            }
+            };
+            shutdown(&mut swarm);
+            // Connections are closed along with the swarm.
+            drop(swarm);
+            handle_callback_sender!(() => callback);
         });
         drop(guard);
         manager
@@ -242,4 +272,8 @@ pub(crate) enum InEvent {
         peer_id: PeerId,
         callback: Callback<Result<(), ()>>,
     },
+    /// Stop the event loop, see [`handle::SwarmHandle::shutdown`].
+    Shutdown {
+        callback: Callback<()>,
+    },
 }
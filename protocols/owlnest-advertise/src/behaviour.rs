@@ -1,55 +1,520 @@
-use self::config::Config;
+use self::config::{AccessMode, Config, Eviction, RateLimit};
 
 use super::*;
+use crate::persist::{PersistedRecord, Persister, Snapshot};
+use crate::record::{unix_millis, AnsweredRecord, ChangeNotice};
+use futures::FutureExt;
+use futures_timer::Delay;
 use owlnest_macro::handle_callback_sender;
 use owlnest_prelude::behaviour_prelude::*;
-use std::collections::{HashSet, VecDeque};
-use tracing::{debug, trace};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tracing::{debug, trace, warn};
 
-#[derive(Debug, Default)]
+/// Interval between checks for expired advertisements.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
 pub struct Behaviour {
     config: Config,
     /// Pending events to emit to `Swarm`
     pending_out_events: VecDeque<OutEvent>,
     /// Pending events to be processed by this `Behaviour`.
     in_events: VecDeque<InEvent>,
-    /// A set for all connected peers.
-    advertised_peers: HashSet<PeerId>,
-    pending_query_answer: VecDeque<PeerId>,
+    /// Advertisements posted on local provider, indexed by advertiser.
+    advertised_peers: HashMap<PeerId, StoredAdvertisement>,
+    /// Queries from remote to answer, with their tag filter.
+    pending_query_answer: VecDeque<(PeerId, Option<String>)>,
     connected_peers: HashSet<PeerId>,
     is_providing: bool,
+    /// Addresses of local peer, filled into advertisements without addresses.
+    local_addrs: HashSet<Multiaddr>,
+    expiry_check_throttle: Delay,
+    /// Keypair of local peer to sign advertisements with.
+    keypair: Keypair,
+    /// Sequence number of the last advertisement signed.
+    last_seq: u64,
+    /// Results of advertise requests to send back to the advertisers.
+    pending_advertise_result: VecDeque<(PeerId, Result<(), Rejection>)>,
+    /// Start of the current rate limit window and requests made in it, by peer.
+    request_counts: HashMap<PeerId, (Instant, u32)>,
+    /// Remote peers subscribing to changes on local provider, with their tag filter.
+    subscribers: HashMap<PeerId, Option<String>>,
+    /// Changes to push to subscribers.
+    pending_notices: VecDeque<(PeerId, ChangeNotice)>,
+    /// `None` if persistence is disabled.
+    persister: Option<Persister>,
+    snapshot_throttle: Delay,
+    /// Whether local provider has changed since the last snapshot.
+    is_dirty: bool,
+}
+
+#[derive(Debug)]
+struct StoredAdvertisement {
+    signed: SignedAdvertisement,
+    seq: u64,
+    advertisement: Advertisement,
+    expires_at: Instant,
+    /// When the advertisement was first posted.
+    created_at: Instant,
+    /// When the advertisement was last posted.
+    renewed_at: Instant,
+}
+impl StoredAdvertisement {
+    fn answered(&self, now: Instant) -> AnsweredRecord {
+        AnsweredRecord {
+            signed: self.signed.clone(),
+            expires_in_secs: self.expires_at.saturating_duration_since(now).as_secs(),
+        }
+    }
+    fn persisted(&self, now: Instant, now_millis: u64) -> PersistedRecord {
+        let to_unix = |instant: Instant| {
+            if instant >= now {
+                now_millis + instant.duration_since(now).as_millis() as u64
+            } else {
+                now_millis.saturating_sub(now.duration_since(instant).as_millis() as u64)
+            }
+        };
+        PersistedRecord {
+            signed: self.signed.clone(),
+            expires_at: to_unix(self.expires_at),
+            created_at: to_unix(self.created_at),
+            renewed_at: to_unix(self.renewed_at),
+        }
+    }
+    /// Verify the record again, as the snapshot may have been tampered with.
+    /// `None` if invalid or expired.
+    fn restore(record: PersistedRecord, now: Instant, now_millis: u64) -> Option<(PeerId, Self)> {
+        if record.expires_at <= now_millis {
+            return None;
+        }
+        let (peer, payload) = match record.signed.verify() {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Dropped advertisement in snapshot: {}", e);
+                return None;
+            }
+        };
+        let to_instant = |millis: u64| {
+            now.checked_sub(Duration::from_millis(now_millis.saturating_sub(millis)))
+                .unwrap_or(now)
+        };
+        Some((
+            peer,
+            Self {
+                signed: record.signed,
+                seq: payload.seq,
+                advertisement: payload.advertisement,
+                expires_at: now + Duration::from_millis(record.expires_at - now_millis),
+                created_at: to_instant(record.created_at),
+                renewed_at: to_instant(record.renewed_at),
+            },
+        ))
+    }
 }
 
 impl Behaviour {
-    pub fn new(config: Config) -> Self {
-        Behaviour {
+    /// Local provider is restored from the last snapshot if persistence is enabled.
+    pub fn new(config: Config, keypair: Keypair) -> Self {
+        let mut behaviour = Behaviour {
+            snapshot_throttle: Delay::new(Duration::from_secs(
+                config.snapshot_interval_secs.max(1),
+            )),
             config,
-            ..Default::default()
+            pending_out_events: Default::default(),
+            in_events: Default::default(),
+            advertised_peers: Default::default(),
+            pending_query_answer: Default::default(),
+            connected_peers: Default::default(),
+            is_providing: false,
+            local_addrs: Default::default(),
+            expiry_check_throttle: Delay::new(EXPIRY_CHECK_INTERVAL),
+            keypair,
+            last_seq: 0,
+            pending_advertise_result: Default::default(),
+            request_counts: Default::default(),
+            subscribers: Default::default(),
+            pending_notices: Default::default(),
+            persister: None,
+            is_dirty: false,
+        };
+        if !behaviour.config.persist_path.is_empty() {
+            let (persister, snapshot) =
+                Persister::load(behaviour.config.persist_path.clone().into());
+            if let Some(snapshot) = snapshot {
+                behaviour.restore(snapshot);
+            }
+            behaviour.persister = Some(persister);
+        }
+        behaviour
+    }
+    /// Bring back local provider as it was when the snapshot was taken,
+    /// advertisements that have expired since are dropped.
+    fn restore(&mut self, snapshot: Snapshot) {
+        let now = Instant::now();
+        let now_millis = unix_millis();
+        self.is_providing = snapshot.is_providing;
+        for record in snapshot.records {
+            if let Some((peer, stored)) = StoredAdvertisement::restore(record, now, now_millis) {
+                self.advertised_peers.insert(peer, stored);
+            }
         }
+        debug!(
+            "Restored {} advertisements from snapshot",
+            self.advertised_peers.len()
+        );
+    }
+    /// Write a snapshot if persistence is enabled and something has changed.
+    fn take_snapshot(&mut self) {
+        if let Some(snapshot) = self.dirty_snapshot() {
+            self.persister
+                .as_ref()
+                .expect("Snapshot only taken with persistence")
+                .save(snapshot);
+        }
+    }
+    /// Write changes since the last snapshot to disk and wait for the write.
+    /// Called when the swarm shuts down, as snapshots are only taken on an interval.
+    pub fn flush(&mut self) {
+        if let Some(snapshot) = self.dirty_snapshot() {
+            self.persister
+                .as_ref()
+                .expect("Snapshot only taken with persistence")
+                .flush(snapshot);
+        }
+    }
+    /// Snapshot of local provider, `None` if persistence is disabled or nothing has changed.
+    fn dirty_snapshot(&mut self) -> Option<Snapshot> {
+        if self.persister.is_none() || !self.is_dirty {
+            return None;
+        }
+        let now = Instant::now();
+        let now_millis = unix_millis();
+        self.is_dirty = false;
+        Some(Snapshot {
+            is_providing: self.is_providing,
+            records: self
+                .advertised_peers
+                .values()
+                .map(|stored| stored.persisted(now, now_millis))
+                .collect(),
+        })
     }
     pub fn push_event(&mut self, ev: InEvent) {
         self.in_events.push_back(ev)
     }
     pub fn is_advertising(&self, peer: &PeerId) -> bool {
-        self.advertised_peers.contains(peer)
+        self.advertised_peers.contains_key(peer)
     }
-    pub fn advertised_peers(&self) -> &HashSet<PeerId> {
-        &self.advertised_peers
+    /// All advertisements on local provider, expired ones are excluded.
+    pub fn advertised_peers(&self) -> Vec<AdvertisedRecord> {
+        self.records(None)
     }
+    /// Subscribers are dropped when local provider stops.
     pub fn set_provider_status(&mut self, status: bool) {
+        if !status {
+            self.subscribers.clear();
+        }
+        self.is_dirty |= self.is_providing != status;
         self.is_providing = status
     }
     pub fn get_provider_status(&self) -> bool {
         self.is_providing
     }
     pub fn remove_advertised(&mut self, peer_id: &PeerId) -> bool {
-        self.advertised_peers.remove(peer_id)
+        self.remove_record(peer_id, ChangeNotice::Removed)
     }
     pub fn clear_advertised(&mut self) {
-        self.advertised_peers.clear()
+        let peers = self.advertised_peers.keys().copied().collect::<Vec<_>>();
+        for peer in peers {
+            self.remove_record(&peer, ChangeNotice::Removed);
+        }
+    }
+    /// Remote peers subscribing to changes on local provider.
+    pub fn subscribers(&self) -> impl Iterator<Item = &PeerId> {
+        self.subscribers.keys()
+    }
+    pub fn new_pending_query(&mut self, peer: &PeerId, tag: Option<String>) {
+        self.pending_query_answer.push_back((*peer, tag))
+    }
+    /// Unexpired advertisements, only those with the tag if supplied.
+    fn records(&self, tag: Option<&str>) -> Vec<AdvertisedRecord> {
+        let now = Instant::now();
+        self.unexpired(tag)
+            .map(|(peer, stored)| AdvertisedRecord {
+                peer: *peer,
+                seq: stored.seq,
+                advertisement: stored.advertisement.clone(),
+                expires_in_secs: stored.expires_at.saturating_duration_since(now).as_secs(),
+            })
+            .collect()
+    }
+    /// Answer to a query, signed advertisements are sent as is for remote to verify.
+    fn answers(&self, tag: Option<&str>) -> Box<[AnsweredRecord]> {
+        let now = Instant::now();
+        self.unexpired(tag)
+            .map(|(_, stored)| stored.answered(now))
+            .collect()
+    }
+    /// Queue the change for subscribers interested in the tags.
+    fn publish(&mut self, notice: ChangeNotice, tags: &[String]) {
+        if !self.is_providing {
+            return;
+        }
+        for (subscriber, tag) in self.subscribers.iter() {
+            let interested = match tag {
+                Some(tag) => tags.contains(tag),
+                None => true,
+            };
+            if interested {
+                self.pending_notices
+                    .push_back((*subscriber, notice.clone()));
+            }
+        }
+    }
+    /// Remove the advertisement and tell subscribers, returns whether it existed.
+    fn remove_record(&mut self, peer: &PeerId, notice: fn(PeerId) -> ChangeNotice) -> bool {
+        match self.advertised_peers.remove(peer) {
+            Some(stored) => {
+                self.is_dirty = true;
+                self.publish(notice(*peer), &stored.advertisement.tags);
+                true
+            }
+            None => false,
+        }
+    }
+    fn unexpired<'a>(
+        &'a self,
+        tag: Option<&'a str>,
+    ) -> impl Iterator<Item = (&'a PeerId, &'a StoredAdvertisement)> {
+        let now = Instant::now();
+        self.advertised_peers
+            .iter()
+            .filter(move |(_, stored)| stored.expires_at > now)
+            .filter(move |(_, stored)| match tag {
+                Some(tag) => stored.advertisement.has_tag(tag),
+                None => true,
+            })
+    }
+    /// Sign the advertisement with a sequence number larger than all previous ones.
+    fn sign(&mut self, advertisement: Advertisement) -> Option<SignedAdvertisement> {
+        let seq = unix_millis().max(self.last_seq + 1);
+        self.last_seq = seq;
+        match SignedAdvertisement::sign(&self.keypair, seq, advertisement) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!("Failed to sign advertisement: {e}");
+                None
+            }
+        }
+    }
+    /// Check the signature and freshness of an advertisement from remote.
+    fn verify_request(
+        &self,
+        peer_id: &PeerId,
+        signed: &SignedAdvertisement,
+    ) -> Result<(u64, Advertisement), Rejection> {
+        let (signer, payload) = signed.verify().map_err(|e| match e {
+            Error::InvalidAdvertisement(msg) => Rejection::Invalid(msg),
+            e => Rejection::Invalid(e.to_string()),
+        })?;
+        if signer != *peer_id {
+            return Err(Rejection::Invalid(format!(
+                "Signed by {signer} but sent by {peer_id}"
+            )));
+        }
+        if payload.is_stale() {
+            return Err(Rejection::Invalid("Advertisement has expired".into()));
+        }
+        if let Some(stored) = self.advertised_peers.get(peer_id) {
+            if payload.seq <= stored.seq {
+                return Err(Rejection::Invalid(
+                    "Advertisement is older than the one stored".into(),
+                ));
+            }
+        }
+        Ok((payload.seq, payload.advertisement))
+    }
+    /// Count the request against the rate limit of the peer.
+    fn check_rate_limit(&mut self, peer_id: &PeerId) -> Result<(), Rejection> {
+        let RateLimit {
+            max_requests,
+            interval_secs,
+        } = self.config.rate_limit;
+        if max_requests == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let (window_start, count) = self.request_counts.entry(*peer_id).or_insert((now, 0));
+        if now.duration_since(*window_start) >= Duration::from_secs(interval_secs) {
+            *window_start = now;
+            *count = 0;
+        }
+        *count += 1;
+        if *count > max_requests {
+            return Err(Rejection::RateLimited);
+        }
+        Ok(())
+    }
+    fn check_access(&self, peer_id: &PeerId, token: Option<&str>) -> Result<(), Rejection> {
+        let access = &self.config.access;
+        match access.mode {
+            AccessMode::Open => Ok(()),
+            AccessMode::AllowList if access.allow_list.contains(peer_id) => Ok(()),
+            AccessMode::AllowList => Err(Rejection::NotAllowed),
+            // An empty token would let anyone in.
+            AccessMode::Token
+                if !access.token.is_empty() && token == Some(access.token.as_str()) =>
+            {
+                Ok(())
+            }
+            AccessMode::Token => Err(Rejection::InvalidToken),
+        }
+    }
+    /// Make room for a new advertisement according to the eviction policy.
+    fn evict(&mut self) -> Result<(), Rejection> {
+        let evicted = match self.config.eviction {
+            Eviction::RejectNew => return Err(Rejection::CapacityReached),
+            Eviction::Oldest => self
+                .advertised_peers
+                .iter()
+                .min_by_key(|(_, stored)| stored.created_at),
+            Eviction::LeastRecentlyRenewed => self
+                .advertised_peers
+                .iter()
+                .min_by_key(|(_, stored)| stored.renewed_at),
+        }
+        .map(|(peer, _)| *peer);
+        match evicted {
+            Some(peer) => {
+                debug!("Evicted advertisement of peer {}", peer);
+                self.remove_record(&peer, ChangeNotice::Removed);
+                self.pending_out_events
+                    .push_back(OutEvent::AdvertisedPeerChanged(peer, false));
+                Ok(())
+            }
+            // Capacity of 0.
+            None => Err(Rejection::CapacityReached),
+        }
+    }
+    fn on_advertise_request(
+        &mut self,
+        peer_id: PeerId,
+        signed: Option<SignedAdvertisement>,
+        token: Option<String>,
+    ) {
+        let result = self.try_store(peer_id, signed, token);
+        if let Err(reason) = &result {
+            debug!("Advertisement from {} rejected: {}", peer_id, reason);
+        }
+        self.pending_advertise_result.push_back((peer_id, result));
+    }
+    fn try_store(
+        &mut self,
+        peer_id: PeerId,
+        signed: Option<SignedAdvertisement>,
+        token: Option<String>,
+    ) -> Result<(), Rejection> {
+        self.check_rate_limit(&peer_id)?;
+        let signed = match signed {
+            Some(v) => v,
+            None => {
+                if self.remove_record(&peer_id, ChangeNotice::Removed) {
+                    debug!("Stopped advertising peer {}", peer_id);
+                    self.pending_out_events
+                        .push_back(OutEvent::AdvertisedPeerChanged(peer_id, false));
+                }
+                return Ok(());
+            }
+        };
+        if !self.is_providing {
+            return Err(Rejection::NotProviding);
+        }
+        self.check_access(&peer_id, token.as_deref())?;
+        let (seq, advertisement) = self.verify_request(&peer_id, &signed)?;
+        let now = Instant::now();
+        let created_at = match self.advertised_peers.get(&peer_id) {
+            Some(stored) => Some(stored.created_at),
+            None if self.advertised_peers.len() >= self.config.max_advertise_capacity => {
+                self.evict()?;
+                None
+            }
+            None => None,
+        };
+        let ttl_secs = advertisement.ttl_secs.min(self.config.max_ttl_secs).max(1);
+        let stored = StoredAdvertisement {
+            signed,
+            seq,
+            advertisement,
+            expires_at: now + Duration::from_secs(ttl_secs),
+            created_at: created_at.unwrap_or(now),
+            renewed_at: now,
+        };
+        let notice = match created_at {
+            Some(_) => ChangeNotice::Renewed(stored.answered(now)),
+            None => ChangeNotice::Added(stored.answered(now)),
+        };
+        let tags = stored.advertisement.tags.clone();
+        self.advertised_peers.insert(peer_id, stored);
+        self.is_dirty = true;
+        self.publish(notice, &tags);
+        if created_at.is_none() {
+            debug!("Now advertising peer {}", peer_id);
+            self.pending_out_events
+                .push_back(OutEvent::AdvertisedPeerChanged(peer_id, true));
+        }
+        Ok(())
     }
-    pub fn new_pending_query(&mut self, peer: &PeerId) {
-        self.pending_query_answer.push_back(*peer)
+    /// Verify the answer again, so that the provider can't alter or make up records.
+    fn on_query_answered(&mut self, peer_id: PeerId, answer: Option<Box<[AnsweredRecord]>>) {
+        let list = answer.map(|answer| {
+            answer
+                .iter()
+                .filter_map(|record| {
+                    let verified = record.verify();
+                    if let Err(e) = &verified {
+                        warn!("Dropped advertisement answered by {}: {}", peer_id, e);
+                    }
+                    verified.ok()
+                })
+                .collect()
+        });
+        self.pending_out_events.push_back(OutEvent::QueryAnswered {
+            from: peer_id,
+            list,
+        })
+    }
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let interval = Duration::from_secs(self.config.rate_limit.interval_secs);
+        self.request_counts
+            .retain(|_, (window_start, _)| now.duration_since(*window_start) < interval);
+        let expired = self
+            .advertised_peers
+            .iter()
+            .filter(|(_, stored)| stored.expires_at <= now)
+            .map(|(peer, _)| *peer)
+            .collect::<Vec<_>>();
+        for peer in expired {
+            debug!("Advertisement of peer {} expired", peer);
+            self.remove_record(&peer, ChangeNotice::Expired);
+            self.pending_out_events
+                .push_back(OutEvent::AdvertisedPeerChanged(peer, false));
+        }
+    }
+    /// Fill in addresses of local peer if none is supplied.
+    fn with_local_addrs(&self, mut advertisement: Advertisement) -> Advertisement {
+        if !advertisement.listen_addrs.is_empty() || !advertisement.relay_addrs.is_empty() {
+            return advertisement;
+        }
+        for addr in self.local_addrs.iter() {
+            if addr.iter().any(|protocol| protocol.tag() == "p2p-circuit") {
+                advertisement.relay_addrs.push(addr.clone());
+            } else {
+                advertisement.listen_addrs.push(addr.clone());
+            }
+        }
+        advertisement
     }
 }
 
@@ -65,25 +530,43 @@ impl NetworkBehaviour for Behaviour {
     ) {
         use handler::ToBehaviour::*;
         match event {
-            IncomingQuery => {
+            IncomingQuery(tag) => {
                 trace!("incoming query from {}", peer_id);
-                self.pending_query_answer.push_back(peer_id);
-            }
-            IncomingAdvertiseReq(bool) => {
-                if bool {
-                    if self.advertised_peers.len() <= self.config.max_advertise_capacity
-                        && self.advertised_peers.insert(peer_id)
-                    {
-                        debug!("Now advertising peer {}", peer_id);
-                    }
-                } else if self.advertised_peers.remove(&peer_id) {
-                    debug!("Stopped advertising peer {}", peer_id);
-                };
+                self.pending_query_answer.push_back((peer_id, tag));
+            }
+            IncomingAdvertiseReq {
+                advertisement,
+                token,
+            } => self.on_advertise_request(peer_id, advertisement, token),
+            IncomingSubscribe(tag) => {
+                trace!("incoming subscription from {}", peer_id);
+                if self.is_providing {
+                    self.subscribers.insert(peer_id, tag.clone());
+                }
+                // Answered with current advertisements, or `None` if not providing.
+                self.pending_query_answer.push_back((peer_id, tag));
+            }
+            IncomingUnsubscribe => {
+                self.subscribers.remove(&peer_id);
             }
-            QueryAnswered(result) => self.pending_out_events.push_back(OutEvent::QueryAnswered {
-                from: peer_id,
-                list: result,
-            }),
+            ChangeNotified(notice) => match notice.verify() {
+                Ok(change) => {
+                    self.pending_out_events
+                        .push_back(OutEvent::RemoteAdvertisementChanged {
+                            from: peer_id,
+                            change,
+                        })
+                }
+                Err(e) => warn!("Dropped change pushed by {}: {}", peer_id, e),
+            },
+            AdvertiseAnswered(result) => {
+                self.pending_out_events
+                    .push_back(OutEvent::RemoteAdvertisementResult {
+                        from: peer_id,
+                        result,
+                    })
+            }
+            QueryAnswered(result) => self.on_query_answered(peer_id, result),
             Error(e) => self.pending_out_events.push_back(OutEvent::Error(e)),
             InboundNegotiated => {}
             OutboundNegotiated => {
@@ -93,16 +576,36 @@ impl NetworkBehaviour for Behaviour {
     }
     fn poll(
         &mut self,
-        _cx: &mut std::task::Context<'_>,
+        cx: &mut std::task::Context<'_>,
     ) -> Poll<ToSwarm<super::OutEvent, handler::FromBehaviour>> {
-        if let Some(peer_id) = self.pending_query_answer.pop_front() {
+        if self.expiry_check_throttle.poll_unpin(cx).is_ready() {
+            self.remove_expired();
+            self.expiry_check_throttle.reset(EXPIRY_CHECK_INTERVAL);
+            // Register the waker, so that expiry is checked without other activity.
+            let _ = self.expiry_check_throttle.poll_unpin(cx);
+        }
+        if self.snapshot_throttle.poll_unpin(cx).is_ready() {
+            self.take_snapshot();
+            self.snapshot_throttle.reset(Duration::from_secs(
+                self.config.snapshot_interval_secs.max(1),
+            ));
+            let _ = self.snapshot_throttle.poll_unpin(cx);
+        }
+        if let Some((peer_id, result)) = self.pending_advertise_result.pop_front() {
+            return Poll::Ready(ToSwarm::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::Any,
+                event: handler::FromBehaviour::AnswerAdvertiseSelf(result),
+            });
+        }
+        if let Some((peer_id, tag)) = self.pending_query_answer.pop_front() {
             trace!("Answering query from {}", peer_id);
             if self.is_providing {
                 return Poll::Ready(ToSwarm::NotifyHandler {
                     peer_id,
                     handler: NotifyHandler::Any,
                     event: handler::FromBehaviour::AnswerAdvertisedPeer(Some(
-                        self.advertised_peers.iter().copied().collect(),
+                        self.answers(tag.as_deref()),
                     )),
                 });
             }
@@ -112,6 +615,13 @@ impl NetworkBehaviour for Behaviour {
                 event: handler::FromBehaviour::AnswerAdvertisedPeer(None),
             });
         }
+        if let Some((peer_id, notice)) = self.pending_notices.pop_front() {
+            return Poll::Ready(ToSwarm::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::Any,
+                event: handler::FromBehaviour::NotifyChange(notice),
+            });
+        }
         if let Some(ev) = self.handle_in_event() {
             return Poll::Ready(ev);
         }
@@ -122,11 +632,27 @@ impl NetworkBehaviour for Behaviour {
     }
 
     fn on_swarm_event(&mut self, event: FromSwarm) {
-        if let FromSwarm::ConnectionClosed(closed) = event {
-            if closed.remaining_established < 1 {
-                self.advertised_peers.remove(&closed.peer_id);
-                self.connected_peers.remove(&closed.peer_id);
+        match event {
+            // Advertisements outlive the connection, until they expire.
+            FromSwarm::ConnectionClosed(closed) => {
+                if closed.remaining_established < 1 {
+                    self.connected_peers.remove(&closed.peer_id);
+                    self.subscribers.remove(&closed.peer_id);
+                }
+            }
+            FromSwarm::NewListenAddr(info) => {
+                self.local_addrs.insert(info.addr.clone());
+            }
+            FromSwarm::ExpiredListenAddr(info) => {
+                self.local_addrs.remove(info.addr);
             }
+            FromSwarm::ExternalAddrConfirmed(info) => {
+                self.local_addrs.insert(info.addr.clone());
+            }
+            FromSwarm::ExternalAddrExpired(info) => {
+                self.local_addrs.remove(info.addr);
+            }
+            _ => {}
         }
     }
 
@@ -157,12 +683,12 @@ impl Behaviour {
         while let Some(ev) = self.in_events.pop_front() {
             use InEvent::*;
             match ev {
-                QueryAdvertisedPeer { peer } => {
+                QueryAdvertisedPeer { peer, tag } => {
                     if self.connected_peers.contains(&peer) {
                         return Some(ToSwarm::NotifyHandler {
                             peer_id: peer,
                             handler: NotifyHandler::Any,
-                            event: handler::FromBehaviour::QueryAdvertisedPeer,
+                            event: handler::FromBehaviour::QueryAdvertisedPeer(tag),
                         });
                     }
                     self.pending_out_events
@@ -176,14 +702,50 @@ impl Behaviour {
                 }
                 SetRemoteAdvertisement {
                     remote,
-                    state,
-                    callback,
+                    advertisement,
+                    token,
                 } => {
-                    handle_callback_sender!(()=>callback);
+                    if !self.connected_peers.contains(&remote) {
+                        self.pending_out_events
+                            .push_back(OutEvent::Error(Error::NotProviding(remote)));
+                        continue;
+                    }
+                    let signed = match advertisement {
+                        Some(advertisement) => {
+                            let advertisement = self.with_local_addrs(advertisement);
+                            match self.sign(advertisement) {
+                                Some(v) => Some(v),
+                                None => continue,
+                            }
+                        }
+                        None => None,
+                    };
                     return Some(ToSwarm::NotifyHandler {
                         peer_id: remote,
                         handler: NotifyHandler::Any,
-                        event: handler::FromBehaviour::SetAdvertiseSelf(state),
+                        event: handler::FromBehaviour::SetAdvertiseSelf {
+                            advertisement: signed,
+                            token,
+                        },
+                    });
+                }
+                SetRemoteSignedAdvertisement {
+                    remote,
+                    advertisement,
+                    token,
+                } => {
+                    if !self.connected_peers.contains(&remote) {
+                        self.pending_out_events
+                            .push_back(OutEvent::Error(Error::NotProviding(remote)));
+                        continue;
+                    }
+                    return Some(ToSwarm::NotifyHandler {
+                        peer_id: remote,
+                        handler: NotifyHandler::Any,
+                        event: handler::FromBehaviour::SetAdvertiseSelf {
+                            advertisement: Some(advertisement),
+                            token,
+                        },
                     });
                 }
                 SetProviderState {
@@ -197,15 +759,35 @@ impl Behaviour {
                     )));
                 }
                 RemoveAdvertised { peer } => {
-                    let result = self.advertised_peers.remove(&peer);
+                    let result = self.remove_record(&peer, ChangeNotice::Removed);
                     return Some(ToSwarm::GenerateEvent(OutEvent::AdvertisedPeerChanged(
                         peer, result,
                     )));
                 }
                 ListAdvertised { callback } => {
-                    handle_callback_sender!(self.advertised_peers.iter().cloned().collect()=>callback);
+                    handle_callback_sender!(self.records(None).into()=>callback);
+                }
+                ClearAdvertised {} => self.clear_advertised(),
+                Subscribe { provider, tag } => {
+                    if self.connected_peers.contains(&provider) {
+                        return Some(ToSwarm::NotifyHandler {
+                            peer_id: provider,
+                            handler: NotifyHandler::Any,
+                            event: handler::FromBehaviour::Subscribe(tag),
+                        });
+                    }
+                    self.pending_out_events
+                        .push_back(OutEvent::Error(Error::NotProviding(provider)))
+                }
+                Unsubscribe { provider } => {
+                    if self.connected_peers.contains(&provider) {
+                        return Some(ToSwarm::NotifyHandler {
+                            peer_id: provider,
+                            handler: NotifyHandler::Any,
+                            event: handler::FromBehaviour::Unsubscribe,
+                        });
+                    }
                 }
-                ClearAdvertised {} => self.advertised_peers.clear(),
                 ListConnected { callback } => {
                     handle_callback_sender!(self.connected_peers.iter().copied().collect() => callback);
                 }
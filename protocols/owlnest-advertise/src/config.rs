@@ -1,24 +1,126 @@
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Config {
-    pub timeout_ms: u64,
-    pub max_advertise_capacity: usize,
-}
-impl Config {
-    pub fn new() -> Self {
-        Self::default()
-    }
-    pub fn with_timeout(mut self, timeout_ms: u64) -> Self {
-        self.timeout_ms = timeout_ms;
-        self
-    }
-}
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            timeout_ms: 30 * 1000,
-            max_advertise_capacity: 32,
-        }
-    }
-}
+use owlnest_prelude::lib_prelude::PeerId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub timeout_ms: u64,
+    /// Advertisements stored at most, see `eviction` for what happens beyond.
+    pub max_advertise_capacity: usize,
+    /// Upper bound of the time to live requested by advertisers.
+    /// Advertisements are kept until they expire, even if the advertiser
+    /// has disconnected.
+    pub max_ttl_secs: u64,
+    /// Who can advertise on local provider.
+    pub access: Access,
+    pub rate_limit: RateLimit,
+    pub eviction: Eviction,
+    /// Path to the snapshot of local provider, so that its state and
+    /// advertisements survive restart. Persistence is disabled if left blank.
+    pub persist_path: String,
+    /// Interval between snapshots, only taken if something has changed.
+    pub snapshot_interval_secs: u64,
+}
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_timeout(mut self, timeout_ms: u64) -> Self {
+        self.timeout_ms = timeout_ms;
+        self
+    }
+    pub fn with_max_ttl(mut self, max_ttl_secs: u64) -> Self {
+        self.max_ttl_secs = max_ttl_secs;
+        self
+    }
+    pub fn with_capacity(mut self, max_advertise_capacity: usize, eviction: Eviction) -> Self {
+        self.max_advertise_capacity = max_advertise_capacity;
+        self.eviction = eviction;
+        self
+    }
+    pub fn with_access(mut self, access: Access) -> Self {
+        self.access = access;
+        self
+    }
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+    pub fn with_persistence(
+        mut self,
+        persist_path: impl Into<String>,
+        snapshot_interval_secs: u64,
+    ) -> Self {
+        self.persist_path = persist_path.into();
+        self.snapshot_interval_secs = snapshot_interval_secs;
+        self
+    }
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 30 * 1000,
+            max_advertise_capacity: 32,
+            max_ttl_secs: 24 * 60 * 60,
+            access: Access::default(),
+            rate_limit: RateLimit::default(),
+            eviction: Eviction::default(),
+            persist_path: String::new(),
+            snapshot_interval_secs: 60,
+        }
+    }
+}
+
+/// Access control of local provider.
+/// Retracting an advertisement is always allowed.
+/// Queries and subscriptions are not controlled, advertisements are signed
+/// and meant to be found by any peer, only posting them is restricted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Access {
+    pub mode: AccessMode,
+    /// Peers allowed to advertise in `AllowList` mode.
+    pub allow_list: Vec<PeerId>,
+    /// Token that advertisers must supply in `Token` mode.
+    /// No advertiser is accepted if it's empty.
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccessMode {
+    /// Any connected peer can advertise.
+    #[default]
+    Open,
+    /// Only peers in `allow_list` can advertise.
+    AllowList,
+    /// Only peers that supply `token` can advertise.
+    Token,
+}
+
+/// Advertise requests each peer can make within an interval,
+/// requests beyond are rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// 0 for no limit.
+    pub max_requests: u32,
+    pub interval_secs: u64,
+}
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            max_requests: 10,
+            interval_secs: 60,
+        }
+    }
+}
+
+/// What to do with a new advertisement when the capacity is reached.
+/// Renewals are always accepted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Eviction {
+    /// Reject the new advertisement.
+    #[default]
+    RejectNew,
+    /// Remove the advertisement that was first posted.
+    Oldest,
+    /// Remove the advertisement that was renewed the longest time ago.
+    LeastRecentlyRenewed,
+}
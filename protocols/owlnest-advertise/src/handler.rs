@@ -1,4 +1,5 @@
-use super::{protocol, Error, PeerId};
+use super::record::{AnsweredRecord, ChangeNotice};
+use super::{protocol, Error, Rejection, SignedAdvertisement};
 use futures_timer::Delay;
 use owlnest_prelude::handler_prelude::*;
 use serde::{Deserialize, Serialize};
@@ -7,15 +8,29 @@ use tracing::trace;
 
 #[derive(Debug)]
 pub enum FromBehaviour {
-    QueryAdvertisedPeer,
-    AnswerAdvertisedPeer(Option<Box<[PeerId]>>),
-    SetAdvertiseSelf(bool),
+    QueryAdvertisedPeer(Option<String>),
+    AnswerAdvertisedPeer(Option<Box<[AnsweredRecord]>>),
+    SetAdvertiseSelf {
+        advertisement: Option<SignedAdvertisement>,
+        token: Option<String>,
+    },
+    AnswerAdvertiseSelf(Result<(), Rejection>),
+    Subscribe(Option<String>),
+    Unsubscribe,
+    NotifyChange(ChangeNotice),
 }
 #[derive(Debug)]
 pub enum ToBehaviour {
-    IncomingQuery,
-    QueryAnswered(Option<Box<[PeerId]>>),
-    IncomingAdvertiseReq(bool),
+    IncomingQuery(Option<String>),
+    QueryAnswered(Option<Box<[AnsweredRecord]>>),
+    IncomingAdvertiseReq {
+        advertisement: Option<SignedAdvertisement>,
+        token: Option<String>,
+    },
+    AdvertiseAnswered(Result<(), Rejection>),
+    IncomingSubscribe(Option<String>),
+    IncomingUnsubscribe,
+    ChangeNotified(ChangeNotice),
     Error(Error),
     InboundNegotiated,
     OutboundNegotiated,
@@ -23,8 +38,18 @@ pub enum ToBehaviour {
 impl From<Packet> for ToBehaviour {
     fn from(value: Packet) -> Self {
         match value {
-            Packet::AdvertiseSelf(bool) => ToBehaviour::IncomingAdvertiseReq(bool),
-            Packet::QueryAdvertisedPeer => ToBehaviour::IncomingQuery,
+            Packet::AdvertiseSelf {
+                advertisement,
+                token,
+            } => ToBehaviour::IncomingAdvertiseReq {
+                advertisement,
+                token,
+            },
+            Packet::AdvertiseResult(result) => ToBehaviour::AdvertiseAnswered(result),
+            Packet::Subscribe(tag) => ToBehaviour::IncomingSubscribe(tag),
+            Packet::Unsubscribe => ToBehaviour::IncomingUnsubscribe,
+            Packet::Change(notice) => ToBehaviour::ChangeNotified(notice),
+            Packet::QueryAdvertisedPeer(tag) => ToBehaviour::IncomingQuery(tag),
             Packet::AnswerAdvertisedPeer(result) => ToBehaviour::QueryAnswered(result),
         }
     }
@@ -42,9 +67,22 @@ impl Default for State {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 enum Packet {
-    AdvertiseSelf(bool),
-    QueryAdvertisedPeer,
-    AnswerAdvertisedPeer(Option<Box<[PeerId]>>),
+    /// Advertise the sender, or retract with `None`.
+    AdvertiseSelf {
+        advertisement: Option<SignedAdvertisement>,
+        token: Option<String>,
+    },
+    /// Whether the advertisement is accepted.
+    AdvertiseResult(Result<(), Rejection>),
+    /// Subscribe to changes, only those with the tag if supplied.
+    /// Answered with current advertisements by `AnswerAdvertisedPeer`.
+    Subscribe(Option<String>),
+    Unsubscribe,
+    /// A change pushed to subscribers.
+    Change(ChangeNotice),
+    /// Query for advertisements, only those with the tag if supplied.
+    QueryAdvertisedPeer(Option<String>),
+    AnswerAdvertisedPeer(Option<Box<[AnsweredRecord]>>),
 }
 impl Packet {
     #[inline]
@@ -239,9 +277,9 @@ impl Handler {
                     trace!("Taking out event {:?} from behaviour", ev);
                     use FromBehaviour::*;
                     match ev {
-                        QueryAdvertisedPeer => {
+                        QueryAdvertisedPeer(tag) => {
                             self.outbound = Some(OutboundState::Busy(
-                                protocol::send(stream, Packet::QueryAdvertisedPeer.as_bytes())
+                                protocol::send(stream, Packet::QueryAdvertisedPeer(tag).as_bytes())
                                     .boxed(),
                                 Delay::new(self.timeout),
                             ))
@@ -256,13 +294,44 @@ impl Handler {
                                 Delay::new(self.timeout),
                             ))
                         }
-                        SetAdvertiseSelf(state) => {
+                        SetAdvertiseSelf {
+                            advertisement,
+                            token,
+                        } => {
+                            let packet = Packet::AdvertiseSelf {
+                                advertisement,
+                                token,
+                            };
                             self.outbound = Some(OutboundState::Busy(
-                                protocol::send(stream, Packet::AdvertiseSelf(state).as_bytes())
+                                protocol::send(stream, packet.as_bytes()).boxed(),
+                                Delay::new(self.timeout),
+                            ))
+                        }
+                        AnswerAdvertiseSelf(result) => {
+                            self.outbound = Some(OutboundState::Busy(
+                                protocol::send(stream, Packet::AdvertiseResult(result).as_bytes())
                                     .boxed(),
                                 Delay::new(self.timeout),
                             ))
                         }
+                        Subscribe(tag) => {
+                            self.outbound = Some(OutboundState::Busy(
+                                protocol::send(stream, Packet::Subscribe(tag).as_bytes()).boxed(),
+                                Delay::new(self.timeout),
+                            ))
+                        }
+                        Unsubscribe => {
+                            self.outbound = Some(OutboundState::Busy(
+                                protocol::send(stream, Packet::Unsubscribe.as_bytes()).boxed(),
+                                Delay::new(self.timeout),
+                            ))
+                        }
+                        NotifyChange(notice) => {
+                            self.outbound = Some(OutboundState::Busy(
+                                protocol::send(stream, Packet::Change(notice).as_bytes()).boxed(),
+                                Delay::new(self.timeout),
+                            ))
+                        }
                     }
                 }
                 Some(OutboundState::OpenStream) => {
@@ -5,35 +5,50 @@ use serde::{Deserialize, Serialize};
 pub mod behaviour;
 pub mod config;
 mod handler;
+mod persist;
+pub mod record;
 
 pub use behaviour::Behaviour;
 pub use protocol::PROTOCOL_NAME;
+pub use record::{AdvertisedRecord, Advertisement, AdvertisementChange, SignedAdvertisement};
 
 #[derive(Debug, Clone)]
 pub enum OutEvent {
     /// A query sent to a remote peer is answered.
+    /// `list` is `None` if the remote is not providing.
     QueryAnswered {
         from: PeerId,
-        list: Option<Box<[PeerId]>>,
+        list: Option<Box<[AdvertisedRecord]>>,
     },
     /// A advertisement result from remote peer arrived.
     RemoteAdvertisementResult {
         from: PeerId,
-        result: Result<(), ()>,
+        result: Result<(), Rejection>,
     },
     /// Local provider state.
     ProviderState(bool),
+    /// Advertisement of the peer is added(`true`), or removed or expired(`false`)
+    /// on local provider.
     AdvertisedPeerChanged(PeerId, bool),
+    /// Advertisements on a remote provider that local peer subscribes to have changed.
+    RemoteAdvertisementChanged {
+        from: PeerId,
+        change: AdvertisementChange,
+    },
     Error(Error),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Error {
     ConnectionClosed,
     VerifierMismatch,
     /// Queried peer is not providing or doesn't support this protocol.
     NotProviding(PeerId),
     Timeout,
+    /// The advertisement has a bad signature, or is stale or replayed.
+    InvalidAdvertisement(String),
+    /// The remote provider rejected the advertisement.
+    Rejected(Rejection),
     UnrecognizedMessage(String), // Serialzied not available on the original type
     IO(String),                  // Serialize not available on the original type
     Channel,
@@ -47,6 +62,8 @@ impl std::fmt::Display for Error {
             VerifierMismatch => f.write_str("Message verifier mismatch"),
             Timeout => f.write_str("Message timed out"),
             NotProviding(peer) => write!(f, "Peer {peer} is not providing"),
+            InvalidAdvertisement(msg) => write!(f, "Invalid advertisement: {msg}"),
+            Rejected(reason) => write!(f, "Advertisement rejected: {reason}"),
             UnrecognizedMessage(msg) => f.write_str(msg),
             IO(msg) => f.write_str(msg),
             Channel => f.write_str("Callback channel closed unexpectedly"),
@@ -54,8 +71,37 @@ impl std::fmt::Display for Error {
     }
 }
 
+/// Reasons for a provider to reject an advertisement.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rejection {
+    NotProviding,
+    /// Not in the allow list of the provider.
+    NotAllowed,
+    /// The token is missing or doesn't match.
+    InvalidToken,
+    /// Too many requests within the interval.
+    RateLimited,
+    /// The provider is full and doesn't evict advertisements.
+    CapacityReached,
+    /// The advertisement has a bad signature, or is stale or replayed.
+    Invalid(String),
+}
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Rejection::*;
+        match self {
+            NotProviding => f.write_str("Provider is not providing"),
+            NotAllowed => f.write_str("Not in the allow list"),
+            InvalidToken => f.write_str("Token is missing or doesn't match"),
+            RateLimited => f.write_str("Too many requests"),
+            CapacityReached => f.write_str("Provider is full"),
+            Invalid(msg) => write!(f, "Invalid advertisement: {msg}"),
+        }
+    }
+}
+
 mod protocol {
-    pub const PROTOCOL_NAME: &str = "/owlnest/advertise/0.0.1";
+    pub const PROTOCOL_NAME: &str = "/owlnest/advertise/0.0.5";
     pub use owlnest_prelude::utils::protocol::universal::*;
 }
 
@@ -70,15 +116,39 @@ pub enum InEvent {
     GetProviderState {
         callback: Callback<bool>,
     },
-    /// Send a query to a remote peer for advertised peers.
+    /// Send a query to a remote peer for advertised peers,
+    /// only those with the tag if supplied.
     QueryAdvertisedPeer {
         peer: PeerId,
+        tag: Option<String>,
     },
-    /// Set remote provider state to advertise or stop advertising local peer.
+    /// Post an advertisement of local peer on the remote provider,
+    /// or retract it with `None`.
+    /// The result is reported by [`OutEvent::RemoteAdvertisementResult`].
     SetRemoteAdvertisement {
         remote: PeerId,
-        state: bool,
-        callback: Callback<()>,
+        advertisement: Option<Advertisement>,
+        /// Required by providers in [`config::AccessMode::Token`] mode.
+        token: Option<String>,
+    },
+    /// Post an advertisement that is already signed, as is.
+    /// Providers only accept it if it's signed by local peer.
+    /// The result is reported by [`OutEvent::RemoteAdvertisementResult`].
+    SetRemoteSignedAdvertisement {
+        remote: PeerId,
+        advertisement: SignedAdvertisement,
+        token: Option<String>,
+    },
+    /// Get pushed changes of advertisements on a remote provider,
+    /// only those with the tag if supplied.
+    /// Current advertisements are answered by [`OutEvent::QueryAnswered`].
+    Subscribe {
+        provider: PeerId,
+        tag: Option<String>,
+    },
+    /// Stop getting pushed changes from a remote provider.
+    Unsubscribe {
+        provider: PeerId,
     },
     /// Remove a advertised peer from local provider.
     RemoveAdvertised {
@@ -87,7 +157,7 @@ pub enum InEvent {
     /// Remove all advertised peers from local provider.
     ClearAdvertised {},
     ListAdvertised {
-        callback: Callback<Box<[PeerId]>>,
+        callback: Callback<Box<[AdvertisedRecord]>>,
     },
     ListConnected {
         callback: Callback<Box<[PeerId]>>,
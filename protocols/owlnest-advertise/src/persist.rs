@@ -0,0 +1,42 @@
+//! Snapshots of local provider, see [`config::Config::persist_path`].
+use super::*;
+use owlnest_core::snapshot::{self, SnapshotWriter};
+use std::path::PathBuf;
+
+/// An advertisement as written to disk.
+/// Times are unix time in milliseconds, as `Instant` can't outlive the process.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct PersistedRecord {
+    pub signed: SignedAdvertisement,
+    pub expires_at: u64,
+    pub created_at: u64,
+    pub renewed_at: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    pub is_providing: bool,
+    pub records: Vec<PersistedRecord>,
+}
+
+/// Writes snapshots to disk on a dedicated thread.
+#[derive(Debug)]
+pub(crate) struct Persister {
+    writer: SnapshotWriter<Snapshot>,
+}
+impl Persister {
+    /// Load the last snapshot at the given path, `None` if the file
+    /// doesn't exist or cannot be parsed.
+    pub fn load(path: PathBuf) -> (Self, Option<Snapshot>) {
+        let snapshot = snapshot::load(&path, "advertise snapshot");
+        let writer = SnapshotWriter::spawn(path, "advertise snapshot");
+        (Self { writer }, snapshot)
+    }
+    pub fn save(&self, snapshot: Snapshot) {
+        self.writer.save(snapshot);
+    }
+    /// Save and wait until the snapshot is on disk.
+    pub fn flush(&self, snapshot: Snapshot) {
+        self.writer.flush(snapshot);
+    }
+}
@@ -0,0 +1,201 @@
+use super::*;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Time to live of an advertisement if not set by the advertiser.
+pub const DEFAULT_TTL_SECS: u64 = 60 * 60;
+const DOMAIN_SEPARATION: &str = "owlnest-advertise";
+const PAYLOAD_TYPE: &[u8] = b"/owlnest/advertise/record";
+
+/// Information a peer posts on a provider about itself.
+/// Addresses are filled with those of the local peer when sent,
+/// if both `listen_addrs` and `relay_addrs` are left empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Advertisement {
+    /// Addresses the peer can be reached on directly.
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Addresses the peer can be reached on through a relay.
+    pub relay_addrs: Vec<Multiaddr>,
+    /// Services provided by the peer, used for filtering queries.
+    pub tags: Vec<String>,
+    /// Free-form information about the peer.
+    pub metadata: BTreeMap<String, String>,
+    /// Seconds until the advertisement expires on the provider,
+    /// capped by the provider's `max_ttl_secs`.
+    pub ttl_secs: u64,
+}
+impl Advertisement {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_listen_addrs(mut self, addrs: impl IntoIterator<Item = Multiaddr>) -> Self {
+        self.listen_addrs = addrs.into_iter().collect();
+        self
+    }
+    pub fn with_relay_addrs(mut self, addrs: impl IntoIterator<Item = Multiaddr>) -> Self {
+        self.relay_addrs = addrs.into_iter().collect();
+        self
+    }
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+    pub fn with_ttl(mut self, ttl_secs: u64) -> Self {
+        self.ttl_secs = ttl_secs;
+        self
+    }
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|v| v == tag)
+    }
+}
+impl Default for Advertisement {
+    fn default() -> Self {
+        Self {
+            listen_addrs: Vec::new(),
+            relay_addrs: Vec::new(),
+            tags: Vec::new(),
+            metadata: BTreeMap::new(),
+            ttl_secs: DEFAULT_TTL_SECS,
+        }
+    }
+}
+
+/// An advertisement as stored on a provider, verified to be signed by `peer`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdvertisedRecord {
+    /// The peer that posted and signed the advertisement.
+    pub peer: PeerId,
+    /// Sequence number of the advertisement, see [`SignedAdvertisement::sign`].
+    pub seq: u64,
+    pub advertisement: Advertisement,
+    /// Seconds until the record expires on the provider, at the time of answering.
+    pub expires_in_secs: u64,
+}
+
+/// An advertisement in a signed envelope, so that it can't be posted
+/// on behalf of others or altered by the provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedAdvertisement {
+    /// Protobuf encoding of the signed envelope.
+    envelope: Vec<u8>,
+}
+impl SignedAdvertisement {
+    /// Sign the advertisement with the keypair of the advertiser.
+    /// `seq` must increase with every advertisement signed by the same peer,
+    /// it's the unix time in milliseconds when signed by [`crate::Behaviour`].
+    pub fn sign(
+        keypair: &Keypair,
+        seq: u64,
+        advertisement: Advertisement,
+    ) -> Result<Self, SigningError> {
+        let payload = serde_json::to_vec(&SignedPayload { seq, advertisement })
+            .expect("Serialization to succeed");
+        let envelope = SignedEnvelope::new(
+            keypair,
+            DOMAIN_SEPARATION.into(),
+            PAYLOAD_TYPE.to_vec(),
+            payload,
+        )?;
+        Ok(Self {
+            envelope: envelope.into_protobuf_encoding(),
+        })
+    }
+    /// Check the signature, returns the signer and the signed content.
+    pub(crate) fn verify(&self) -> Result<(PeerId, SignedPayload), Error> {
+        let envelope = SignedEnvelope::from_protobuf_encoding(&self.envelope)
+            .map_err(|e| Error::InvalidAdvertisement(e.to_string()))?;
+        let (payload, key) = envelope
+            .payload_and_signing_key(DOMAIN_SEPARATION.into(), PAYLOAD_TYPE)
+            .map_err(|e| Error::InvalidAdvertisement(e.to_string()))?;
+        let payload = serde_json::from_slice(payload)
+            .map_err(|e| Error::InvalidAdvertisement(e.to_string()))?;
+        Ok((key.to_peer_id(), payload))
+    }
+}
+
+/// Content of a [`SignedAdvertisement`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SignedPayload {
+    pub seq: u64,
+    pub advertisement: Advertisement,
+}
+impl SignedPayload {
+    /// Whether the advertisement was signed longer ago than its TTL,
+    /// so that expired advertisements can't be replayed.
+    pub fn is_stale(&self) -> bool {
+        unix_millis().saturating_sub(self.seq) > self.advertisement.ttl_secs.saturating_mul(1000)
+    }
+}
+
+/// An advertisement as answered by a provider, verified again by the querier.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct AnsweredRecord {
+    pub signed: SignedAdvertisement,
+    pub expires_in_secs: u64,
+}
+impl AnsweredRecord {
+    pub fn verify(&self) -> Result<AdvertisedRecord, Error> {
+        let (peer, payload) = self.signed.verify()?;
+        if payload.is_stale() {
+            return Err(Error::InvalidAdvertisement(format!(
+                "Advertisement from {peer} has expired"
+            )));
+        }
+        let SignedPayload { seq, advertisement } = payload;
+        Ok(AdvertisedRecord {
+            peer,
+            seq,
+            advertisement,
+            expires_in_secs: self.expires_in_secs,
+        })
+    }
+}
+
+/// A change to advertisements on a provider, pushed to subscribers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdvertisementChange {
+    Added(AdvertisedRecord),
+    Renewed(AdvertisedRecord),
+    Expired(PeerId),
+    /// Retracted by the advertiser, evicted or removed by the provider.
+    Removed(PeerId),
+}
+impl AdvertisementChange {
+    /// The advertiser whose advertisement has changed.
+    pub fn peer(&self) -> &PeerId {
+        match self {
+            Self::Added(record) | Self::Renewed(record) => &record.peer,
+            Self::Expired(peer) | Self::Removed(peer) => peer,
+        }
+    }
+}
+
+/// [`AdvertisementChange`] as sent by a provider, verified again by the subscriber.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum ChangeNotice {
+    Added(AnsweredRecord),
+    Renewed(AnsweredRecord),
+    Expired(PeerId),
+    Removed(PeerId),
+}
+impl ChangeNotice {
+    pub fn verify(&self) -> Result<AdvertisementChange, Error> {
+        Ok(match self {
+            Self::Added(record) => AdvertisementChange::Added(record.verify()?),
+            Self::Renewed(record) => AdvertisementChange::Renewed(record.verify()?),
+            Self::Expired(peer) => AdvertisementChange::Expired(*peer),
+            Self::Removed(peer) => AdvertisementChange::Removed(*peer),
+        })
+    }
+}
+
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_millis() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,204 @@
+//! Accepting incoming files without user interaction, see [`config::AutoAccept`].
+use super::*;
+use crate::config::AutoAccept;
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::sync::mpsc;
+
+/// Outcome of the auto-accept policy for an incoming file.
+#[derive(Debug, Clone, Serialize)]
+pub enum AutoAcceptDecision {
+    /// The file is being written to the path.
+    Accepted { path: PathBuf },
+    /// The offer is cancelled.
+    Rejected { reason: String },
+    /// The offer doesn't match the policy and is left for manual handling.
+    Deferred { reason: String },
+}
+
+/// Files that have been or are about to be accepted by the policy.
+#[derive(Debug)]
+pub(crate) struct AutoAcceptor {
+    /// Bytes reserved against the disk quota, indexed by local recv ID.
+    reserved: HashMap<u64, u64>,
+    prepared_tx: mpsc::UnboundedSender<(u64, Result<(File, PathBuf), String>)>,
+    prepared_rx: mpsc::UnboundedReceiver<(u64, Result<(File, PathBuf), String>)>,
+}
+impl Default for AutoAcceptor {
+    fn default() -> Self {
+        let (prepared_tx, prepared_rx) = mpsc::unbounded_channel();
+        Self {
+            reserved: HashMap::new(),
+            prepared_tx,
+            prepared_rx,
+        }
+    }
+}
+impl AutoAcceptor {
+    /// Pick a path and create the partial file on the blocking thread pool.
+    /// The result will be available from [`AutoAcceptor::poll_prepared`].
+    pub fn prepare(
+        &mut self,
+        policy: &AutoAccept,
+        local_recv_id: u64,
+        file_name: String,
+        bytes_total: u64,
+        is_active: impl Fn(u64) -> bool,
+    ) {
+        // Recvs that are done no longer take up space beyond their files.
+        self.reserved.retain(|id, _| is_active(*id));
+        let reserved = self.reserved.values().sum();
+        self.reserved.insert(local_recv_id, bytes_total);
+        let download_dir = PathBuf::from(&policy.download_dir);
+        let disk_quota = policy.disk_quota;
+        let tx = self.prepared_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let result =
+                create_target(&download_dir, &file_name, bytes_total, reserved, disk_quota);
+            let _ = tx.send((local_recv_id, result));
+        });
+    }
+    /// Poll for the next file prepared, along with its local recv ID.
+    pub fn poll_prepared(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<(u64, Result<(File, PathBuf), String>)> {
+        self.prepared_rx
+            .poll_recv(cx)
+            .map(|v| v.expect("Sender to be kept alive"))
+    }
+    /// Release the reservation of a file that is not accepted after all.
+    pub fn release(&mut self, local_recv_id: u64) {
+        self.reserved.remove(&local_recv_id);
+    }
+}
+
+/// Check the offer against the policy, returns the reason if it doesn't match.
+pub(crate) fn check(
+    policy: &AutoAccept,
+    from: &PeerId,
+    file_name: &str,
+    bytes_total: u64,
+    size_is_hint: bool,
+) -> Result<(), String> {
+    if !policy.trusted_peers.contains(from) {
+        return Err(format!("Peer {from} is not trusted"));
+    }
+    if size_is_hint && (policy.max_file_size > 0 || policy.disk_quota > 0) {
+        return Err("Size of streamed data is unknown".into());
+    }
+    if policy.max_file_size > 0 && bytes_total > policy.max_file_size {
+        return Err(format!(
+            "File size {bytes_total} exceeds the limit of {}",
+            policy.max_file_size
+        ));
+    }
+    if !policy.file_patterns.is_empty()
+        && !policy
+            .file_patterns
+            .iter()
+            .any(|pattern| glob_match(pattern, file_name))
+    {
+        return Err(format!("File name {file_name:?} doesn't match any pattern"));
+    }
+    Ok(())
+}
+
+/// Create an empty partial file in the directory with a name that is not taken,
+/// after checking the disk quota.
+fn create_target(
+    download_dir: &Path,
+    file_name: &str,
+    bytes_total: u64,
+    reserved: u64,
+    disk_quota: u64,
+) -> Result<(File, PathBuf), String> {
+    // Only the last component is used, so the file can't escape the directory.
+    let file_name = match Path::new(file_name).file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => return Err(format!("Invalid file name {file_name:?}")),
+    };
+    std::fs::create_dir_all(download_dir)
+        .map_err(|e| format!("Cannot create {download_dir:?}: {e}"))?;
+    if disk_quota > 0 {
+        let used =
+            dir_usage(download_dir).map_err(|e| format!("Cannot read {download_dir:?}: {e}"))?;
+        if used + reserved + bytes_total > disk_quota {
+            return Err(format!(
+                "Disk quota of {disk_quota} bytes exceeded, {} bytes in use",
+                used + reserved
+            ));
+        }
+    }
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (file_name.as_str(), String::new()),
+    };
+    for attempt in 0u32.. {
+        let candidate = if attempt == 0 {
+            download_dir.join(&file_name)
+        } else {
+            download_dir.join(format!("{stem} ({attempt}){extension}"))
+        };
+        if candidate.exists() {
+            continue;
+        }
+        // Creating the partial file claims the name.
+        match std::fs::OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(part_path(&candidate))
+        {
+            Ok(file) => return Ok((file, candidate)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("Cannot create {candidate:?}: {e}")),
+        }
+    }
+    unreachable!()
+}
+
+/// Total size of complete files directly in the directory.
+/// Partial files are counted by their reservations instead.
+fn dir_usage(dir: &Path) -> std::io::Result<u64> {
+    let mut used = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && !entry.file_name().to_string_lossy().ends_with(".part") {
+            used += metadata.len();
+        }
+    }
+    Ok(used)
+}
+
+/// Match the name against a pattern where `*` matches any sequence
+/// and `?` matches a single character.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it was tried at.
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, tried)) => {
+                    p = star + 1;
+                    n = tried + 1;
+                    backtrack = Some((star, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
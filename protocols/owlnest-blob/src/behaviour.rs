@@ -1,11 +1,21 @@
+use super::auto_accept::{self, AutoAcceptDecision, AutoAcceptor};
+use super::bundle::{sanitize_relative_path, BundleFile, BundleFileInfo, BundleInfo, Bundles};
+use super::compression::{self, Compressor};
+use super::config::{SendQueue, Throttle};
+use super::file_io::{part_path, FileReader, FileWriter, Sink, Source, WriteError};
+use super::journal::{Journal, JournalRecv, JournalSend};
+use super::share::{CatalogEntry, Served, ShareServer, SharedFile};
+use super::store::{ServedChunk, StoreServer};
+use super::throttle::TokenBucket;
 use super::*;
 use futures::FutureExt;
 use futures_timer::Delay;
-use handler::FromBehaviourEvent;
+use handler::{messages, FromBehaviourEvent};
 use owlnest_macro::handle_callback_sender;
 use owlnest_prelude::behaviour_prelude::*;
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Instant;
 use tracing::{debug, debug_span, warn, Span};
 
 pub(crate) const FILE_CHUNK_SIZE: usize = 1 << 18; // 256KB
@@ -33,6 +43,10 @@ pub struct Behaviour {
     recv_counter: u64,
     /// Unique ID tracker for send operations.
     send_counter: u64,
+    /// Unique ID tracker for bundles, shared by both directions.
+    bundle_counter: u64,
+    /// Unique ID tracker for browse, fetch and chunk requests.
+    request_counter: u64,
     /// List of pending receive indexed by recv ID.
     pending_recv: HashMap<u64, PendingRecv>,
     /// List of pending send indexed by send ID.
@@ -43,6 +57,34 @@ pub struct Behaviour {
     /// List of ongoing send indexed by local send id.
     /// If the record is removed from the list, no more bytes will be send to remote.
     ongoing_send: HashMap<u64, OngoingFileSend>,
+    /// Recvs that lost connection to the sender, indexed by local recv id.
+    interrupted_recv: HashMap<u64, InterruptedRecv>,
+    /// Recvs waiting for the sender to accept the resumption, indexed by transfer id.
+    pending_resume: HashMap<u64, PendingResume>,
+    /// Sends that lost connection to the receiver, indexed by transfer id.
+    resumable_send: HashMap<u64, ResumableSend>,
+    /// Unfinished transfers persisted to disk, `None` if disabled.
+    journal: Option<Journal>,
+    /// Aggregate progress of multi-file transfers.
+    bundles: Bundles,
+    /// Files being accepted by the auto-accept policy.
+    auto_acceptor: AutoAcceptor,
+    /// Sends accepted by remote that wait for a free slot, in the order they are accepted.
+    queued_send: VecDeque<PendingSend>,
+    /// Limit of all sends combined, `None` if unlimited.
+    send_bucket: Option<TokenBucket>,
+    /// Wakes the behaviour up once throttled sends can continue.
+    throttle_timer: Option<Delay>,
+    /// Serves browse and fetch requests from remote.
+    share_server: ShareServer,
+    /// Browse requests waiting for remote to respond, indexed by request ID.
+    pending_browse: HashMap<u64, PendingBrowse>,
+    /// Fetch requests waiting for remote to offer the file, indexed by request ID.
+    pending_fetch: HashMap<u64, PendingFetch>,
+    /// Serves chunk requests from remote.
+    store_server: StoreServer,
+    /// Chunk requests waiting for remote to respond, indexed by request ID.
+    pending_chunk: HashMap<u64, PendingChunk>,
     expiry_check_throttle: Delay,
 }
 impl Default for Behaviour {
@@ -54,10 +96,26 @@ impl Default for Behaviour {
             connected_peers: Default::default(),
             recv_counter: Default::default(),
             send_counter: Default::default(),
+            bundle_counter: Default::default(),
+            request_counter: Default::default(),
             pending_recv: Default::default(),
             pending_send: Default::default(),
             ongoing_recv: Default::default(),
             ongoing_send: Default::default(),
+            interrupted_recv: Default::default(),
+            pending_resume: Default::default(),
+            resumable_send: Default::default(),
+            journal: None,
+            bundles: Default::default(),
+            auto_acceptor: Default::default(),
+            queued_send: Default::default(),
+            send_bucket: None,
+            throttle_timer: None,
+            share_server: Default::default(),
+            pending_browse: Default::default(),
+            pending_fetch: Default::default(),
+            store_server: StoreServer::new(""),
+            pending_chunk: Default::default(),
             expiry_check_throttle: Delay::new(Duration::from_secs(5)),
         }
     }
@@ -72,6 +130,43 @@ pub struct RecvInfo {
     pub file_name: String,
     pub remote: PeerId,
     pub timestamp: u64,
+    pub transfer_id: u64,
+    /// Whether the recv has been accepted.
+    pub started: bool,
+    /// Whether the connection to the sender was lost, see [`InEvent::ResumeRecv`].
+    pub interrupted: bool,
+}
+impl RecvInfo {
+    /// Percentage of bytes received.
+    pub fn percent(&self) -> f64 {
+        percent(self.bytes_received, self.bytes_total)
+    }
+}
+impl std::fmt::Display for RecvInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "ID({}){:30}",
+            self.local_recv_id,
+            tail(&self.file_name, 30)
+        )?;
+        writeln!(f, "Remote: {}", self.remote)?;
+        if self.started {
+            write!(
+                f,
+                "Bytes total: {}; Bytes received: {}({:.1}%)",
+                self.bytes_total,
+                self.bytes_received,
+                self.percent()
+            )?;
+        } else {
+            write!(f, "Bytes total: {}", self.bytes_total)?;
+        }
+        if self.interrupted {
+            write!(f, "; Interrupted")?;
+        }
+        Ok(())
+    }
 }
 impl From<&PendingRecv> for RecvInfo {
     fn from(value: &PendingRecv) -> Self {
@@ -82,6 +177,9 @@ impl From<&PendingRecv> for RecvInfo {
             bytes_total: value.bytes_total,
             bytes_received: 0,
             timestamp: value.timestamp,
+            transfer_id: value.transfer_id,
+            started: false,
+            interrupted: false,
         }
     }
 }
@@ -99,6 +197,29 @@ impl From<&OngoingFileRecv> for RecvInfo {
                 .to_string(),
             remote: value.remote,
             timestamp: value.last_active,
+            transfer_id: value.transfer_id,
+            started: true,
+            interrupted: false,
+        }
+    }
+}
+impl From<&InterruptedRecv> for RecvInfo {
+    fn from(value: &InterruptedRecv) -> Self {
+        Self {
+            local_recv_id: value.local_recv_id,
+            bytes_total: value.bytes_total,
+            bytes_received: value.bytes_received,
+            file_name: value
+                .file_path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+            remote: value.remote,
+            timestamp: value.timestamp,
+            transfer_id: value.transfer_id,
+            started: true,
+            interrupted: true,
         }
     }
 }
@@ -112,28 +233,60 @@ pub struct SendInfo {
     pub started: bool,
     pub remote: PeerId,
     pub file_path: PathBuf,
+    pub transfer_id: u64,
+    /// Whether the connection to the receiver was lost.
+    pub interrupted: bool,
+    /// Whether the send is accepted by the receiver but waits for a free slot.
+    pub queued: bool,
+    pub priority: u8,
+}
+impl SendInfo {
+    /// Percentage of bytes sent.
+    pub fn percent(&self) -> f64 {
+        percent(self.bytes_sent, self.bytes_total)
+    }
 }
 impl std::fmt::Display for SendInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let file_path = self.file_path.to_string_lossy();
-        writeln!(
-            f,
-            "ID({}){:30}",
-            self.local_send_id,
-            &file_path[file_path.len().saturating_sub(30)..]
-        )?;
+        writeln!(f, "ID({}){:30}", self.local_send_id, tail(&file_path, 30))?;
         writeln!(f, "Remote: {}", self.remote)?;
         if self.started {
-            writeln!(
+            write!(
                 f,
                 "Bytes total: {}; Bytes sent: {}({:.1}%)",
                 self.bytes_total,
                 self.bytes_sent,
-                self.bytes_total / self.bytes_sent
-            )
+                self.percent()
+            )?;
         } else {
-            write!(f, "Bytes total: {}", self.bytes_total)
+            write!(f, "Bytes total: {}", self.bytes_total)?;
+        }
+        if self.queued {
+            write!(f, "; Queued")?;
+        }
+        if self.interrupted {
+            write!(f, "; Interrupted")?;
         }
+        Ok(())
+    }
+}
+
+/// Percentage of `done` in `total`, a transfer of nothing is complete.
+/// Streamed transfers may exceed their size hint, the result is capped at 100.
+fn percent(done: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    (done as f64 * 100.0 / total as f64).min(100.0)
+}
+
+/// The last `len` characters of the string.
+fn tail(s: &str, len: usize) -> &str {
+    let skip = s.chars().count().saturating_sub(len);
+    match s.char_indices().nth(skip) {
+        Some((index, _)) => &s[index..],
+        None => "",
     }
 }
 impl From<&PendingSend> for SendInfo {
@@ -145,6 +298,10 @@ impl From<&PendingSend> for SendInfo {
             bytes_sent: 0,
             started: false,
             file_path: value.file_path.clone(),
+            transfer_id: value.transfer_id,
+            interrupted: false,
+            queued: false,
+            priority: value.priority,
         }
     }
 }
@@ -157,21 +314,99 @@ impl From<&OngoingFileSend> for SendInfo {
             bytes_total: value.bytes_total,
             bytes_sent: value.bytes_sent,
             started: true,
+            transfer_id: value.transfer_id,
+            interrupted: false,
+            queued: false,
+            priority: value.priority,
+        }
+    }
+}
+impl From<&ResumableSend> for SendInfo {
+    fn from(value: &ResumableSend) -> Self {
+        Self {
+            local_send_id: value.local_send_id,
+            remote: value.remote,
+            file_path: value.file_path.clone(),
+            bytes_total: value.bytes_total,
+            bytes_sent: value.bytes_sent,
+            started: true,
+            transfer_id: value.transfer_id,
+            interrupted: true,
+            queued: false,
+            priority: value.priority,
         }
     }
 }
 
 impl Behaviour {
     pub fn new(config: Config) -> Self {
-        Self {
+        let mut behaviour = Self {
+            send_bucket: TokenBucket::new(config.throttle.max_send_rate),
+            store_server: StoreServer::new(&config.store_path),
             config,
             ..Default::default()
+        };
+        if !behaviour.config.journal_path.is_empty() {
+            behaviour.restore_journal(Journal::load(behaviour.config.journal_path.clone().into()));
+        }
+        behaviour
+    }
+    /// Bring back transfers that were unfinished when the journal was last written.
+    /// Recvs whose partial file is gone are dropped.
+    fn restore_journal(&mut self, mut journal: Journal) {
+        let timestamp = time_now!();
+        let mut lost = Vec::new();
+        for entry in journal.recv().cloned().collect::<Vec<_>>() {
+            let bytes_received = match std::fs::metadata(part_path(&entry.file_path)) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => {
+                    lost.push(entry.transfer_id);
+                    continue;
+                }
+            };
+            let local_recv_id = self.next_recv_id();
+            self.interrupted_recv.insert(
+                local_recv_id,
+                InterruptedRecv {
+                    local_recv_id,
+                    transfer_id: entry.transfer_id,
+                    remote: entry.remote,
+                    file_path: entry.file_path,
+                    bytes_received,
+                    bytes_queued: bytes_received,
+                    bytes_total: entry.bytes_total,
+                    content_hash: entry.content_hash,
+                    writer: None,
+                    span: debug_span!("Blob Recv", id = local_recv_id),
+                    timestamp,
+                    started: Instant::now(),
+                },
+            );
+        }
+        for transfer_id in lost {
+            journal.remove_recv(transfer_id);
+        }
+        for entry in journal.send().cloned().collect::<Vec<_>>() {
+            let local_send_id = self.next_send_id();
+            self.resumable_send.insert(
+                entry.transfer_id,
+                ResumableSend {
+                    local_send_id,
+                    transfer_id: entry.transfer_id,
+                    remote: entry.remote,
+                    file_path: entry.file_path,
+                    bytes_sent: 0,
+                    bytes_total: entry.bytes_total,
+                    content_hash: entry.content_hash,
+                    span: debug_span!("Blob Send", id = local_send_id),
+                    started: Instant::now(),
+                    priority: 0,
+                },
+            );
         }
+        self.journal = Some(journal);
     }
     /// Call this to insert an event.
-    /// ## Silent failure
-    /// This function will return without producing error result:
-    /// - File metadata cannot be retreived.
     pub fn push_event(&mut self, ev: InEvent) {
         use InEvent::*;
         match ev {
@@ -179,64 +414,84 @@ impl Behaviour {
                 file,
                 file_path,
                 to,
+                content_hash,
                 callback,
             } => {
-                let local_send_id = self.next_send_id();
-                let span = debug_span!("Blob Send", id = local_send_id);
-                let entered = span.enter();
-                debug!("Send request spawned.");
-                if !self.connected_peers.contains(&to) {
-                    // Return error when the peer is not connected
-                    callback
-                        .send(Err(FileSendError::PeerNotFound))
-                        .expect("callback to succeed");
-                    trace!(
-                        "Send request {local_send_id} is dropped because the target is not found.",
-                    );
-                    return;
-                }
                 let bytes_total = match file.metadata() {
                     Ok(metadata) => metadata.len(),
-                    Err(_) => {
+                    Err(e) => {
                         trace!("Send request dropped because metadata cannot be read.");
+                        handle_callback_sender!(Err(FileSendError::OtherFsError(e.kind())) => callback);
                         return;
                     }
                 };
-                trace!("Send request queued.");
-                drop(entered);
-                let timestamp = time_now!();
-                // Queue the send
-                self.pending_send.insert(
-                    local_send_id,
-                    PendingSend {
-                        local_send_id,
-                        remote: to,
-                        file_path: file_path.clone(),
-                        span,
-                        bytes_total,
-                        file,
-                        timestamp,
-                    },
+                let source = Source::File(file);
+                self.send_source(
+                    source,
+                    file_path,
+                    bytes_total,
+                    false,
+                    to,
+                    content_hash,
+                    0,
+                    Some(callback),
+                );
+            }
+            SendStream {
+                stream,
+                name,
+                size_hint,
+                to,
+                content_hash,
+                callback,
+            } => {
+                let source = Source::Stream(stream);
+                let file_path = PathBuf::from(name);
+                self.send_source(
+                    source,
+                    file_path,
+                    size_hint,
+                    true,
+                    to,
+                    content_hash,
+                    0,
+                    Some(callback),
                 );
-                // Notify the remote for new send
-                self.pending_handler_event
-                    .push_back(ToSwarm::NotifyHandler {
-                        peer_id: to,
-                        handler: NotifyHandler::Any,
-                        event: FromBehaviourEvent::NewFileSend {
-                            file_name: file_path.file_name().unwrap().to_string_lossy().to_string(),
-                            local_send_id,
-                            callback,
-                            bytes_total,
-                        },
-                    });
             }
             AcceptFile {
                 file,
                 recv_id,
                 callback,
                 path,
-            } => self.accept_pending_recv(file, path, recv_id, callback),
+            } => {
+                let sink = Sink::File { file, target: path };
+                self.accept_pending_recv(sink, recv_id, Some(callback))
+            }
+            AcceptStream {
+                stream,
+                recv_id,
+                callback,
+            } => self.accept_pending_recv(Sink::Stream(stream), recv_id, Some(callback)),
+            SendBundle {
+                files,
+                to,
+                callback,
+            } => self.send_bundle(files, to, callback),
+            AcceptBundle {
+                local_bundle_id,
+                files,
+                callback,
+            } => {
+                let result = self.accept_pending_bundle(local_bundle_id, files);
+                handle_callback_sender!(result => callback);
+            }
+            ListBundle { callback } => {
+                handle_callback_sender!(self.bundles.list() => callback)
+            }
+            ResumeRecv {
+                local_recv_id,
+                callback,
+            } => self.resume_recv(local_recv_id, callback),
             CancelRecv {
                 local_recv_id: recv_id,
                 callback,
@@ -263,23 +518,205 @@ impl Behaviour {
             ListRecv { callback } => {
                 let ongoing = self.ongoing_recv.values().map(Into::into);
                 let pending = self.pending_recv.values().map(Into::into);
-                handle_callback_sender!(ongoing.chain(pending).collect()=>callback)
+                let interrupted = self
+                    .interrupted_recv
+                    .values()
+                    .chain(self.pending_resume.values().map(|v| &v.recv))
+                    .map(Into::into);
+                handle_callback_sender!(ongoing.chain(pending).chain(interrupted).collect()=>callback)
             }
             ListSend { callback } => {
                 let ongoing = self.ongoing_send.values().map(Into::into);
                 let pending = self.pending_send.values().map(Into::into);
-                handle_callback_sender!(ongoing.chain(pending).collect()=>callback)
+                let queued = self.queued_send.iter().map(|v| SendInfo {
+                    queued: true,
+                    ..v.into()
+                });
+                let interrupted = self.resumable_send.values().map(Into::into);
+                handle_callback_sender!(ongoing.chain(pending).chain(queued).chain(interrupted).collect()=>callback)
+            }
+            SetThrottle { throttle, callback } => {
+                self.set_throttle(throttle);
+                handle_callback_sender!(()=>callback)
+            }
+            GetThrottle { callback } => {
+                handle_callback_sender!(self.config.throttle.clone()=>callback)
+            }
+            SetPriority {
+                local_send_id,
+                priority,
+                callback,
+            } => {
+                let found = self.set_priority(local_send_id, priority);
+                handle_callback_sender!(found=>callback)
+            }
+            Browse {
+                peer,
+                path,
+                callback,
+            } => {
+                if !self.connected_peers.contains(&peer) {
+                    handle_callback_sender!(Err(ShareError::PeerNotFound)=>callback);
+                    return;
+                }
+                let request_id = self.next_request_id();
+                self.pending_browse
+                    .insert(request_id, PendingBrowse { peer, callback });
+                self.pending_handler_event
+                    .push_back(ToSwarm::NotifyHandler {
+                        peer_id: peer,
+                        handler: NotifyHandler::Any,
+                        event: FromBehaviourEvent::BrowseRequest { request_id, path },
+                    });
+            }
+            Fetch {
+                peer,
+                path,
+                file,
+                target,
+                callback,
+            } => {
+                if !self.connected_peers.contains(&peer) {
+                    handle_callback_sender!(Err(ShareError::PeerNotFound)=>callback);
+                    return;
+                }
+                let request_id = self.next_request_id();
+                self.pending_fetch.insert(
+                    request_id,
+                    PendingFetch {
+                        peer,
+                        file,
+                        target,
+                        callback,
+                    },
+                );
+                self.pending_handler_event
+                    .push_back(ToSwarm::NotifyHandler {
+                        peer_id: peer,
+                        handler: NotifyHandler::Any,
+                        event: FromBehaviourEvent::FetchRequest { request_id, path },
+                    });
+            }
+            SetShare { share, callback } => {
+                self.config.shares.retain(|v| v.name != share.name);
+                self.config.shares.push(share);
+                handle_callback_sender!(()=>callback)
+            }
+            RemoveShare { name, callback } => {
+                let shares_before = self.config.shares.len();
+                self.config.shares.retain(|v| v.name != name);
+                handle_callback_sender!((self.config.shares.len() != shares_before)=>callback)
+            }
+            ListShares { callback } => {
+                handle_callback_sender!(self.config.shares.clone().into()=>callback)
+            }
+            RequestChunk {
+                peer,
+                hash,
+                callback,
+            } => {
+                if !self.connected_peers.contains(&peer) {
+                    handle_callback_sender!(Err(StoreError::PeerNotFound)=>callback);
+                    return;
+                }
+                let request_id = self.next_request_id();
+                self.pending_chunk
+                    .insert(request_id, PendingChunk { peer, callback });
+                self.pending_handler_event
+                    .push_back(ToSwarm::NotifyHandler {
+                        peer_id: peer,
+                        handler: NotifyHandler::Any,
+                        event: FromBehaviourEvent::ChunkRequest { request_id, hash },
+                    });
+            }
+        }
+    }
+
+    /// Queue a send and notify the remote.
+    /// Files and streams only differ in where the bytes are read from.
+    #[allow(clippy::too_many_arguments)]
+    fn send_source(
+        &mut self,
+        source: Source,
+        file_path: PathBuf,
+        bytes_total: u64,
+        size_is_hint: bool,
+        to: PeerId,
+        content_hash: Vec<u8>,
+        fetch_id: u64,
+        callback: Option<oneshot::Sender<Result<u64, FileSendError>>>,
+    ) -> Option<u64> {
+        let local_send_id = self.next_send_id();
+        let span = debug_span!("Blob Send", id = local_send_id);
+        let entered = span.enter();
+        debug!("Send request spawned.");
+        if !self.connected_peers.contains(&to) {
+            // Return error when the peer is not connected
+            if let Some(callback) = callback {
+                handle_callback_sender!(Err(FileSendError::PeerNotFound)=>callback);
             }
+            trace!("Send request {local_send_id} is dropped because the target is not found.",);
+            return None;
         }
+        trace!("Send request queued.");
+        drop(entered);
+        let timestamp = time_now!();
+        let transfer_id = new_transfer_id(&content_hash, &to, &file_path);
+        let file_name = file_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // Queue the send
+        self.pending_send.insert(
+            local_send_id,
+            PendingSend {
+                local_send_id,
+                remote: to,
+                file_path,
+                span,
+                bytes_total,
+                size_is_hint,
+                source,
+                timestamp,
+                transfer_id,
+                content_hash: content_hash.clone(),
+                priority: 0,
+                compression: false,
+            },
+        );
+        // Notify the remote for new send
+        self.pending_handler_event
+            .push_back(ToSwarm::NotifyHandler {
+                peer_id: to,
+                handler: NotifyHandler::Any,
+                event: FromBehaviourEvent::NewFileSend {
+                    file_name,
+                    local_send_id,
+                    callback,
+                    bytes_total,
+                    size_is_hint,
+                    transfer_id,
+                    content_hash,
+                    compression: self.config.compression.enabled,
+                    fetch_id,
+                },
+            });
+        Some(local_send_id)
     }
 
     /// Called when received send request from remote.
+    #[allow(clippy::too_many_arguments)]
     fn on_new_pending_recv(
         &mut self,
         from: PeerId,
         file_name: String,
         remote_send_id: u64,
         bytes_total: u64,
+        size_is_hint: bool,
+        transfer_id: u64,
+        content_hash: Vec<u8>,
+        compression: bool,
+        fetch_id: u64,
     ) {
         let local_recv_id = self.next_recv_id();
         let span = debug_span!("Blob Recv", id = local_recv_id);
@@ -297,288 +734,1409 @@ impl Behaviour {
                 file_name: file_name.clone(),
                 span,
                 timestamp,
+                size_is_hint,
+                transfer_id,
+                content_hash,
+                compression: compression && self.config.compression.enabled,
             },
         );
+        // Files fetched by local are accepted right away.
+        if let Some(fetch) = self.pending_fetch.remove(&fetch_id) {
+            if fetch.peer == from {
+                let PendingFetch {
+                    file,
+                    target,
+                    callback,
+                    ..
+                } = fetch;
+                self.accept_pending_recv(Sink::File { file, target }, local_recv_id, None);
+                handle_callback_sender!(Ok((local_recv_id, bytes_total))=>callback);
+                return;
+            }
+            self.pending_fetch.insert(fetch_id, fetch);
+        }
         self.out_events.push_back(OutEvent::IncomingFile {
             file_name,
             from,
             local_recv_id,
             bytes_total,
+            size_is_hint,
+            transfer_id,
+        });
+        if self.config.auto_accept.enabled {
+            self.auto_accept(local_recv_id);
+        }
+    }
+
+    /// Check the pending recv against the auto-accept policy.
+    /// Files that match are accepted once the partial files are created.
+    fn auto_accept(&mut self, local_recv_id: u64) {
+        let pending = match self.pending_recv.get(&local_recv_id) {
+            Some(v) => v,
+            None => return,
+        };
+        let policy = &self.config.auto_accept;
+        let checked = auto_accept::check(
+            policy,
+            &pending.remote,
+            &pending.file_name,
+            pending.bytes_total,
+            pending.size_is_hint,
+        );
+        if let Err(reason) = checked {
+            pending
+                .span
+                .in_scope(|| debug!("Not auto-accepted: {reason}"));
+            let decision = if policy.reject_unmatched {
+                AutoAcceptDecision::Rejected { reason }
+            } else {
+                AutoAcceptDecision::Deferred { reason }
+            };
+            self.on_auto_accept_decision(local_recv_id, decision);
+            return;
+        }
+        let (file_name, bytes_total) = (pending.file_name.clone(), pending.bytes_total);
+        let (ongoing, interrupted) = (&self.ongoing_recv, &self.interrupted_recv);
+        self.auto_acceptor
+            .prepare(policy, local_recv_id, file_name, bytes_total, |id| {
+                interrupted.contains_key(&id) || ongoing.values().any(|v| v.local_recv_id == id)
+            });
+    }
+
+    /// Accept the files that have their partial files created.
+    fn poll_auto_accept(&mut self, cx: &mut std::task::Context<'_>) {
+        while let Poll::Ready((local_recv_id, prepared)) = self.auto_acceptor.poll_prepared(cx) {
+            let (file, path) = match prepared {
+                Ok(v) => v,
+                Err(reason) => {
+                    self.auto_acceptor.release(local_recv_id);
+                    let decision = AutoAcceptDecision::Rejected { reason };
+                    self.on_auto_accept_decision(local_recv_id, decision);
+                    continue;
+                }
+            };
+            if !self.pending_recv.contains_key(&local_recv_id) {
+                // Cancelled or expired while the file was being created.
+                self.auto_acceptor.release(local_recv_id);
+                drop(file);
+                let _ = std::fs::remove_file(part_path(&path));
+                continue;
+            }
+            let decision = AutoAcceptDecision::Accepted { path: path.clone() };
+            self.on_auto_accept_decision(local_recv_id, decision);
+            self.accept_pending_recv(Sink::File { file, target: path }, local_recv_id, None);
+        }
+    }
+
+    /// Report the decision, and cancel the pending recv if it's rejected.
+    fn on_auto_accept_decision(&mut self, local_recv_id: u64, decision: AutoAcceptDecision) {
+        let pending = match self.pending_recv.get(&local_recv_id) {
+            Some(v) => v,
+            None => return,
+        };
+        self.out_events.push_back(OutEvent::AutoAccept {
+            from: pending.remote,
+            local_recv_id,
+            file_name: pending.file_name.clone(),
+            decision: decision.clone(),
         });
+        if let AutoAcceptDecision::Rejected { .. } = decision {
+            self.cancel_recv_by_local_recv_id(local_recv_id);
+        }
     }
 
     /// Called when local decided to accept the file.
+    /// Only recvs written to files can be resumed.
     fn accept_pending_recv(
         &mut self,
-        file: File,
-        mut path: PathBuf,
+        sink: Sink,
         recv_id: u64,
-        callback: oneshot::Sender<Result<Duration, error::FileRecvError>>,
+        callback: Option<oneshot::Sender<Result<Duration, error::FileRecvError>>>,
     ) {
         let PendingRecv {
             remote_send_id,
             remote,
             bytes_total,
-            span,
             file_name,
+            span,
+            size_is_hint,
+            transfer_id,
+            content_hash,
+            compression,
             ..
         } = match self.pending_recv.remove(&recv_id) {
             Some(v) => v,
             None => {
                 // Not found in pending recv, report the error directly to caller using callback
-                handle_callback_sender!( Err(error::FileRecvError::PendingRecvNotFound(recv_id)) => callback);
+                if let Some(callback) = callback {
+                    handle_callback_sender!( Err(error::FileRecvError::PendingRecvNotFound(recv_id)) => callback);
+                }
                 return;
             }
         };
         span.in_scope(|| debug!("Pending recv accepted"));
-        path.push(file_name);
+        let (path, resumable) = match &sink {
+            Sink::File { target, .. } => (target.clone(), !size_is_hint),
+            Sink::Stream(_) => (PathBuf::from(file_name), false),
+        };
+        if let (Some(journal), true) = (self.journal.as_mut(), resumable) {
+            journal.insert_recv(JournalRecv {
+                transfer_id,
+                remote,
+                file_path: path.clone(),
+                bytes_total,
+                content_hash: content_hash.clone(),
+            });
+        }
+        let writer = FileWriter::spawn(sink, content_hash.clone(), self.config.write_behind_chunks);
         self.ongoing_recv.insert(
             remote_send_id,
             OngoingFileRecv {
                 remote_send_id,
                 local_recv_id: recv_id,
-                file_handle: file,
+                writer,
+                connection: None,
+                paused: false,
+                bytes_queued: 0,
+                bytes_on_wire: 0,
                 bytes_received: 0,
                 bytes_total,
                 remote,
                 span,
-                file_path: path,
+                file_path: path,
+                last_active: time_now!(),
+                transfer_id,
+                content_hash,
+                started: Instant::now(),
+                size_is_hint,
+                resumable,
+            },
+        );
+        let ev = ToSwarm::NotifyHandler {
+            peer_id: remote,
+            handler: NotifyHandler::Any,
+            event: FromBehaviourEvent::AcceptFile {
+                remote_send_id,
+                callback,
+                compression,
+            },
+        };
+        self.pending_handler_event.push_back(ev);
+    }
+
+    /// Queue all files as pending sends, and send the manifest to remote.
+    fn send_bundle(
+        &mut self,
+        files: Vec<BundleFile>,
+        to: PeerId,
+        callback: oneshot::Sender<Result<u64, FileSendError>>,
+    ) {
+        if !self.connected_peers.contains(&to) {
+            handle_callback_sender!(Err(FileSendError::PeerNotFound) => callback);
+            return;
+        }
+        if files.is_empty() || files.len() > bundle::MAX_BUNDLE_FILES {
+            let reason = format!(
+                "A bundle contains 1 to {} files, got {}",
+                bundle::MAX_BUNDLE_FILES,
+                files.len()
+            );
+            handle_callback_sender!(Err(FileSendError::InvalidBundle(reason)) => callback);
+            return;
+        }
+        let mut sized_files = Vec::with_capacity(files.len());
+        for file in files {
+            if sanitize_relative_path(&file.relative_path).is_none() {
+                let reason = format!("Unsafe relative path {}", file.relative_path);
+                handle_callback_sender!(Err(FileSendError::InvalidBundle(reason)) => callback);
+                return;
+            }
+            match file.file.metadata() {
+                Ok(metadata) => sized_files.push((metadata.len(), file)),
+                Err(e) => {
+                    handle_callback_sender!(Err(FileSendError::OtherFsError(e.kind())) => callback);
+                    return;
+                }
+            }
+        }
+        let local_bundle_id = self.next_bundle_id();
+        let span = debug_span!("Blob Bundle Send", id = local_bundle_id);
+        span.in_scope(|| debug!("Sending {} files", sized_files.len()));
+        let timestamp = time_now!();
+        let mut entries = Vec::with_capacity(sized_files.len());
+        let mut infos = Vec::with_capacity(sized_files.len());
+        for (bytes_total, file) in sized_files {
+            let BundleFile {
+                file,
+                file_path,
+                relative_path,
+                mode,
+                content_hash,
+            } = file;
+            let local_send_id = self.next_send_id();
+            let transfer_id = new_transfer_id(&content_hash, &to, &file_path);
+            self.pending_send.insert(
+                local_send_id,
+                PendingSend {
+                    local_send_id,
+                    remote: to,
+                    file_path,
+                    bytes_total,
+                    size_is_hint: false,
+                    source: Source::File(file),
+                    span: debug_span!(parent: &span, "Blob Send", id = local_send_id),
+                    timestamp,
+                    transfer_id,
+                    content_hash: content_hash.clone(),
+                    priority: 0,
+                    compression: false,
+                },
+            );
+            infos.push(BundleFileInfo {
+                local_id: local_send_id,
+                relative_path: relative_path.clone(),
+                bytes_total,
+                bytes_done: 0,
+                mode,
+                completed: false,
+            });
+            entries.push(messages::BundleEntry {
+                remote_send_id: local_send_id,
+                relative_path,
+                bytes_total,
+                mode,
+                transfer_id,
+                content_hash,
+                compression: handler::compression(self.config.compression.enabled),
+            });
+        }
+        self.bundles.insert(BundleInfo {
+            local_bundle_id,
+            remote: to,
+            outbound: true,
+            files: infos,
+        });
+        self.pending_handler_event
+            .push_back(ToSwarm::NotifyHandler {
+                peer_id: to,
+                handler: NotifyHandler::Any,
+                event: FromBehaviourEvent::NewBundleSend {
+                    local_bundle_id,
+                    entries,
+                    callback,
+                },
+            });
+    }
+
+    /// Called when received a bundle from remote.
+    /// The whole bundle is rejected if any of the paths is unsafe.
+    fn on_new_pending_bundle(
+        &mut self,
+        from: PeerId,
+        remote_bundle_id: u64,
+        entries: Vec<messages::BundleEntry>,
+    ) {
+        let unsafe_path = entries
+            .iter()
+            .find(|v| sanitize_relative_path(&v.relative_path).is_none())
+            .map(|v| v.relative_path.clone());
+        if unsafe_path.is_some() || entries.len() > bundle::MAX_BUNDLE_FILES {
+            let unsafe_path = unsafe_path.unwrap_or_default();
+            warn!("Rejecting bundle {remote_bundle_id} from {from}: unsafe path {unsafe_path:?}");
+            self.out_events
+                .push_back(OutEvent::Error(error::Error::UnsafePath(unsafe_path)));
+            for entry in entries {
+                self.pending_handler_event
+                    .push_back(ToSwarm::NotifyHandler {
+                        peer_id: from,
+                        handler: NotifyHandler::Any,
+                        event: FromBehaviourEvent::LocalCancelRecv {
+                            remote_send_id: entry.remote_send_id,
+                            span: Span::none(),
+                        },
+                    });
+            }
+            return;
+        }
+        let local_bundle_id = self.next_bundle_id();
+        let timestamp = time_now!();
+        let mut infos = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let local_recv_id = self.next_recv_id();
+            self.pending_recv.insert(
+                local_recv_id,
+                PendingRecv {
+                    local_recv_id,
+                    remote_send_id: entry.remote_send_id,
+                    bytes_total: entry.bytes_total,
+                    file_name: entry.relative_path.clone(),
+                    remote: from,
+                    timestamp,
+                    span: debug_span!("Blob Recv", id = local_recv_id),
+                    size_is_hint: false,
+                    transfer_id: entry.transfer_id,
+                    content_hash: entry.content_hash,
+                    compression: handler::is_zstd(entry.compression)
+                        && self.config.compression.enabled,
+                },
+            );
+            infos.push(BundleFileInfo {
+                local_id: local_recv_id,
+                relative_path: entry.relative_path,
+                bytes_total: entry.bytes_total,
+                bytes_done: 0,
+                mode: entry.mode,
+                completed: false,
+            });
+        }
+        let bundle = BundleInfo {
+            local_bundle_id,
+            remote: from,
+            outbound: false,
+            files: infos,
+        };
+        self.out_events.push_back(OutEvent::IncomingBundle {
+            from,
+            local_bundle_id,
+            files_total: bundle.files.len(),
+            bytes_total: bundle.bytes_total(),
+        });
+        self.bundles.insert(bundle);
+    }
+
+    /// Called when local decided to accept all files of a bundle.
+    fn accept_pending_bundle(
+        &mut self,
+        local_bundle_id: u64,
+        files: Vec<(u64, File, PathBuf)>,
+    ) -> Result<(), error::FileRecvError> {
+        let bundle = match self.bundles.get(local_bundle_id) {
+            Some(bundle) if !bundle.outbound => bundle,
+            _ => return Err(error::FileRecvError::PendingRecvNotFound(local_bundle_id)),
+        };
+        // Files must be accepted as a whole.
+        for file in bundle.files.iter() {
+            if !self.pending_recv.contains_key(&file.local_id)
+                || !files
+                    .iter()
+                    .any(|(recv_id, _, _)| *recv_id == file.local_id)
+            {
+                return Err(error::FileRecvError::PendingRecvNotFound(file.local_id));
+            }
+        }
+        for (recv_id, file, target) in files {
+            self.accept_pending_recv(Sink::File { file, target }, recv_id, None);
+        }
+        Ok(())
+    }
+
+    /// Called when rmeote accepts a pending send.
+    /// The send is queued, and started once there is a free slot.
+    fn pending_send_accepted(&mut self, local_send_id: u64, compression: bool) {
+        let mut send = match self.pending_send.remove(&local_send_id) {
+            Some(v) => v,
+            None => return,
+        };
+        send.span.in_scope(|| debug!("Send request accepted."));
+        // Only offered if enabled, but the config may have changed since.
+        send.compression = compression && self.config.compression.enabled;
+        self.queued_send.push_back(send);
+        self.start_queued_sends();
+        if let Some(position) = self
+            .queued_send
+            .iter()
+            .position(|v| v.local_send_id == local_send_id)
+        {
+            self.out_events.push_back(OutEvent::SendQueued {
+                local_send_id,
+                position,
+            });
+        }
+    }
+
+    /// Start queued sends until the limit of concurrent sends is reached.
+    fn start_queued_sends(&mut self) {
+        let max_concurrent_sends = self.config.throttle.max_concurrent_sends;
+        while !self.queued_send.is_empty()
+            && (max_concurrent_sends == 0 || self.ongoing_send.len() < max_concurrent_sends)
+        {
+            let index = match self.config.throttle.send_queue {
+                SendQueue::Fifo => 0,
+                // The first one among those with the highest priority.
+                SendQueue::Priority => self
+                    .queued_send
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(index, v)| (v.priority, std::cmp::Reverse(*index)))
+                    .map(|(index, _)| index)
+                    .expect("Queue is not empty"),
+            };
+            let send = self.queued_send.remove(index).expect("Index is valid");
+            self.start_send(send);
+        }
+    }
+
+    fn start_send(&mut self, send: PendingSend) {
+        let PendingSend {
+            local_send_id,
+            remote,
+            source,
+            span,
+            bytes_total,
+            file_path,
+            transfer_id,
+            content_hash,
+            priority,
+            compression,
+            ..
+        } = send;
+        span.in_scope(|| debug!("Send started."));
+        // Streams can't be read again from an offset.
+        let resumable = matches!(source, Source::File(_));
+        if let (Some(journal), true) = (self.journal.as_mut(), resumable) {
+            journal.insert_send(JournalSend {
+                transfer_id,
+                remote,
+                file_path: file_path.clone(),
+                bytes_total,
+                content_hash: content_hash.clone(),
+            });
+        }
+        // The first chunk will be picked up when polled.
+        self.ongoing_send.insert(
+            local_send_id,
+            OngoingFileSend {
+                local_send_id,
+                remote,
+                bytes_total,
+                reader: FileReader::spawn(
+                    source,
+                    content_hash.clone(),
+                    FILE_CHUNK_SIZE,
+                    self.config.read_ahead_chunks,
+                    self.compressor(compression),
+                ),
+                awaiting_chunk: true,
+                file_path,
+                bytes_sent: 0,
+                bytes_on_wire: 0,
+                span,
+                last_active: time_now!(),
+                transfer_id,
+                content_hash,
+                started: Instant::now(),
+                resumable,
+                priority,
+                bucket: TokenBucket::new(self.config.throttle.max_send_rate_per_transfer),
+            },
+        );
+        self.out_events.push_back(OutEvent::SendProgressed {
+            local_send_id,
+            bytes_total,
+            bytes_sent: 0,
+        });
+    }
+
+    /// Called when remote responded to a browse request.
+    fn on_browse_responded(
+        &mut self,
+        from: PeerId,
+        request_id: u64,
+        entries: Vec<messages::CatalogEntry>,
+        error: String,
+    ) {
+        let browse = match self.pending_browse.remove(&request_id) {
+            Some(v) if v.peer == from => v,
+            Some(v) => {
+                self.pending_browse.insert(request_id, v);
+                return;
+            }
+            None => return,
+        };
+        let result = if error.is_empty() {
+            Ok(entries.into_iter().map(CatalogEntry::from).collect())
+        } else {
+            Err(ShareError::Rejected(error))
+        };
+        handle_callback_sender!(result=>browse.callback);
+    }
+
+    /// Called when remote refused to serve a fetch request.
+    fn on_fetch_rejected(&mut self, from: PeerId, request_id: u64, reason: String) {
+        let fetch = match self.pending_fetch.remove(&request_id) {
+            Some(v) if v.peer == from => v,
+            Some(v) => {
+                self.pending_fetch.insert(request_id, v);
+                return;
+            }
+            None => return,
+        };
+        handle_callback_sender!(Err(ShareError::Rejected(reason))=>fetch.callback);
+    }
+
+    /// Called when remote responded to a chunk request.
+    fn on_chunk_responded(&mut self, from: PeerId, request_id: u64, data: Vec<u8>, found: bool) {
+        let chunk = match self.pending_chunk.remove(&request_id) {
+            Some(v) if v.peer == from => v,
+            Some(v) => {
+                self.pending_chunk.insert(request_id, v);
+                return;
+            }
+            None => return,
+        };
+        let result = if found {
+            Ok(data)
+        } else {
+            Err(StoreError::NotFound)
+        };
+        handle_callback_sender!(result=>chunk.callback);
+    }
+
+    /// Respond to chunk requests that have been served.
+    fn poll_store_server(&mut self, cx: &mut std::task::Context<'_>) {
+        while let Poll::Ready(served) = self.store_server.poll_served(cx) {
+            let ServedChunk {
+                peer,
+                request_id,
+                data,
+            } = served;
+            self.pending_handler_event
+                .push_back(ToSwarm::NotifyHandler {
+                    peer_id: peer,
+                    handler: NotifyHandler::Any,
+                    event: FromBehaviourEvent::ChunkResponse {
+                        request_id,
+                        found: data.is_some(),
+                        data: data.unwrap_or_default(),
+                    },
+                });
+        }
+    }
+
+    /// Respond to browse and fetch requests that have been served.
+    fn poll_share_server(&mut self, cx: &mut std::task::Context<'_>) {
+        while let Poll::Ready(served) = self.share_server.poll_served(cx) {
+            match served {
+                Served::Browse {
+                    peer,
+                    request_id,
+                    result,
+                } => {
+                    let (entries, error) = match result {
+                        Ok(entries) => {
+                            (entries.into_iter().map(Into::into).collect(), String::new())
+                        }
+                        Err(error) => (Vec::new(), error),
+                    };
+                    self.pending_handler_event
+                        .push_back(ToSwarm::NotifyHandler {
+                            peer_id: peer,
+                            handler: NotifyHandler::Any,
+                            event: FromBehaviourEvent::BrowseResponse {
+                                request_id,
+                                entries,
+                                error,
+                            },
+                        });
+                }
+                Served::Fetch {
+                    peer,
+                    request_id,
+                    result: Ok(shared),
+                } => {
+                    let SharedFile {
+                        file,
+                        path,
+                        bytes_total,
+                        content_hash,
+                    } = shared;
+                    let local_send_id = self.send_source(
+                        Source::File(file),
+                        path.clone(),
+                        bytes_total,
+                        false,
+                        peer,
+                        content_hash,
+                        request_id,
+                        None,
+                    );
+                    if let Some(local_send_id) = local_send_id {
+                        self.out_events.push_back(OutEvent::FetchServed {
+                            to: peer,
+                            local_send_id,
+                            file_path: path,
+                        });
+                    }
+                }
+                Served::Fetch {
+                    peer,
+                    request_id,
+                    result: Err(reason),
+                } => self
+                    .pending_handler_event
+                    .push_back(ToSwarm::NotifyHandler {
+                        peer_id: peer,
+                        handler: NotifyHandler::Any,
+                        event: FromBehaviourEvent::FetchRejected { request_id, reason },
+                    }),
+            }
+        }
+    }
+
+    fn compressor(&self, compression: bool) -> Option<Compressor> {
+        compression.then(|| Compressor::new(self.config.compression.level))
+    }
+
+    /// Apply new limits, ongoing sends are affected immediately.
+    fn set_throttle(&mut self, throttle: Throttle) {
+        self.send_bucket = TokenBucket::new(throttle.max_send_rate);
+        for ongoing_send in self.ongoing_send.values_mut() {
+            ongoing_send.bucket = TokenBucket::new(throttle.max_send_rate_per_transfer);
+        }
+        self.config.throttle = throttle;
+        self.start_queued_sends();
+    }
+
+    /// Change the priority of a send, returns false if the send is not found.
+    fn set_priority(&mut self, local_send_id: u64, priority: u8) -> bool {
+        if let Some(send) = self.pending_send.get_mut(&local_send_id) {
+            send.priority = priority;
+            return true;
+        }
+        if let Some(send) = self
+            .queued_send
+            .iter_mut()
+            .find(|v| v.local_send_id == local_send_id)
+        {
+            send.priority = priority;
+            return true;
+        }
+        if let Some(send) = self.ongoing_send.get_mut(&local_send_id) {
+            send.priority = priority;
+            return true;
+        }
+        if let Some(send) = self
+            .resumable_send
+            .values_mut()
+            .find(|v| v.local_send_id == local_send_id)
+        {
+            send.priority = priority;
+            return true;
+        }
+        false
+    }
+
+    /// Called when local node cancels the transmission.
+    /// Once called, it is guaranteed that no more bytes will be written to the file.
+    /// Receiving operation on local node will stop immediately without acknowledgement from remote.
+    fn cancel_recv_by_local_recv_id(&mut self, local_recv_id: u64) -> bool {
+        // The sender has no ongoing send for an interrupted recv, nothing to notify.
+        if let Some(recv) = self.interrupted_recv.remove(&local_recv_id) {
+            recv.span
+                .in_scope(|| debug!("Cancelling interrupted recv. By: Local"));
+            self.forget_recv(recv.transfer_id);
+            self.bundles.remove_file(false, local_recv_id);
+            self.out_events
+                .push_back(OutEvent::CancelledRecv(local_recv_id));
+            return true;
+        }
+        // Try to remove all recv record associted with the provided id.
+        // If none is found, false is returned.
+        if let Some((remote, remote_send_id, span)) = self.remove_recv_record(local_recv_id) {
+            span.in_scope(|| {
+                debug!("Cancelling recv. By: Local, Reason: timeout or user request.")
+            });
+            // Notify the remote about the cancellation.
+            self.pending_handler_event
+                .push_back(ToSwarm::NotifyHandler {
+                    peer_id: remote,
+                    handler: NotifyHandler::Any,
+                    event: FromBehaviourEvent::LocalCancelRecv {
+                        remote_send_id,
+                        span,
+                    },
+                });
+            self.out_events
+                .push_back(OutEvent::CancelledRecv(local_recv_id));
+            return true;
+        }
+        false
+    }
+
+    /// Called when local cancelled transmission.
+    /// Once called, it is guaranteed that no more bytes will be read.
+    fn cancel_send_by_local_send_id(&mut self, local_send_id: u64) -> bool {
+        // The receiver may have been restarted, so the send ID means nothing to it.
+        if let Some((transfer_id, send)) = self
+            .resumable_send
+            .extract_if(|_, v| v.local_send_id == local_send_id)
+            .next()
+        {
+            send.span
+                .in_scope(|| debug!("Cancelling interrupted send. By: Local"));
+            self.forget_send(transfer_id);
+            self.bundles.remove_file(true, local_send_id);
+            self.out_events
+                .push_back(OutEvent::CancelledSend(local_send_id));
+            return true;
+        }
+        if let Some((remote, span)) = self.remove_send_record(local_send_id) {
+            let entered = span.enter();
+            debug!("Cancelling send. By: Local, Reason: timeout or user request.");
+            drop(entered);
+            // Notify remote about the cancellation.
+            self.pending_handler_event
+                .push_back(ToSwarm::NotifyHandler {
+                    peer_id: remote,
+                    handler: NotifyHandler::Any,
+                    event: FromBehaviourEvent::LocalCancelSend {
+                        local_send_id,
+                        span,
+                    },
+                });
+            self.out_events
+                .push_back(OutEvent::CancelledSend(local_send_id));
+            return true;
+        };
+        false
+    }
+
+    /// Called when remote cancelled its sending.
+    /// Once called, it is guaranteed that no more bytes will be written to the file.
+    fn remove_recv_by_remote_send_id(
+        &mut self,
+        remote_send_id: u64,
+    ) -> Option<(PeerId, u64, Span)> {
+        if let Some(v) = self.ongoing_recv.remove(&remote_send_id) {
+            self.release_inbound(&v);
+            self.forget_recv(v.transfer_id);
+            self.bundles.remove_file(false, v.local_recv_id);
+            return Some((v.remote, v.local_recv_id, v.span));
+        };
+        if let Some((_, v)) = self
+            .pending_recv
+            .extract_if(|_, v| v.remote_send_id == remote_send_id)
+            .next()
+        {
+            self.bundles.remove_file(false, v.local_recv_id);
+            return Some((v.remote, v.local_recv_id, v.span));
+        };
+        None
+    }
+
+    /// Call this to progress a send operation.
+    /// The next chunk will be sent once read from the file.
+    fn progress_ongoing_send(&mut self, local_send_id: u64) {
+        if let Some(ongoing_send) = self.ongoing_send.get_mut(&local_send_id) {
+            ongoing_send.last_active = time_now!();
+            ongoing_send.awaiting_chunk = true;
+        }
+    }
+
+    /// Forward chunks that have been read from files to handlers.
+    fn poll_ongoing_send(&mut self, cx: &mut std::task::Context<'_>) {
+        let mut finished = Vec::new();
+        let mut failed = Vec::new();
+        // Sends that come first take the shared bandwidth first.
+        let mut awaiting = self
+            .ongoing_send
+            .values()
+            .filter(|v| v.awaiting_chunk)
+            .map(|v| (std::cmp::Reverse(v.priority), v.local_send_id))
+            .collect::<Vec<_>>();
+        match self.config.throttle.send_queue {
+            SendQueue::Fifo => awaiting.sort_unstable_by_key(|(_, id)| *id),
+            SendQueue::Priority => awaiting.sort_unstable(),
+        }
+        // The shortest time until a throttled send can continue.
+        let mut wait: Option<Duration> = None;
+        for (_, local_send_id) in awaiting {
+            let ongoing_send = self
+                .ongoing_send
+                .get_mut(&local_send_id)
+                .expect("ID to be collected above");
+            let delay = self
+                .send_bucket
+                .as_mut()
+                .and_then(TokenBucket::delay)
+                .or_else(|| ongoing_send.bucket.as_mut().and_then(TokenBucket::delay));
+            if let Some(delay) = delay {
+                // Being throttled is not inactivity.
+                ongoing_send.last_active = time_now!();
+                wait = Some(wait.map_or(delay, |v| v.min(delay)));
+                continue;
+            }
+            let chunk = match ongoing_send.reader.poll_next(cx) {
+                Poll::Pending => continue,
+                Poll::Ready(chunk) => chunk,
+            };
+            let local_send_id = &local_send_id;
+            let _entered = ongoing_send.span.enter();
+            trace!("Progressing send");
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    error!("{:?}", e);
+                    self.out_events.push_back(OutEvent::OngoingSendError {
+                        local_send_id: *local_send_id,
+                        error: format!("{e:?}"),
+                    });
+                    failed.push(*local_send_id);
+                    continue; // Error reading the file, or the file has changed
+                }
+            };
+            // Progress is in bytes of the file, bandwidth in bytes on the wire.
+            let bytes_read = chunk.raw_len;
+            let bytes_on_wire = chunk.data.len();
+            if let Some(bucket) = ongoing_send.bucket.as_mut() {
+                bucket.consume(bytes_on_wire);
+            }
+            if let Some(bucket) = self.send_bucket.as_mut() {
+                bucket.consume(bytes_on_wire);
+            }
+            ongoing_send.awaiting_chunk = false;
+            ongoing_send.bytes_sent += bytes_read as u64;
+            ongoing_send.bytes_on_wire += bytes_on_wire as u64;
+            trace!(
+                "Reading {bytes_read} bytes, {bytes_on_wire} bytes on wire, total bytes sent {}",
+                ongoing_send.bytes_sent
+            );
+            self.pending_handler_event
+                .push_back(ToSwarm::NotifyHandler {
+                    peer_id: ongoing_send.remote,
+                    handler: NotifyHandler::Any,
+                    event: FromBehaviourEvent::FileChunk {
+                        bytes_to_send: chunk.data,
+                        local_send_id: *local_send_id,
+                        compressed: chunk.compressed,
+                        digest: chunk.digest,
+                    },
+                });
+            if bytes_read == 0 {
+                debug!(
+                    "Finished, {} bytes total, {} bytes sent",
+                    ongoing_send.bytes_total, ongoing_send.bytes_sent
+                );
+                let elapsed = ongoing_send.started.elapsed();
+                self.out_events.push_back(OutEvent::SendCompleted {
+                    local_send_id: *local_send_id,
+                    file_path: ongoing_send.file_path.clone(),
+                    // Streams may end at a different length than announced.
+                    bytes_total: ongoing_send.bytes_sent,
+                    bytes_on_wire: ongoing_send.bytes_on_wire,
+                    elapsed,
+                    throughput: throughput(ongoing_send.bytes_sent, elapsed),
+                });
+                self.bundles.progressed(
+                    true,
+                    *local_send_id,
+                    ongoing_send.bytes_sent,
+                    true,
+                    &mut self.out_events,
+                );
+                finished.push(*local_send_id);
+            }
+        }
+        self.throttle_timer = wait.map(Delay::new);
+        if let Some(timer) = self.throttle_timer.as_mut() {
+            // Register the waker, the sends will be polled again once it fires.
+            let _ = timer.poll_unpin(cx);
+        }
+        for local_send_id in finished {
+            if let Some(ongoing_send) = self.ongoing_send.remove(&local_send_id) {
+                self.forget_send(ongoing_send.transfer_id);
+            }
+        }
+        // The receiver won't get the rest of the file, stop it from waiting.
+        for local_send_id in failed {
+            self.cancel_send_by_local_send_id(local_send_id);
+        }
+    }
+
+    /// Called when local received a chunk of file.
+    fn progress_ongoing_recv(
+        &mut self,
+        remote_send_id: u64,
+        content: Vec<u8>,
+        compressed: bool,
+        digest: Vec<u8>,
+        connection: ConnectionId,
+    ) {
+        let ongoing_recv = if let Some(v) = self.ongoing_recv.get_mut(&remote_send_id) {
+            v
+        } else {
+            warn!(
+                "A chunk of file arrived but its record is dropped or not found. 
+                This suggests a corrupted internal state of either or both ends."
+            );
+            return; // Record not found
+        };
+        ongoing_recv.last_active = time_now!();
+        ongoing_recv.connection = Some(connection);
+        ongoing_recv.bytes_on_wire += content.len() as u64;
+        let content = if compressed {
+            // A chunk never exceeds the chunk size before compression.
+            match compression::decompress(&content, FILE_CHUNK_SIZE) {
+                Ok(v) => v,
+                Err(e) => {
+                    let local_recv_id = ongoing_recv.local_recv_id;
+                    ongoing_recv
+                        .span
+                        .in_scope(|| warn!("Failed to decompress a chunk: {e}"));
+                    self.out_events.push_back(OutEvent::OngoingRecvError {
+                        local_recv_id,
+                        error: format!("Failed to decompress a chunk: {e}"),
+                    });
+                    self.cancel_recv_by_local_recv_id(local_recv_id);
+                    return;
+                }
+            }
+        } else {
+            content
+        };
+        let entered = ongoing_recv.span.enter();
+        let len = content.len();
+        trace!("Received {len} bytes");
+        // The actual length of streamed data is only known at EOF.
+        if len == 0 && ongoing_recv.size_is_hint {
+            ongoing_recv.bytes_total = ongoing_recv.bytes_queued;
+        }
+        if len == 0 && ongoing_recv.bytes_queued != ongoing_recv.bytes_total {
+            warn!("Unexpected EOF met, expecting {} bytes but total received is {}, terminating the transmission.", ongoing_recv.bytes_total, ongoing_recv.bytes_queued);
+            self.out_events
+                .push_back(OutEvent::Error(error::Error::UnexpectedEOF(
+                    ongoing_recv.local_recv_id,
+                )));
+            drop(entered);
+            let ongoing_recv = self
+                .ongoing_recv
+                .remove(&remote_send_id)
+                .expect("Already handled");
+            self.release_inbound(&ongoing_recv);
+            return; // Unexpected EOF.
+        }
+        // Never write beyond the size that was offered and accepted.
+        if !ongoing_recv.size_is_hint
+            && ongoing_recv.bytes_queued + len as u64 > ongoing_recv.bytes_total
+        {
+            let local_recv_id = ongoing_recv.local_recv_id;
+            let error = format!(
+                "Remote sent more than the {} bytes offered",
+                ongoing_recv.bytes_total
+            );
+            warn!("{error}, terminating the transmission.");
+            drop(entered);
+            self.out_events.push_back(OutEvent::OngoingRecvError {
+                local_recv_id,
+                error,
+            });
+            self.cancel_recv_by_local_recv_id(local_recv_id);
+            return;
+        }
+        ongoing_recv.bytes_queued += len as u64;
+        if len == 0 {
+            // EOF, flush and verify the file against the digest from remote.
+            ongoing_recv.writer.finish(digest);
+        } else {
+            ongoing_recv.writer.push(content);
+        }
+        if ongoing_recv.writer.is_saturated() && !ongoing_recv.paused {
+            trace!("Writer falls behind, pausing inbound");
+            ongoing_recv.paused = true;
+            self.pending_handler_event
+                .push_back(ToSwarm::NotifyHandler {
+                    peer_id: ongoing_recv.remote,
+                    handler: NotifyHandler::One(connection),
+                    event: FromBehaviourEvent::PauseInbound { remote_send_id },
+                });
+        }
+    }
+
+    /// Collect results of writes to files.
+    fn poll_ongoing_recv(&mut self, cx: &mut std::task::Context<'_>) {
+        let mut finished = Vec::new();
+        for (remote_send_id, ongoing_recv) in self.ongoing_recv.iter_mut() {
+            while let Poll::Ready(result) = ongoing_recv.writer.poll_written(cx) {
+                let _entered = ongoing_recv.span.enter();
+                ongoing_recv.last_active = time_now!();
+                match result {
+                    Ok(0) => {
+                        self.out_events.push_back(OutEvent::RecvProgressed {
+                            local_recv_id: ongoing_recv.local_recv_id,
+                            bytes_received: ongoing_recv.bytes_total,
+                            bytes_total: ongoing_recv.bytes_total,
+                        });
+                        let elapsed = ongoing_recv.started.elapsed();
+                        self.out_events.push_back(OutEvent::RecvCompleted {
+                            local_recv_id: ongoing_recv.local_recv_id,
+                            file_path: ongoing_recv.file_path.clone(),
+                            bytes_total: ongoing_recv.bytes_total,
+                            bytes_on_wire: ongoing_recv.bytes_on_wire,
+                            elapsed,
+                            throughput: throughput(ongoing_recv.bytes_total, elapsed),
+                        });
+                        self.bundles.progressed(
+                            false,
+                            ongoing_recv.local_recv_id,
+                            ongoing_recv.bytes_total,
+                            true,
+                            &mut self.out_events,
+                        );
+                        debug!("All bytes received, lifecycle ended.");
+                        finished.push(*remote_send_id);
+                        break; // EOF and all bytes written, transmission complete.
+                    }
+                    Ok(bytes_written) => {
+                        trace!("Written {bytes_written} bytes to file");
+                        ongoing_recv.bytes_received += bytes_written as u64;
+                        if ongoing_recv.bytes_received == ongoing_recv.bytes_total {
+                            continue; // Completion is reported once the file is verified.
+                        }
+                        self.out_events.push_back(OutEvent::RecvProgressed {
+                            local_recv_id: ongoing_recv.local_recv_id,
+                            bytes_received: ongoing_recv.bytes_received,
+                            bytes_total: ongoing_recv.bytes_total,
+                        });
+                        self.bundles.progressed(
+                            false,
+                            ongoing_recv.local_recv_id,
+                            ongoing_recv.bytes_received,
+                            false,
+                            &mut self.out_events,
+                        );
+                    }
+                    Err(WriteError::DigestMismatch) => {
+                        let error = error::FileRecvError::IntegrityMismatch {
+                            path: ongoing_recv.file_path.to_string_lossy().to_string(),
+                        };
+                        warn!("{error}");
+                        self.out_events.push_back(OutEvent::OngoingRecvError {
+                            local_recv_id: ongoing_recv.local_recv_id,
+                            error: error.to_string(),
+                        });
+                        self.bundles.remove_file(false, ongoing_recv.local_recv_id);
+                        finished.push(*remote_send_id);
+                        break; // The partial file has been deleted
+                    }
+                    Err(WriteError::Io(e)) => {
+                        debug!(
+                            "Failed to write data to file {:?}, terminating.",
+                            ongoing_recv.file_path
+                        );
+                        self.out_events.push_back(OutEvent::OngoingRecvError {
+                            local_recv_id: ongoing_recv.local_recv_id,
+                            error: format!("{e:?}"),
+                        });
+                        self.bundles.remove_file(false, ongoing_recv.local_recv_id);
+                        finished.push(*remote_send_id);
+                        break; // Failed to write to file
+                    }
+                }
+            }
+            if ongoing_recv.paused && !ongoing_recv.writer.is_saturated() {
+                trace!("Writer caught up, resuming inbound");
+                ongoing_recv.paused = false;
+                if let Some(connection) = ongoing_recv.connection {
+                    self.pending_handler_event
+                        .push_back(ToSwarm::NotifyHandler {
+                            peer_id: ongoing_recv.remote,
+                            handler: NotifyHandler::One(connection),
+                            event: FromBehaviourEvent::ResumeInbound {
+                                remote_send_id: *remote_send_id,
+                            },
+                        });
+                }
+            }
+        }
+        for remote_send_id in finished {
+            if let Some(ongoing_recv) = self.ongoing_recv.remove(&remote_send_id) {
+                self.release_inbound(&ongoing_recv);
+                self.forget_recv(ongoing_recv.transfer_id);
+            }
+        }
+    }
+
+    /// Keep writing chunks that arrived before the recv was interrupted,
+    /// so that the transfer can be resumed from where the remote left.
+    fn poll_interrupted_recv(&mut self, cx: &mut std::task::Context<'_>) {
+        let mut failed = Vec::new();
+        for (local_recv_id, recv) in self.interrupted_recv.iter_mut() {
+            let writer = match recv.writer.as_mut() {
+                Some(writer) => writer,
+                None => continue,
+            };
+            while let Poll::Ready(result) = writer.poll_written(cx) {
+                match result {
+                    Ok(bytes_written) if bytes_written > 0 => {
+                        recv.bytes_received += bytes_written as u64
+                    }
+                    Ok(_) => break,
+                    Err(e) => {
+                        self.out_events.push_back(OutEvent::OngoingRecvError {
+                            local_recv_id: *local_recv_id,
+                            error: format!("{e:?}"),
+                        });
+                        failed.push(*local_recv_id);
+                        break;
+                    }
+                }
+            }
+        }
+        for local_recv_id in failed {
+            if let Some(recv) = self.interrupted_recv.remove(&local_recv_id) {
+                self.forget_recv(recv.transfer_id);
+                self.bundles.remove_file(false, local_recv_id);
+            }
+        }
+    }
+
+    /// Called when local wants to continue an interrupted recv.
+    fn resume_recv(
+        &mut self,
+        local_recv_id: u64,
+        callback: oneshot::Sender<Result<u64, error::FileRecvError>>,
+    ) {
+        let recv = match self.interrupted_recv.remove(&local_recv_id) {
+            Some(v) => v,
+            None => {
+                handle_callback_sender!(Err(error::FileRecvError::PendingRecvNotFound(local_recv_id)) => callback);
+                return;
+            }
+        };
+        if !self.connected_peers.contains(&recv.remote) {
+            self.interrupted_recv.insert(local_recv_id, recv);
+            handle_callback_sender!(Err(error::FileRecvError::ResumeRejected("Sender is not connected".into())) => callback);
+            return;
+        }
+        recv.span
+            .in_scope(|| debug!("Requesting resumption from {} bytes", recv.bytes_queued));
+        // Bytes queued will eventually be written, no need to ask for them again.
+        self.pending_handler_event
+            .push_back(ToSwarm::NotifyHandler {
+                peer_id: recv.remote,
+                handler: NotifyHandler::Any,
+                event: FromBehaviourEvent::ResumeFile {
+                    transfer_id: recv.transfer_id,
+                    offset: recv.bytes_queued,
+                    compression: self.config.compression.enabled,
+                },
+            });
+        self.pending_resume
+            .insert(recv.transfer_id, PendingResume { recv, callback });
+    }
+
+    /// Called when the sender agreed to continue an interrupted recv.
+    fn on_resume_accepted(
+        &mut self,
+        from: PeerId,
+        transfer_id: u64,
+        remote_send_id: u64,
+        offset: u64,
+    ) {
+        let PendingResume { recv, callback } = match self.pending_resume.remove(&transfer_id) {
+            Some(v) if v.recv.remote == from => v,
+            Some(v) => {
+                self.pending_resume.insert(transfer_id, v);
+                return;
+            }
+            None => return,
+        };
+        if offset != recv.bytes_queued {
+            let reason = format!(
+                "Sender continues from {offset} bytes, expecting {}",
+                recv.bytes_queued
+            );
+            // Stop the sender, the recv can be resumed again.
+            self.pending_handler_event
+                .push_back(ToSwarm::NotifyHandler {
+                    peer_id: from,
+                    handler: NotifyHandler::Any,
+                    event: FromBehaviourEvent::LocalCancelRecv {
+                        remote_send_id,
+                        span: recv.span.clone(),
+                    },
+                });
+            self.pending_resume
+                .insert(transfer_id, PendingResume { recv, callback });
+            self.on_resume_rejected(from, transfer_id, reason);
+            return;
+        }
+        let InterruptedRecv {
+            local_recv_id,
+            remote,
+            file_path,
+            bytes_received,
+            bytes_queued,
+            bytes_total,
+            content_hash,
+            writer,
+            span,
+            started,
+            ..
+        } = recv;
+        span.in_scope(|| debug!("Recv resumed from {offset} bytes"));
+        let writer = writer.unwrap_or_else(|| {
+            FileWriter::resume(
+                file_path.clone(),
+                offset,
+                content_hash.clone(),
+                self.config.write_behind_chunks,
+            )
+        });
+        self.ongoing_recv.insert(
+            remote_send_id,
+            OngoingFileRecv {
+                remote_send_id,
+                local_recv_id,
+                remote,
+                bytes_received,
+                bytes_queued,
+                bytes_total,
+                bytes_on_wire: 0,
+                writer,
+                connection: None,
+                paused: false,
+                span,
+                file_path,
                 last_active: time_now!(),
+                transfer_id,
+                content_hash,
+                started,
+                size_is_hint: false,
+                resumable: true,
             },
         );
-        let ev = ToSwarm::NotifyHandler {
-            peer_id: remote,
-            handler: NotifyHandler::Any,
-            event: FromBehaviourEvent::AcceptFile {
-                remote_send_id,
-                callback,
-            },
+        self.out_events.push_back(OutEvent::RecvResumed {
+            local_recv_id,
+            offset,
+        });
+        handle_callback_sender!(Ok(offset) => callback);
+    }
+
+    /// Called when the sender refused to continue an interrupted recv.
+    /// The recv stays interrupted so that it can be cancelled or retried.
+    fn on_resume_rejected(&mut self, from: PeerId, transfer_id: u64, reason: String) {
+        let PendingResume { recv, callback } = match self.pending_resume.remove(&transfer_id) {
+            Some(v) if v.recv.remote == from => v,
+            Some(v) => {
+                self.pending_resume.insert(transfer_id, v);
+                return;
+            }
+            None => return,
         };
-        self.pending_handler_event.push_back(ev);
+        recv.span
+            .in_scope(|| debug!("Resumption rejected by remote: {reason}"));
+        self.interrupted_recv.insert(recv.local_recv_id, recv);
+        handle_callback_sender!(Err(error::FileRecvError::ResumeRejected(reason)) => callback);
     }
 
-    /// Called when rmeote accepts a pending send.
-    /// If not found in the pending list, will return `Result::Err`.
-    fn pending_send_accepted(&mut self, local_send_id: u64) -> Result<u64, ()> {
-        if !self.pending_send.contains_key(&local_send_id)
-            && !self.ongoing_send.contains_key(&local_send_id)
-        {
-            return Err(());
-        }
-        let PendingSend {
+    /// Called when the receiver wants to continue an interrupted send.
+    fn on_resume_requested(
+        &mut self,
+        from: PeerId,
+        connection: ConnectionId,
+        transfer_id: u64,
+        offset: u64,
+        compression: bool,
+    ) {
+        let reject = |reason: &str| ToSwarm::NotifyHandler {
+            peer_id: from,
+            handler: NotifyHandler::One(connection),
+            event: FromBehaviourEvent::RejectResume {
+                transfer_id,
+                reason: reason.into(),
+            },
+        };
+        let send = match self.resumable_send.remove(&transfer_id) {
+            Some(v) if v.remote == from && offset <= v.bytes_total => v,
+            Some(v) => {
+                let ev = if v.remote == from {
+                    reject("Offset exceeds the size of the file")
+                } else {
+                    reject("Transfer not found")
+                };
+                self.resumable_send.insert(transfer_id, v);
+                self.pending_handler_event.push_back(ev);
+                return;
+            }
+            None => {
+                self.pending_handler_event
+                    .push_back(reject("Transfer not found"));
+                return;
+            }
+        };
+        let ResumableSend {
             local_send_id,
             remote,
-            file,
-            span,
-            bytes_total,
             file_path,
+            bytes_total,
+            content_hash,
+            span,
+            started,
+            priority,
             ..
-        } = self
-            .pending_send
-            .remove(&local_send_id)
-            .expect("Already handled");
-        let entered = span.enter();
-        debug!("Send request accepted.");
-        drop(entered);
+        } = send;
+        span.in_scope(|| debug!("Send resumed from {offset} bytes"));
+        let reader = FileReader::open(
+            file_path.clone(),
+            offset,
+            bytes_total,
+            content_hash.clone(),
+            FILE_CHUNK_SIZE,
+            self.config.read_ahead_chunks,
+            self.compressor(compression && self.config.compression.enabled),
+        );
         self.ongoing_send.insert(
             local_send_id,
             OngoingFileSend {
                 local_send_id,
                 remote,
+                bytes_sent: offset,
                 bytes_total,
-                file_handle: file,
+                bytes_on_wire: 0,
+                reader,
+                awaiting_chunk: true,
                 file_path,
-                bytes_sent: 0,
                 span,
                 last_active: time_now!(),
+                transfer_id,
+                content_hash,
+                started,
+                resumable: true,
+                priority,
+                bucket: TokenBucket::new(self.config.throttle.max_send_rate_per_transfer),
             },
         );
-        self.progress_ongoing_send(local_send_id);
-        Ok(bytes_total)
-    }
-
-    /// Called when local node cancels the transmission.
-    /// Once called, it is guaranteed that no more bytes will be written to the file.
-    /// Receiving operation on local node will stop immediately without acknowledgement from remote.
-    fn cancel_recv_by_local_recv_id(&mut self, local_recv_id: u64) -> bool {
-        // Try to remove all recv record associted with the provided id.
-        // If none is found, false is returned.
-        if let Some((remote, remote_send_id, span)) = self.remove_recv_record(local_recv_id) {
-            span.in_scope(|| {
-                debug!("Cancelling recv. By: Local, Reason: timeout or user request.")
+        // Chunks should follow the acceptance on the same connection.
+        self.pending_handler_event
+            .push_back(ToSwarm::NotifyHandler {
+                peer_id: from,
+                handler: NotifyHandler::One(connection),
+                event: FromBehaviourEvent::AcceptResume {
+                    transfer_id,
+                    local_send_id,
+                    offset,
+                },
             });
-            // Notify the remote about the cancellation.
-            self.pending_handler_event
-                .push_back(ToSwarm::NotifyHandler {
-                    peer_id: remote,
-                    handler: NotifyHandler::Any,
-                    event: FromBehaviourEvent::LocalCancelRecv {
-                        remote_send_id,
-                        span,
-                    },
-                });
-            self.out_events
-                .push_back(OutEvent::CancelledRecv(local_recv_id));
-            return true;
-        }
-        false
+        self.out_events.push_back(OutEvent::SendResumed {
+            local_send_id,
+            offset,
+        });
     }
 
-    /// Called when local cancelled transmission.
-    /// Once called, it is guaranteed that no more bytes will be read.
-    fn cancel_send_by_local_send_id(&mut self, local_send_id: u64) -> bool {
-        if let Some((remote, span)) = self.remove_send_record(local_send_id) {
-            let entered = span.enter();
-            debug!("Cancelling send. By: Local, Reason: timeout or user request.");
-            drop(entered);
-            // Notify remote about the cancellation.
+    /// Resume reading from remote if the recv has paused it.
+    /// Called when an ongoing recv is removed.
+    fn release_inbound(&mut self, ongoing_recv: &OngoingFileRecv) {
+        if let (true, Some(connection)) = (ongoing_recv.paused, ongoing_recv.connection) {
             self.pending_handler_event
                 .push_back(ToSwarm::NotifyHandler {
-                    peer_id: remote,
-                    handler: NotifyHandler::Any,
-                    event: FromBehaviourEvent::LocalCancelSend {
-                        local_send_id,
-                        span,
+                    peer_id: ongoing_recv.remote,
+                    handler: NotifyHandler::One(connection),
+                    event: FromBehaviourEvent::ResumeInbound {
+                        remote_send_id: ongoing_recv.remote_send_id,
                     },
                 });
-
-            return true;
-        };
-        false
-    }
-
-    /// Called when remote cancelled its sending.
-    /// Once called, it is guaranteed that no more bytes will be written to the file.
-    fn remove_recv_by_remote_send_id(
-        &mut self,
-        remote_send_id: u64,
-    ) -> Option<(PeerId, u64, Span)> {
-        if let Some(v) = self.ongoing_recv.remove(&remote_send_id) {
-            return Some((v.remote, v.local_recv_id, v.span));
-        };
-        if let Some((_, v)) = self
-            .pending_recv
-            .extract_if(|_, v| v.remote_send_id == remote_send_id)
-            .next()
-        {
-            return Some((v.remote, v.remote_send_id, v.span));
-        };
-        None
-    }
-
-    /// Call this to progress a send operation.
-    fn progress_ongoing_send(&mut self, local_send_id: u64) {
-        if self.ongoing_send.get_mut(&local_send_id).is_none() {
-            return; // record not found
-        }
-        let ongoing_send = self
-            .ongoing_send
-            .get_mut(&local_send_id)
-            .expect("Already handled");
-        ongoing_send.last_active = time_now!();
-        let entered = ongoing_send.span.enter();
-        trace!("Progressing send");
-        let mut buf = Vec::from([0u8; FILE_CHUNK_SIZE]);
-        let bytes_read = match ongoing_send.file_handle.read(&mut buf) {
-            Err(e) => {
-                error!("{:?}", e);
-                drop(entered);
-                self.ongoing_send.remove(&local_send_id);
-                return; // Error reading the file
-            }
-            Ok(bytes_read) => bytes_read,
-        };
-        ongoing_send.bytes_sent += bytes_read as u64;
-        trace!(
-            "Reading {bytes_read} bytes, total bytes sent {}",
-            ongoing_send.bytes_sent
-        );
-        buf.truncate(bytes_read);
-        self.pending_handler_event
-            .push_back(ToSwarm::NotifyHandler {
-                peer_id: ongoing_send.remote,
-                handler: NotifyHandler::Any,
-                event: FromBehaviourEvent::FileChunk {
-                    bytes_to_send: buf,
-                    local_send_id: ongoing_send.local_send_id,
-                },
-            });
-        if bytes_read == 0 {
-            debug!(
-                "Finished, {} bytes total, {} bytes sent",
-                ongoing_send.bytes_total, ongoing_send.bytes_sent
-            );
-            drop(entered);
-            self.ongoing_send.remove(&local_send_id);
-        }
-    }
-
-    /// Called when local received a chunk of file.
-    fn progress_ongoing_recv(&mut self, remote_send_id: u64, content: Vec<u8>) {
-        let mut ongoing_recv = if let Some(v) = self.ongoing_recv.remove(&remote_send_id) {
-            v
-        } else {
-            warn!(
-                "A chunk of file arrived but its record is dropped or not found. 
-                This suggests a corrupted internal state of either or both ends."
-            );
-            return; // Record not found
-        };
-        ongoing_recv.last_active = time_now!();
-        let entered = ongoing_recv.span.enter();
-        let len = content.len();
-        trace!("Received {len} bytes");
-        if len == 0 && ongoing_recv.bytes_received != ongoing_recv.bytes_total {
-            warn!("Unexpected EOF met, expecting {} bytes but total received is {}, terminating the transmission.", ongoing_recv.bytes_total, ongoing_recv.bytes_received);
-            self.out_events
-                .push_back(OutEvent::Error(error::Error::UnexpectedEOF(
-                    ongoing_recv.local_recv_id,
-                )));
-            return; // Unexpected EOF.
-        }
-        if len == 0 {
-            self.out_events.push_back(OutEvent::RecvProgressed {
-                local_recv_id: ongoing_recv.local_recv_id,
-                bytes_received: ongoing_recv.bytes_total,
-                bytes_total: ongoing_recv.bytes_total,
-            });
-            if let Err(e) = ongoing_recv.file_handle.flush() {
-                tracing::error!("Unsuccessful file flushing: {e:?}")
-            };
-            debug!("All bytes received, lifecycle ended.");
-            return; // EOF and all bytes written, transmission complete.
-        }
-        let mut cursor = 0;
-        loop {
-            println!("attempt to write file");
-            match ongoing_recv.file_handle.write(&content[cursor..]) {
-                Ok(bytes_written) => {
-                    trace!("Writing {bytes_written} bytes to file");
-                    cursor += bytes_written;
-                    if cursor >= len {
-                        break;
-                    }
-                }
-                Err(e) => {
-                    let ev = OutEvent::OngoingRecvError {
-                        local_recv_id: ongoing_recv.local_recv_id,
-                        error: format!("{e:?}"),
-                    };
-                    debug!(
-                        "Failed to write data to file {:?}, terminating.",
-                        ongoing_recv.file_path
-                    );
-                    self.out_events.push_back(ev);
-                    return; // Failed to write to file
-                }
-            };
         }
-        ongoing_recv.bytes_received += cursor as u64;
-        drop(entered);
-        // The record will be inserted back only if
-        // - All bytes have been successfully written.
-        // - EOF not encountered.
-        self.ongoing_recv.insert(remote_send_id, ongoing_recv); // Back in queue
     }
 
     fn check_expiry(&mut self) {
@@ -622,6 +2180,8 @@ impl Behaviour {
         if self.config.ongoing_recv_timeout_sec > 0 {
             self.ongoing_recv
                 .iter()
+                // Nothing has arrived if the send is still queued by the sender.
+                .filter(|(_, v)| v.bytes_queued > 0)
                 .filter(|(_, v)| time_now - v.last_active > self.config.ongoing_recv_timeout_sec)
                 .map(|(_, v)| v.local_recv_id)
                 .collect::<Box<[u64]>>()
@@ -635,10 +2195,17 @@ impl Behaviour {
     /// Try to remove a recv record from all record store.
     /// Called when the recv is terminated, e.g cancelled or on error.
     fn remove_recv_record(&mut self, local_recv_id: u64) -> Option<(PeerId, u64, Span)> {
+        self.bundles.remove_file(false, local_recv_id);
         if let Some(v) = self.pending_recv.remove(&local_recv_id) {
             return Some((v.remote, v.remote_send_id, v.span));
         }
-        if let Some(v) = self.ongoing_recv.remove(&local_recv_id) {
+        if let Some((_, v)) = self
+            .ongoing_recv
+            .extract_if(|_, v| v.local_recv_id == local_recv_id)
+            .next()
+        {
+            self.release_inbound(&v);
+            self.forget_recv(v.transfer_id);
             return Some((v.remote, v.remote_send_id, v.span));
         };
         None
@@ -647,25 +2214,190 @@ impl Behaviour {
     /// Called when remote cancelled its receiving.
     /// Once called, it is guaranteed that no more bytes will be sent to remote.
     fn remove_send_record(&mut self, local_send_id: u64) -> Option<(PeerId, Span)> {
+        self.bundles.remove_file(true, local_send_id);
         if let Some(PendingSend { remote, span, .. }) = self.pending_send.remove(&local_send_id) {
             return Some((remote, span));
         };
-        if let Some(OngoingFileSend { remote, span, .. }) = self.ongoing_send.remove(&local_send_id)
+        if let Some(index) = self
+            .queued_send
+            .iter()
+            .position(|v| v.local_send_id == local_send_id)
+        {
+            let PendingSend { remote, span, .. } =
+                self.queued_send.remove(index).expect("Index is valid");
+            return Some((remote, span));
+        }
+        if let Some(OngoingFileSend {
+            remote,
+            span,
+            transfer_id,
+            ..
+        }) = self.ongoing_send.remove(&local_send_id)
         {
+            self.forget_send(transfer_id);
             return Some((remote, span));
         }
         None
     }
 
+    /// Remove a recv from the journal once it's completed or terminated.
+    fn forget_recv(&mut self, transfer_id: u64) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.remove_recv(transfer_id);
+        }
+    }
+
+    /// Remove a send from the journal once it's completed or terminated.
+    fn forget_send(&mut self, transfer_id: u64) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.remove_send(transfer_id);
+        }
+    }
+
     /// Called when a peer disconnected.
     fn on_disconnect(&mut self, info: &ConnectionClosed) {
         if info.remaining_established < 1 {
             self.connected_peers.remove(&info.peer_id);
             trace!("Peer {} disconnected", info.peer_id);
-            self.pending_send.retain(|_, v| v.remote != info.peer_id);
-            self.pending_recv.retain(|_, v| v.remote != info.peer_id);
-            self.ongoing_send.retain(|_, v| v.remote != info.peer_id);
-            self.ongoing_recv.retain(|_, v| v.remote != info.peer_id);
+            for (local_send_id, _) in self
+                .pending_send
+                .extract_if(|_, v| v.remote == info.peer_id)
+                .collect::<Vec<_>>()
+            {
+                self.bundles.remove_file(true, local_send_id);
+            }
+            for (_, browse) in self
+                .pending_browse
+                .extract_if(|_, v| v.peer == info.peer_id)
+                .collect::<Vec<_>>()
+            {
+                handle_callback_sender!(Err(ShareError::ConnectionLost)=>browse.callback);
+            }
+            for (_, fetch) in self
+                .pending_fetch
+                .extract_if(|_, v| v.peer == info.peer_id)
+                .collect::<Vec<_>>()
+            {
+                handle_callback_sender!(Err(ShareError::ConnectionLost)=>fetch.callback);
+            }
+            for (_, chunk) in self
+                .pending_chunk
+                .extract_if(|_, v| v.peer == info.peer_id)
+                .collect::<Vec<_>>()
+            {
+                handle_callback_sender!(Err(StoreError::ConnectionLost)=>chunk.callback);
+            }
+            let (lost_send, queued_send) =
+                std::mem::take(&mut self.queued_send)
+                    .into_iter()
+                    .partition::<VecDeque<_>, _>(|v| v.remote == info.peer_id);
+            self.queued_send = queued_send;
+            for send in lost_send {
+                send.span
+                    .in_scope(|| debug!("Queued send lost its receiver"));
+                self.bundles.remove_file(true, send.local_send_id);
+                self.out_events.push_back(OutEvent::OngoingSendError {
+                    local_send_id: send.local_send_id,
+                    error: "Connection to the receiver lost before the send started".into(),
+                });
+            }
+            for (local_recv_id, _) in self
+                .pending_recv
+                .extract_if(|_, v| v.remote == info.peer_id)
+                .collect::<Vec<_>>()
+            {
+                self.bundles.remove_file(false, local_recv_id);
+            }
+            // Ongoing transfers are kept so that they can be resumed later.
+            let interrupted_send = self
+                .ongoing_send
+                .extract_if(|_, v| v.remote == info.peer_id)
+                .map(|(_, v)| v)
+                .collect::<Vec<_>>();
+            for send in interrupted_send {
+                if !send.resumable {
+                    send.span
+                        .in_scope(|| debug!("Streamed send lost its receiver"));
+                    self.out_events.push_back(OutEvent::OngoingSendError {
+                        local_send_id: send.local_send_id,
+                        error: "Connection to the receiver lost".into(),
+                    });
+                    continue;
+                }
+                send.span
+                    .in_scope(|| debug!("Send interrupted at {} bytes", send.bytes_sent));
+                self.out_events.push_back(OutEvent::SendInterrupted {
+                    local_send_id: send.local_send_id,
+                    bytes_sent: send.bytes_sent,
+                    bytes_total: send.bytes_total,
+                });
+                // The chunk in flight may not have reached the receiver,
+                // the receiver decides where to continue from.
+                self.resumable_send.insert(
+                    send.transfer_id,
+                    ResumableSend {
+                        local_send_id: send.local_send_id,
+                        transfer_id: send.transfer_id,
+                        remote: send.remote,
+                        file_path: send.file_path,
+                        bytes_sent: send.bytes_sent,
+                        bytes_total: send.bytes_total,
+                        content_hash: send.content_hash,
+                        span: send.span,
+                        started: send.started,
+                        priority: send.priority,
+                    },
+                );
+            }
+            let interrupted_recv = self
+                .ongoing_recv
+                .extract_if(|_, v| v.remote == info.peer_id)
+                .map(|(_, v)| v)
+                .collect::<Vec<_>>();
+            for recv in interrupted_recv {
+                if !recv.resumable {
+                    recv.span
+                        .in_scope(|| debug!("Streamed recv lost its sender"));
+                    self.out_events.push_back(OutEvent::OngoingRecvError {
+                        local_recv_id: recv.local_recv_id,
+                        error: "Connection to the sender lost".into(),
+                    });
+                    continue;
+                }
+                recv.span
+                    .in_scope(|| debug!("Recv interrupted at {} bytes", recv.bytes_queued));
+                self.out_events.push_back(OutEvent::RecvInterrupted {
+                    local_recv_id: recv.local_recv_id,
+                    bytes_received: recv.bytes_queued,
+                    bytes_total: recv.bytes_total,
+                });
+                self.interrupted_recv.insert(
+                    recv.local_recv_id,
+                    InterruptedRecv {
+                        local_recv_id: recv.local_recv_id,
+                        transfer_id: recv.transfer_id,
+                        remote: recv.remote,
+                        file_path: recv.file_path,
+                        bytes_received: recv.bytes_received,
+                        bytes_queued: recv.bytes_queued,
+                        bytes_total: recv.bytes_total,
+                        content_hash: recv.content_hash,
+                        writer: Some(recv.writer),
+                        span: recv.span,
+                        timestamp: time_now!(),
+                        started: recv.started,
+                    },
+                );
+            }
+            let pending_resume = self
+                .pending_resume
+                .extract_if(|_, v| v.recv.remote == info.peer_id)
+                .map(|(_, v)| v)
+                .collect::<Vec<_>>();
+            for PendingResume { recv, callback } in pending_resume {
+                self.interrupted_recv.insert(recv.local_recv_id, recv);
+                handle_callback_sender!(Err(error::FileRecvError::ResumeRejected("Sender disconnected".into())) => callback);
+            }
         }
     }
 
@@ -674,11 +2406,42 @@ impl Behaviour {
         self.recv_counter += 1;
         id
     }
+    fn next_bundle_id(&mut self) -> u64 {
+        let id = self.bundle_counter;
+        self.bundle_counter += 1;
+        id
+    }
     fn next_send_id(&mut self) -> u64 {
         let id = self.send_counter;
         self.send_counter += 1;
         id
     }
+    /// Request IDs start from 1, 0 means no request.
+    fn next_request_id(&mut self) -> u64 {
+        self.request_counter += 1;
+        self.request_counter
+    }
+}
+
+/// Average bytes per second over the given duration.
+fn throughput(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+/// An ID that tells transfers apart even after restart.
+fn new_transfer_id(content_hash: &[u8], to: &PeerId, file_path: &Path) -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let mut seed = content_hash.to_vec();
+    seed.extend(to.to_bytes());
+    seed.extend(file_path.to_string_lossy().as_bytes());
+    seed.extend(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+            .to_be_bytes(),
+    );
+    xxhash_rust::xxh3::xxh3_64(&seed)
 }
 
 impl NetworkBehaviour for Behaviour {
@@ -696,12 +2459,77 @@ impl NetworkBehaviour for Behaviour {
             RecvProgressed {
                 remote_send_id,
                 content,
-            } => self.progress_ongoing_recv(remote_send_id, content),
+                compressed,
+                digest,
+            } => self.progress_ongoing_recv(
+                remote_send_id,
+                content,
+                compressed,
+                digest,
+                connection_id,
+            ),
             IncomingFile {
                 file_name,
                 remote_send_id,
                 bytes_total,
-            } => self.on_new_pending_recv(peer_id, file_name, remote_send_id, bytes_total),
+                size_is_hint,
+                transfer_id,
+                content_hash,
+                compression,
+                fetch_id,
+            } => self.on_new_pending_recv(
+                peer_id,
+                file_name,
+                remote_send_id,
+                bytes_total,
+                size_is_hint,
+                transfer_id,
+                content_hash,
+                compression,
+                fetch_id,
+            ),
+            BrowseRequested { request_id, path } => {
+                self.share_server
+                    .browse(self.config.shares.clone(), peer_id, request_id, path)
+            }
+            BrowseResponded {
+                request_id,
+                entries,
+                error,
+            } => self.on_browse_responded(peer_id, request_id, entries, error),
+            FetchRequested { request_id, path } => {
+                self.share_server
+                    .fetch(self.config.shares.clone(), peer_id, request_id, path)
+            }
+            FetchRejected { request_id, reason } => {
+                self.on_fetch_rejected(peer_id, request_id, reason)
+            }
+            ChunkRequested { request_id, hash } => {
+                self.store_server.serve(peer_id, request_id, hash)
+            }
+            ChunkResponded {
+                request_id,
+                data,
+                found,
+            } => self.on_chunk_responded(peer_id, request_id, data, found),
+            IncomingBundle {
+                remote_bundle_id,
+                entries,
+            } => self.on_new_pending_bundle(peer_id, remote_bundle_id, entries),
+            ResumeRequested {
+                transfer_id,
+                offset,
+                compression,
+            } => self.on_resume_requested(peer_id, connection_id, transfer_id, offset, compression),
+            ResumeAccepted {
+                transfer_id,
+                remote_send_id,
+                offset,
+            } => self.on_resume_accepted(peer_id, transfer_id, remote_send_id, offset),
+            ResumeRejected {
+                transfer_id,
+                reason,
+            } => self.on_resume_rejected(peer_id, transfer_id, reason),
             Error(e) => {
                 debug!("Error occurred on peer {peer_id}:{connection_id:?}: {e:#?}",);
                 self.out_events.push_back(OutEvent::Error(e));
@@ -727,15 +2555,10 @@ impl NetworkBehaviour for Behaviour {
                 }
                 warn!("A pending send is not found but acknowledged by remote, ID {local_send_id}",);
             }
-            FileSendAccepted { local_send_id } => {
-                if let Ok(bytes_total) = self.pending_send_accepted(local_send_id) {
-                    self.out_events.push_back(OutEvent::SendProgressed {
-                        local_send_id,
-                        bytes_total,
-                        bytes_sent: 0,
-                    });
-                }
-            }
+            FileSendAccepted {
+                local_send_id,
+                compression,
+            } => self.pending_send_accepted(local_send_id, compression),
             SendProgressed { local_send_id, .. } => {
                 if let Some(v) = self.ongoing_send.get(&local_send_id) {
                     self.out_events.push_back(OutEvent::SendProgressed {
@@ -743,6 +2566,13 @@ impl NetworkBehaviour for Behaviour {
                         bytes_sent: v.bytes_sent,
                         bytes_total: v.bytes_total,
                     });
+                    self.bundles.progressed(
+                        true,
+                        local_send_id,
+                        v.bytes_sent,
+                        false,
+                        &mut self.out_events,
+                    );
                 }
                 self.progress_ongoing_send(local_send_id);
             }
@@ -786,6 +2616,13 @@ impl NetworkBehaviour for Behaviour {
             self.check_expiry();
             self.expiry_check_throttle.reset(Duration::from_secs(5));
         }
+        self.start_queued_sends();
+        self.poll_ongoing_send(cx);
+        self.poll_ongoing_recv(cx);
+        self.poll_interrupted_recv(cx);
+        self.poll_auto_accept(cx);
+        self.poll_share_server(cx);
+        self.poll_store_server(cx);
         if let Some(ev) = self.out_events.pop_front() {
             return Poll::Ready(ToSwarm::GenerateEvent(ev));
         }
@@ -833,18 +2670,84 @@ struct PendingRecv {
     remote: PeerId,
     timestamp: u64,
     span: tracing::Span,
+    /// Whether `bytes_total` is only an estimate of streamed data.
+    size_is_hint: bool,
+    transfer_id: u64,
+    content_hash: Vec<u8>,
+    /// Whether compression is offered by remote and agreed by local.
+    compression: bool,
+}
+#[derive(Debug)]
+struct PendingBrowse {
+    peer: PeerId,
+    callback: oneshot::Sender<Result<Box<[CatalogEntry]>, ShareError>>,
+}
+
+#[derive(Debug)]
+struct PendingFetch {
+    peer: PeerId,
+    /// The partial file of `target`.
+    file: File,
+    target: PathBuf,
+    callback: oneshot::Sender<Result<(u64, u64), ShareError>>,
 }
+
+#[derive(Debug)]
+struct PendingChunk {
+    peer: PeerId,
+    callback: oneshot::Sender<Result<Vec<u8>, StoreError>>,
+}
+
 #[derive(Debug)]
 struct OngoingFileRecv {
     remote_send_id: u64,
     local_recv_id: u64,
     remote: PeerId,
+    /// Bytes written to the file.
     bytes_received: u64,
+    /// Bytes received from remote, including those not yet written.
+    bytes_queued: u64,
     bytes_total: u64,
-    file_handle: std::fs::File,
+    /// Bytes received before decompression, since accepted or resumed.
+    bytes_on_wire: u64,
+    writer: FileWriter,
+    /// The connection that the latest chunk came from.
+    connection: Option<ConnectionId>,
+    /// Whether reading from remote has been paused for the writer to catch up.
+    paused: bool,
     span: tracing::Span,
+    /// Path of the complete file.
     file_path: PathBuf,
     last_active: u64,
+    transfer_id: u64,
+    content_hash: Vec<u8>,
+    /// When the recv was accepted, or restored from the journal.
+    started: Instant,
+    size_is_hint: bool,
+    /// Whether the recv can continue after disconnection.
+    resumable: bool,
+}
+#[derive(Debug)]
+struct InterruptedRecv {
+    local_recv_id: u64,
+    transfer_id: u64,
+    remote: PeerId,
+    file_path: PathBuf,
+    bytes_received: u64,
+    bytes_queued: u64,
+    bytes_total: u64,
+    content_hash: Vec<u8>,
+    /// Writer that is still writing chunks arrived before the interruption,
+    /// `None` if restored from the journal.
+    writer: Option<FileWriter>,
+    span: tracing::Span,
+    timestamp: u64,
+    started: Instant,
+}
+#[derive(Debug)]
+struct PendingResume {
+    recv: InterruptedRecv,
+    callback: oneshot::Sender<Result<u64, error::FileRecvError>>,
 }
 
 #[derive(Debug)]
@@ -853,9 +2756,17 @@ struct PendingSend {
     remote: PeerId,
     file_path: PathBuf,
     bytes_total: u64,
-    file: File,
+    /// Whether `bytes_total` is only an estimate of streamed data.
+    size_is_hint: bool,
+    source: Source,
+    /// Sends with higher priority are started first.
+    priority: u8,
+    /// Whether remote agreed to compression, known once accepted.
+    compression: bool,
     span: tracing::Span,
     timestamp: u64,
+    transfer_id: u64,
+    content_hash: Vec<u8>,
 }
 #[derive(Debug)]
 struct OngoingFileSend {
@@ -863,8 +2774,34 @@ struct OngoingFileSend {
     remote: PeerId,
     bytes_sent: u64,
     bytes_total: u64,
-    file_handle: std::fs::File,
+    /// Bytes sent after compression, since started or resumed.
+    bytes_on_wire: u64,
+    reader: FileReader,
+    /// Whether the next chunk can be sent.
+    awaiting_chunk: bool,
     file_path: PathBuf,
     span: tracing::Span,
     last_active: u64,
+    transfer_id: u64,
+    content_hash: Vec<u8>,
+    /// When the send was accepted by remote, or restored from the journal.
+    started: Instant,
+    /// Whether the send can continue after disconnection.
+    resumable: bool,
+    priority: u8,
+    /// Limit of this send, `None` if unlimited.
+    bucket: Option<TokenBucket>,
+}
+#[derive(Debug)]
+struct ResumableSend {
+    local_send_id: u64,
+    transfer_id: u64,
+    remote: PeerId,
+    file_path: PathBuf,
+    bytes_sent: u64,
+    bytes_total: u64,
+    content_hash: Vec<u8>,
+    span: tracing::Span,
+    started: Instant,
+    priority: u8,
 }
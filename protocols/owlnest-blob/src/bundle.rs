@@ -0,0 +1,188 @@
+//! Multiple files sent and accepted as a whole.
+use super::*;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Component, Path};
+
+/// Maximum number of files in a single bundle,
+/// so that the manifest fits in a single frame.
+pub const MAX_BUNDLE_FILES: usize = 512;
+/// Maximum length of a relative path in the manifest, in bytes.
+pub const MAX_RELATIVE_PATH_LEN: usize = 256;
+
+/// A file to be sent as part of a bundle.
+#[derive(Debug)]
+pub struct BundleFile {
+    pub file: File,
+    /// Full path to the file on local file system.
+    pub file_path: PathBuf,
+    /// Path relative to the root of the bundle, separated by `/`.
+    pub relative_path: String,
+    /// Unix permission bits, 0 if unknown.
+    pub mode: u32,
+    /// BLAKE3 hash of the whole file, see [`hash_file`].
+    pub content_hash: Vec<u8>,
+}
+
+/// A file in the manifest of a bundle.
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleFileInfo {
+    /// Send ID on sender side, recv ID on receiver side.
+    pub local_id: u64,
+    /// Path relative to the root of the bundle, separated by `/`.
+    pub relative_path: String,
+    pub bytes_total: u64,
+    pub bytes_done: u64,
+    /// Unix permission bits, 0 if unknown.
+    pub mode: u32,
+    pub completed: bool,
+}
+
+/// Information about a bundle that is pending or ongoing.
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleInfo {
+    pub local_bundle_id: u64,
+    pub remote: PeerId,
+    /// Whether local peer is the sender.
+    pub outbound: bool,
+    pub files: Vec<BundleFileInfo>,
+}
+impl BundleInfo {
+    pub fn bytes_total(&self) -> u64 {
+        self.files.iter().map(|v| v.bytes_total).sum()
+    }
+    pub fn bytes_done(&self) -> u64 {
+        self.files.iter().map(|v| v.bytes_done).sum()
+    }
+    pub fn files_done(&self) -> usize {
+        self.files.iter().filter(|v| v.completed).count()
+    }
+}
+
+/// Turn a relative path from the manifest into a path that
+/// can be safely joined to the destination directory.
+/// Returns `None` for empty or absolute paths, and paths that contain `..`.
+pub fn sanitize_relative_path(relative_path: &str) -> Option<PathBuf> {
+    if relative_path.len() > MAX_RELATIVE_PATH_LEN {
+        return None;
+    }
+    let mut sanitized = PathBuf::new();
+    for component in Path::new(relative_path).components() {
+        match component {
+            Component::Normal(part) => sanitized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    // Backslashes are separators on Windows but not on Unix.
+    if sanitized.as_os_str().is_empty() || relative_path.contains('\\') {
+        return None;
+    }
+    Some(sanitized)
+}
+
+/// Tracks aggregate progress of bundles, indexed by bundle ID.
+#[derive(Debug, Default)]
+pub(crate) struct Bundles {
+    bundles: HashMap<u64, BundleInfo>,
+    /// Bundle ID indexed by local send ID.
+    send_index: HashMap<u64, u64>,
+    /// Bundle ID indexed by local recv ID.
+    recv_index: HashMap<u64, u64>,
+}
+impl Bundles {
+    pub fn insert(&mut self, bundle: BundleInfo) {
+        let index = if bundle.outbound {
+            &mut self.send_index
+        } else {
+            &mut self.recv_index
+        };
+        for file in bundle.files.iter() {
+            index.insert(file.local_id, bundle.local_bundle_id);
+        }
+        self.bundles.insert(bundle.local_bundle_id, bundle);
+    }
+    pub fn get(&self, local_bundle_id: u64) -> Option<&BundleInfo> {
+        self.bundles.get(&local_bundle_id)
+    }
+    pub fn list(&self) -> Box<[BundleInfo]> {
+        self.bundles.values().cloned().collect()
+    }
+    /// Update progress of a file in a bundle, aggregated events will be pushed to `out_events`.
+    /// Does nothing if the file doesn't belong to a bundle.
+    pub fn progressed(
+        &mut self,
+        outbound: bool,
+        local_id: u64,
+        bytes_done: u64,
+        completed: bool,
+        out_events: &mut VecDeque<OutEvent>,
+    ) {
+        let index = if outbound {
+            &mut self.send_index
+        } else {
+            &mut self.recv_index
+        };
+        let local_bundle_id = match index.get(&local_id) {
+            Some(id) => *id,
+            None => return,
+        };
+        let bundle = match self.bundles.get_mut(&local_bundle_id) {
+            Some(bundle) => bundle,
+            None => return,
+        };
+        let file = match bundle.files.iter_mut().find(|v| v.local_id == local_id) {
+            Some(file) => file,
+            None => return,
+        };
+        file.bytes_done = bytes_done;
+        if completed && !file.completed {
+            file.completed = true;
+            out_events.push_back(OutEvent::BundleFileCompleted {
+                local_bundle_id,
+                relative_path: file.relative_path.clone(),
+            });
+        }
+        out_events.push_back(OutEvent::BundleProgressed {
+            local_bundle_id,
+            bytes_done: bundle.bytes_done(),
+            bytes_total: bundle.bytes_total(),
+            files_done: bundle.files_done(),
+            files_total: bundle.files.len(),
+        });
+        if bundle.files_done() == bundle.files.len() {
+            out_events.push_back(OutEvent::BundleCompleted(local_bundle_id));
+            self.remove(local_bundle_id);
+        }
+    }
+    /// Drop a file that is cancelled or failed from its bundle.
+    /// The bundle is dropped once all files left are completed.
+    pub fn remove_file(&mut self, outbound: bool, local_id: u64) {
+        let index = if outbound {
+            &mut self.send_index
+        } else {
+            &mut self.recv_index
+        };
+        let local_bundle_id = match index.remove(&local_id) {
+            Some(id) => id,
+            None => return,
+        };
+        if let Some(bundle) = self.bundles.get_mut(&local_bundle_id) {
+            bundle.files.retain(|v| v.local_id != local_id);
+            if bundle.files.iter().all(|v| v.completed) {
+                self.remove(local_bundle_id);
+            }
+        }
+    }
+    fn remove(&mut self, local_bundle_id: u64) {
+        if let Some(bundle) = self.bundles.remove(&local_bundle_id) {
+            let index = if bundle.outbound {
+                &mut self.send_index
+            } else {
+                &mut self.recv_index
+            };
+            for file in bundle.files {
+                index.remove(&file.local_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! zstd compression of chunks, see [`config::Compression`].
+use std::io;
+
+/// A chunk ready to be sent. An empty chunk means EOF.
+#[derive(Debug)]
+pub(crate) struct Chunk {
+    pub data: Vec<u8>,
+    pub compressed: bool,
+    /// Length of the chunk before compression.
+    pub raw_len: usize,
+    /// BLAKE3 hash of everything sent, only set on the empty chunk at EOF.
+    pub digest: Vec<u8>,
+}
+impl Chunk {
+    pub fn raw(data: Vec<u8>) -> Self {
+        Self {
+            raw_len: data.len(),
+            data,
+            compressed: false,
+            digest: Vec::new(),
+        }
+    }
+}
+
+/// Compresses the chunks of a single transfer.
+/// The first chunk is used as a sample, compression is given up for the
+/// rest of the transfer if it doesn't save at least a tenth of its size.
+#[derive(Debug)]
+pub(crate) struct Compressor {
+    level: i32,
+    sampled: bool,
+    given_up: bool,
+}
+impl Compressor {
+    pub fn new(level: i32) -> Self {
+        Self {
+            level,
+            sampled: false,
+            given_up: false,
+        }
+    }
+    pub fn compress(&mut self, raw: Vec<u8>) -> Chunk {
+        if raw.is_empty() || self.given_up {
+            return Chunk::raw(raw);
+        }
+        let compressed = match zstd::bulk::compress(&raw, self.level) {
+            Ok(v) => v,
+            Err(_) => return Chunk::raw(raw),
+        };
+        let worthwhile = compressed.len() <= raw.len() - raw.len() / 10;
+        if !self.sampled {
+            self.sampled = true;
+            self.given_up = !worthwhile;
+        }
+        // Some chunks may not compress even if the sample does.
+        if !worthwhile {
+            return Chunk::raw(raw);
+        }
+        Chunk {
+            data: compressed,
+            compressed: true,
+            raw_len: raw.len(),
+            digest: Vec::new(),
+        }
+    }
+}
+
+/// Decompress a chunk that is no larger than `capacity` before compression.
+pub(crate) fn decompress(data: &[u8], capacity: usize) -> io::Result<Vec<u8>> {
+    zstd::bulk::decompress(data, capacity)
+}
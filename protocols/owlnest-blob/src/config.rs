@@ -11,6 +11,28 @@ pub struct Config {
     /// Timeout in seconds. 0 for no timeout(wait forever).
     pub pending_send_timeout_sec: u64,
     pub ongoing_send_timeout_sec: u64,
+    /// Chunks of a file read from disk ahead of sending.
+    pub read_ahead_chunks: usize,
+    /// Chunks received but not yet written to disk before local peer
+    /// stops reading from the remote.
+    pub write_behind_chunks: usize,
+    /// Path to the journal of unfinished transfers,
+    /// which allows transfers to be resumed after restart.
+    /// Journal is disabled if left blank.
+    pub journal_path: String,
+    /// Policy for accepting incoming files without user interaction.
+    pub auto_accept: AutoAccept,
+    /// Limits on outbound bandwidth and concurrent sends.
+    /// Can be changed at runtime using [`crate::InEvent::SetThrottle`].
+    pub throttle: Throttle,
+    /// Compression of chunks, used only if both peers enable it.
+    pub compression: Compression,
+    /// Directories that remote peers can browse and fetch files from.
+    /// Can be changed at runtime using [`crate::InEvent::SetShare`].
+    pub shares: Vec<Share>,
+    /// Directory of the content-addressed store, see [`crate::store`].
+    /// Store is disabled if left blank.
+    pub store_path: String,
 }
 impl Config {
     pub fn new() -> Self {
@@ -20,6 +42,38 @@ impl Config {
         self.timeout_ms = timeout_ms;
         self
     }
+    pub fn with_read_ahead_chunks(mut self, read_ahead_chunks: usize) -> Self {
+        self.read_ahead_chunks = read_ahead_chunks;
+        self
+    }
+    pub fn with_journal_path(mut self, journal_path: impl Into<String>) -> Self {
+        self.journal_path = journal_path.into();
+        self
+    }
+    pub fn with_write_behind_chunks(mut self, write_behind_chunks: usize) -> Self {
+        self.write_behind_chunks = write_behind_chunks;
+        self
+    }
+    pub fn with_auto_accept(mut self, auto_accept: AutoAccept) -> Self {
+        self.auto_accept = auto_accept;
+        self
+    }
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+    pub fn with_shares(mut self, shares: Vec<Share>) -> Self {
+        self.shares = shares;
+        self
+    }
+    pub fn with_store_path(mut self, store_path: impl Into<String>) -> Self {
+        self.store_path = store_path.into();
+        self
+    }
 }
 impl Default for Config {
     fn default() -> Self {
@@ -30,6 +84,98 @@ impl Default for Config {
             ongoing_recv_timeout_sec: 60,
             pending_send_timeout_sec: 0,
             ongoing_send_timeout_sec: 3 * 60,
+            read_ahead_chunks: 4,
+            write_behind_chunks: 4,
+            journal_path: String::new(),
+            auto_accept: AutoAccept::default(),
+            throttle: Throttle::default(),
+            compression: Compression::default(),
+            shares: Vec::new(),
+            store_path: String::new(),
         }
     }
 }
+
+/// Incoming files that match the policy are accepted into `download_dir`,
+/// a number is appended to the name if a file with the same name exists.  
+/// Bundles are not covered by the policy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AutoAccept {
+    pub enabled: bool,
+    /// Only files from these peers are accepted.
+    pub trusted_peers: Vec<PeerId>,
+    /// Maximum size of a single file in bytes. 0 for no limit.
+    pub max_file_size: u64,
+    /// Patterns that the file name must match one of, `*` and `?` are supported.
+    /// Empty to allow all names.
+    pub file_patterns: Vec<String>,
+    /// Directory to write accepted files to.
+    pub download_dir: String,
+    /// Maximum total size of files in `download_dir` in bytes. 0 for no limit.
+    pub disk_quota: u64,
+    /// Cancel offers that don't match the policy,
+    /// instead of leaving them pending for manual handling.
+    pub reject_unmatched: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Throttle {
+    /// Bytes per second of all sends combined. 0 for no limit.
+    pub max_send_rate: u64,
+    /// Bytes per second of a single send. 0 for no limit.
+    /// Chunks are 256KiB, rates that take longer than `ongoing_recv_timeout_sec`
+    /// of the receiver to send a chunk will get the transfer cancelled.
+    pub max_send_rate_per_transfer: u64,
+    /// Sends accepted by remote beyond this number wait in a queue.
+    /// Resumed sends are not queued. 0 for no limit.
+    pub max_concurrent_sends: usize,
+    pub send_queue: SendQueue,
+}
+
+/// Order in which queued sends are started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SendQueue {
+    /// In the order they are accepted by remote.
+    #[default]
+    Fifo,
+    /// Higher priority first, then in the order they are accepted by remote.
+    /// Sends with higher priority also take bandwidth first.
+    Priority,
+}
+
+/// zstd compression of chunks, negotiated per transfer.  
+/// Compression is given up for the rest of a transfer
+/// if its first chunk doesn't compress well.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compression {
+    /// Offer compression when sending, and agree to it when receiving.
+    pub enabled: bool,
+    /// zstd compression level, from 1 to 22.
+    pub level: i32,
+}
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            level: 3,
+        }
+    }
+}
+
+/// A directory shared read-only with remote peers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Share {
+    /// Name that remote peers see, the first component of paths they browse.
+    pub name: String,
+    /// Local directory to share.
+    pub path: String,
+    /// Allow all peers regardless of `allowed_peers`.
+    pub public: bool,
+    /// Peers that can browse and fetch from this share.
+    pub allowed_peers: Vec<PeerId>,
+}
+impl Share {
+    pub fn allows(&self, peer: &PeerId) -> bool {
+        self.public || self.allowed_peers.contains(peer)
+    }
+}
@@ -11,6 +11,8 @@ pub enum Error {
     IO(String),                  // Serialize not available on the original type
     Channel,
     UnexpectedEOF(u64),
+    /// A bundle from remote contains a path that may escape the destination.
+    UnsafePath(String),
 }
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -22,6 +24,7 @@ impl std::fmt::Display for Error {
             UnexpectedEOF(recv_id) => {
                 write!(f, "The file of recv ID {recv_id} meets an unexpected EOF")
             }
+            UnsafePath(path) => write!(f, "Bundle rejected because of unsafe path {path}"),
         }
     }
 }
@@ -39,6 +42,8 @@ pub enum FileSendError {
     #[from]
     OtherFsError(std::io::ErrorKind),
     PeerNotFound,
+    /// The files cannot be sent as a bundle.
+    InvalidBundle(String),
     #[from]
     Channel(ChannelError),
     #[from]
@@ -56,6 +61,7 @@ impl Display for FileSendError {
                 write!(f, "Other file system error: {error_kind}")
             }
             PeerNotFound => write!(f, "Target peer is not found"),
+            InvalidBundle(reason) => write!(f, "Invalid bundle: {reason}"),
             Channel(e) => e.fmt(f),
             Operation(e) => e.fmt(f),
         }
@@ -67,6 +73,13 @@ pub enum FileRecvError {
     #[from]
     PendingRecvNotFound(u64),
     Timeout,
+    /// The sender refused to continue the transfer.
+    ResumeRejected(String),
+    /// The received file doesn't match the hash from the sender.
+    /// The partial file has been deleted.
+    IntegrityMismatch {
+        path: String,
+    },
     FsError {
         path: String,
         error: std::io::ErrorKind,
@@ -83,6 +96,8 @@ impl Display for FileRecvError {
         match self{
             PendingRecvNotFound(id) => write!(f,"Cannot find operation associated with recv ID {id}, is the request already accepted or cancaled?"),
             Timeout => write!(f,"Timeout when waiting response from remote."),
+            ResumeRejected(reason) => write!(f,"Remote refused to resume the transfer: {reason}"),
+            IntegrityMismatch{path} => write!(f,"File {path} doesn't match the hash from sender and has been deleted."),
             FsError{path,error} => {
                 match error {
                     ErrorKind::AlreadyExists => write!(f,"File(or folder) {path} already exists. Overwritting is not allowed. Please delete the file before accepting the request."),
@@ -111,3 +126,95 @@ impl Display for CancellationError {
         }
     }
 }
+
+/// Errors of browsing or fetching from shares of remote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShareError {
+    PeerNotFound,
+    /// Remote refused the request, or the path cannot be served.
+    Rejected(String),
+    /// The local file to write to cannot be created.
+    FsError {
+        path: String,
+        error: std::io::ErrorKind,
+    },
+    /// The connection to remote is lost before it responded.
+    ConnectionLost,
+}
+impl std::error::Error for ShareError {}
+impl Display for ShareError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ShareError::*;
+        match self {
+            PeerNotFound => write!(f, "Target peer is not found"),
+            Rejected(reason) => write!(f, "Remote refused the request: {reason}"),
+            FsError { path, error } => write!(f, "Cannot write to {path}: {error}"),
+            ConnectionLost => write!(f, "Connection to remote lost before it responded"),
+        }
+    }
+}
+
+/// Errors of the content store, and of getting content from remote stores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreError {
+    /// `store_path` is not set.
+    Disabled,
+    PeerNotFound,
+    /// The chunk or content is not in the store.
+    NotFound,
+    /// Data from remote doesn't match its hash.
+    Corrupted,
+    /// The file has more chunks than a manifest can hold.
+    TooLarge,
+    /// No peer provides the content.
+    NoProvider,
+    ConnectionLost,
+    FsError {
+        path: String,
+        error: std::io::ErrorKind,
+    },
+    /// The content cannot be announced to the network.
+    Announce(String),
+}
+impl std::error::Error for StoreError {}
+impl Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use StoreError::*;
+        match self {
+            Disabled => write!(f, "Content store is disabled"),
+            PeerNotFound => write!(f, "Target peer is not found"),
+            NotFound => write!(f, "Content not found"),
+            Corrupted => write!(f, "Data from remote doesn't match its hash"),
+            TooLarge => write!(f, "File is too large for the content store"),
+            NoProvider => write!(f, "No peer provides the content"),
+            ConnectionLost => write!(f, "Connection to remote lost before it responded"),
+            FsError { path, error } => write!(f, "Cannot access {path}: {error}"),
+            Announce(error) => write!(f, "Content cannot be announced: {error}"),
+        }
+    }
+}
+
+/// Reasons for a transfer to end without completion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    /// Cancelled by either side.
+    Cancelled,
+    /// Failed with the error reported by the behaviour.
+    Failed(String),
+    /// The swarm stopped before the transfer ended.
+    SwarmStopped,
+    /// The transfer ended while its events were missed, so the outcome is unknown.
+    Untracked,
+}
+impl std::error::Error for TransferError {}
+impl Display for TransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use TransferError::*;
+        match self {
+            Cancelled => write!(f, "Transfer cancelled"),
+            Failed(error) => write!(f, "Transfer failed: {error}"),
+            SwarmStopped => write!(f, "Swarm stopped before the transfer ended"),
+            Untracked => write!(f, "Transfer ended while its events were missed"),
+        }
+    }
+}
@@ -0,0 +1,483 @@
+//! File IO that runs on the blocking thread pool,
+//! so that a slow disk won't stall the swarm event loop.
+//! Streams are driven on their own tasks for the same reason.
+use crate::compression::{Chunk, Compressor};
+use crate::{StreamSink, StreamSource};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+/// Where the bytes of a send come from.
+#[derive(Debug)]
+pub(crate) enum Source {
+    File(File),
+    Stream(StreamSource),
+}
+
+/// Where the bytes of a recv go to.
+#[derive(Debug)]
+pub(crate) enum Sink {
+    /// The partial file of `target`.
+    File {
+        file: File,
+        target: PathBuf,
+    },
+    Stream(StreamSink),
+}
+
+/// Reads a file in chunks ahead of time.
+/// At most `read_ahead` chunks are kept in memory.
+/// The file is hashed as it's read, the digest comes with the empty chunk at EOF.
+/// The read fails at EOF if the hash doesn't match the expected one.
+/// Chunks are compressed along the way if a compressor is given.
+#[derive(Debug)]
+pub(crate) struct FileReader {
+    chunks: mpsc::Receiver<io::Result<Chunk>>,
+}
+impl FileReader {
+    /// Skip verification if `content_hash` is empty.
+    pub fn spawn(
+        source: Source,
+        content_hash: Vec<u8>,
+        chunk_size: usize,
+        read_ahead: usize,
+        compressor: Option<Compressor>,
+    ) -> Self {
+        match source {
+            Source::File(file) => Self::spawn_with(
+                move || Ok(file),
+                0,
+                content_hash,
+                chunk_size,
+                read_ahead,
+                compressor,
+            ),
+            Source::Stream(stream) => {
+                Self::spawn_stream(stream, content_hash, chunk_size, read_ahead, compressor)
+            }
+        }
+    }
+    /// Read the stream on a separate task, chunks are only short at the end of the stream.
+    fn spawn_stream(
+        mut stream: StreamSource,
+        content_hash: Vec<u8>,
+        chunk_size: usize,
+        read_ahead: usize,
+        mut compressor: Option<Compressor>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(read_ahead.max(1));
+        tokio::spawn(async move {
+            let mut hasher = blake3::Hasher::new();
+            loop {
+                let mut buf = vec![0u8; chunk_size];
+                let mut filled = 0;
+                let result = loop {
+                    match stream.0.read(&mut buf[filled..]).await {
+                        Ok(0) => break Ok(()),
+                        Ok(bytes_read) => {
+                            filled += bytes_read;
+                            if filled == buf.len() {
+                                break Ok(());
+                            }
+                        }
+                        Err(e) => break Err(e),
+                    }
+                };
+                let result = result.and_then(|_| {
+                    buf.truncate(filled);
+                    hasher.update(&buf);
+                    if filled == 0 {
+                        verify(
+                            &hasher,
+                            &content_hash,
+                            "Stream doesn't match the given hash",
+                        )?;
+                    }
+                    Ok(compress(&mut compressor, buf, &hasher))
+                });
+                let stop = !matches!(&result, Ok(chunk) if chunk.raw_len > 0);
+                if tx.send(result).await.is_err() || stop {
+                    return;
+                }
+            }
+        });
+        Self { chunks: rx }
+    }
+    /// Open the file and start sending from `offset`.
+    /// Bytes before `offset` are still read for hashing.
+    /// Fails if the length of the file is no longer `bytes_total`,
+    /// or its hash no longer matches `content_hash`, before anything is sent.
+    pub fn open(
+        path: PathBuf,
+        offset: u64,
+        bytes_total: u64,
+        content_hash: Vec<u8>,
+        chunk_size: usize,
+        read_ahead: usize,
+        compressor: Option<Compressor>,
+    ) -> Self {
+        let expected = content_hash.clone();
+        let open = move || {
+            let mut file = File::open(path)?;
+            let changed = file.metadata()?.len() != bytes_total
+                || (!expected.is_empty() && hash_file(&mut file)?[..] != *expected);
+            if changed {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "File has changed since the transfer started",
+                ));
+            }
+            Ok(file)
+        };
+        Self::spawn_with(
+            open,
+            offset,
+            content_hash,
+            chunk_size,
+            read_ahead,
+            compressor,
+        )
+    }
+    fn spawn_with<F>(
+        open: F,
+        offset: u64,
+        content_hash: Vec<u8>,
+        chunk_size: usize,
+        read_ahead: usize,
+        compressor: Option<Compressor>,
+    ) -> Self
+    where
+        F: FnOnce() -> io::Result<File> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(read_ahead.max(1));
+        tokio::task::spawn_blocking(move || {
+            let mut hasher = blake3::Hasher::new();
+            let opened = open().and_then(|mut file| {
+                io::copy(&mut (&mut file).take(offset), &mut hasher)?;
+                Ok(file)
+            });
+            let mut file = match opened {
+                Ok(file) => file,
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(e));
+                    return;
+                }
+            };
+            Self::read_loop(&mut file, hasher, &content_hash, tx, chunk_size, compressor)
+        });
+        Self { chunks: rx }
+    }
+    fn read_loop(
+        file: &mut File,
+        mut hasher: blake3::Hasher,
+        content_hash: &[u8],
+        tx: mpsc::Sender<io::Result<Chunk>>,
+        chunk_size: usize,
+        mut compressor: Option<Compressor>,
+    ) {
+        loop {
+            let mut buf = vec![0u8; chunk_size];
+            let result = file.read(&mut buf).and_then(|bytes_read| {
+                buf.truncate(bytes_read);
+                hasher.update(&buf);
+                if bytes_read == 0 {
+                    verify(&hasher, content_hash, "File has changed while sending")?;
+                }
+                Ok(compress(&mut compressor, buf, &hasher))
+            });
+            let stop = !matches!(&result, Ok(chunk) if chunk.raw_len > 0);
+            // Receiver dropped, the send is cancelled or finished.
+            if tx.blocking_send(result).is_err() || stop {
+                return;
+            }
+        }
+    }
+    /// Poll for the next chunk. An empty chunk means EOF.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Chunk>> {
+        self.chunks.poll_recv(cx).map(|chunk| {
+            chunk.unwrap_or_else(|| {
+                Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "File reader exited unexpectedly",
+                ))
+            })
+        })
+    }
+}
+
+/// Failure of a [`FileWriter`].
+#[derive(Debug)]
+pub(crate) enum WriteError {
+    Io(io::Error),
+    /// The written bytes don't match the digest from sender or the hash in the offer,
+    /// or the sender gave no digest.
+    /// The partial file has been deleted.
+    DigestMismatch,
+}
+impl From<io::Error> for WriteError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// What the caller hands to the writing task.
+#[derive(Debug)]
+enum Pushed {
+    Chunk(Vec<u8>),
+    /// End of data, along with the digest from sender.
+    End(Vec<u8>),
+}
+
+/// Writes chunks to a file behind the caller.
+/// Chunks pushed beyond the window are buffered until the writer catches up,
+/// the caller should stop producing chunks once [`FileWriter::is_saturated`] returns true.
+#[derive(Debug)]
+pub(crate) struct FileWriter {
+    chunks: mpsc::Sender<Pushed>,
+    reports: mpsc::UnboundedReceiver<Result<usize, WriteError>>,
+    overflow: VecDeque<Pushed>,
+    /// Chunks pushed but not yet written.
+    queued: usize,
+    window: usize,
+}
+impl FileWriter {
+    /// Write to the sink. For a file sink, the file is the partial file of `target`.
+    /// Once flushed, the partial file will be renamed to `target` if its hash
+    /// matches the digest given to [`FileWriter::finish`] and `content_hash`,
+    /// or deleted otherwise. Stream sinks are shut down before verification,
+    /// so a mismatch is only reported.
+    /// `content_hash` is the hash in the offer, skip checking it if empty.
+    pub fn spawn(sink: Sink, content_hash: Vec<u8>, write_behind: usize) -> Self {
+        match sink {
+            Sink::File { file, target } => {
+                let open = move || Ok((file, blake3::Hasher::new()));
+                Self::spawn_with(open, target, content_hash, write_behind)
+            }
+            Sink::Stream(stream) => Self::spawn_stream(stream, content_hash, write_behind),
+        }
+    }
+    fn spawn_stream(mut stream: StreamSink, content_hash: Vec<u8>, write_behind: usize) -> Self {
+        let (writer, mut rx, report_tx) = Self::channels(write_behind);
+        tokio::spawn(async move {
+            let mut hasher = blake3::Hasher::new();
+            while let Some(pushed) = rx.recv().await {
+                let result = match pushed {
+                    Pushed::End(digest) => match stream.0.shutdown().await {
+                        Ok(_) if digest_matches(&hasher, &digest, &content_hash) => Ok(0),
+                        Ok(_) => Err(WriteError::DigestMismatch),
+                        Err(e) => Err(e.into()),
+                    },
+                    Pushed::Chunk(chunk) => {
+                        hasher.update(&chunk);
+                        stream.0.write_all(&chunk).await.map(|_| chunk.len())
+                    }
+                    .map_err(WriteError::from),
+                };
+                let stop = !matches!(result, Ok(len) if len > 0);
+                if report_tx.send(result).is_err() || stop {
+                    return;
+                }
+            }
+        });
+        writer
+    }
+    /// Continue writing to the partial file of `target`,
+    /// bytes beyond `offset` will be discarded.
+    pub fn resume(
+        target: PathBuf,
+        offset: u64,
+        content_hash: Vec<u8>,
+        write_behind: usize,
+    ) -> Self {
+        let part = part_path(&target);
+        let open = move || {
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(part)?;
+            let mut hasher = blake3::Hasher::new();
+            let hashed = io::copy(&mut (&mut file).take(offset), &mut hasher)?;
+            if hashed != offset {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Partial file is shorter than expected",
+                ));
+            }
+            file.set_len(offset)?;
+            file.seek(SeekFrom::End(0))?;
+            Ok((file, hasher))
+        };
+        Self::spawn_with(open, target, content_hash, write_behind)
+    }
+    fn spawn_with<F>(open: F, target: PathBuf, content_hash: Vec<u8>, write_behind: usize) -> Self
+    where
+        F: FnOnce() -> io::Result<(File, blake3::Hasher)> + Send + 'static,
+    {
+        let (writer, mut rx, report_tx) = Self::channels(write_behind);
+        tokio::task::spawn_blocking(move || {
+            let (mut file, mut hasher) = match open() {
+                Ok(opened) => opened,
+                Err(e) => {
+                    let _ = report_tx.send(Err(e.into()));
+                    return;
+                }
+            };
+            while let Some(pushed) = rx.blocking_recv() {
+                let result = match pushed {
+                    Pushed::End(digest) => file
+                        .flush()
+                        .map_err(WriteError::from)
+                        .and_then(|_| {
+                            Self::verify_and_rename(&hasher, &digest, &content_hash, &target)
+                        })
+                        .map(|_| 0),
+                    Pushed::Chunk(chunk) => {
+                        hasher.update(&chunk);
+                        file.write_all(&chunk)
+                            .map(|_| chunk.len())
+                            .map_err(WriteError::from)
+                    }
+                };
+                let stop = !matches!(result, Ok(len) if len > 0);
+                if report_tx.send(result).is_err() || stop {
+                    return;
+                }
+            }
+        });
+        writer
+    }
+    /// Create the writer, along with the ends to be driven by the writing task.
+    fn channels(
+        write_behind: usize,
+    ) -> (
+        Self,
+        mpsc::Receiver<Pushed>,
+        mpsc::UnboundedSender<Result<usize, WriteError>>,
+    ) {
+        let window = write_behind.max(1);
+        let (tx, rx) = mpsc::channel::<Pushed>(window);
+        let (report_tx, report_rx) = mpsc::unbounded_channel();
+        let writer = Self {
+            chunks: tx,
+            reports: report_rx,
+            overflow: VecDeque::new(),
+            queued: 0,
+            window,
+        };
+        (writer, rx, report_tx)
+    }
+    /// Move the partial file to its target, or delete it if it's corrupted.
+    fn verify_and_rename(
+        hasher: &blake3::Hasher,
+        digest: &[u8],
+        content_hash: &[u8],
+        target: &Path,
+    ) -> Result<(), WriteError> {
+        let part = part_path(target);
+        if !digest_matches(hasher, digest, content_hash) {
+            std::fs::remove_file(part)?;
+            return Err(WriteError::DigestMismatch);
+        }
+        Ok(std::fs::rename(part, target)?)
+    }
+    /// Queue a chunk for writing.
+    pub fn push(&mut self, chunk: Vec<u8>) {
+        self.enqueue(Pushed::Chunk(chunk));
+    }
+    /// Flush and close the file once all chunks are written,
+    /// then verify it against the digest from sender.
+    pub fn finish(&mut self, digest: Vec<u8>) {
+        self.enqueue(Pushed::End(digest));
+    }
+    fn enqueue(&mut self, pushed: Pushed) {
+        self.queued += 1;
+        if !self.overflow.is_empty() {
+            self.overflow.push_back(pushed);
+            return;
+        }
+        if let Err(mpsc::error::TrySendError::Full(pushed)) = self.chunks.try_send(pushed) {
+            self.overflow.push_back(pushed);
+        }
+    }
+    /// Whether there are more chunks waiting to be written than the window allows.
+    pub fn is_saturated(&self) -> bool {
+        self.queued >= self.window
+    }
+    /// Poll for the result of the next write.
+    /// `Ok(0)` means the file has been flushed, verified and renamed to its target,
+    /// or the stream has been shut down and verified.
+    pub fn poll_written(&mut self, cx: &mut Context<'_>) -> Poll<Result<usize, WriteError>> {
+        while let Some(pushed) = self.overflow.pop_front() {
+            if let Err(e) = self.chunks.try_send(pushed) {
+                match e {
+                    mpsc::error::TrySendError::Full(pushed) => {
+                        self.overflow.push_front(pushed);
+                        break;
+                    }
+                    // The writer has exited, the error will be reported below.
+                    mpsc::error::TrySendError::Closed(_) => break,
+                }
+            }
+        }
+        self.reports.poll_recv(cx).map(|report| {
+            self.queued = self.queued.saturating_sub(1);
+            report.unwrap_or_else(|| {
+                Err(WriteError::Io(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "File writer exited unexpectedly",
+                )))
+            })
+        })
+    }
+}
+
+/// Path of the partial file that is written to until the transfer completes.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// BLAKE3 hash of the whole file.
+/// The file is rewound so it can be read from start again.
+/// This reads the whole file, don't call it in async context.
+pub fn hash_file(file: &mut File) -> io::Result<[u8; 32]> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(&mut *file)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(*hasher.finalize().as_bytes())
+}
+
+/// A missing digest never matches, the hash in the offer is only checked if given.
+fn digest_matches(hasher: &blake3::Hasher, digest: &[u8], content_hash: &[u8]) -> bool {
+    hasher.finalize().as_bytes()[..] == *digest
+        && (content_hash.is_empty() || content_hash == digest)
+}
+
+/// Compare the hash with the expected one, an empty expected hash always passes.
+fn verify(hasher: &blake3::Hasher, expected: &[u8], message: &str) -> io::Result<()> {
+    if expected.is_empty() || hasher.finalize().as_bytes()[..] == *expected {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        message.to_owned(),
+    ))
+}
+
+/// The empty chunk at EOF carries the digest of everything read.
+fn compress(compressor: &mut Option<Compressor>, buf: Vec<u8>, hasher: &blake3::Hasher) -> Chunk {
+    let mut chunk = match compressor {
+        Some(compressor) => compressor.compress(buf),
+        None => Chunk::raw(buf),
+    };
+    if chunk.raw_len == 0 {
+        chunk.digest = hasher.finalize().as_bytes().to_vec();
+    }
+    chunk
+}
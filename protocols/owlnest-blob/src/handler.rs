@@ -4,7 +4,8 @@ use futures_timer::Delay;
 use owlnest_macro::handle_callback_sender;
 use owlnest_prelude::handler_prelude::*;
 use prost::{DecodeError, Message};
-use std::{collections::VecDeque, time::Duration};
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
 use tokio::sync::oneshot;
 use tracing::{debug, trace, trace_span, Span};
 
@@ -13,22 +14,100 @@ pub enum FromBehaviourEvent {
     NewFileSend {
         file_name: String,
         local_send_id: u64,
-        callback: oneshot::Sender<Result<u64, FileSendError>>,
+        callback: Option<oneshot::Sender<Result<u64, FileSendError>>>,
         bytes_total: u64,
+        /// Whether `bytes_total` is only an estimate of a stream.
+        size_is_hint: bool,
+        transfer_id: u64,
+        content_hash: Vec<u8>,
+        /// Whether to offer zstd compression of chunks.
+        compression: bool,
+        /// Request ID of the fetch that the file answers, 0 if not fetched.
+        fetch_id: u64,
     },
     /// A chunk of file
     FileChunk {
         local_send_id: u64,
         bytes_to_send: Vec<u8>,
+        compressed: bool,
+        /// BLAKE3 hash of everything sent, only on the final empty chunk.
+        digest: Vec<u8>,
+    },
+    /// Send the manifest of a bundle, the files are queued as pending sends.
+    NewBundleSend {
+        local_bundle_id: u64,
+        entries: Vec<messages::BundleEntry>,
+        callback: oneshot::Sender<Result<u64, FileSendError>>,
     },
     AcceptFile {
         remote_send_id: u64,
-        callback: oneshot::Sender<Result<Duration, FileRecvError>>,
+        callback: Option<oneshot::Sender<Result<Duration, FileRecvError>>>,
+        /// Whether to agree to the compression offered.
+        compression: bool,
     },
     /// Cancel command sent by file sender
-    LocalCancelSend { local_send_id: u64, span: Span },
+    LocalCancelSend {
+        local_send_id: u64,
+        span: Span,
+    },
     /// Cancel command sent by file receiver
-    LocalCancelRecv { remote_send_id: u64, span: Span },
+    LocalCancelRecv {
+        remote_send_id: u64,
+        span: Span,
+    },
+    /// Ask the sender to continue an interrupted transfer.
+    ResumeFile {
+        transfer_id: u64,
+        offset: u64,
+        compression: bool,
+    },
+    /// Tell the receiver the transfer continues with the given send ID.
+    AcceptResume {
+        transfer_id: u64,
+        local_send_id: u64,
+        offset: u64,
+    },
+    RejectResume {
+        transfer_id: u64,
+        reason: String,
+    },
+    /// Stop reading from remote because the file can't be written fast enough.
+    PauseInbound {
+        remote_send_id: u64,
+    },
+    /// The file has caught up, or the recv has ended.
+    ResumeInbound {
+        remote_send_id: u64,
+    },
+    /// List entries in a share of remote.
+    BrowseRequest {
+        request_id: u64,
+        path: String,
+    },
+    BrowseResponse {
+        request_id: u64,
+        entries: Vec<messages::CatalogEntry>,
+        error: String,
+    },
+    /// Ask remote to send a file in its shares.
+    FetchRequest {
+        request_id: u64,
+        path: String,
+    },
+    FetchRejected {
+        request_id: u64,
+        reason: String,
+    },
+    /// Ask remote for a chunk in its content store.
+    ChunkRequest {
+        request_id: u64,
+        hash: Vec<u8>,
+    },
+    ChunkResponse {
+        request_id: u64,
+        data: Vec<u8>,
+        found: bool,
+    },
 }
 
 #[derive(Debug)]
@@ -38,6 +117,9 @@ pub enum ToBehaviourEvent {
     RecvProgressed {
         remote_send_id: u64,
         content: Vec<u8>,
+        compressed: bool,
+        /// BLAKE3 hash of everything sent, only on the final empty chunk.
+        digest: Vec<u8>,
     },
     /// A chunk of file has been sent.
     SendProgressed {
@@ -49,11 +131,25 @@ pub enum ToBehaviourEvent {
         file_name: String,
         remote_send_id: u64,
         bytes_total: u64,
+        size_is_hint: bool,
+        transfer_id: u64,
+        content_hash: Vec<u8>,
+        /// Whether remote offers zstd compression of chunks.
+        compression: bool,
+        /// Request ID of the fetch that the file answers, 0 if not fetched.
+        fetch_id: u64,
+    },
+    /// Remote wants to send multiple files to local peer.
+    IncomingBundle {
+        remote_bundle_id: u64,
+        entries: Vec<messages::BundleEntry>,
     },
     /// Remote has accepted our file.
     /// Now local peer can start streaming the file.
     FileSendAccepted {
         local_send_id: u64,
+        /// Whether remote agrees to zstd compression of chunks.
+        compression: bool,
     },
     /// Remote has received our request to send a file.
     FileSendPending {
@@ -67,6 +163,52 @@ pub enum ToBehaviourEvent {
     RemoteCancelRecv {
         remote_send_id: u64,
     },
+    /// Remote wants to continue an interrupted transfer.
+    ResumeRequested {
+        transfer_id: u64,
+        offset: u64,
+        /// Whether remote supports zstd compression of chunks.
+        compression: bool,
+    },
+    /// Remote continues an interrupted transfer with the given send ID.
+    ResumeAccepted {
+        transfer_id: u64,
+        remote_send_id: u64,
+        offset: u64,
+    },
+    ResumeRejected {
+        transfer_id: u64,
+        reason: String,
+    },
+    /// Remote wants to list entries in local shares.
+    BrowseRequested {
+        request_id: u64,
+        path: String,
+    },
+    BrowseResponded {
+        request_id: u64,
+        entries: Vec<messages::CatalogEntry>,
+        error: String,
+    },
+    /// Remote wants a file in local shares.
+    FetchRequested {
+        request_id: u64,
+        path: String,
+    },
+    FetchRejected {
+        request_id: u64,
+        reason: String,
+    },
+    /// Remote wants a chunk in local content store.
+    ChunkRequested {
+        request_id: u64,
+        hash: Vec<u8>,
+    },
+    ChunkResponded {
+        request_id: u64,
+        data: Vec<u8>,
+        found: bool,
+    },
     Error(Error),
     InboundNegotiated,
     OutboundNegotiated,
@@ -78,18 +220,34 @@ impl From<messages::IncomingFile> for ToBehaviourEvent {
             remote_send_id,
             bytes_total,
             file_name,
+            transfer_id,
+            content_hash,
+            size_is_hint,
+            compression,
+            fetch_id,
         } = value;
         ToBehaviourEvent::IncomingFile {
             file_name,
             remote_send_id,
             bytes_total,
+            size_is_hint,
+            transfer_id,
+            content_hash,
+            compression: is_zstd(compression),
+            fetch_id,
         }
     }
 }
 impl From<messages::AcceptFile> for ToBehaviourEvent {
     fn from(value: messages::AcceptFile) -> Self {
-        let messages::AcceptFile { local_send_id } = value;
-        ToBehaviourEvent::FileSendAccepted { local_send_id }
+        let messages::AcceptFile {
+            local_send_id,
+            compression,
+        } = value;
+        ToBehaviourEvent::FileSendAccepted {
+            local_send_id,
+            compression: is_zstd(compression),
+        }
     }
 }
 impl From<messages::CancelSend> for ToBehaviourEvent {
@@ -109,13 +267,133 @@ impl From<messages::FileChunk> for ToBehaviourEvent {
         let messages::FileChunk {
             remote_send_id,
             content,
+            compressed,
+            digest,
         } = value;
         ToBehaviourEvent::RecvProgressed {
             remote_send_id,
             content,
+            compressed,
+            digest,
+        }
+    }
+}
+impl From<messages::ResumeFile> for ToBehaviourEvent {
+    fn from(value: messages::ResumeFile) -> Self {
+        let messages::ResumeFile {
+            transfer_id,
+            offset,
+            compression,
+        } = value;
+        ToBehaviourEvent::ResumeRequested {
+            transfer_id,
+            offset,
+            compression: is_zstd(compression),
+        }
+    }
+}
+impl From<messages::AcceptResume> for ToBehaviourEvent {
+    fn from(value: messages::AcceptResume) -> Self {
+        let messages::AcceptResume {
+            transfer_id,
+            remote_send_id,
+            offset,
+        } = value;
+        ToBehaviourEvent::ResumeAccepted {
+            transfer_id,
+            remote_send_id,
+            offset,
+        }
+    }
+}
+impl From<messages::RejectResume> for ToBehaviourEvent {
+    fn from(value: messages::RejectResume) -> Self {
+        let messages::RejectResume {
+            transfer_id,
+            reason,
+        } = value;
+        ToBehaviourEvent::ResumeRejected {
+            transfer_id,
+            reason,
+        }
+    }
+}
+impl From<messages::IncomingBundle> for ToBehaviourEvent {
+    fn from(value: messages::IncomingBundle) -> Self {
+        let messages::IncomingBundle {
+            remote_bundle_id,
+            entries,
+        } = value;
+        ToBehaviourEvent::IncomingBundle {
+            remote_bundle_id,
+            entries,
+        }
+    }
+}
+impl From<messages::BrowseRequest> for ToBehaviourEvent {
+    fn from(value: messages::BrowseRequest) -> Self {
+        let messages::BrowseRequest { request_id, path } = value;
+        ToBehaviourEvent::BrowseRequested { request_id, path }
+    }
+}
+impl From<messages::BrowseResponse> for ToBehaviourEvent {
+    fn from(value: messages::BrowseResponse) -> Self {
+        let messages::BrowseResponse {
+            request_id,
+            entries,
+            error,
+        } = value;
+        ToBehaviourEvent::BrowseResponded {
+            request_id,
+            entries,
+            error,
+        }
+    }
+}
+impl From<messages::FetchRequest> for ToBehaviourEvent {
+    fn from(value: messages::FetchRequest) -> Self {
+        let messages::FetchRequest { request_id, path } = value;
+        ToBehaviourEvent::FetchRequested { request_id, path }
+    }
+}
+impl From<messages::FetchRejected> for ToBehaviourEvent {
+    fn from(value: messages::FetchRejected) -> Self {
+        let messages::FetchRejected { request_id, reason } = value;
+        ToBehaviourEvent::FetchRejected { request_id, reason }
+    }
+}
+impl From<messages::ChunkRequest> for ToBehaviourEvent {
+    fn from(value: messages::ChunkRequest) -> Self {
+        let messages::ChunkRequest { request_id, hash } = value;
+        ToBehaviourEvent::ChunkRequested { request_id, hash }
+    }
+}
+impl From<messages::ChunkResponse> for ToBehaviourEvent {
+    fn from(value: messages::ChunkResponse) -> Self {
+        let messages::ChunkResponse {
+            request_id,
+            data,
+            found,
+        } = value;
+        ToBehaviourEvent::ChunkResponded {
+            request_id,
+            data,
+            found,
         }
     }
 }
+/// Whether the compression field of a message is zstd.
+pub(crate) fn is_zstd(compression: i32) -> bool {
+    compression == messages::Compression::Zstd as i32
+}
+/// The compression field of a message.
+pub(crate) fn compression(zstd: bool) -> i32 {
+    if zstd {
+        messages::Compression::Zstd as i32
+    } else {
+        messages::Compression::None as i32
+    }
+}
 pub mod messages {
     #[cfg(target_os = "windows")]
     include!(concat!(env!("OUT_DIR"), "\\messages.rs"));
@@ -134,6 +412,10 @@ pub struct Handler {
     pending_out_events: VecDeque<ToBehaviourEvent>,
     timeout: Duration,
     inbound: Option<PendingVerf>,
+    /// Inbound stream that is not being read from while paused.
+    idle_inbound: Option<Stream>,
+    /// Recvs that requested inbound to be paused, indexed by remote send ID.
+    paused_recv: HashSet<u64>,
     outbound: Option<OutboundState>,
 }
 
@@ -145,6 +427,8 @@ impl Handler {
             pending_out_events: VecDeque::new(),
             timeout: Duration::from_millis(config.timeout_ms),
             inbound: None,
+            idle_inbound: None,
+            paused_recv: HashSet::new(),
             outbound: None,
         }
     }
@@ -161,7 +445,15 @@ impl ConnectionHandler for Handler {
         SubstreamProtocol::new(ReadyUpgrade::new(PROTOCOL_NAME), ())
     }
     fn on_behaviour_event(&mut self, event: Self::FromBehaviour) {
-        self.pending_in_events.push_back(event)
+        match event {
+            FromBehaviourEvent::PauseInbound { remote_send_id } => {
+                self.paused_recv.insert(remote_send_id);
+            }
+            FromBehaviourEvent::ResumeInbound { remote_send_id } => {
+                self.paused_recv.remove(&remote_send_id);
+            }
+            ev => self.pending_in_events.push_back(ev),
+        }
     }
     fn connection_keep_alive(&self) -> bool {
         true
@@ -245,7 +537,10 @@ enum OutboundState {
 enum SendType {
     ControlSend(Option<oneshot::Sender<Result<u64, FileSendError>>>, u64),
     ControlRecv(Option<oneshot::Sender<Result<Duration, FileRecvError>>>),
+    ControlBundle(oneshot::Sender<Result<u64, FileSendError>>, u64),
     Cancel(Span),
+    /// Control messages that expect no acknowledgement.
+    Control,
     FileSend(u64),
 }
 
@@ -257,6 +552,12 @@ type PollResult = ConnectionHandlerEvent<
 
 impl Handler {
     fn poll_inbound(&mut self, cx: &mut std::task::Context<'_>) -> Option<PollResult> {
+        if self.paused_recv.is_empty() {
+            if let Some(stream) = self.idle_inbound.take() {
+                trace!("Inbound resumed");
+                self.inbound = Some(super::protocol::recv(stream).boxed());
+            }
+        }
         if let Some(fut) = self.inbound.as_mut() {
             trace!("Polling inbound");
             let poll_result = fut.poll_unpin(cx);
@@ -269,7 +570,13 @@ impl Handler {
                 ));
             }
             if let Poll::Ready(Ok((stream, bytes, message_type))) = poll_result {
-                self.inbound = Some(super::protocol::recv(stream).boxed());
+                if self.paused_recv.is_empty() {
+                    self.inbound = Some(super::protocol::recv(stream).boxed());
+                } else {
+                    trace!("Inbound paused");
+                    self.inbound = None;
+                    self.idle_inbound = Some(stream);
+                }
                 if let Err(e) = self.on_message(bytes.as_ref(), message_type) {
                     self.pending_out_events.push_back(ToBehaviourEvent::Error(
                         Error::UnrecognizedMessage(format!(
@@ -312,10 +619,17 @@ impl Handler {
                             SendType::ControlSend(callback, local_send_id) => {
                                 self.pending_out_events
                                     .push_back(ToBehaviourEvent::FileSendPending { local_send_id });
-                                handle_callback_sender!(Ok(local_send_id)=>callback.unwrap());
+                                if let Some(callback) = callback {
+                                    handle_callback_sender!(Ok(local_send_id)=>callback);
+                                }
                             }
                             SendType::ControlRecv(callback) => {
-                                handle_callback_sender!(Ok(rtt)=>callback.unwrap());
+                                if let Some(callback) = callback {
+                                    handle_callback_sender!(Ok(rtt)=>callback);
+                                }
+                            }
+                            SendType::ControlBundle(callback, local_bundle_id) => {
+                                handle_callback_sender!(Ok(local_bundle_id)=>callback);
                             }
                             SendType::FileSend(id) => {
                                 self.pending_out_events.push_back(
@@ -328,6 +642,7 @@ impl Handler {
                             SendType::Cancel(span) => {
                                 span.in_scope(||debug!("Cancellation message has been sent successfully. Lifecycle ended."))
                             }
+                            SendType::Control => trace!("Control message sent"),
                         }
                         self.outbound = Some(OutboundState::Idle(stream));
                     }
@@ -368,6 +683,16 @@ impl Handler {
             2 => CancelSend::decode(bytes)?.into(),
             3 => CancelRecv::decode(bytes)?.into(),
             4 => FileChunk::decode(bytes)?.into(),
+            5 => ResumeFile::decode(bytes)?.into(),
+            6 => AcceptResume::decode(bytes)?.into(),
+            7 => RejectResume::decode(bytes)?.into(),
+            8 => IncomingBundle::decode(bytes)?.into(),
+            9 => BrowseRequest::decode(bytes)?.into(),
+            10 => BrowseResponse::decode(bytes)?.into(),
+            11 => FetchRequest::decode(bytes)?.into(),
+            12 => FetchRejected::decode(bytes)?.into(),
+            13 => ChunkRequest::decode(bytes)?.into(),
+            14 => ChunkResponse::decode(bytes)?.into(),
             _ => ToBehaviourEvent::Error(Error::IO("Unexpected header value".into())),
         };
         self.pending_out_events.push_back(ev);
@@ -384,25 +709,37 @@ impl Handler {
                 local_send_id,
                 callback,
                 bytes_total,
+                size_is_hint,
+                transfer_id,
+                content_hash,
+                compression,
+                fetch_id,
             } => {
                 let message = messages::IncomingFile {
                     remote_send_id: local_send_id,
                     bytes_total,
                     file_name,
+                    transfer_id,
+                    content_hash,
+                    size_is_hint,
+                    compression: self::compression(compression),
+                    fetch_id,
                 };
-                send_type = SendType::ControlSend(Some(callback), local_send_id);
+                send_type = SendType::ControlSend(callback, local_send_id);
                 bytes = message.encode_to_vec();
                 message_type = 0;
             }
             AcceptFile {
                 remote_send_id,
                 callback,
+                compression,
             } => {
                 let message = messages::AcceptFile {
                     local_send_id: remote_send_id,
+                    compression: self::compression(compression),
                 };
                 bytes = message.encode_to_vec();
-                send_type = SendType::ControlRecv(Some(callback));
+                send_type = SendType::ControlRecv(callback);
                 message_type = 1;
             }
             LocalCancelSend {
@@ -430,15 +767,129 @@ impl Handler {
             FileChunk {
                 local_send_id,
                 bytes_to_send,
+                compressed,
+                digest,
             } => {
                 let message = messages::FileChunk {
                     remote_send_id: local_send_id,
                     content: bytes_to_send,
+                    compressed,
+                    digest,
                 };
                 bytes = message.encode_to_vec();
                 send_type = SendType::FileSend(local_send_id);
                 message_type = 4;
             }
+            NewBundleSend {
+                local_bundle_id,
+                entries,
+                callback,
+            } => {
+                let message = messages::IncomingBundle {
+                    remote_bundle_id: local_bundle_id,
+                    entries,
+                };
+                bytes = message.encode_to_vec();
+                send_type = SendType::ControlBundle(callback, local_bundle_id);
+                message_type = 8;
+            }
+            ResumeFile {
+                transfer_id,
+                offset,
+                compression,
+            } => {
+                let message = messages::ResumeFile {
+                    transfer_id,
+                    offset,
+                    compression: self::compression(compression),
+                };
+                bytes = message.encode_to_vec();
+                send_type = SendType::Control;
+                message_type = 5;
+            }
+            AcceptResume {
+                transfer_id,
+                local_send_id,
+                offset,
+            } => {
+                let message = messages::AcceptResume {
+                    transfer_id,
+                    remote_send_id: local_send_id,
+                    offset,
+                };
+                bytes = message.encode_to_vec();
+                send_type = SendType::Control;
+                message_type = 6;
+            }
+            RejectResume {
+                transfer_id,
+                reason,
+            } => {
+                let message = messages::RejectResume {
+                    transfer_id,
+                    reason,
+                };
+                bytes = message.encode_to_vec();
+                send_type = SendType::Control;
+                message_type = 7;
+            }
+            BrowseRequest { request_id, path } => {
+                let message = messages::BrowseRequest { request_id, path };
+                bytes = message.encode_to_vec();
+                send_type = SendType::Control;
+                message_type = 9;
+            }
+            BrowseResponse {
+                request_id,
+                entries,
+                error,
+            } => {
+                let message = messages::BrowseResponse {
+                    request_id,
+                    entries,
+                    error,
+                };
+                bytes = message.encode_to_vec();
+                send_type = SendType::Control;
+                message_type = 10;
+            }
+            FetchRequest { request_id, path } => {
+                let message = messages::FetchRequest { request_id, path };
+                bytes = message.encode_to_vec();
+                send_type = SendType::Control;
+                message_type = 11;
+            }
+            FetchRejected { request_id, reason } => {
+                let message = messages::FetchRejected { request_id, reason };
+                bytes = message.encode_to_vec();
+                send_type = SendType::Control;
+                message_type = 12;
+            }
+            ChunkRequest { request_id, hash } => {
+                let message = messages::ChunkRequest { request_id, hash };
+                bytes = message.encode_to_vec();
+                send_type = SendType::Control;
+                message_type = 13;
+            }
+            ChunkResponse {
+                request_id,
+                data,
+                found,
+            } => {
+                let message = messages::ChunkResponse {
+                    request_id,
+                    data,
+                    found,
+                };
+                bytes = message.encode_to_vec();
+                send_type = SendType::Control;
+                message_type = 14;
+            }
+            PauseInbound { .. } | ResumeInbound { .. } => {
+                // Handled upon arrival, never queued.
+                self.outbound = Some(OutboundState::Idle(stream));
+                return;
+            }
         }
         self.outbound = Some(OutboundState::Busy(
             protocol::send(stream, bytes, message_type).boxed(),
@@ -0,0 +1,90 @@
+//! Record of unfinished transfers, so that they can be resumed after restart.
+use super::*;
+use owlnest_core::snapshot::{self, SnapshotWriter};
+use std::collections::HashMap;
+
+/// A recv that has been accepted but not yet completed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct JournalRecv {
+    pub transfer_id: u64,
+    pub remote: PeerId,
+    /// Path of the complete file, the partial file is derived from it.
+    pub file_path: PathBuf,
+    pub bytes_total: u64,
+    pub content_hash: Vec<u8>,
+}
+
+/// A send that has been accepted by remote but not yet completed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct JournalSend {
+    pub transfer_id: u64,
+    pub remote: PeerId,
+    pub file_path: PathBuf,
+    pub bytes_total: u64,
+    pub content_hash: Vec<u8>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Entries {
+    recv: Vec<JournalRecv>,
+    send: Vec<JournalSend>,
+}
+
+/// Unfinished transfers indexed by transfer ID.
+/// Every change is written to disk on a dedicated thread.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    recv: HashMap<u64, JournalRecv>,
+    send: HashMap<u64, JournalSend>,
+    writer: SnapshotWriter<Entries>,
+}
+impl Journal {
+    /// Load the journal at the given path, an empty journal is used
+    /// if the file doesn't exist or cannot be parsed.
+    pub fn load(path: PathBuf) -> Self {
+        let entries: Entries = snapshot::load(&path, "blob journal").unwrap_or_default();
+        Self {
+            recv: entries
+                .recv
+                .into_iter()
+                .map(|v| (v.transfer_id, v))
+                .collect(),
+            send: entries
+                .send
+                .into_iter()
+                .map(|v| (v.transfer_id, v))
+                .collect(),
+            writer: SnapshotWriter::spawn(path, "blob journal"),
+        }
+    }
+    pub fn recv(&self) -> impl Iterator<Item = &JournalRecv> {
+        self.recv.values()
+    }
+    pub fn send(&self) -> impl Iterator<Item = &JournalSend> {
+        self.send.values()
+    }
+    pub fn insert_recv(&mut self, entry: JournalRecv) {
+        self.recv.insert(entry.transfer_id, entry);
+        self.save();
+    }
+    pub fn insert_send(&mut self, entry: JournalSend) {
+        self.send.insert(entry.transfer_id, entry);
+        self.save();
+    }
+    pub fn remove_recv(&mut self, transfer_id: u64) {
+        if self.recv.remove(&transfer_id).is_some() {
+            self.save();
+        }
+    }
+    pub fn remove_send(&mut self, transfer_id: u64) {
+        if self.send.remove(&transfer_id).is_some() {
+            self.save();
+        }
+    }
+    fn save(&self) {
+        self.writer.save(Entries {
+            recv: self.recv.values().cloned().collect(),
+            send: self.send.values().cloned().collect(),
+        });
+    }
+}
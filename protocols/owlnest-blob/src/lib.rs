@@ -1,4 +1,4 @@
-use error::{CancellationError, FileSendError};
+use error::{CancellationError, FileSendError, ShareError, StoreError};
 use owlnest_core::alias::Callback;
 use owlnest_prelude::lib_prelude::*;
 use serde::{Deserialize, Serialize};
@@ -6,17 +6,55 @@ use std::{fs::File, path::PathBuf, time::Duration};
 use tokio::sync::oneshot;
 use tracing::{error, trace};
 
+mod auto_accept;
 mod behaviour;
+pub mod bundle;
+mod compression;
 pub mod config;
 pub mod error;
+mod file_io;
 mod handler;
+mod journal;
 mod op;
 mod protocol;
+mod share;
+pub mod store;
+mod throttle;
 
+pub use auto_accept::AutoAcceptDecision;
 pub use behaviour::Behaviour;
 pub use behaviour::{RecvInfo, SendInfo};
 pub use config::Config;
+pub use file_io::{hash_file, part_path};
 pub use protocol::PROTOCOL_NAME;
+pub use share::CatalogEntry;
+pub use store::{Cid, ContentStore};
+
+/// Bytes to be sent, see [`InEvent::SendStream`].
+pub struct StreamSource(pub Box<dyn tokio::io::AsyncRead + Send + Unpin>);
+impl StreamSource {
+    pub fn new(stream: impl tokio::io::AsyncRead + Send + Unpin + 'static) -> Self {
+        Self(Box::new(stream))
+    }
+}
+impl std::fmt::Debug for StreamSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StreamSource")
+    }
+}
+
+/// Destination of received bytes, see [`InEvent::AcceptStream`].
+pub struct StreamSink(pub Box<dyn tokio::io::AsyncWrite + Send + Unpin>);
+impl StreamSink {
+    pub fn new(stream: impl tokio::io::AsyncWrite + Send + Unpin + 'static) -> Self {
+        Self(Box::new(stream))
+    }
+}
+impl std::fmt::Debug for StreamSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StreamSink")
+    }
+}
 
 /// Events that this behaviour accepts.
 #[derive(Debug)]
@@ -29,19 +67,68 @@ pub enum InEvent {
         /// Full path to the file, including file name.
         file_path: PathBuf,
         to: PeerId,
+        /// BLAKE3 hash of the whole file, see [`hash_file`].
+        content_hash: Vec<u8>,
+        callback: Callback<Result<u64, FileSendError>>,
+    },
+    /// Initiate a send of data that is not backed by a file.
+    /// Streamed sends cannot be resumed after disconnection.
+    SendStream {
+        stream: StreamSource,
+        /// Name that the receiver sees as the file name.
+        name: String,
+        /// Expected amount of bytes, the stream may end at a different length.
+        size_hint: u64,
+        to: PeerId,
+        /// BLAKE3 hash of the whole stream if known in advance, empty otherwise.
+        content_hash: Vec<u8>,
         callback: Callback<Result<u64, FileSendError>>,
     },
     /// Local acceptes a pending recv.
     AcceptFile {
-        /// An empty file to write to.
+        /// An empty partial file to write to, see [`part_path`].
         file: File,
         /// A monotonic ID of this request.
         /// The ID is unique during the lifetime of the app,
         /// but the order is not guaranteed.
         recv_id: u64,
+        /// Path of the complete file.
+        /// The partial file will be renamed to it once all bytes are written.
         path: PathBuf,
         callback: Callback<Result<Duration, error::FileRecvError>>,
     },
+    /// Local accepts a pending recv, writing bytes to the stream instead of a file.
+    /// The stream is shut down once all bytes are written.
+    /// Streamed recvs cannot be resumed after disconnection.
+    AcceptStream {
+        stream: StreamSink,
+        recv_id: u64,
+        callback: Callback<Result<Duration, error::FileRecvError>>,
+    },
+    /// Ask the sender to continue an interrupted recv.
+    /// Resolves to the offset the transfer continues from once the sender accepted.
+    ResumeRecv {
+        local_recv_id: u64,
+        callback: Callback<Result<u64, error::FileRecvError>>,
+    },
+    /// Send multiple files at once, the receiver will accept them as a whole.
+    /// Resolves to the bundle ID once the manifest has been sent.
+    SendBundle {
+        files: Vec<bundle::BundleFile>,
+        to: PeerId,
+        callback: Callback<Result<u64, FileSendError>>,
+    },
+    /// Local accepts all files of a pending bundle.
+    AcceptBundle {
+        local_bundle_id: u64,
+        /// Recv ID, empty partial file and path of the complete file for every file in the bundle.
+        files: Vec<(u64, File, PathBuf)>,
+        callback: Callback<Result<(), error::FileRecvError>>,
+    },
+    /// List all bundles that are pending or ongoing.
+    ListBundle {
+        callback: Callback<Box<[bundle::BundleInfo]>>,
+    },
     /// List all peers that are connected and support this protocol.
     ListConnected { callback: Callback<Box<[PeerId]>> },
     /// List all recv activities, including pending and ongoing.
@@ -60,6 +147,61 @@ pub enum InEvent {
         local_send_id: u64,
         callback: Callback<Result<(), CancellationError>>,
     },
+    /// Replace the bandwidth limits and the send queue policy.
+    /// Ongoing sends are affected immediately.
+    SetThrottle {
+        throttle: config::Throttle,
+        callback: Callback<()>,
+    },
+    /// Get the bandwidth limits and the send queue policy in effect.
+    GetThrottle {
+        callback: Callback<config::Throttle>,
+    },
+    /// Change the priority of a send, higher goes first.
+    /// Responds `false` if the send is not found.
+    SetPriority {
+        local_send_id: u64,
+        priority: u8,
+        callback: Callback<bool>,
+    },
+    /// List entries of a directory shared by remote.
+    /// An empty path lists the shares that local peer can access.
+    Browse {
+        peer: PeerId,
+        path: String,
+        callback: Callback<Result<Box<[CatalogEntry]>, ShareError>>,
+    },
+    /// Ask remote to send a file it shares.
+    /// The file is accepted into `target` as soon as it's offered,
+    /// `file` should be the partial file of `target`.
+    /// Responds with the local recv ID and the size of the file.
+    Fetch {
+        peer: PeerId,
+        path: String,
+        file: File,
+        target: PathBuf,
+        callback: Callback<Result<(u64, u64), ShareError>>,
+    },
+    /// Share a directory, replacing the share with the same name.
+    SetShare {
+        share: config::Share,
+        callback: Callback<()>,
+    },
+    /// Stop sharing a directory, responds `false` if not shared.
+    RemoveShare {
+        name: String,
+        callback: Callback<bool>,
+    },
+    ListShares {
+        callback: Callback<Box<[config::Share]>>,
+    },
+    /// Request a chunk or a manifest from the content store of remote.
+    /// Data is returned as is, it should be verified against the hash.
+    RequestChunk {
+        peer: PeerId,
+        hash: Vec<u8>,
+        callback: Callback<Result<Vec<u8>, StoreError>>,
+    },
 }
 
 #[derive(Debug)]
@@ -74,7 +216,41 @@ pub enum OutEvent {
         /// but the order is not guaranteed.
         local_recv_id: u64,
         bytes_total: u64,
+        /// Whether `bytes_total` is only an estimate of streamed data.
+        size_is_hint: bool,
+        /// ID of the transfer that stays the same across reconnections.
+        transfer_id: u64,
+    },
+    /// The auto-accept policy made a decision on an incoming file.
+    AutoAccept {
+        from: PeerId,
+        local_recv_id: u64,
+        file_name: String,
+        decision: AutoAcceptDecision,
+    },
+    /// A remote informed local of a pending bundle of files.
+    /// Files in the bundle can be found using [`InEvent::ListBundle`].
+    IncomingBundle {
+        from: PeerId,
+        local_bundle_id: u64,
+        files_total: usize,
+        bytes_total: u64,
     },
+    /// Aggregate progress of all files in a bundle.
+    BundleProgressed {
+        local_bundle_id: u64,
+        bytes_done: u64,
+        bytes_total: u64,
+        files_done: usize,
+        files_total: usize,
+    },
+    /// A file in the bundle has been completely sent or received.
+    BundleFileCompleted {
+        local_bundle_id: u64,
+        relative_path: String,
+    },
+    /// All files in the bundle have been completely sent or received.
+    BundleCompleted(u64),
     /// Remote has sent us a chunk of file and has been written.
     RecvProgressed {
         local_recv_id: u64,
@@ -96,6 +272,69 @@ pub enum OutEvent {
         local_send_id: u64,
         error: String,
     },
+    /// A file in local shares is being sent to a remote that fetched it.
+    FetchServed {
+        to: PeerId,
+        local_send_id: u64,
+        file_path: PathBuf,
+    },
+    /// The send is accepted by remote but waits for a free slot.
+    SendQueued {
+        local_send_id: u64,
+        /// Number of sends ahead in the queue.
+        position: usize,
+    },
+    /// All bytes of the file have been sent to remote.
+    SendCompleted {
+        local_send_id: u64,
+        file_path: PathBuf,
+        bytes_total: u64,
+        /// Bytes sent after compression since the send started or resumed,
+        /// equal to `bytes_total` if not compressed.
+        bytes_on_wire: u64,
+        /// Time since the receiver accepted the file, including interruptions.
+        elapsed: Duration,
+        /// Average bytes per second over `elapsed`.
+        throughput: f64,
+    },
+    /// All bytes of the file have been written and verified.
+    RecvCompleted {
+        local_recv_id: u64,
+        /// Path of the complete file.
+        file_path: PathBuf,
+        bytes_total: u64,
+        /// Bytes received before decompression since the recv started or resumed,
+        /// equal to `bytes_total` if not compressed.
+        bytes_on_wire: u64,
+        /// Time since the file was accepted, including interruptions.
+        elapsed: Duration,
+        /// Average bytes per second over `elapsed`.
+        throughput: f64,
+    },
+    /// The connection to the receiver is lost before the send is completed.
+    /// The send can be resumed by the receiver.
+    SendInterrupted {
+        local_send_id: u64,
+        bytes_sent: u64,
+        bytes_total: u64,
+    },
+    /// The connection to the sender is lost before the recv is completed.
+    /// The recv can be resumed using [`InEvent::ResumeRecv`].
+    RecvInterrupted {
+        local_recv_id: u64,
+        bytes_received: u64,
+        bytes_total: u64,
+    },
+    /// An interrupted send continues from the given offset.
+    SendResumed {
+        local_send_id: u64,
+        offset: u64,
+    },
+    /// An interrupted recv continues from the given offset.
+    RecvResumed {
+        local_recv_id: u64,
+        offset: u64,
+    },
     /// The send operation associsted with the ID is cancelled.
     CancelledSend(u64),
     /// The recv operation associated with the ID is cancelled.
@@ -3,7 +3,7 @@ use std::io;
 use std::time::{Duration, Instant};
 use tracing::{trace, trace_span};
 use xxhash_rust::xxh3::xxh3_128;
-pub const PROTOCOL_NAME: &str = "/owlnest/blob/0.0.1";
+pub const PROTOCOL_NAME: &str = "/owlnest/blob/0.0.2";
 #[allow(unused)]
 const MAX_PACKET_SIZE: usize = 1 << 18;
 
@@ -0,0 +1,337 @@
+//! Directories shared read-only with remote peers, see [`config::Share`].
+//! Requests are served on the blocking thread pool because listing
+//! a directory hashes every file in it.
+use super::*;
+use crate::bundle::sanitize_relative_path;
+use crate::config::Share;
+use crate::handler::messages;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+
+/// Entries beyond this number are left out of a listing,
+/// so that the response fits in a single message.
+pub const MAX_CATALOG_ENTRIES: usize = 1024;
+/// Browse and fetch requests of a single peer that can be served at the same time,
+/// requests beyond this number are rejected.
+pub const MAX_REQUESTS_IN_FLIGHT: usize = 4;
+
+/// An entry in a shared directory, see [`InEvent::Browse`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// 0 for directories.
+    pub size: u64,
+    /// BLAKE3 hash of the file, empty for directories.
+    pub content_hash: Vec<u8>,
+}
+impl From<messages::CatalogEntry> for CatalogEntry {
+    fn from(value: messages::CatalogEntry) -> Self {
+        let messages::CatalogEntry {
+            name,
+            is_dir,
+            size,
+            content_hash,
+        } = value;
+        Self {
+            name,
+            is_dir,
+            size,
+            content_hash,
+        }
+    }
+}
+impl From<CatalogEntry> for messages::CatalogEntry {
+    fn from(value: CatalogEntry) -> Self {
+        let CatalogEntry {
+            name,
+            is_dir,
+            size,
+            content_hash,
+        } = value;
+        Self {
+            name,
+            is_dir,
+            size,
+            content_hash,
+        }
+    }
+}
+
+/// A file opened for a fetch.
+#[derive(Debug)]
+pub(crate) struct SharedFile {
+    pub file: File,
+    pub path: PathBuf,
+    pub bytes_total: u64,
+    pub content_hash: Vec<u8>,
+}
+
+/// A request from remote that has been served.
+#[derive(Debug)]
+pub(crate) enum Served {
+    Browse {
+        peer: PeerId,
+        request_id: u64,
+        result: Result<Vec<CatalogEntry>, String>,
+    },
+    Fetch {
+        peer: PeerId,
+        request_id: u64,
+        result: Result<SharedFile, String>,
+    },
+}
+
+/// Hash of a file, along with the modification time and size it's computed at.
+type HashCache = Arc<Mutex<HashMap<PathBuf, (SystemTime, u64, Vec<u8>)>>>;
+
+/// Number of requests being served, indexed by remote peer.
+type InFlight = Arc<Mutex<HashMap<PeerId, usize>>>;
+
+#[derive(Debug)]
+pub(crate) struct ShareServer {
+    hashes: HashCache,
+    in_flight: InFlight,
+    served_tx: mpsc::UnboundedSender<Served>,
+    served_rx: mpsc::UnboundedReceiver<Served>,
+}
+impl Default for ShareServer {
+    fn default() -> Self {
+        let (served_tx, served_rx) = mpsc::unbounded_channel();
+        Self {
+            hashes: Default::default(),
+            in_flight: Default::default(),
+            served_tx,
+            served_rx,
+        }
+    }
+}
+impl ShareServer {
+    /// List entries under the path, the result will be available from
+    /// [`ShareServer::poll_served`].
+    pub fn browse(&self, shares: Vec<Share>, peer: PeerId, request_id: u64, path: String) {
+        let Some(slot) = self.acquire(peer) else {
+            let _ = self.served_tx.send(Served::Browse {
+                peer,
+                request_id,
+                result: Err(BUSY.into()),
+            });
+            return;
+        };
+        let hashes = self.hashes.clone();
+        let tx = self.served_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let _slot = slot;
+            let result = list(&shares, &peer, &path, &hashes);
+            let _ = tx.send(Served::Browse {
+                peer,
+                request_id,
+                result,
+            });
+        });
+    }
+    /// Open the file at the path, the result will be available from
+    /// [`ShareServer::poll_served`].
+    pub fn fetch(&self, shares: Vec<Share>, peer: PeerId, request_id: u64, path: String) {
+        let Some(slot) = self.acquire(peer) else {
+            let _ = self.served_tx.send(Served::Fetch {
+                peer,
+                request_id,
+                result: Err(BUSY.into()),
+            });
+            return;
+        };
+        let hashes = self.hashes.clone();
+        let tx = self.served_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let _slot = slot;
+            let result = open(&shares, &peer, &path, &hashes);
+            let _ = tx.send(Served::Fetch {
+                peer,
+                request_id,
+                result,
+            });
+        });
+    }
+    /// Take a slot of the peer, `None` if all of its slots are taken.
+    fn acquire(&self, peer: PeerId) -> Option<Slot> {
+        let mut in_flight = self.in_flight.lock().expect("Not poisoned");
+        let count = in_flight.entry(peer).or_default();
+        if *count >= MAX_REQUESTS_IN_FLIGHT {
+            return None;
+        }
+        *count += 1;
+        Some(Slot {
+            in_flight: self.in_flight.clone(),
+            peer,
+        })
+    }
+    pub fn poll_served(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<Served> {
+        self.served_rx
+            .poll_recv(cx)
+            .map(|v| v.expect("Sender to be kept alive"))
+    }
+}
+
+/// Find the local path of the shared path, and the root of its share.
+/// Shares that the peer is not allowed to access are treated as non-existent.
+fn resolve(shares: &[Share], peer: &PeerId, path: &str) -> Result<(PathBuf, PathBuf), String> {
+    let path = path.trim_matches('/');
+    let (name, relative) = path.split_once('/').unwrap_or((path, ""));
+    let not_found = || format!("{path:?} not found");
+    let share = shares
+        .iter()
+        .find(|v| v.name == name && v.allows(peer))
+        .ok_or_else(not_found)?;
+    let root = Path::new(&share.path)
+        .canonicalize()
+        .map_err(|e| format!("Share {name:?} is unavailable: {e}"))?;
+    if relative.is_empty() {
+        return Ok((root.clone(), root));
+    }
+    let relative =
+        sanitize_relative_path(relative).ok_or_else(|| format!("Invalid path {path:?}"))?;
+    let resolved = root
+        .join(relative)
+        .canonicalize()
+        .map_err(|_| not_found())?;
+    // Symlinks may point outside of the share.
+    if !resolved.starts_with(&root) {
+        return Err(not_found());
+    }
+    Ok((resolved, root))
+}
+
+fn list(
+    shares: &[Share],
+    peer: &PeerId,
+    path: &str,
+    hashes: &HashCache,
+) -> Result<Vec<CatalogEntry>, String> {
+    if path.trim_matches('/').is_empty() {
+        return Ok(shares
+            .iter()
+            .filter(|v| v.allows(peer))
+            .take(MAX_CATALOG_ENTRIES)
+            .map(|v| CatalogEntry {
+                name: v.name.clone(),
+                is_dir: true,
+                size: 0,
+                content_hash: Vec::new(),
+            })
+            .collect());
+    }
+    let (resolved, root) = resolve(shares, peer, path)?;
+    let read_error = |e: io::Error| format!("Cannot read {path:?}: {e}");
+    if resolved.is_file() {
+        let name = resolved
+            .file_name()
+            .map(|v| v.to_string_lossy().to_string())
+            .unwrap_or_default();
+        return Ok(vec![
+            catalog_entry(name, &resolved, hashes).map_err(read_error)?
+        ]);
+    }
+    let mut children = Vec::new();
+    for entry in std::fs::read_dir(&resolved).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        children.push((
+            entry.file_name().to_string_lossy().to_string(),
+            entry.path(),
+        ));
+    }
+    children.sort();
+    let entries = children
+        .into_iter()
+        .filter_map(|(name, path)| match path.canonicalize() {
+            Ok(v) if v.starts_with(&root) => Some((name, v)),
+            // Broken links, or links to outside of the share.
+            _ => None,
+        })
+        // Entries that can't be read are not listed.
+        .filter_map(|(name, path)| catalog_entry(name, &path, hashes).ok())
+        .take(MAX_CATALOG_ENTRIES)
+        .collect();
+    Ok(entries)
+}
+
+fn catalog_entry(name: String, path: &Path, hashes: &HashCache) -> io::Result<CatalogEntry> {
+    let metadata = std::fs::metadata(path)?;
+    if metadata.is_dir() {
+        return Ok(CatalogEntry {
+            name,
+            is_dir: true,
+            size: 0,
+            content_hash: Vec::new(),
+        });
+    }
+    let content_hash = cached_hash(path, &mut File::open(path)?, hashes)?;
+    Ok(CatalogEntry {
+        name,
+        is_dir: false,
+        size: metadata.len(),
+        content_hash,
+    })
+}
+
+fn open(
+    shares: &[Share],
+    peer: &PeerId,
+    path: &str,
+    hashes: &HashCache,
+) -> Result<SharedFile, String> {
+    let (resolved, _) = resolve(shares, peer, path)?;
+    if !resolved.is_file() {
+        return Err(format!("{path:?} is not a file"));
+    }
+    let read_error = |e: io::Error| format!("Cannot read {path:?}: {e}");
+    let mut file = File::open(&resolved).map_err(read_error)?;
+    let bytes_total = file.metadata().map_err(read_error)?.len();
+    let content_hash = cached_hash(&resolved, &mut file, hashes).map_err(read_error)?;
+    Ok(SharedFile {
+        file,
+        path: resolved,
+        bytes_total,
+        content_hash,
+    })
+}
+
+const BUSY: &str = "Too many requests in flight, try again later";
+
+/// A request being served, the slot is released when dropped.
+struct Slot {
+    in_flight: InFlight,
+    peer: PeerId,
+}
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().expect("Not poisoned");
+        if let Some(count) = in_flight.get_mut(&self.peer) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.peer);
+            }
+        }
+    }
+}
+
+/// Hash the file unless it's unchanged since last hashed.
+fn cached_hash(path: &Path, file: &mut File, hashes: &HashCache) -> io::Result<Vec<u8>> {
+    let metadata = file.metadata()?;
+    let stamp = (metadata.modified()?, metadata.len());
+    if let Some((modified, len, hash)) = hashes.lock().expect("Not poisoned").get(path) {
+        if (*modified, *len) == stamp {
+            return Ok(hash.clone());
+        }
+    }
+    let hash = hash_file(file)?.to_vec();
+    hashes
+        .lock()
+        .expect("Not poisoned")
+        .insert(path.to_owned(), (stamp.0, stamp.1, hash.clone()));
+    Ok(hash)
+}
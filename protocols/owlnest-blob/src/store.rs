@@ -0,0 +1,345 @@
+//! Content-addressed store, see [`config::Config::store_path`].
+//! Files are split into chunks named by their BLAKE3 hash, listed in order
+//! by a manifest. The content ID is the hash of the encoded manifest,
+//! so manifests are requested and verified the same way as chunks.
+use super::*;
+use crate::error::StoreError;
+use crate::handler::messages;
+use owlnest_core::snapshot::write_atomic;
+use prost::Message;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use tokio::sync::mpsc;
+
+/// Chunks are smaller than those of transfers, so that a chunk
+/// and its header fit in a single message.
+pub const STORE_CHUNK_SIZE: usize = 1 << 17;
+/// Manifests with more chunks than this won't fit in a single message,
+/// which limits stored files to 960MiB.
+pub const MAX_MANIFEST_CHUNKS: usize = 7680;
+
+/// ID of stored content, the BLAKE3 hash of its manifest.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Cid(pub [u8; 32]);
+impl Cid {
+    pub fn of(manifest: &[u8]) -> Self {
+        Self(hash_chunk(manifest))
+    }
+}
+impl std::fmt::Display for Cid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&blake3::Hash::from(self.0).to_hex())
+    }
+}
+impl std::fmt::Debug for Cid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cid({self})")
+    }
+}
+impl FromStr for Cid {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        blake3::Hash::from_hex(s)
+            .map(|v| Self(*v.as_bytes()))
+            .map_err(|e| format!("Invalid content ID {s:?}: {e}"))
+    }
+}
+
+/// Hash that a chunk is stored and requested under.
+pub fn hash_chunk(data: &[u8]) -> [u8; 32] {
+    *blake3::hash(data).as_bytes()
+}
+
+/// The list of chunks that make up a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub name: String,
+    pub size: u64,
+    pub chunk_size: u32,
+    pub chunks: Vec<[u8; 32]>,
+}
+impl Manifest {
+    pub fn encode(&self) -> Vec<u8> {
+        messages::Manifest {
+            name: self.name.clone(),
+            size: self.size,
+            chunk_size: self.chunk_size,
+            chunks: self.chunks.iter().map(|v| v.to_vec()).collect(),
+        }
+        .encode_to_vec()
+    }
+    /// Decode a manifest, `None` if malformed.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let messages::Manifest {
+            name,
+            size,
+            chunk_size,
+            chunks,
+        } = messages::Manifest::decode(bytes).ok()?;
+        let chunks = chunks
+            .into_iter()
+            .map(|v| v.try_into().ok())
+            .collect::<Option<Vec<[u8; 32]>>>()?;
+        let expected_chunks = size.div_ceil(chunk_size.max(1) as u64);
+        if chunk_size == 0 || chunks.len() as u64 != expected_chunks {
+            return None;
+        }
+        Some(Self {
+            name,
+            size,
+            chunk_size,
+            chunks,
+        })
+    }
+    pub fn cid(&self) -> Cid {
+        Cid::of(&self.encode())
+    }
+}
+
+/// Content in the local store.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredContent {
+    pub cid: Cid,
+    pub name: String,
+    pub size: u64,
+    /// Whether all chunks are present.
+    pub complete: bool,
+}
+
+/// Chunks and manifests on disk. All methods block on file IO.
+#[derive(Debug, Clone)]
+pub struct ContentStore {
+    root: PathBuf,
+}
+impl ContentStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+    /// Split the file into chunks and store them along with the manifest.
+    pub fn put_file(&self, path: impl AsRef<Path>) -> Result<Cid, StoreError> {
+        let path = path.as_ref();
+        let fs_error = fs_error(path);
+        let mut file = File::open(path).map_err(&fs_error)?;
+        let size = file.metadata().map_err(&fs_error)?.len();
+        if size.div_ceil(STORE_CHUNK_SIZE as u64) > MAX_MANIFEST_CHUNKS as u64 {
+            return Err(StoreError::TooLarge);
+        }
+        let mut chunks = Vec::new();
+        let mut buf = vec![0u8; STORE_CHUNK_SIZE];
+        loop {
+            let len = read_full(&mut file, &mut buf).map_err(&fs_error)?;
+            if len == 0 {
+                break;
+            }
+            chunks.push(self.insert_chunk(&buf[..len])?);
+        }
+        let manifest = Manifest {
+            name: path
+                .file_name()
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            size,
+            chunk_size: STORE_CHUNK_SIZE as u32,
+            chunks,
+        };
+        self.insert_manifest(&manifest)
+    }
+    /// Store a chunk under its hash, returns the hash.
+    pub fn insert_chunk(&self, data: &[u8]) -> Result<[u8; 32], StoreError> {
+        let hash = hash_chunk(data);
+        let path = self.chunk_path(&hash);
+        if !path.exists() {
+            write_shared(&path, data)?;
+        }
+        Ok(hash)
+    }
+    pub fn insert_manifest(&self, manifest: &Manifest) -> Result<Cid, StoreError> {
+        let bytes = manifest.encode();
+        let cid = Cid::of(&bytes);
+        let path = self.manifest_path(&cid.0);
+        if !path.exists() {
+            write_shared(&path, &bytes)?;
+        }
+        Ok(cid)
+    }
+    /// Read a chunk, or the encoded manifest if the hash is a content ID.
+    pub fn get(&self, hash: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        let hash: [u8; 32] = match hash.try_into() {
+            Ok(v) => v,
+            Err(_) => return Ok(None),
+        };
+        for path in [self.chunk_path(&hash), self.manifest_path(&hash)] {
+            match std::fs::read(&path) {
+                Ok(data) => return Ok(Some(data)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(fs_error(&path)(e)),
+            }
+        }
+        Ok(None)
+    }
+    pub fn manifest(&self, cid: &Cid) -> Result<Option<Manifest>, StoreError> {
+        let path = self.manifest_path(&cid.0);
+        match std::fs::read(&path) {
+            Ok(bytes) => Ok(Manifest::decode(&bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(fs_error(&path)(e)),
+        }
+    }
+    pub fn has_chunk(&self, hash: &[u8; 32]) -> bool {
+        self.chunk_path(hash).exists()
+    }
+    /// All content with a manifest in the store.
+    pub fn list(&self) -> Result<Vec<StoredContent>, StoreError> {
+        let dir = self.root.join("manifests");
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(fs_error(&dir)(e)),
+        };
+        let mut list = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(fs_error(&dir))?;
+            let cid = match Cid::from_str(&entry.file_name().to_string_lossy()) {
+                Ok(v) => v,
+                // Temporary files and such.
+                Err(_) => continue,
+            };
+            if let Some(manifest) = self.manifest(&cid)? {
+                list.push(StoredContent {
+                    cid,
+                    complete: manifest.chunks.iter().all(|v| self.has_chunk(v)),
+                    name: manifest.name,
+                    size: manifest.size,
+                });
+            }
+        }
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(list)
+    }
+    /// Write the content to the target by joining its chunks.
+    /// The file is written under `.part` and renamed once complete.
+    pub fn export(&self, cid: &Cid, target: impl AsRef<Path>) -> Result<(), StoreError> {
+        let target = target.as_ref();
+        let manifest = self.manifest(cid)?.ok_or(StoreError::NotFound)?;
+        let part_path = part_path(target);
+        let mut file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&part_path)
+            .map_err(fs_error(&part_path))?;
+        let result = manifest.chunks.iter().try_for_each(|hash| {
+            let data = self.get(hash)?.ok_or(StoreError::NotFound)?;
+            file.write_all(&data).map_err(fs_error(&part_path))
+        });
+        let result = result
+            .and_then(|_| file.sync_all().map_err(fs_error(&part_path)))
+            .and_then(|_| std::fs::rename(&part_path, target).map_err(fs_error(target)));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&part_path);
+        }
+        result
+    }
+    fn chunk_path(&self, hash: &[u8; 32]) -> PathBuf {
+        self.root
+            .join("chunks")
+            .join(blake3::Hash::from(*hash).to_hex().as_str())
+    }
+    fn manifest_path(&self, hash: &[u8; 32]) -> PathBuf {
+        self.root
+            .join("manifests")
+            .join(blake3::Hash::from(*hash).to_hex().as_str())
+    }
+}
+
+fn fs_error(path: &Path) -> impl Fn(io::Error) -> StoreError + '_ {
+    move |e| StoreError::FsError {
+        path: path.to_string_lossy().to_string(),
+        error: e.kind(),
+    }
+}
+
+/// Write a file that may be written by others at the same time,
+/// e.g. the same chunk appears twice in a file.
+fn write_shared(path: &Path, bytes: &[u8]) -> Result<(), StoreError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(fs_error(parent))?;
+    }
+    match write_atomic(path, bytes) {
+        Ok(()) => Ok(()),
+        // Content is named by hash, whoever wins writes the same bytes.
+        Err(_) if path.exists() => Ok(()),
+        Err(e) => Err(fs_error(path)(e)),
+    }
+}
+
+/// Read until the buffer is full or EOF is reached.
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// A chunk request from remote that has been served.
+#[derive(Debug)]
+pub(crate) struct ServedChunk {
+    pub peer: PeerId,
+    pub request_id: u64,
+    pub data: Option<Vec<u8>>,
+}
+
+/// Serves chunk requests from remote on the blocking thread pool.
+#[derive(Debug)]
+pub(crate) struct StoreServer {
+    store: Option<ContentStore>,
+    served_tx: mpsc::UnboundedSender<ServedChunk>,
+    served_rx: mpsc::UnboundedReceiver<ServedChunk>,
+}
+impl StoreServer {
+    /// Requests are answered with nothing found if `store_path` is blank.
+    pub fn new(store_path: &str) -> Self {
+        let (served_tx, served_rx) = mpsc::unbounded_channel();
+        Self {
+            store: (!store_path.is_empty()).then(|| ContentStore::new(store_path)),
+            served_tx,
+            served_rx,
+        }
+    }
+    pub fn serve(&self, peer: PeerId, request_id: u64, hash: Vec<u8>) {
+        let store = match &self.store {
+            Some(v) => v.clone(),
+            None => {
+                let _ = self.served_tx.send(ServedChunk {
+                    peer,
+                    request_id,
+                    data: None,
+                });
+                return;
+            }
+        };
+        let tx = self.served_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let data = store.get(&hash).unwrap_or_else(|e| {
+                tracing::warn!("Failed to read from content store: {e}");
+                None
+            });
+            let _ = tx.send(ServedChunk {
+                peer,
+                request_id,
+                data,
+            });
+        });
+    }
+    pub fn poll_served(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<ServedChunk> {
+        self.served_rx
+            .poll_recv(cx)
+            .map(|v| v.expect("Sender to be kept alive"))
+    }
+}
@@ -0,0 +1,39 @@
+//! Rate limiting and scheduling of outbound chunks, see [`config::Throttle`].
+use std::time::{Duration, Instant};
+
+/// A token bucket that allows up to one second of burst.
+/// Tokens can go below zero, so chunks larger than the rate still get through
+/// while the average rate is kept.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+impl TokenBucket {
+    /// Returns `None` when `rate` is 0(unlimited).
+    pub fn new(rate: u64) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+        Some(Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        })
+    }
+    /// Time to wait until the next chunk can be sent, `None` if it can be sent now.
+    pub fn delay(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+        if self.tokens >= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(-self.tokens / self.rate))
+    }
+    pub fn consume(&mut self, bytes: usize) {
+        self.tokens -= bytes as f64;
+    }
+}
@@ -1,8 +1,12 @@
+use super::message::Payload;
+use super::rate_limit::RateLimiter;
 use super::*;
 use owlnest_macro::handle_callback_sender;
 use owlnest_prelude::behaviour_prelude::*;
-use std::collections::{HashSet, VecDeque};
-use tracing::info;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 
 pub struct Behaviour {
     config: Config,
@@ -12,6 +16,12 @@ pub struct Behaviour {
     in_events: VecDeque<InEvent>,
     /// A set for all connected peers.
     connected_peers: HashSet<PeerId>,
+    /// Inbound rate limiters indexed by remote peer.
+    rate_limiters: HashMap<PeerId, Arc<Mutex<RateLimiter>>>,
+    /// When the peers whose limiters are kept for cooldown disconnected.
+    disconnected_at: HashMap<PeerId, Instant>,
+    /// Peers that will be disconnected for exceeding the rate limit.
+    pending_disconnect: VecDeque<PeerId>,
 }
 
 impl Behaviour {
@@ -21,6 +31,9 @@ impl Behaviour {
             out_events: VecDeque::new(),
             in_events: VecDeque::new(),
             connected_peers: HashSet::new(),
+            rate_limiters: HashMap::new(),
+            disconnected_at: HashMap::new(),
+            pending_disconnect: VecDeque::new(),
         }
     }
     pub fn push_event(&mut self, msg: InEvent) {
@@ -29,8 +42,31 @@ impl Behaviour {
     pub fn on_disconnect(&mut self, info: &ConnectionClosed) {
         if info.remaining_established < 1 {
             self.connected_peers.remove(&info.peer_id);
+            self.disconnected_at.insert(info.peer_id, Instant::now());
+            self.remove_cooled_down_limiters();
         }
     }
+    /// Drop limiters of peers that have been disconnected for longer than the cooldown.
+    fn remove_cooled_down_limiters(&mut self) {
+        let cooldown = Duration::from_secs(self.config.rate_limit.cooldown_sec);
+        let rate_limiters = &mut self.rate_limiters;
+        self.disconnected_at.retain(|peer, disconnected_at| {
+            if disconnected_at.elapsed() < cooldown {
+                return true;
+            }
+            rate_limiters.remove(peer);
+            false
+        });
+    }
+    fn new_handler(&mut self, peer: PeerId) -> handler::Handler {
+        self.disconnected_at.remove(&peer);
+        let rate_limiter = self
+            .rate_limiters
+            .entry(peer)
+            .or_insert_with(|| Arc::new(Mutex::new(RateLimiter::new(&self.config.rate_limit))))
+            .clone();
+        handler::Handler::new(self.config.clone(), rate_limiter)
+    }
 }
 
 impl NetworkBehaviour for Behaviour {
@@ -52,6 +88,12 @@ impl NetworkBehaviour for Behaviour {
         if let Some(ev) = self.out_events.pop_front() {
             return Poll::Ready(ToSwarm::GenerateEvent(ev));
         }
+        if let Some(peer_id) = self.pending_disconnect.pop_front() {
+            return Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection: CloseConnection::All,
+            });
+        }
         if let Some(ev) = self.handle_in_events() {
             return Poll::Ready(ev);
         }
@@ -67,22 +109,22 @@ impl NetworkBehaviour for Behaviour {
     fn handle_established_inbound_connection(
         &mut self,
         _connection_id: ConnectionId,
-        _peer: PeerId,
+        peer: PeerId,
         _local_addr: &Multiaddr,
         _remote_addr: &Multiaddr,
     ) -> Result<Self::ConnectionHandler, ConnectionDenied> {
-        Ok(handler::Handler::new(self.config.clone()))
+        Ok(self.new_handler(peer))
     }
 
     fn handle_established_outbound_connection(
         &mut self,
         _connection_id: ConnectionId,
-        _peer: PeerId,
+        peer: PeerId,
         _addr: &Multiaddr,
         _role_override: Endpoint,
         _port_use: PortUse,
     ) -> Result<Self::ConnectionHandler, ConnectionDenied> {
-        Ok(handler::Handler::new(self.config.clone()))
+        Ok(self.new_handler(peer))
     }
 }
 
@@ -96,14 +138,28 @@ impl Behaviour {
     ) {
         use handler::ToBehaviourEvent::*;
         match event {
-            IncomingMessage(bytes) => match serde_json::from_slice::<Message>(&bytes) {
-                Ok(msg) => {
+            IncomingMessage(bytes) => match serde_json::from_slice::<Payload>(&bytes) {
+                Ok(Payload::Message(msg)) => {
                     trace!("Incoming message from {}: {}", peer_id, msg.msg);
                     self.out_events.push_back(OutEvent::IncomingMessage {
                         from: msg.from,
                         msg,
                     })
                 }
+                Ok(Payload::Rpc(packet)) => {
+                    trace!("Incoming RPC packet from {}: {:?}", peer_id, packet);
+                    self.out_events.push_back(OutEvent::IncomingRpc {
+                        from: peer_id,
+                        packet,
+                    })
+                }
+                Ok(Payload::Group(packet)) => {
+                    trace!("Incoming group packet from {}: {:?}", peer_id, packet);
+                    self.out_events.push_back(OutEvent::IncomingGroupPacket {
+                        from: peer_id,
+                        packet,
+                    })
+                }
                 Err(e) => {
                     self.out_events
                         .push_back(OutEvent::Error(super::Error::UnrecognizedMessage(format!(
@@ -113,6 +169,23 @@ impl Behaviour {
                         ))))
                 }
             },
+            RateLimited {
+                violations,
+                exceeded,
+            } => {
+                if exceeded && !self.pending_disconnect.contains(&peer_id) {
+                    warn!(
+                        "Disconnecting peer {} after {} rate limit violations",
+                        peer_id, violations
+                    );
+                    self.pending_disconnect.push_back(peer_id);
+                }
+                self.out_events.push_back(OutEvent::RateLimited {
+                    peer: peer_id,
+                    violations,
+                    disconnected: exceeded,
+                });
+            }
             Error(e) => {
                 info!(
                     "Error occurred on peer {}:{:?}: {:#?}",
@@ -154,16 +227,27 @@ impl Behaviour {
                     message,
                     callback,
                 } => {
-                    if self.connected_peers.contains(&peer) {
-                        let ev = ToSwarm::NotifyHandler {
-                            peer_id: peer,
-                            handler: NotifyHandler::Any,
-                            event: handler::FromBehaviourEvent::PostMessage(message, callback),
-                        };
+                    if let Some(ev) = self.post(peer, Payload::Message(message), callback) {
+                        return Some(ev);
+                    }
+                }
+                SendRpc {
+                    peer,
+                    packet,
+                    callback,
+                } => {
+                    if let Some(ev) = self.post(peer, Payload::Rpc(packet), callback) {
+                        return Some(ev);
+                    }
+                }
+                SendGroupPacket {
+                    peer,
+                    packet,
+                    callback,
+                } => {
+                    if let Some(ev) = self.post(peer, Payload::Group(packet), callback) {
                         return Some(ev);
-                    } else {
-                        handle_callback_sender!(Err(SendError::PeerNotFound(peer))=>callback);
-                    };
+                    }
                 }
                 ListConnected { callback } => {
                     handle_callback_sender!(self.connected_peers.iter().copied().collect() => callback);
@@ -172,4 +256,21 @@ impl Behaviour {
         }
         None
     }
+    #[inline]
+    fn post(
+        &self,
+        peer: PeerId,
+        payload: Payload,
+        callback: Callback<Result<Duration, SendError>>,
+    ) -> Option<ToSwarm<OutEvent, handler::FromBehaviourEvent>> {
+        if self.connected_peers.contains(&peer) {
+            return Some(ToSwarm::NotifyHandler {
+                peer_id: peer,
+                handler: NotifyHandler::Any,
+                event: handler::FromBehaviourEvent::PostMessage(payload, callback),
+            });
+        }
+        handle_callback_sender!(Err(SendError::PeerNotFound(peer))=>callback);
+        None
+    }
 }
@@ -3,6 +3,18 @@ use super::*;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub timeout_ms: u64,
+    /// Maximum size of a single frame in bytes that local peer will accept.
+    /// This value is advertised to remote peers when a stream is negotiated,
+    /// messages exceeding remote's limit will be sent in chunks.
+    pub max_message_size: usize,
+    /// Maximum size in bytes of a message reassembled from chunks.
+    /// Set to 0 to reject all chunked messages.
+    pub max_chunked_message_size: usize,
+    /// Limits on messages coming from a single remote peer.
+    pub rate_limit: RateLimit,
+    /// Maximum number of RPC calls from a single remote peer handled at the same time.
+    /// Further calls will fail with `RpcError::Busy`. 0 for no limit.
+    pub max_concurrent_rpc: usize,
     pub store: Store,
 }
 impl Config {
@@ -13,16 +25,75 @@ impl Config {
         self.timeout_ms = timeout_ms;
         self
     }
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+    pub fn with_max_chunked_message_size(mut self, max_chunked_message_size: usize) -> Self {
+        self.max_chunked_message_size = max_chunked_message_size;
+        self
+    }
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+    pub fn with_max_concurrent_rpc(mut self, max_concurrent_rpc: usize) -> Self {
+        self.max_concurrent_rpc = max_concurrent_rpc;
+        self
+    }
 }
 impl Default for Config {
     fn default() -> Self {
         Self {
             timeout_ms: 30 * 1000,
+            max_message_size: 128 * 1024,
+            max_chunked_message_size: 16 * 1024 * 1024,
+            rate_limit: RateLimit::default(),
+            max_concurrent_rpc: 16,
             store: Store::Volatile,
         }
     }
 }
 
+/// Token-bucket limits applied to inbound messages, per remote peer.  
+/// Messages over the limit are throttled by delaying their acknowledgement,
+/// and rejected if they would have to wait too long. The sender will be informed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Messages allowed per second. 0 for no limit.
+    pub messages_per_sec: u64,
+    /// Messages that can be received in a burst.
+    pub message_burst: u64,
+    /// Bytes allowed per second. 0 for no limit.
+    pub bytes_per_sec: u64,
+    /// Bytes that can be received in a burst.
+    pub byte_burst: u64,
+    /// Longest time in milliseconds to hold a frame back before it's rejected.
+    pub max_delay_ms: u64,
+    /// Rejected frames allowed within a window before the peer is disconnected.
+    /// 0 to never disconnect.
+    pub max_violations: u32,
+    /// Length of the window in seconds, the count of rejected frames resets afterwards.
+    pub violation_window_sec: u64,
+    /// Seconds to keep the limiter of a peer after it disconnects,
+    /// so that reconnecting won't reset its tokens and violations.
+    pub cooldown_sec: u64,
+}
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            messages_per_sec: 20,
+            message_burst: 50,
+            bytes_per_sec: 1024 * 1024,
+            byte_burst: 8 * 1024 * 1024,
+            max_delay_ms: 1000,
+            max_violations: 64,
+            violation_window_sec: 60,
+            cooldown_sec: 10 * 60,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Store {
     Volatile,
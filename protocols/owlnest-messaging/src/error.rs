@@ -30,6 +30,14 @@ pub enum SendError {
     VerifierMismatch,
     PeerNotFound(PeerId),
     Timeout,
+    /// The message is larger than what the remote is willing to accept,
+    /// even when sent in chunks.
+    TooLarge {
+        size: usize,
+        limit: usize,
+    },
+    /// Remote refused to take the message because local peer is sending too fast.
+    Rejected,
 }
 
 impl Display for SendError {
@@ -40,6 +48,11 @@ impl Display for SendError {
             VerifierMismatch => f.write_str("Message verifier mismatch"),
             Timeout => f.write_str("Message timed out"),
             PeerNotFound(peer) => write!(f, "Peer {peer} not connected"),
+            Rejected => f.write_str("Message rejected by remote: rate limit exceeded"),
+            TooLarge { size, limit } => write!(
+                f,
+                "Message of {size} bytes exceeds the limit of {limit} bytes accepted by remote"
+            ),
         }
     }
 }
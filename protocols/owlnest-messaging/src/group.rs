@@ -0,0 +1,62 @@
+use super::*;
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Identifier of a group conversation, chosen randomly by the creator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct GroupId(pub u64);
+impl Display for GroupId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+impl FromStr for GroupId {
+    type Err = std::num::ParseIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(GroupId)
+    }
+}
+
+/// A closed group conversation.  
+/// Only the creator can change the member list,
+/// and only members can send or receive messages of the group.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Group {
+    pub id: GroupId,
+    pub name: String,
+    pub creator: PeerId,
+    /// All members of the group, including the creator.
+    pub members: BTreeSet<PeerId>,
+}
+impl Group {
+    pub fn is_member(&self, peer: &PeerId) -> bool {
+        self.members.contains(peer)
+    }
+}
+
+/// Control and content packets of group conversations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Packet {
+    /// Sent by the creator to a newly added member.
+    /// `member` is the receiving peer.
+    Join { group: Group, member: PeerId },
+    /// Sent by the creator to existing members when the member list changed.
+    Members { group: Group },
+    /// Sent by a member to all other members when it leaves the group.
+    Leave { group_id: GroupId },
+    /// Sent by the creator to a member that has been removed from the group.
+    Kick { group_id: GroupId },
+    /// A message of the group, fanned out to every other member.
+    Content { group_id: GroupId, message: Message },
+}
+impl Packet {
+    pub fn group_id(&self) -> GroupId {
+        match self {
+            Packet::Join { group, .. } | Packet::Members { group } => group.id,
+            Packet::Leave { group_id }
+            | Packet::Kick { group_id }
+            | Packet::Content { group_id, .. } => *group_id,
+        }
+    }
+}
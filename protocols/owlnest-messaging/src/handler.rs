@@ -1,20 +1,29 @@
 use super::error::SendError;
-use super::{protocol, Config, Error, Message, PROTOCOL_NAME};
+use super::message::Payload;
+use super::protocol::{Frame, Limits, Verdict};
+use super::rate_limit::{Admission, RateLimiter};
+use super::{protocol, Config, Error, PROTOCOL_NAME};
 use futures_timer::Delay;
 use owlnest_core::alias::Callback;
 use owlnest_macro::handle_callback_sender;
 use owlnest_prelude::handler_prelude::*;
+use std::sync::{Arc, Mutex};
 use std::task::Context;
 use std::{collections::VecDeque, time::Duration};
 use tracing::{debug, trace};
 
 #[derive(Debug)]
 pub enum FromBehaviourEvent {
-    PostMessage(Message, Callback<Result<Duration, SendError>>),
+    PostMessage(Payload, Callback<Result<Duration, SendError>>),
 }
 #[derive(Debug)]
 pub enum ToBehaviourEvent {
     IncomingMessage(Vec<u8>),
+    /// A frame from remote has been rejected by the rate limiter.
+    RateLimited {
+        violations: u32,
+        exceeded: bool,
+    },
     Error(Error),
     InboundNegotiated,
     OutboundNegotiated,
@@ -33,10 +42,20 @@ pub struct Handler {
     timeout: Duration,
     inbound: Option<PendingVerf>,
     outbound: Option<OutboundState>,
+    /// Limits of local peer, advertised on inbound streams.
+    local_limits: Limits,
+    /// Limits of remote peer, learned on outbound streams.
+    remote_limits: Option<Limits>,
+    /// Chunked message that is being reassembled.
+    reassembly: Option<Reassembly>,
+    /// ID tracker for chunked messages sent on this connection.
+    message_counter: u64,
+    /// Inbound rate limiter shared by all connections to the same peer.
+    rate_limiter: Arc<Mutex<RateLimiter>>,
 }
 
 impl Handler {
-    pub fn new(config: Config) -> Self {
+    pub fn new(config: Config, rate_limiter: Arc<Mutex<RateLimiter>>) -> Self {
         Self {
             state: State::Active,
             pending_in_events: VecDeque::new(),
@@ -44,10 +63,23 @@ impl Handler {
             timeout: Duration::from_millis(config.timeout_ms),
             inbound: None,
             outbound: None,
+            local_limits: Limits::from(&config),
+            remote_limits: None,
+            reassembly: None,
+            message_counter: 0,
+            rate_limiter,
         }
     }
 }
 
+/// State of a chunked message that is being received.
+struct Reassembly {
+    message_id: u64,
+    next_index: u32,
+    total: u32,
+    buf: Vec<u8>,
+}
+
 impl ConnectionHandler for Handler {
     type FromBehaviour = FromBehaviourEvent;
     type ToBehaviour = ToBehaviourEvent;
@@ -110,7 +142,7 @@ impl ConnectionHandler for Handler {
                 protocol: stream,
                 info: (),
             }) => {
-                self.inbound = Some(super::protocol::recv(stream).boxed());
+                self.inbound = Some(protocol::accept(stream, self.local_limits).boxed());
                 self.pending_out_events
                     .push_back(ToBehaviourEvent::InboundNegotiated)
             }
@@ -118,9 +150,10 @@ impl ConnectionHandler for Handler {
                 protocol: stream,
                 ..
             }) => {
-                self.outbound = Some(OutboundState::Idle(stream));
-                self.pending_out_events
-                    .push_back(ToBehaviourEvent::OutboundNegotiated)
+                // Peer is considered connected once remote limits are known.
+                self.outbound = Some(OutboundState::Handshake(
+                    protocol::handshake(stream).boxed(),
+                ));
             }
             ConnectionEvent::AddressChange(_) => {}
             ConnectionEvent::DialUpgradeError(e) => {
@@ -135,12 +168,23 @@ impl ConnectionHandler for Handler {
 }
 
 type PendingVerf = BoxFuture<'static, Result<(Stream, Vec<u8>), io::Error>>;
-type PendingSend = BoxFuture<'static, Result<(Stream, Duration), io::Error>>;
+type PendingSend = BoxFuture<'static, Result<(Stream, Duration, Verdict), io::Error>>;
+type PendingHandshake = BoxFuture<'static, Result<(Stream, Limits), io::Error>>;
 
 enum OutboundState {
     OpenStream,
+    Handshake(PendingHandshake),
     Idle(Stream),
-    Busy(PendingSend, Callback<Result<Duration, SendError>>, Delay),
+    Busy(PendingSend, PendingPost, Delay),
+}
+
+/// A message whose frames are being sent.
+struct PendingPost {
+    /// Encoded frames that are yet to be sent.
+    frames: VecDeque<Vec<u8>>,
+    /// Accumulated round-trip time of all sent frames.
+    rtt: Duration,
+    callback: Callback<Result<Duration, SendError>>,
 }
 
 type PollResult = ConnectionHandlerEvent<
@@ -173,52 +217,143 @@ impl Handler {
     }
     #[inline]
     fn poll_inbound(&mut self, cx: &mut Context<'_>) -> Option<PollResult> {
-        if let Some(fut) = self.inbound.as_mut() {
-            let poll_result = fut.poll_unpin(cx);
-            if let Poll::Ready(Ok((stream, bytes))) = poll_result {
-                self.inbound = Some(super::protocol::recv(stream).boxed());
-                let event = ConnectionHandlerEvent::NotifyBehaviour(
-                    ToBehaviourEvent::IncomingMessage(bytes),
-                );
-                return Some(event);
-            }
-            if let Poll::Ready(Err(e)) = poll_result {
-                let error = Error::IO(format!("IO Error: {e:?}"));
-                self.pending_out_events
-                    .push_back(ToBehaviourEvent::Error(error));
-                self.inbound = None;
+        loop {
+            let fut = self.inbound.as_mut()?;
+            match fut.poll_unpin(cx) {
+                Poll::Pending => return None,
+                Poll::Ready(Err(e)) => {
+                    let error = Error::IO(format!("IO Error: {e:?}"));
+                    self.pending_out_events
+                        .push_back(ToBehaviourEvent::Error(error));
+                    self.inbound = None;
+                    return None;
+                }
+                Poll::Ready(Ok((stream, bytes))) => {
+                    let verifier = protocol::verifier(&bytes);
+                    let frame_len = bytes.len();
+                    let mut message = None;
+                    let verdict = match Frame::decode(bytes) {
+                        Ok(frame) => {
+                            // Only the first frame of a message counts as a new message
+                            let new_messages = match frame {
+                                Frame::Chunk { index, .. } if index != 0 => 0,
+                                _ => 1,
+                            };
+                            match self.admit(new_messages, frame_len) {
+                                Admission::Admitted => {
+                                    message = self.on_frame(frame);
+                                    Verdict::Accepted
+                                }
+                                Admission::Throttled(wait) => {
+                                    // Hold back the ack, so the sender can't send the next frame
+                                    // until the frame is checked again.
+                                    let bytes = frame.encode();
+                                    self.inbound = Some(
+                                        async move {
+                                            Delay::new(wait).await;
+                                            Ok::<_, io::Error>((stream, bytes))
+                                        }
+                                        .boxed(),
+                                    );
+                                    continue;
+                                }
+                                Admission::Rejected => {
+                                    // The sender will abandon the rest of the message
+                                    self.reassembly = None;
+                                    Verdict::Rejected
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            self.pending_out_events
+                                .push_back(ToBehaviourEvent::Error(Error::UnrecognizedMessage(e)));
+                            Verdict::Accepted
+                        }
+                    };
+                    self.inbound = Some(
+                        protocol::ack_and_recv(
+                            stream,
+                            verifier,
+                            verdict,
+                            self.local_limits.max_frame_size,
+                        )
+                        .boxed(),
+                    );
+                    if let Some(bytes) = message {
+                        let event = ConnectionHandlerEvent::NotifyBehaviour(
+                            ToBehaviourEvent::IncomingMessage(bytes),
+                        );
+                        return Some(event);
+                    }
+                    // continue to poll the new future for wake-up to be scheduled
+                }
             }
         }
-        None
     }
     #[inline]
     fn poll_outbound(&mut self, cx: &mut Context<'_>) -> Option<PollResult> {
         loop {
             match self.outbound.take() {
-                Some(OutboundState::Busy(mut task, callback, mut timer)) => {
+                Some(OutboundState::Busy(mut task, mut post, mut timer)) => {
                     let poll_result = task.poll_unpin(cx);
                     if poll_result.is_pending() {
                         trace!("outbound pending");
                         if timer.poll_unpin(cx).is_ready() {
-                            handle_callback_sender!(Err(SendError::Timeout)=>callback);
+                            handle_callback_sender!(Err(SendError::Timeout)=>post.callback);
                             break;
                             // exit and drop the task(with negotiated stream)
                         }
-                        self.outbound = Some(OutboundState::Busy(task, callback, timer));
+                        self.outbound = Some(OutboundState::Busy(task, post, timer));
                         break; // exit loop because of pending future, checking pending out events
                     }
                     if let Poll::Ready(Err(e)) = poll_result {
+                        handle_callback_sender!(Err(SendError::ConnectionClosed)=>post.callback);
                         return Some(ConnectionHandlerEvent::NotifyBehaviour(
                             ToBehaviourEvent::Error(Error::IO(e.to_string())),
                         )); // exit because of error
                     }
-                    if let Poll::Ready(Ok((stream, rtt))) = poll_result {
-                        handle_callback_sender!(Ok(rtt)=>callback);
+                    if let Poll::Ready(Ok((stream, _, Verdict::Rejected))) = poll_result {
+                        // Abandon remaining chunks of the message
+                        handle_callback_sender!(Err(SendError::Rejected)=>post.callback);
+                        self.outbound = Some(OutboundState::Idle(stream));
+                        continue;
+                    }
+                    if let Poll::Ready(Ok((stream, rtt, Verdict::Accepted))) = poll_result {
+                        post.rtt += rtt;
+                        if let Some(frame) = post.frames.pop_front() {
+                            // Send the next chunk of the same message
+                            self.outbound = Some(OutboundState::Busy(
+                                protocol::send(stream, frame).boxed(),
+                                post,
+                                Delay::new(self.timeout),
+                            ));
+                            continue;
+                        }
+                        handle_callback_sender!(Ok(post.rtt)=>post.callback);
                         // Free the outbound
                         self.outbound = Some(OutboundState::Idle(stream));
                         // continue to see if there is any pending activity
                     }
                 }
+                Some(OutboundState::Handshake(mut task)) => match task.poll_unpin(cx) {
+                    Poll::Pending => {
+                        self.outbound = Some(OutboundState::Handshake(task));
+                        break;
+                    }
+                    Poll::Ready(Ok((stream, limits))) => {
+                        trace!("Remote limits: {:?}", limits);
+                        self.remote_limits = Some(limits);
+                        self.outbound = Some(OutboundState::Idle(stream));
+                        return Some(ConnectionHandlerEvent::NotifyBehaviour(
+                            ToBehaviourEvent::OutboundNegotiated,
+                        ));
+                    }
+                    Poll::Ready(Err(e)) => {
+                        return Some(ConnectionHandlerEvent::NotifyBehaviour(
+                            ToBehaviourEvent::Error(Error::IO(e.to_string())),
+                        ));
+                    }
+                },
                 Some(OutboundState::Idle(stream)) => {
                     if self.pending_in_events.is_empty() {
                         self.outbound = Some(OutboundState::Idle(stream));
@@ -246,15 +381,97 @@ impl Handler {
     fn progress_in_events(&mut self, stream: Stream) {
         let ev = self.pending_in_events.pop_front().expect("already handled");
         match ev {
-            FromBehaviourEvent::PostMessage(msg, id) => {
-                trace!("sending message: {}", msg.msg);
+            FromBehaviourEvent::PostMessage(payload, callback) => {
+                trace!("sending payload: {:?}", payload);
+                let limits = self
+                    .remote_limits
+                    .expect("Stream becomes idle only after handshake");
+                let message_id = self.message_counter;
+                self.message_counter += 1;
+                let mut frames = match protocol::split(message_id, payload.as_bytes(), &limits) {
+                    Ok(frames) => frames,
+                    Err(e) => {
+                        handle_callback_sender!(Err(e)=>callback);
+                        self.outbound = Some(OutboundState::Idle(stream));
+                        return;
+                    }
+                };
+                let first = frames.pop_front().expect("At least one frame");
                 // Put Outbound into send state
                 self.outbound = Some(OutboundState::Busy(
-                    protocol::send(stream, msg.as_bytes()).boxed(),
-                    id,
+                    protocol::send(stream, first).boxed(),
+                    PendingPost {
+                        frames,
+                        rtt: Duration::ZERO,
+                        callback,
+                    },
                     Delay::new(self.timeout),
                 ))
             }
         }
     }
+    /// Check the frame against the rate limiter of the remote peer.
+    fn admit(&mut self, messages: u64, bytes: usize) -> Admission {
+        let mut rate_limiter = self.rate_limiter.lock().expect("lock not poisoned");
+        let admission = rate_limiter.admit(messages, bytes);
+        if admission == Admission::Rejected {
+            self.pending_out_events
+                .push_back(ToBehaviourEvent::RateLimited {
+                    violations: rate_limiter.violations(),
+                    exceeded: rate_limiter.exceeded(),
+                });
+        }
+        admission
+    }
+    /// Returns the full message once all of its frames have arrived.
+    fn on_frame(&mut self, frame: Frame) -> Option<Vec<u8>> {
+        let (message_id, index, total, content) = match frame {
+            Frame::Whole(bytes) => return Some(bytes),
+            Frame::Chunk {
+                message_id,
+                index,
+                total,
+                content,
+            } => (message_id, index, total, content),
+        };
+        if index == 0 {
+            if self.reassembly.is_some() {
+                debug!("Discarding incomplete chunked message");
+            }
+            self.reassembly = Some(Reassembly {
+                message_id,
+                next_index: 0,
+                total,
+                buf: Vec::new(),
+            });
+        }
+        let mut reassembly = match self.reassembly.take() {
+            Some(v) if v.message_id == message_id && v.next_index == index => v,
+            _ => {
+                self.pending_out_events.push_back(ToBehaviourEvent::Error(
+                    Error::UnrecognizedMessage(format!(
+                        "Unexpected chunk {index} of message {message_id}"
+                    )),
+                ));
+                return None;
+            }
+        };
+        if reassembly.buf.len() + content.len() > self.local_limits.max_message_size {
+            self.pending_out_events
+                .push_back(ToBehaviourEvent::Error(Error::UnrecognizedMessage(
+                    format!(
+                        "Chunked message {message_id} exceeds the limit of {} bytes",
+                        self.local_limits.max_message_size
+                    ),
+                )));
+            return None;
+        }
+        reassembly.buf.extend_from_slice(&content);
+        reassembly.next_index += 1;
+        if reassembly.next_index >= reassembly.total {
+            return Some(reassembly.buf);
+        }
+        self.reassembly = Some(reassembly);
+        None
+    }
 }
@@ -8,12 +8,16 @@ use tracing::trace;
 mod behaviour;
 mod config;
 pub mod error;
+pub mod group;
 mod handler;
 pub mod message;
 mod op;
+mod protocol;
+mod rate_limit;
+pub mod rpc;
 
 pub use behaviour::Behaviour;
-pub use config::Config;
+pub use config::{Config, RateLimit};
 pub use error::Error;
 pub use message::Message;
 pub use protocol::PROTOCOL_NAME;
@@ -25,6 +29,18 @@ pub enum InEvent {
         message: Message,
         callback: Callback<Result<Duration, SendError>>,
     },
+    /// Send a RPC packet to the peer.
+    SendRpc {
+        peer: PeerId,
+        packet: rpc::Packet,
+        callback: Callback<Result<Duration, SendError>>,
+    },
+    /// Send a group conversation packet to the peer.
+    SendGroupPacket {
+        peer: PeerId,
+        packet: group::Packet,
+        callback: Callback<Result<Duration, SendError>>,
+    },
     ListConnected {
         callback: Callback<Box<[PeerId]>>,
     },
@@ -32,14 +48,28 @@ pub enum InEvent {
 
 #[derive(Debug)]
 pub enum OutEvent {
-    IncomingMessage { from: PeerId, msg: Message },
+    IncomingMessage {
+        from: PeerId,
+        msg: Message,
+    },
+    IncomingRpc {
+        from: PeerId,
+        packet: rpc::Packet,
+    },
+    IncomingGroupPacket {
+        from: PeerId,
+        packet: group::Packet,
+    },
+    /// Messages from the peer are being rejected for exceeding the rate limit.
+    RateLimited {
+        peer: PeerId,
+        /// Frames rejected within the current window.
+        violations: u32,
+        /// Whether the peer has been disconnected for too many violations.
+        disconnected: bool,
+    },
     Error(Error),
     InboundNegotiated(PeerId),
     OutboundNegotiated(PeerId),
     Unsupported(PeerId),
 }
-
-mod protocol {
-    pub const PROTOCOL_NAME: &str = "/owlnest/messaging/0.0.1";
-    pub use owlnest_prelude::utils::protocol::universal::*;
-}
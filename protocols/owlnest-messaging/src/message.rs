@@ -25,3 +25,17 @@ impl Message {
         serde_json::to_vec(self).unwrap()
     }
 }
+
+/// Everything that can be sent over the messaging stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Payload {
+    Message(Message),
+    Rpc(rpc::Packet),
+    Group(group::Packet),
+}
+impl Payload {
+    #[inline]
+    pub fn as_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+}
@@ -0,0 +1,257 @@
+use super::error::SendError;
+use futures::prelude::*;
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+use xxhash_rust::xxh3::xxh3_128;
+
+pub const PROTOCOL_NAME: &str = "/owlnest/messaging/0.0.2";
+
+/// Header length of a whole message: frame type.
+const WHOLE_HEADER_LEN: usize = 1;
+/// Header length of a chunk: frame type, message ID, chunk index and chunk count.
+const CHUNK_HEADER_LEN: usize = 1 + 8 + 4 + 4;
+
+const FRAME_WHOLE: u8 = 0;
+const FRAME_CHUNK: u8 = 1;
+
+/// Size limits of a peer, exchanged right after the stream is negotiated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum bytes of a single frame the peer will accept.
+    pub max_frame_size: usize,
+    /// Maximum bytes of a message reassembled from chunks.
+    /// `0` means the peer doesn't accept chunked messages.
+    pub max_message_size: usize,
+}
+impl From<&super::Config> for Limits {
+    fn from(value: &super::Config) -> Self {
+        Self {
+            max_frame_size: value.max_message_size,
+            max_message_size: value.max_chunked_message_size,
+        }
+    }
+}
+
+/// Called on inbound streams: tell the remote about local limits,
+/// then wait for the first frame.
+pub async fn accept<S>(mut stream: S, limits: Limits) -> io::Result<(S, Vec<u8>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream
+        .write_all(&(limits.max_frame_size as u64).to_be_bytes())
+        .await?;
+    stream
+        .write_all(&(limits.max_message_size as u64).to_be_bytes())
+        .await?;
+    stream.flush().await?;
+    recv(stream, limits.max_frame_size).await
+}
+
+/// Called on outbound streams: read the limits advertised by the remote.
+pub async fn handshake<S>(mut stream: S) -> io::Result<(S, Limits)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = [0u8; 8];
+    stream.read_exact(&mut buf).await?;
+    let max_frame_size = u64::from_be_bytes(buf) as usize;
+    stream.read_exact(&mut buf).await?;
+    let max_message_size = u64::from_be_bytes(buf) as usize;
+    if max_frame_size <= CHUNK_HEADER_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Remote advertised an unusable frame size of {max_frame_size} bytes"),
+        ));
+    }
+    Ok((
+        stream,
+        Limits {
+            max_frame_size,
+            max_message_size,
+        },
+    ))
+}
+
+/// Whether the receiver has taken the frame, sent along with the verifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accepted,
+    /// The receiver refused to process the frame, e.g. the sender is rate limited.
+    Rejected,
+}
+
+/// Send a frame and wait for the receiver to acknowledge it.  
+///```norun
+///      send()-->Outbound------>Inbound-->recv()
+///                                           |
+///      Verdict<--Outbound<------Inbound<--ack()
+/// ```
+pub async fn send<S>(mut stream: S, frame: Vec<u8>) -> io::Result<(S, Duration, Verdict)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let verf = xxh3_128(&frame);
+    stream
+        .write_all(&(frame.len() as u64).to_be_bytes())
+        .await?;
+    stream.write_all(&frame).await?;
+    stream.flush().await?;
+    let now = Instant::now();
+    let mut verdict = [0u8; 1];
+    stream.read_exact(&mut verdict).await?;
+    let mut verf_read = [0u8; 16];
+    stream.read_exact(&mut verf_read).await?;
+    if u128::from_be_bytes(verf_read) != verf {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Verifier mismatch",
+        ));
+    }
+    let verdict = match verdict[0] {
+        0 => Verdict::Accepted,
+        _ => Verdict::Rejected,
+    };
+    Ok((stream, now.elapsed(), verdict))
+}
+
+/// Read a frame of at most `max_size` bytes.  
+/// The frame must be acknowledged with [`ack_and_recv`] before reading the next one.
+pub async fn recv<S>(mut stream: S, max_size: usize) -> io::Result<(S, Vec<u8>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = [0u8; 8];
+    stream.read_exact(&mut buf).await?;
+    let bytes_to_read = u64::from_be_bytes(buf) as usize;
+    if bytes_to_read > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            format!("Stream too long({bytes_to_read} bytes, limit {max_size} bytes). Terminating."),
+        ));
+    }
+    let mut frame = vec![0u8; bytes_to_read];
+    stream.read_exact(&mut frame).await?;
+    Ok((stream, frame))
+}
+
+/// Acknowledge the last frame with the given verdict, then wait for the next frame.
+pub async fn ack_and_recv<S>(
+    mut stream: S,
+    verifier: u128,
+    verdict: Verdict,
+    max_size: usize,
+) -> io::Result<(S, Vec<u8>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let verdict = match verdict {
+        Verdict::Accepted => 0u8,
+        Verdict::Rejected => 1u8,
+    };
+    stream.write_all(&[verdict]).await?;
+    stream.write_all(&verifier.to_be_bytes()).await?;
+    stream.flush().await?;
+    recv(stream, max_size).await
+}
+
+/// Compute the verifier of a received frame.
+pub fn verifier(frame: &[u8]) -> u128 {
+    xxh3_128(frame)
+}
+
+/// A unit of data sent over the stream.
+#[derive(Debug)]
+pub enum Frame {
+    /// A message that fits in a single frame.
+    Whole(Vec<u8>),
+    /// A part of a message that is too large for a single frame.
+    Chunk {
+        message_id: u64,
+        index: u32,
+        total: u32,
+        content: Vec<u8>,
+    },
+}
+impl Frame {
+    pub fn encode(self) -> Vec<u8> {
+        match self {
+            Frame::Whole(content) => {
+                let mut buf = Vec::with_capacity(WHOLE_HEADER_LEN + content.len());
+                buf.push(FRAME_WHOLE);
+                buf.extend_from_slice(&content);
+                buf
+            }
+            Frame::Chunk {
+                message_id,
+                index,
+                total,
+                content,
+            } => {
+                let mut buf = Vec::with_capacity(CHUNK_HEADER_LEN + content.len());
+                buf.push(FRAME_CHUNK);
+                buf.extend_from_slice(&message_id.to_be_bytes());
+                buf.extend_from_slice(&index.to_be_bytes());
+                buf.extend_from_slice(&total.to_be_bytes());
+                buf.extend_from_slice(&content);
+                buf
+            }
+        }
+    }
+    pub fn decode(mut bytes: Vec<u8>) -> Result<Self, String> {
+        match bytes.first() {
+            Some(&FRAME_WHOLE) => {
+                bytes.remove(0);
+                Ok(Frame::Whole(bytes))
+            }
+            Some(&FRAME_CHUNK) if bytes.len() >= CHUNK_HEADER_LEN => {
+                let message_id = u64::from_be_bytes(bytes[1..9].try_into().expect("8 bytes"));
+                let index = u32::from_be_bytes(bytes[9..13].try_into().expect("4 bytes"));
+                let total = u32::from_be_bytes(bytes[13..17].try_into().expect("4 bytes"));
+                Ok(Frame::Chunk {
+                    message_id,
+                    index,
+                    total,
+                    content: bytes.split_off(CHUNK_HEADER_LEN),
+                })
+            }
+            Some(frame_type) => Err(format!("Malformed frame of type {frame_type}")),
+            None => Err("Empty frame".into()),
+        }
+    }
+}
+
+/// Split the serialized message into encoded frames that fit in the remote's limits.
+pub fn split(
+    message_id: u64,
+    bytes: Vec<u8>,
+    limits: &Limits,
+) -> Result<VecDeque<Vec<u8>>, SendError> {
+    if bytes.len() + WHOLE_HEADER_LEN <= limits.max_frame_size {
+        return Ok(VecDeque::from([Frame::Whole(bytes).encode()]));
+    }
+    if bytes.len() > limits.max_message_size {
+        return Err(SendError::TooLarge {
+            size: bytes.len(),
+            limit: limits
+                .max_message_size
+                .max(limits.max_frame_size - WHOLE_HEADER_LEN),
+        });
+    }
+    let chunk_size = limits.max_frame_size - CHUNK_HEADER_LEN;
+    let total = bytes.len().div_ceil(chunk_size) as u32;
+    Ok(bytes
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, content)| {
+            Frame::Chunk {
+                message_id,
+                index: index as u32,
+                total,
+                content: content.to_vec(),
+            }
+            .encode()
+        })
+        .collect())
+}
@@ -0,0 +1,127 @@
+use super::config::RateLimit;
+use std::time::{Duration, Instant};
+
+/// A token bucket that refills continuously.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+impl TokenBucket {
+    /// Returns `None` when `rate` is 0(unlimited).
+    fn new(rate: u64, burst: u64) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+        let capacity = burst.max(rate) as f64;
+        Some(Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec: rate as f64,
+            last_refill: Instant::now(),
+        })
+    }
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+    /// Time to wait until `amount` tokens are available,
+    /// `None` if the bucket can never hold that many.
+    fn wait_for(&mut self, amount: f64) -> Option<Duration> {
+        self.refill();
+        if amount > self.capacity {
+            return None;
+        }
+        let missing = (amount - self.tokens).max(0.0);
+        Some(Duration::from_secs_f64(missing / self.refill_per_sec))
+    }
+    fn take(&mut self, amount: f64) {
+        self.tokens -= amount;
+    }
+}
+
+/// Outcome of checking a frame against the rate limiter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Admitted,
+    /// Check again after the given duration, when enough tokens will be available.
+    Throttled(Duration),
+    Rejected,
+}
+
+/// Inbound rate limiter of a single peer, shared by all connections to that peer.
+#[derive(Debug)]
+pub struct RateLimiter {
+    messages: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    max_delay: Duration,
+    max_violations: u32,
+    violation_window: Duration,
+    /// Violations since `window_start`.
+    violations: u32,
+    window_start: Instant,
+}
+impl RateLimiter {
+    pub fn new(config: &RateLimit) -> Self {
+        Self {
+            messages: TokenBucket::new(config.messages_per_sec, config.message_burst),
+            bytes: TokenBucket::new(config.bytes_per_sec, config.byte_burst),
+            max_delay: Duration::from_millis(config.max_delay_ms),
+            max_violations: config.max_violations,
+            violation_window: Duration::from_secs(config.violation_window_sec),
+            violations: 0,
+            window_start: Instant::now(),
+        }
+    }
+    /// Try to admit a frame of `bytes` bytes, carrying `messages` new messages.
+    /// Tokens are only consumed when the frame is admitted.
+    /// Frames that can be admitted within the maximum delay are throttled instead of rejected.
+    pub fn admit(&mut self, messages: u64, bytes: usize) -> Admission {
+        let wait_messages = match self.messages.as_mut() {
+            Some(bucket) => bucket.wait_for(messages as f64),
+            None => Some(Duration::ZERO),
+        };
+        let wait_bytes = match self.bytes.as_mut() {
+            Some(bucket) => bucket.wait_for(bytes as f64),
+            None => Some(Duration::ZERO),
+        };
+        let wait = match (wait_messages, wait_bytes) {
+            (Some(a), Some(b)) => a.max(b),
+            _ => return self.reject(),
+        };
+        if wait.is_zero() {
+            if let Some(bucket) = self.messages.as_mut() {
+                bucket.take(messages as f64)
+            }
+            if let Some(bucket) = self.bytes.as_mut() {
+                bucket.take(bytes as f64)
+            }
+            return Admission::Admitted;
+        }
+        if wait > self.max_delay {
+            return self.reject();
+        }
+        Admission::Throttled(wait)
+    }
+    fn reject(&mut self) -> Admission {
+        if self.window_start.elapsed() >= self.violation_window {
+            self.violations = 0;
+            self.window_start = Instant::now();
+        }
+        self.violations += 1;
+        Admission::Rejected
+    }
+    /// Number of frames rejected in the current window.
+    pub fn violations(&self) -> u32 {
+        self.violations
+    }
+    /// Whether the peer has been rejected too many times within the window
+    /// and should be disconnected.
+    pub fn exceeded(&self) -> bool {
+        self.max_violations != 0 && self.violations >= self.max_violations
+    }
+}
@@ -0,0 +1,58 @@
+use super::error::SendError;
+use super::*;
+
+/// Packets exchanged between peers to perform remote procedure calls.  
+/// Carried over the messaging stream alongside regular messages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Packet {
+    /// Invoke a method registered on the remote peer.
+    Request {
+        /// Correlation ID of the call, unique among calls made by the sender.
+        id: u64,
+        method: String,
+        payload: Vec<u8>,
+    },
+    /// Result of a previous request.
+    Response {
+        id: u64,
+        result: Result<Vec<u8>, RpcError>,
+    },
+    /// The caller is no longer interested in the result of the request.
+    Cancel { id: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RpcError {
+    /// No method with the given name is registered on the remote peer.
+    MethodNotFound(String),
+    /// Remote peer is already handling too many calls from local peer,
+    /// or a call with the same ID.
+    Busy,
+    /// The handler on the remote peer returned an error.
+    Failed(String),
+    /// The call was cancelled before a response arrived.
+    Cancelled,
+    /// No response arrived in time.
+    Timeout,
+    /// The request or response cannot be (de)serialized.
+    Codec(String),
+    /// The request cannot be delivered to the remote peer.
+    #[serde(skip)]
+    Send(SendError),
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use RpcError::*;
+        match self {
+            MethodNotFound(method) => write!(f, "Method {method} not found on remote"),
+            Busy => f.write_str("Remote is busy handling other calls"),
+            Failed(msg) => write!(f, "Remote handler failed: {msg}"),
+            Cancelled => f.write_str("Call cancelled"),
+            Timeout => f.write_str("Call timed out"),
+            Codec(msg) => write!(f, "Failed to encode or decode payload: {msg}"),
+            Send(e) => write!(f, "Failed to send request: {e}"),
+        }
+    }
+}
+impl std::error::Error for RpcError {}